@@ -0,0 +1,257 @@
+//! User-defined command aliases for `qiwi-cli run`.
+//!
+//! An alias is a command line kept in the config, e.g.
+//! `outgoing = "payment-history --source {source} --output json"`. It is split into words the
+//! way a POSIX shell would, then `{name}` placeholders are filled with the arguments of `run`
+//! in the order the placeholders first appear. `{{` and `}}` stand for literal braces.
+
+use std::collections::BTreeMap;
+
+/// Part of a template word.
+#[derive(Debug, PartialEq)]
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+fn pieces(word: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = word;
+    while !rest.is_empty() {
+        let (text, tail) = match rest.find(|c: char| c == '{' || c == '}') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        rest = if let Some(tail) = tail.strip_prefix("{{") {
+            pieces.push(Piece::Text("{"));
+            tail
+        } else if let Some(tail) = tail.strip_prefix("}}") {
+            pieces.push(Piece::Text("}"));
+            tail
+        } else if let Some(tail) = tail.strip_prefix('{') {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in `{}`", word))?;
+            let name = &tail[..end];
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(format!("invalid placeholder `{{{}}}`", name));
+            }
+            pieces.push(Piece::Placeholder(name));
+            &tail[end + 1..]
+        } else if tail.starts_with('}') {
+            return Err(format!("unmatched `}}` in `{}`", word));
+        } else {
+            tail
+        };
+    }
+
+    Ok(pieces)
+}
+
+/// Splits and checks a template before it is saved.
+///
+/// Aliases can not run other aliases, so that expansion always ends.
+pub fn parse_template(template: &str) -> Result<Vec<String>, String> {
+    let words = shell_words::split(template).map_err(|e| format!("{}: {}", template, e))?;
+    match words.first().map(String::as_str) {
+        None => return Err("alias command is empty".to_string()),
+        Some("run") => return Err("aliases can not run other aliases".to_string()),
+        Some(_) => {}
+    }
+    for word in &words {
+        pieces(word)?;
+    }
+
+    Ok(words)
+}
+
+/// Placeholder names of the template words, in order of first appearance.
+pub fn placeholders(words: &[String]) -> Result<Vec<String>, String> {
+    let mut names = Vec::<String>::new();
+    for word in words {
+        for piece in pieces(word)? {
+            if let Piece::Placeholder(name) = piece {
+                if !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// Command line of alias `name` with placeholders filled with `args`.
+///
+/// Every placeholder needs exactly one argument, so mistakes are reported before anything runs.
+pub fn expand(
+    name: &str,
+    aliases: &BTreeMap<String, String>,
+    args: &[String],
+) -> Result<Vec<String>, String> {
+    let template = aliases
+        .get(name)
+        .ok_or_else(|| format!("unknown alias `{}`", name))?;
+    let words = parse_template(template)?;
+    let names = placeholders(&words)?;
+    if args.len() != names.len() {
+        return Err(format!(
+            "alias `{}` takes {} argument(s): {}, got {}",
+            name,
+            names.len(),
+            names
+                .iter()
+                .map(|name| format!("{{{}}}", name))
+                .collect::<Vec<_>>()
+                .join(" "),
+            args.len()
+        ));
+    }
+    let values = names
+        .iter()
+        .map(String::as_str)
+        .zip(args.iter().map(String::as_str))
+        .collect::<BTreeMap<_, _>>();
+
+    words
+        .iter()
+        .map(|word| {
+            Ok(pieces(word)?
+                .into_iter()
+                .map(|piece| match piece {
+                    Piece::Text(text) => text,
+                    Piece::Placeholder(name) => values[name],
+                })
+                .collect())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, template)| (name.to_string(), template.to_string()))
+            .collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn words_are_split_into_text_and_placeholders() {
+        assert_eq!(
+            pieces("--since={since}d").unwrap(),
+            vec![
+                Piece::Text("--since="),
+                Piece::Placeholder("since"),
+                Piece::Text("d")
+            ]
+        );
+        assert_eq!(
+            pieces("{a}{b_2}").unwrap(),
+            vec![Piece::Placeholder("a"), Piece::Placeholder("b_2")]
+        );
+        assert_eq!(pieces("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(
+            pieces("{{x}}").unwrap(),
+            vec![Piece::Text("{"), Piece::Text("x"), Piece::Text("}")]
+        );
+        assert_eq!(
+            pieces("{{{x}}}").unwrap(),
+            vec![Piece::Text("{"), Piece::Placeholder("x"), Piece::Text("}")]
+        );
+    }
+
+    #[test]
+    fn malformed_placeholders_are_rejected() {
+        assert!(pieces("{source").unwrap_err().contains("unclosed"));
+        assert!(pieces("source}").unwrap_err().contains("unmatched"));
+        assert!(pieces("{}").unwrap_err().contains("invalid placeholder"));
+        assert!(pieces("{a b}").unwrap_err().contains("invalid placeholder"));
+    }
+
+    #[test]
+    fn templates_are_split_like_a_shell() {
+        assert_eq!(
+            parse_template(r#"pay 26476 --comment "for {month}" --field 'account=a b'"#).unwrap(),
+            args(&[
+                "pay",
+                "26476",
+                "--comment",
+                "for {month}",
+                "--field",
+                "account=a b"
+            ])
+        );
+        assert!(parse_template(r#"pay --comment "unterminated"#).is_err());
+        assert!(parse_template("  ").unwrap_err().contains("empty"));
+        assert!(parse_template("payment-history {since").is_err());
+    }
+
+    #[test]
+    fn aliases_can_not_run_aliases() {
+        assert!(parse_template("run other")
+            .unwrap_err()
+            .contains("can not run other aliases"));
+
+        let aliases = aliases(&[("loop", "run loop")]);
+        assert!(expand("loop", &aliases, &[]).is_err());
+    }
+
+    #[test]
+    fn placeholders_are_filled_in_order_of_appearance() {
+        let aliases = aliases(&[(
+            "outgoing",
+            "payment-history --source {source} --since {since} --output json --tag {source}-{{x}}",
+        )]);
+        assert_eq!(
+            expand("outgoing", &aliases, &args(&["qw_wallet_rub", "30d"])).unwrap(),
+            args(&[
+                "payment-history",
+                "--source",
+                "qw_wallet_rub",
+                "--since",
+                "30d",
+                "--output",
+                "json",
+                "--tag",
+                "qw_wallet_rub-{x}"
+            ])
+        );
+    }
+
+    #[test]
+    fn argument_count_must_match_placeholders() {
+        let aliases = aliases(&[(
+            "outgoing",
+            "payment-history --source {source} --since {since}",
+        )]);
+
+        let error = expand("outgoing", &aliases, &args(&["qw_wallet_rub"])).unwrap_err();
+        assert!(
+            error.contains("takes 2 argument(s): {source} {since}, got 1"),
+            "{}",
+            error
+        );
+        assert!(expand("outgoing", &aliases, &args(&["a", "b", "c"])).is_err());
+        assert!(expand("missing", &aliases, &[])
+            .unwrap_err()
+            .contains("unknown alias"));
+    }
+}
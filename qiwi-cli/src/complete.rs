@@ -0,0 +1,88 @@
+//! Dynamic shell completions for `qiwi-cli completions`.
+//!
+//! Static completions come from the argument parser, while values that depend on the config,
+//! such as contacts, are printed by the hidden `__complete` command which the scripts call.
+
+use {
+    qiwi::{ProviderId, ProviderMap},
+    std::collections::BTreeMap,
+    structopt::clap::{App, Shell},
+};
+
+const BIN_NAME: &str = "qiwi-cli";
+
+/// Providers suggested for `pay`, known without asking the API.
+const KNOWN_PROVIDERS: &[ProviderId] = &[
+    ProviderId::QIWI,
+    ProviderId::VISA_RU,
+    ProviderId::VISA_CIS,
+    ProviderId::MASTERCARD_RU,
+    ProviderId::MASTERCARD_CIS,
+    ProviderId::MIR,
+    ProviderId::TINKOFF,
+    ProviderId::ALFABANK,
+    ProviderId::PROMSVYAZBANK,
+    ProviderId::RUSSIAN_STANDARD,
+    ProviderId::OTHER_BANK,
+];
+
+/// Known providers followed by the other ones of the config's `providers`.
+fn providers(configured: Option<&ProviderMap>) -> Vec<String> {
+    let mut providers = KNOWN_PROVIDERS.to_vec();
+    for (_, provider) in configured.into_iter().flat_map(ProviderMap::iter) {
+        if !providers.contains(&provider) {
+            providers.push(provider);
+        }
+    }
+    providers.iter().map(ToString::to_string).collect()
+}
+
+/// Candidates for the word following `words`, which are the arguments typed so far without the binary name.
+///
+/// Empty if the position is not a dynamic one, the static completions apply then.
+pub fn candidates(
+    words: &[String],
+    contacts: &BTreeMap<String, String>,
+    configured_providers: Option<&ProviderMap>,
+) -> Vec<String> {
+    let command = match words.first() {
+        Some(command) => command.as_str(),
+        None => return vec![],
+    };
+    let previous = words.last().map(String::as_str);
+
+    match (command, previous) {
+        ("pay", _) | ("commission-info", _) if words.len() == 1 => providers(configured_providers),
+        ("pay-recurring", Some("--provider")) => providers(configured_providers),
+        ("pay-recurring", Some("--account")) => contacts.keys().cloned().collect(),
+        ("transfer", _) if words.len() == 1 => contacts.keys().cloned().collect(),
+        _ => vec![],
+    }
+}
+
+/// Bash hook preferring `__complete` candidates over the generated static completions.
+fn bash_hook() -> String {
+    format!(
+        r#"
+_{bin}_dynamic() {{
+    local candidates
+    candidates=$({bin} __complete "${{COMP_WORDS[@]:1:$((COMP_CWORD - 1))}}" 2>/dev/null)
+    if [[ -n "$candidates" ]]; then
+        COMPREPLY=($(compgen -W "$candidates" -- "${{COMP_WORDS[COMP_CWORD]}}"))
+    else
+        _{bin} "$@"
+    fi
+}}
+complete -F _{bin}_dynamic -o bashdefault -o default {bin}
+"#,
+        bin = BIN_NAME
+    )
+}
+
+/// Prints the completion script for `shell`. Only bash scripts call `__complete`.
+pub fn print_script(mut app: App, shell: Shell) {
+    app.gen_completions_to(BIN_NAME, shell, &mut std::io::stdout());
+    if let Shell::Bash = shell {
+        print!("{}", bash_hook());
+    }
+}
@@ -0,0 +1,410 @@
+//! Environment diagnostics for `qiwi-cli doctor`.
+
+use {
+    crate::{Config, OutputFormat},
+    chrono::prelude::*,
+    phonenumber::PhoneNumber,
+    qiwi::Client,
+    serde::Serialize,
+    std::path::Path,
+};
+
+const API_HOST: &str = "edge.qiwi.com";
+const MAX_CLOCK_SKEW_SECS: i64 = 120;
+/// Personal API tokens are 32 hex characters long.
+const MIN_TOKEN_LEN: usize = 32;
+
+#[derive(Clone, Debug, Serialize)]
+struct CheckResult {
+    name: &'static str,
+    passed: bool,
+    details: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+}
+
+impl CheckResult {
+    fn pass<T: Into<String>>(name: &'static str, details: T) -> Self {
+        Self {
+            name,
+            passed: true,
+            details: details.into(),
+            hint: None,
+        }
+    }
+
+    fn fail<T: Into<String>>(name: &'static str, details: T, hint: &'static str) -> Self {
+        Self {
+            name,
+            passed: false,
+            details: details.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+const LOGIN_HINT: &str = "run `qiwi-cli login` to create a new config";
+
+fn check_config_exists(path: &Path) -> CheckResult {
+    const NAME: &str = "config file exists";
+    match std::fs::metadata(path) {
+        Ok(_) => CheckResult::pass(NAME, path.to_string_lossy()),
+        Err(e) => CheckResult::fail(
+            NAME,
+            format!("{}: {}", path.to_string_lossy(), e),
+            LOGIN_HINT,
+        ),
+    }
+}
+
+fn check_config_parses(path: &Path) -> (CheckResult, Option<Config>) {
+    const NAME: &str = "config file parses";
+    let config = std::fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| toml::from_slice::<Config>(&data).map_err(|e| e.to_string()));
+    match config {
+        Ok(config) => (CheckResult::pass(NAME, "ok"), Some(config)),
+        Err(e) => (CheckResult::fail(NAME, e, LOGIN_HINT), None),
+    }
+}
+
+fn check_phone(config: &Config) -> CheckResult {
+    const NAME: &str = "phone number parses";
+    match config.phone.parse::<PhoneNumber>() {
+        Ok(_) => CheckResult::pass(NAME, "ok"),
+        Err(e) => CheckResult::fail(
+            NAME,
+            e.to_string(),
+            "the phone must be in international format, e.g. +79991234567",
+        ),
+    }
+}
+
+fn check_token(config: &Config) -> CheckResult {
+    const NAME: &str = "token present";
+    let token = config.token.trim();
+    if token.is_empty() {
+        CheckResult::fail(NAME, "token is empty", LOGIN_HINT)
+    } else if token.len() < MIN_TOKEN_LEN {
+        CheckResult::fail(
+            NAME,
+            format!("token is only {} characters long", token.len()),
+            "the token looks truncated, copy it again from qiwi.com/api",
+        )
+    } else {
+        CheckResult::pass(NAME, "ok")
+    }
+}
+
+fn check_token_age(config: &Config, now: DateTime<Utc>) -> CheckResult {
+    const NAME: &str = "token expiry";
+    const RENEW_HINT: &str = "issue a new token at qiwi.com/api and run `qiwi-cli login`";
+    let issued = match config.token_issued {
+        Some(issued) => issued,
+        None => {
+            return CheckResult::pass(
+                NAME,
+                "unknown, `qiwi-cli login` records when the token was issued",
+            )
+        }
+    };
+
+    let expiry = issued + chrono::Duration::days(qiwi::DEFAULT_TOKEN_LIFETIME_DAYS);
+    let left = (expiry - now).num_days();
+    if left < qiwi::DEFAULT_TOKEN_WARNING_DAYS {
+        CheckResult::fail(
+            NAME,
+            format!(
+                "token issued on {} expires around {}",
+                issued.date(),
+                expiry.date()
+            ),
+            RENEW_HINT,
+        )
+    } else {
+        CheckResult::pass(NAME, format!("around {}, in {} days", expiry.date(), left))
+    }
+}
+
+fn check_config_dir_writable(path: &Path) -> CheckResult {
+    const NAME: &str = "config directory writable";
+    let dir = match path.parent() {
+        Some(dir) => dir,
+        None => return CheckResult::fail(NAME, "config path has no parent", LOGIN_HINT),
+    };
+
+    let probe = dir.join(".qiwi-cli-doctor");
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, b""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match result {
+        Ok(()) => CheckResult::pass(NAME, dir.to_string_lossy()),
+        Err(e) => CheckResult::fail(
+            NAME,
+            format!("{}: {}", dir.to_string_lossy(), e),
+            "check permissions of the config directory",
+        ),
+    }
+}
+
+async fn check_dns(host: &str) -> CheckResult {
+    const NAME: &str = "DNS resolution";
+    match tokio::net::lookup_host((host, 443)).await {
+        Ok(mut addrs) => match addrs.next() {
+            Some(addr) => CheckResult::pass(NAME, format!("{} resolves to {}", host, addr.ip())),
+            None => CheckResult::fail(
+                NAME,
+                format!("{} has no addresses", host),
+                "check your DNS settings",
+            ),
+        },
+        Err(e) => CheckResult::fail(NAME, e.to_string(), "check your DNS settings"),
+    }
+}
+
+/// Connects to the API host and returns the server time from the `Date` header.
+async fn check_tls(host: &str) -> (CheckResult, Option<DateTime<Utc>>) {
+    const NAME: &str = "TLS handshake";
+    match reqwest::get(&format!("https://{}/", host)).await {
+        Ok(rsp) => {
+            let server_time = rsp
+                .headers()
+                .get(reqwest::header::DATE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+                .map(|v| v.with_timezone(&Utc));
+            (CheckResult::pass(NAME, "ok"), server_time)
+        }
+        Err(e) => (
+            CheckResult::fail(
+                NAME,
+                e.to_string(),
+                "check that no proxy or firewall intercepts HTTPS traffic",
+            ),
+            None,
+        ),
+    }
+}
+
+fn check_clock_skew(server_time: DateTime<Utc>, now: DateTime<Utc>) -> CheckResult {
+    const NAME: &str = "clock skew";
+    let skew = (now - server_time).num_seconds();
+    if skew.abs() > MAX_CLOCK_SKEW_SECS {
+        CheckResult::fail(
+            NAME,
+            format!("local clock is {} seconds off server time", skew),
+            "synchronize your system clock, e.g. enable NTP",
+        )
+    } else {
+        CheckResult::pass(NAME, format!("{} seconds", skew))
+    }
+}
+
+async fn check_auth(client: &Client) -> CheckResult {
+    const NAME: &str = "authenticated request";
+    match client.profile_info().await {
+        Ok(_) => CheckResult::pass(NAME, "ok"),
+        Err(e) => CheckResult::fail(
+            NAME,
+            e.to_string(),
+            "the token may be expired or revoked, issue a new one and run `qiwi-cli login`",
+        ),
+    }
+}
+
+/// With `base_url`, e.g. of a mock server, network checks of the QIWI host are skipped and the
+/// authenticated request goes to `base_url`.
+async fn run_checks(path: &Path, base_url: Option<String>) -> Vec<CheckResult> {
+    let mut results = vec![check_config_exists(path)];
+
+    let (result, config) = check_config_parses(path);
+    results.push(result);
+    if let Some(config) = &config {
+        results.push(check_phone(config));
+        results.push(check_token(config));
+        results.push(check_token_age(config, Utc::now()));
+    }
+    results.push(check_config_dir_writable(path));
+
+    if base_url.is_none() {
+        results.push(check_dns(API_HOST).await);
+        let (result, server_time) = check_tls(API_HOST).await;
+        results.push(result);
+        if let Some(server_time) = server_time {
+            results.push(check_clock_skew(server_time, Utc::now()));
+        }
+    }
+
+    if let Some(config) = config {
+        if let Ok(phone) = config.phone.parse::<PhoneNumber>() {
+            let mut builder = Client::builder(phone, config.token);
+            if let Some(base_url) = base_url {
+                builder = builder.base_url(base_url);
+            }
+            results.push(check_auth(&builder.build()).await);
+        }
+    }
+
+    results
+}
+
+/// Runs all checks and prints results. Returns whether all checks have passed.
+pub async fn run(
+    path: &Path,
+    base_url: Option<String>,
+    output: OutputFormat,
+) -> Result<bool, serde_json::Error> {
+    let results = run_checks(path, base_url).await;
+
+    match output {
+        OutputFormat::Text => {
+            for result in &results {
+                let status = if result.passed { " OK " } else { "FAIL" };
+                println!("[{}] {}: {}", status, result.name, result.details);
+                if let Some(hint) = result.hint {
+                    println!("       {}", hint);
+                }
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+    }
+
+    Ok(results.iter().all(|result| result.passed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(phone: &str, token: &str, token_issued: Option<&str>) -> Config {
+        let mut config = format!("phone = {:?}\ntoken = {:?}\n", phone, token);
+        if let Some(issued) = token_issued {
+            config += &format!("token_issued = {:?}\n", issued);
+        }
+        toml::from_str(&config).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn config_file_is_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let missing = check_config_exists(&path);
+        assert!(!missing.passed);
+        assert_eq!(missing.hint, Some(LOGIN_HINT));
+        assert!(check_config_parses(&path).1.is_none());
+
+        std::fs::write(&path, "phone = \"+79991234567\"\n").unwrap();
+        assert!(check_config_exists(&path).passed);
+        let (result, config) = check_config_parses(&path);
+        assert!(!result.passed);
+        assert!(result.details.contains("token"), "{}", result.details);
+        assert!(config.is_none());
+
+        std::fs::write(
+            &path,
+            "phone = \"+79991234567\"\ntoken = \"0123456789abcdef0123456789abcdef\"\n",
+        )
+        .unwrap();
+        let (result, config) = check_config_parses(&path);
+        assert!(result.passed);
+        assert_eq!(config.unwrap().phone, "+79991234567");
+    }
+
+    #[test]
+    fn phone_must_be_international() {
+        let token = "0123456789abcdef0123456789abcdef";
+        assert!(check_phone(&config("+79991234567", token, None)).passed);
+        assert!(!check_phone(&config("89991234567", token, None)).passed);
+        assert!(!check_phone(&config("", token, None)).passed);
+    }
+
+    #[test]
+    fn truncated_tokens_fail() {
+        let check = |token: &str| check_token(&config("+79991234567", token, None));
+        assert!(check("0123456789abcdef0123456789abcdef").passed);
+
+        let empty = check("   ");
+        assert!(!empty.passed);
+        assert_eq!(empty.details, "token is empty");
+
+        let short = check("0123456789abcdef");
+        assert!(!short.passed);
+        assert_eq!(short.details, "token is only 16 characters long");
+    }
+
+    #[test]
+    fn token_expiring_soon_fails() {
+        let token = "0123456789abcdef0123456789abcdef";
+        let check = |issued| check_token_age(&config("+79991234567", token, issued), now());
+
+        let unknown = check(None);
+        assert!(unknown.passed);
+        assert!(unknown.details.starts_with("unknown"));
+
+        // Expires in 180 - 150 = 30 days.
+        let valid = check(Some("2020-01-03T12:00:00Z"));
+        assert!(valid.passed, "{}", valid.details);
+        assert!(valid.details.ends_with("in 30 days"), "{}", valid.details);
+
+        // Expires in 10 days, and after it has expired.
+        assert!(!check(Some("2019-12-14T12:00:00Z")).passed);
+        assert!(!check(Some("2019-01-01T00:00:00Z")).passed);
+    }
+
+    #[test]
+    fn clock_skew_beyond_two_minutes_fails() {
+        let seconds = chrono::Duration::seconds;
+        assert!(check_clock_skew(now(), now()).passed);
+        assert!(check_clock_skew(now() - seconds(120), now()).passed);
+        assert!(check_clock_skew(now() + seconds(120), now()).passed);
+
+        let ahead = check_clock_skew(now() - seconds(121), now());
+        assert!(!ahead.passed);
+        assert_eq!(ahead.details, "local clock is 121 seconds off server time");
+        assert!(!check_clock_skew(now() + seconds(121), now()).passed);
+    }
+
+    #[test]
+    fn config_directory_must_be_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qiwi-cli").join("config.toml");
+
+        let result = check_config_dir_writable(&path);
+        assert!(result.passed, "{}", result.details);
+        assert!(path.parent().unwrap().is_dir());
+        assert_eq!(
+            std::fs::read_dir(path.parent().unwrap()).unwrap().count(),
+            0
+        );
+
+        // A file where the directory should be.
+        let blocked = dir.path().join("file");
+        std::fs::write(&blocked, b"").unwrap();
+        assert!(!check_config_dir_writable(&blocked.join("config.toml")).passed);
+    }
+
+    #[test]
+    fn results_serialize_for_bug_reports() {
+        let results = vec![
+            CheckResult::pass("phone number parses", "ok"),
+            CheckResult::fail("token present", "token is empty", LOGIN_HINT),
+        ];
+        assert_eq!(
+            serde_json::to_value(&results).unwrap(),
+            serde_json::json!([
+                { "name": "phone number parses", "passed": true, "details": "ok" },
+                {
+                    "name": "token present",
+                    "passed": false,
+                    "details": "token is empty",
+                    "hint": LOGIN_HINT,
+                },
+            ])
+        );
+    }
+}
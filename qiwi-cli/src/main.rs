@@ -1,22 +1,157 @@
-use {phonenumber::PhoneNumber, qiwi::*, serde::*, std::path::*, structopt::*, tokio::stream::*};
+mod alias;
+mod complete;
+mod doctor;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+use {
+    bigdecimal::BigDecimal,
+    phonenumber::PhoneNumber,
+    qiwi::*,
+    serde::*,
+    serde_json::json,
+    std::{collections::BTreeMap, path::*, str::FromStr},
+    structopt::{clap::AppSettings, *},
+    tokio::{io::Stdin, stream::*},
+    tokio_util::codec::{FramedRead, LinesCodec},
+};
+
+type Lines = FramedRead<Stdin, LinesCodec>;
+
+#[derive(Clone, Serialize, Deserialize)]
 struct Config {
     phone: String,
     token: String,
+    /// When the token was saved by `login`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token_issued: Option<chrono::DateTime<chrono::Utc>>,
+    /// Names usable instead of accounts, e.g. `home = "+79991234567"`.
+    #[serde(default)]
+    contacts: BTreeMap<String, String>,
+    /// Provider ids replacing the built-in ones, e.g. `"card.visa" = 1963`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    providers: Option<ProviderMap>,
+    /// Command lines for `run`, e.g. `outgoing = "payment-history --source {source}"`.
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
 }
 
-fn config_location() -> PathBuf {
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("phone", &display::mask_phone(&self.phone))
+            .field("token", &"<redacted>")
+            .field("token_issued", &self.token_issued)
+            .field("contacts", &self.contacts.len())
+            .field("providers", &self.providers)
+            .field("aliases", &self.aliases.len())
+            .finish()
+    }
+}
+
+fn default_config_location() -> PathBuf {
     let mut path = xdg::BaseDirectories::new().unwrap().get_config_home();
     path.push("qiwi-cli/config.toml");
 
     path
 }
 
+/// Value of `--config`, which is needed before the arguments are parsed since it decides which commands are available.
+fn config_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(path.into());
+        }
+    }
+
+    None
+}
+
+#[derive(Debug, StructOpt)]
+struct GlobalOpts {
+    /// Config file, `$XDG_CONFIG_HOME/qiwi-cli/config.toml` by default
+    #[structopt(long, global = true, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Send requests to another host, e.g. a mock server in tests
+    #[structopt(long, global = true, hidden = true)]
+    base_url: Option<String>,
+    /// Ask for approval on the terminal before payments above this amount, in rubles
+    #[structopt(long, global = true)]
+    mfa: Option<BigDecimal>,
+    /// Append every payment attempt to this file as JSON lines
+    #[structopt(long, global = true, parse(from_os_str))]
+    audit_log: Option<PathBuf>,
+}
+
+impl GlobalOpts {
+    /// Options given to `run` apply unless the alias sets them.
+    fn or(self, other: Self) -> Self {
+        Self {
+            config: self.config.or(other.config),
+            base_url: self.base_url.or(other.base_url),
+            mfa: self.mfa.or(other.mfa),
+            audit_log: self.audit_log.or(other.audit_log),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => Err(format!("unknown output format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct UnauthorizedOpts {
+    #[structopt(flatten)]
+    global: GlobalOpts,
+    #[structopt(subcommand)]
+    cmd: UnauthorizedCmd,
+}
+
+#[derive(Debug, StructOpt)]
+struct AuthorizedOpts {
+    #[structopt(flatten)]
+    global: GlobalOpts,
+    #[structopt(subcommand)]
+    cmd: AuthorizedCmd,
+}
+
 #[derive(Debug, StructOpt)]
 enum UnauthorizedCmd {
     /// Authorize client
     Login,
+    /// Diagnose common environment problems
+    Doctor {
+        /// `text` or `json`
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Print shell completion script
+    Completions {
+        #[structopt(possible_values = &structopt::clap::Shell::variants())]
+        shell: structopt::clap::Shell,
+    },
+    /// Print completion candidates for the arguments typed so far
+    #[structopt(
+        name = "__complete",
+        settings = &[AppSettings::Hidden, AppSettings::TrailingVarArg, AppSettings::AllowLeadingHyphen]
+    )]
+    Complete { words: Vec<String> },
 }
 
 #[derive(Debug, StructOpt)]
@@ -24,20 +159,291 @@ enum UnauthorizedCmd {
 enum AuthorizedCmd {
     /// Reauthorize client
     Login,
+    /// Diagnose common environment problems
+    Doctor {
+        /// `text` or `json`
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Print shell completion script
+    Completions {
+        #[structopt(possible_values = &structopt::clap::Shell::variants())]
+        shell: structopt::clap::Shell,
+    },
+    /// Print completion candidates for the arguments typed so far
+    #[structopt(
+        name = "__complete",
+        settings = &[AppSettings::Hidden, AppSettings::TrailingVarArg, AppSettings::AllowLeadingHyphen]
+    )]
+    Complete {
+        words: Vec<String>,
+    },
+    /// Manage command aliases
+    Alias {
+        #[structopt(subcommand)]
+        cmd: AliasCmd,
+    },
+    /// Run a command alias, filling its placeholders with the arguments in order
+    #[structopt(settings = &[AppSettings::TrailingVarArg, AppSettings::AllowLeadingHyphen])]
+    Run {
+        alias: String,
+        args: Vec<String>,
+    },
+    /// Show wallet balances
+    Balance {
+        /// `text` or `json`, one balance per line
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
     /// Get profile info,
-    ProfileInfo,
+    ProfileInfo {
+        /// Do not mask phone numbers, emails and card numbers
+        #[structopt(long)]
+        show_sensitive: bool,
+    },
     /// Get payment history,
-    PaymentHistory,
+    PaymentHistory {
+        /// Do not mask phone numbers, emails and card numbers
+        #[structopt(long)]
+        show_sensitive: bool,
+        /// Print at most this many entries
+        #[structopt(long)]
+        limit: Option<usize>,
+        /// Only payments from or to this source, e.g. `qw_rub` or `card`. Can be repeated.
+        #[structopt(long = "source")]
+        sources: Vec<HistorySource>,
+        /// `text` or `json`, one entry per line
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Show incoming and outgoing totals of recent payments
+    Stats {
+        /// Sum up payments of this many last days, at most 90
+        #[structopt(long, default_value = "30")]
+        days: i64,
+        /// Only payments from or to this source, e.g. `qw_rub` or `card`. Can be repeated.
+        #[structopt(long = "source")]
+        sources: Vec<HistorySource>,
+        /// `text` or `json`
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Get a transaction
+    Txn {
+        txn_id: u64,
+        /// Look up an incoming transaction instead of an outgoing one
+        #[structopt(long)]
+        incoming: bool,
+        /// Do not mask phone numbers, emails and card numbers
+        #[structopt(long)]
+        show_sensitive: bool,
+        /// Save the bank payment order of a transfer to a bank account
+        #[structopt(long)]
+        bank_doc: Option<PathBuf>,
+    },
+    /// Save the receipt of a transaction
+    Cheque {
+        txn_id: u64,
+        /// Receipt of an incoming transaction instead of an outgoing one
+        #[structopt(long)]
+        incoming: bool,
+        /// `pdf` or `jpeg`
+        #[structopt(long, default_value = "pdf")]
+        format: ChequeFormat,
+        #[structopt(long)]
+        out: PathBuf,
+    },
+    /// Save the official statement of the wallet
+    Statement {
+        /// First day, e.g. `2020-01-31`
+        #[structopt(long)]
+        from: chrono::NaiveDate,
+        /// Last day, included in the statement
+        #[structopt(long)]
+        till: chrono::NaiveDate,
+        /// `pdf` or `xlsx`
+        #[structopt(long, default_value = "pdf")]
+        format: StatementFormat,
+        #[structopt(long)]
+        out: PathBuf,
+    },
     CommissionInfo {
         provider: ProviderId,
     },
+    /// Commission terms for estimating fees offline
+    Commission {
+        #[structopt(subcommand)]
+        cmd: CommissionCmd,
+    },
+    /// Transfer to a QIWI wallet or a mobile phone account
+    Transfer {
+        /// Phone number or contact name from the config
+        to: String,
+        #[structopt(long)]
+        amount: BigDecimal,
+        /// Currency of the amount, sent from the balance in that currency, e.g. `usd`
+        #[structopt(long, default_value = "rub")]
+        currency: QiwiCurrency,
+        /// Top up the mobile phone account instead of the wallet, in rubles only
+        #[structopt(long)]
+        mobile: bool,
+        #[structopt(long)]
+        comment: Option<String>,
+        /// Transfer without confirmation
+        #[structopt(long)]
+        yes: bool,
+        /// Print the quote without transferring
+        #[structopt(long)]
+        dry_run: bool,
+        /// `text` or `json`. JSON mode never prompts.
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Transfer rubles to QIWI wallets listed in a file
+    ///
+    /// The file has one JSON object per line, e.g. `{"to": "home", "amount": "100.50", "comment": "May"}`.
+    Payout {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Compare with the audit log of a previous run and skip transfers paid by it
+        #[structopt(long, parse(from_os_str))]
+        plan_diff: Option<PathBuf>,
+        /// Pay without confirmation, and even if the previous run may have paid some of the payees
+        #[structopt(long)]
+        yes: bool,
+        /// `text` or `json`, one object per line. JSON mode never prompts.
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Pay to an arbitrary provider
+    Pay {
+        provider: ProviderId,
+        /// Amount in the currency of the wallet's region
+        #[structopt(long)]
+        amount: BigDecimal,
+        /// Provider form field in `name=value` form
+        #[structopt(long = "field")]
+        fields: Vec<String>,
+        #[structopt(long)]
+        comment: Option<String>,
+        /// Pay without confirmation
+        #[structopt(long)]
+        yes: bool,
+        /// `text` or `json`. JSON mode never prompts, all fields and `--yes` must be supplied.
+        #[structopt(long, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Pay once per period, safe to re-run e.g. from cron.
+    ///
+    /// Prints `paid` and exits with 0, `already-paid` with 10, or `failed` with 1.
+    PayRecurring {
+        #[structopt(long)]
+        provider: ProviderId,
+        /// Account or contact name from the config
+        #[structopt(long)]
+        account: String,
+        /// Amount in the currency of the wallet's region
+        #[structopt(long)]
+        amount: BigDecimal,
+        /// `day`, `week` or `month`
+        #[structopt(long, default_value = "month")]
+        period: Period,
+        /// Name distinguishing this recurring payment from others
+        #[structopt(long)]
+        tag: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum CommissionCmd {
+    /// Save the commission terms of providers, refreshing only missing or outdated ones if the
+    /// file exists
+    Snapshot {
+        /// Comma separated provider IDs, e.g. `99,1963`
+        #[structopt(long, use_delimiter = true, required = true)]
+        providers: Vec<ProviderId>,
+        #[structopt(long, parse(from_os_str))]
+        out: PathBuf,
+        /// Refetch terms older than this many hours
+        #[structopt(long, default_value = "24")]
+        max_age: i64,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum AliasCmd {
+    /// Add or replace an alias
+    Add {
+        name: String,
+        /// Command line quoted as one argument, e.g. `"payment-history --source {source}"`
+        template: String,
+    },
+    /// List aliases
+    List,
+    /// Remove an alias
+    Remove { name: String },
+}
+
+async fn save_config(
+    path: &Path,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    tokio::fs::write(path, toml::to_vec(config)?).await?;
+
+    Ok(())
+}
+
+async fn do_alias(
+    path: &Path,
+    mut config: Config,
+    cmd: AliasCmd,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match cmd {
+        AliasCmd::Add { name, template } => {
+            alias::parse_template(&template)?;
+            config.aliases.insert(name, template);
+        }
+        AliasCmd::List => {
+            for (name, template) in &config.aliases {
+                println!("{} = {}", name, template);
+            }
+            return Ok(());
+        }
+        AliasCmd::Remove { name } => {
+            if config.aliases.remove(&name).is_none() {
+                return Err(format!("unknown alias `{}`", name).into());
+            }
+        }
+    }
+
+    save_config(path, &config).await
 }
 
-async fn do_authorize() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut stdin = tokio_util::codec::FramedRead::new(
-        tokio::io::stdin(),
-        tokio_util::codec::LinesCodec::new(),
-    );
+fn stdin_lines() -> Lines {
+    FramedRead::new(tokio::io::stdin(), LinesCodec::new())
+}
+
+async fn prompt(
+    stdin: &mut Lines,
+    message: &str,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    println!("{}", message);
+    Ok(stdin
+        .next()
+        .await
+        .unwrap_or_else(|| std::process::exit(0))?)
+}
+
+async fn do_authorize(
+    path: &Path,
+    contacts: BTreeMap<String, String>,
+    providers: Option<ProviderMap>,
+    aliases: BTreeMap<String, String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut stdin = stdin_lines();
 
     println!("Please enter user ID");
 
@@ -55,22 +461,403 @@ async fn do_authorize() -> Result<(), Box<dyn std::error::Error + Send + Sync>>
         .await
         .unwrap_or_else(|| std::process::exit(0))?;
 
-    let path = config_location();
     println!("Saving token on disk to {}", path.to_string_lossy());
-    if let Some(parent) = path.parent() {
-        let _ = std::fs::create_dir_all(parent);
+    save_config(
+        path,
+        &Config {
+            phone,
+            token,
+            token_issued: Some(chrono::Utc::now()),
+            contacts,
+            providers,
+            aliases,
+        },
+    )
+    .await
+}
+
+async fn do_pay(
+    client: &Client,
+    provider: ProviderId,
+    amount: BigDecimal,
+    field_args: Vec<String>,
+    comment: Option<String>,
+    yes: bool,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let interactive = matches!(output, OutputFormat::Text);
+    if !interactive && !yes {
+        return Err("payments are not confirmed without a terminal, pass --yes to pay".into());
+    }
+
+    let form = client.provider_form(provider).await?;
+    let mut fields = TypedFields::from_form(&form);
+    for arg in field_args {
+        let mut parts = arg.splitn(2, '=');
+        let name = parts.next().unwrap_or_default().to_string();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("field must be in name=value form: {}", arg))?;
+        fields.set(name, value)?;
+    }
+
+    let mut stdin = stdin_lines();
+    let missing = fields
+        .missing_required()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    if !interactive && !missing.is_empty() {
+        return Err(MissingFields(missing.into_iter().map(|field| field.name).collect()).into());
+    }
+    for field in missing {
+        loop {
+            let value = prompt(&mut stdin, &format!("Please enter {}", field.title)).await?;
+            match fields.set(field.name.clone(), value) {
+                Ok(_) => break,
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+    let fields = fields.into_fields()?;
+
+    let amount = Money::new(amount, client.region().currency());
+    let quote = client
+        .quote_payment(provider, amount.clone(), &fields)
+        .await?;
+
+    if interactive {
+        println!("Provider: {}", provider);
+        for (name, value) in &fields {
+            println!("{}: {}", name, value);
+        }
+        println!("Amount: {}", amount);
+        println!("Commission: {}", quote.qw_commission);
+        println!("Total: {}", quote.withdraw_sum);
+
+        if !yes {
+            let answer = prompt(&mut stdin, "Proceed? [y/N]").await?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                println!("Cancelled");
+                return Ok(());
+            }
+        }
+    }
+
+    let id = client.next_payment_id();
+    if interactive {
+        println!("Payment id: {}", id);
+    }
+    let payment = client
+        .pay(provider, amount, fields, comment, Some(id))
+        .await?;
+
+    match output {
+        OutputFormat::Text => println!("{:?}", payment),
+        OutputFormat::Json => println!(
+            "{}",
+            json!({
+                "quote": quote,
+                "payment": payment,
+            })
+        ),
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn do_transfer(
+    client: &Client,
+    to: PhoneNumber,
+    amount: BigDecimal,
+    currency: QiwiCurrency,
+    mobile: bool,
+    comment: Option<String>,
+    yes: bool,
+    dry_run: bool,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let interactive = matches!(output, OutputFormat::Text);
+
+    let recipient = to.to_string();
+    let direction = if mobile {
+        TransferDirection::CellularAuto { to_phone: to }
+    } else {
+        TransferDirection::Qiwi {
+            to_phone: to,
+            to_currency: currency.currency(),
+        }
+    };
+    if direction.currency() != currency.currency() {
+        return Err(format!(
+            "mobile top-ups are in {:?} only, not {:?}",
+            direction.currency(),
+            currency.currency()
+        )
+        .into());
+    }
+    let source = AccountAlias::from_currency(currency.currency())
+        .ok_or_else(|| format!("no wallet balance in currency {}", currency))?;
+
+    let requested = Money {
+        amount: amount.clone(),
+        currency: currency.clone(),
+    };
+    let quote = client.quote_transfer(&direction, amount.clone()).await?;
+
+    let mut stdin = stdin_lines();
+    if interactive {
+        println!("Recipient: {}", recipient);
+        println!("Amount: {}", requested);
+        println!("Rate: {}", quote.commission.withdraw_to_enrollment_rate);
+        println!("Commission: {}", quote.commission.qw_commission);
+        println!("Debit from {}: {}", source, quote.commission.withdraw_sum);
+
+        if !yes && !dry_run {
+            let answer = prompt(&mut stdin, "Proceed? [y/N]").await?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                println!("Cancelled");
+                return Ok(());
+            }
+        }
+    }
+
+    if dry_run {
+        match output {
+            OutputFormat::Text => println!("Dry run, nothing transferred"),
+            OutputFormat::Json => println!(
+                "{}",
+                json!({
+                    "requested": requested,
+                    "debited": quote.commission.withdraw_sum,
+                    "rate": quote.commission.withdraw_to_enrollment_rate,
+                    "commission": quote.commission.qw_commission,
+                    "transfer": null,
+                })
+            ),
+        }
+        return Ok(());
+    }
+
+    let req = client
+        .transfer_request(amount, direction, comment.unwrap_or_default())
+        .source(source);
+    if interactive {
+        println!("Payment id: {}", req.idempotency_id());
+    }
+    let transfer = client.transfer(&req).await?;
+
+    match output {
+        OutputFormat::Text => println!("{:?}", transfer),
+        OutputFormat::Json => println!(
+            "{}",
+            json!({
+                "requested": requested,
+                "debited": quote.commission.withdraw_sum,
+                "rate": quote.commission.withdraw_to_enrollment_rate,
+                "commission": quote.commission.qw_commission,
+                "transfer": transfer,
+            })
+        ),
     }
-    tokio::fs::write(path, toml::to_vec(&Config { phone, token })?).await?;
 
     Ok(())
 }
 
+/// Line of a `payout` file.
+#[derive(Deserialize)]
+struct PayoutLine {
+    /// Phone number or contact name from the config
+    to: String,
+    amount: BigDecimal,
+    #[serde(default)]
+    comment: String,
+}
+
+fn print_diff_items(title: &str, items: &[batch::DiffItem]) {
+    if items.is_empty() {
+        return;
+    }
+    println!("{}:", title);
+    for item in items {
+        print!("  #{} {}: {}", item.index + 1, item.recipient, item.planned);
+        if let Some(previous) = &item.previous {
+            print!(", previously {}", previous);
+        }
+        if let Some(txn_id) = &item.previous_txn_id {
+            print!(" in transaction {}", txn_id);
+        } else if let Some(payment_id) = &item.previous_payment_id {
+            print!(" with payment id {}", payment_id);
+        }
+        println!();
+    }
+}
+
+async fn do_payout(
+    client: &Client,
+    contacts: &BTreeMap<String, String>,
+    file: PathBuf,
+    plan_diff: Option<PathBuf>,
+    yes: bool,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let interactive = matches!(output, OutputFormat::Text);
+
+    let mut requests = Vec::new();
+    for (i, line) in tokio::fs::read_to_string(&file).await?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line = serde_json::from_str::<PayoutLine>(line)
+            .map_err(|e| format!("{}:{}: {}", file.display(), i + 1, e))?;
+        let to = contacts.get(&line.to).unwrap_or(&line.to).parse()?;
+        let direction = TransferDirection::Qiwi {
+            to_phone: to,
+            to_currency: deps::penny::Currency::RUB,
+        };
+        requests.push(
+            client
+                .transfer_request(line.amount, direction, line.comment)
+                .source(AccountAlias::QW_WALLET_RUB),
+        );
+    }
+
+    let plan = batch::plan(client, requests).await?;
+    if interactive {
+        println!("Planned transfers:");
+        for item in &plan.items {
+            println!(
+                "  #{} {}: {}, debit {}",
+                item.index + 1,
+                item.recipient,
+                item.sum,
+                item.quote.commission.withdraw_sum
+            );
+        }
+    }
+
+    let mut skipped = std::collections::BTreeSet::new();
+    if let Some(path) = plan_diff {
+        let diff = plan.diff(&audit::AuditLogReader::from_file(path)?);
+        match output {
+            OutputFormat::Text => {
+                print_diff_items("Already paid, skipped", &diff.already_paid);
+                print_diff_items("Paid with another amount", &diff.amount_changed);
+                print_diff_items("Outcome unknown, check history", &diff.unresolved);
+                print_diff_items("To pay", &diff.to_pay);
+            }
+            OutputFormat::Json => println!("{}", json!({ "diff": diff })),
+        }
+        if diff.has_overlaps() && !yes {
+            return Err(
+                "the previous run may have paid some of the payees, pass --yes to pay the rest anyway"
+                    .into(),
+            );
+        }
+        skipped.extend(diff.already_paid.iter().map(|item| item.index));
+    }
+
+    if interactive && !yes {
+        let answer = prompt(&mut stdin_lines(), "Proceed? [y/N]").await?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Cancelled");
+            return Ok(());
+        }
+    }
+
+    for item in plan
+        .items
+        .iter()
+        .filter(|item| !skipped.contains(&item.index))
+    {
+        let transfer = client.transfer(&item.request).await?;
+        match output {
+            OutputFormat::Text => println!("#{} {:?}", item.index + 1, transfer),
+            OutputFormat::Json => {
+                println!("{}", json!({ "index": item.index, "transfer": transfer }))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const EXIT_ALREADY_PAID: i32 = 10;
+
+async fn do_pay_recurring(
+    client: &Client,
+    provider: ProviderId,
+    account: String,
+    amount: BigDecimal,
+    period: Period,
+    tag: String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let account = AccountId::parse_in(&account, provider, None, client.region())?;
+    match client
+        .pay_once_per_period(provider, account, amount, period, &tag)
+        .await
+    {
+        Ok(RecurringOutcome::Paid(_)) => println!("paid"),
+        Ok(RecurringOutcome::AlreadyPaid(_)) => {
+            println!("already-paid");
+            std::process::exit(EXIT_ALREADY_PAID);
+        }
+        Err(e) => {
+            println!("failed");
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+async fn do_doctor(
+    path: &Path,
+    base_url: Option<String>,
+    output: OutputFormat,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !doctor::run(path, base_url, output).await? {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Options of the command an alias stands for if `opts` is `run`.
+fn expand_alias(
+    opts: AuthorizedOpts,
+    aliases: &BTreeMap<String, String>,
+) -> Result<AuthorizedOpts, Box<dyn std::error::Error + Send + Sync>> {
+    let (global, name, args) = match opts {
+        AuthorizedOpts {
+            global,
+            cmd: AuthorizedCmd::Run { alias, args },
+        } => (global, alias, args),
+        opts => return Ok(opts),
+    };
+
+    let argv = alias::expand(&name, aliases, &args)?;
+    let opts = AuthorizedOpts::from_iter_safe(std::iter::once("qiwi-cli".to_string()).chain(argv))
+        .map_err(|e| format!("alias `{}`: {}", name, e.message))?;
+    if let AuthorizedCmd::Run { .. } = opts.cmd {
+        return Err(format!("alias `{}` runs another alias", name).into());
+    }
+
+    Ok(AuthorizedOpts {
+        global: opts.global.or(global),
+        cmd: opts.cmd,
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     env_logger::init();
 
-    let config = async move {
-        if let Ok(data) = tokio::fs::read(config_location()).await {
+    let config_path = config_arg().unwrap_or_else(default_config_location);
+    let config = async {
+        if let Ok(data) = tokio::fs::read(&config_path).await {
             if let Ok(config) = toml::from_slice::<Config>(&data) {
                 return Some(config);
             }
@@ -81,29 +868,296 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     .await;
 
     match config {
-        None => match UnauthorizedCmd::from_args() {
-            UnauthorizedCmd::Login => do_authorize().await?,
+        None => match UnauthorizedOpts::from_args() {
+            UnauthorizedOpts {
+                cmd: UnauthorizedCmd::Login,
+                ..
+            } => do_authorize(&config_path, Default::default(), None, Default::default()).await?,
+            UnauthorizedOpts {
+                global,
+                cmd: UnauthorizedCmd::Doctor { output },
+            } => do_doctor(&config_path, global.base_url, output).await?,
+            UnauthorizedOpts {
+                cmd: UnauthorizedCmd::Completions { shell },
+                ..
+            } => complete::print_script(UnauthorizedOpts::clap(), shell),
+            UnauthorizedOpts {
+                cmd: UnauthorizedCmd::Complete { words },
+                ..
+            } => {
+                for candidate in complete::candidates(&words, &Default::default(), None) {
+                    println!("{}", candidate);
+                }
+            }
         },
-        Some(config) => match AuthorizedCmd::from_args() {
-            AuthorizedCmd::Login => do_authorize().await?,
-            other => {
-                println!("Using config {:?}", config);
-                let client = Client::new(config.phone.parse()?, config.token);
-                let _ = client;
+        Some(config) => match expand_alias(AuthorizedOpts::from_args(), &config.aliases)? {
+            AuthorizedOpts {
+                cmd: AuthorizedCmd::Login,
+                ..
+            } => {
+                do_authorize(
+                    &config_path,
+                    config.contacts,
+                    config.providers,
+                    config.aliases,
+                )
+                .await?
+            }
+            AuthorizedOpts {
+                cmd: AuthorizedCmd::Alias { cmd },
+                ..
+            } => do_alias(&config_path, config, cmd).await?,
+            AuthorizedOpts {
+                cmd: AuthorizedCmd::Completions { shell },
+                ..
+            } => complete::print_script(AuthorizedOpts::clap(), shell),
+            AuthorizedOpts {
+                cmd: AuthorizedCmd::Complete { words },
+                ..
+            } => {
+                for candidate in
+                    complete::candidates(&words, &config.contacts, config.providers.as_ref())
+                {
+                    println!("{}", candidate);
+                }
+            }
+            AuthorizedOpts {
+                global,
+                cmd: AuthorizedCmd::Doctor { output },
+            } => do_doctor(&config_path, global.base_url, output).await?,
+            AuthorizedOpts { global, cmd: other } => {
+                log::debug!("Using config {:?}", config);
+                let mut builder = Client::builder(config.phone.parse()?, config.token);
+                if let Some(base_url) = global.base_url {
+                    builder = builder.base_url(base_url);
+                }
+                if let Some(issued) = config.token_issued {
+                    builder = builder.token_issued_at(issued);
+                }
+                if let Some(providers) = &config.providers {
+                    builder = builder.provider_overrides(providers);
+                }
+                if let Some(threshold) = global.mfa {
+                    builder = builder.mfa(
+                        mfa::TerminalMfa::default(),
+                        Money::new(threshold, Region::Russia.currency()),
+                    );
+                }
+                if let Some(path) = global.audit_log {
+                    builder = builder.audit_sink(audit::FileAuditSink::new(path));
+                }
+                let client = builder.build();
                 match other {
-                    AuthorizedCmd::ProfileInfo => {
+                    AuthorizedCmd::Balance { output } => {
+                        for account in client.accounts().await? {
+                            let balance = match account.balance {
+                                Some(balance) => balance,
+                                None => continue,
+                            };
+                            match output {
+                                OutputFormat::Text => println!("{}: {}", account.alias, balance),
+                                OutputFormat::Json => println!(
+                                    "{}",
+                                    json!({ "alias": account.alias, "balance": balance })
+                                ),
+                            }
+                        }
+                    }
+                    AuthorizedCmd::ProfileInfo { show_sensitive } => {
                         let profile_info = client.profile_info().await?;
                         println!("Profile info:");
-                        println!("{:?}", profile_info);
+                        if show_sensitive {
+                            println!("{:?}", display::Unmasked(&profile_info));
+                        } else {
+                            println!("{:?}", profile_info);
+                        }
+                        println!("Identification level: {:?}", profile_info.effective_level());
+                        match client.token_expiry_estimate().await? {
+                            Some(expiry) => println!(
+                                "Token expires around {}, renew it at qiwi.com/api and run `qiwi-cli login`",
+                                expiry.date()
+                            ),
+                            None => println!(
+                                "Token expiry unknown, run `qiwi-cli login` to record when the token was issued"
+                            ),
+                        }
+                        for record in profile_info.identification_records() {
+                            if record.passport_expired == Some(true) {
+                                println!(
+                                    "Warning: {:?} identification is based on an expired passport and will lapse",
+                                    record.bank_alias
+                                );
+                            }
+                        }
+                    }
+                    AuthorizedCmd::PaymentHistory {
+                        show_sensitive,
+                        limit,
+                        sources,
+                        output,
+                    } => {
+                        let filter = sources
+                            .into_iter()
+                            .fold(PaymentHistoryFilter::new(), |filter, source| {
+                                filter.source(source)
+                            });
+                        let mut history = client
+                            .payment_history_filtered(filter)?
+                            .take(limit.unwrap_or(usize::MAX));
+                        while let Some(entry) = history.next().await.transpose()? {
+                            match output {
+                                OutputFormat::Json => {
+                                    println!("{}", serde_json::to_string(&entry)?)
+                                }
+                                OutputFormat::Text if show_sensitive => {
+                                    println!("{:?}", display::Unmasked(&entry))
+                                }
+                                OutputFormat::Text => println!("{:?}", entry),
+                            }
+                        }
+                    }
+                    AuthorizedCmd::Stats {
+                        days,
+                        sources,
+                        output,
+                    } => {
+                        let filter = sources
+                            .into_iter()
+                            .fold(PaymentHistoryFilter::new(), |filter, source| {
+                                filter.source(source)
+                            });
+                        let end = chrono::Utc::now();
+                        let totals = client
+                            .payment_totals(end - chrono::Duration::days(days), end, &filter)
+                            .await?;
+                        match output {
+                            OutputFormat::Json => println!("{}", serde_json::to_string(&totals)?),
+                            OutputFormat::Text => {
+                                for total in &totals.incoming_total {
+                                    println!("in: {}", total);
+                                }
+                                for total in &totals.outgoing_total {
+                                    println!("out: {}", total);
+                                }
+                            }
+                        }
                     }
-                    AuthorizedCmd::PaymentHistory => {
-                        while let Some(entry) = client.payment_history().next().await.transpose()? {
-                            println!("{:?}", entry);
+                    AuthorizedCmd::Txn {
+                        txn_id,
+                        incoming,
+                        show_sensitive,
+                        bank_doc,
+                    } => {
+                        let txn_type = if incoming {
+                            TransactionType::In
+                        } else {
+                            TransactionType::Out
+                        };
+                        let entry = client.transaction_info(txn_id, txn_type).await?;
+                        if show_sensitive {
+                            println!("{:?}", display::Unmasked(&entry))
+                        } else {
+                            println!("{:?}", entry)
                         }
+                        if let Some(out) = bank_doc {
+                            let document = client.bank_document(txn_id).await?;
+                            tokio::fs::write(&out, document).await?;
+                            println!("Bank document saved to {}", out.display());
+                        }
+                    }
+                    AuthorizedCmd::Cheque {
+                        txn_id,
+                        incoming,
+                        format,
+                        out,
+                    } => {
+                        let txn_type = if incoming {
+                            TransactionType::In
+                        } else {
+                            TransactionType::Out
+                        };
+                        let cheque = client.cheque(txn_id, txn_type, format).await?;
+                        tokio::fs::write(&out, cheque).await?;
+                        println!("Cheque saved to {}", out.display());
+                    }
+                    AuthorizedCmd::Statement {
+                        from,
+                        till,
+                        format,
+                        out,
+                    } => {
+                        let statement = client.account_statement(from, till, format).await?;
+                        tokio::fs::write(&out, statement).await?;
+                        println!("Statement saved to {}", out.display());
                     }
                     AuthorizedCmd::CommissionInfo { provider } => {
                         println!("{:?}", client.commission_info(provider).await?)
                     }
+                    AuthorizedCmd::Commission {
+                        cmd:
+                            CommissionCmd::Snapshot {
+                                providers,
+                                out,
+                                max_age,
+                            },
+                    } => {
+                        let mut snapshot = match tokio::fs::read(&out).await {
+                            Ok(data) => serde_json::from_slice::<CommissionSnapshot>(&data)?,
+                            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                                CommissionSnapshot::default()
+                            }
+                            Err(e) => return Err(e.into()),
+                        };
+                        client
+                            .update_commissions(
+                                &mut snapshot,
+                                &providers,
+                                chrono::Duration::hours(max_age),
+                            )
+                            .await?;
+                        tokio::fs::write(&out, serde_json::to_vec_pretty(&snapshot)?).await?;
+                        println!("Commission snapshot saved to {}", out.display());
+                    }
+                    AuthorizedCmd::Pay {
+                        provider,
+                        amount,
+                        fields,
+                        comment,
+                        yes,
+                        output,
+                    } => do_pay(&client, provider, amount, fields, comment, yes, output).await?,
+                    AuthorizedCmd::Transfer {
+                        to,
+                        amount,
+                        currency,
+                        mobile,
+                        comment,
+                        yes,
+                        dry_run,
+                        output,
+                    } => {
+                        let to = config.contacts.get(&to).cloned().unwrap_or(to).parse()?;
+                        do_transfer(
+                            &client, to, amount, currency, mobile, comment, yes, dry_run, output,
+                        )
+                        .await?
+                    }
+                    AuthorizedCmd::Payout {
+                        file,
+                        plan_diff,
+                        yes,
+                        output,
+                    } => do_payout(&client, &config.contacts, file, plan_diff, yes, output).await?,
+                    AuthorizedCmd::PayRecurring {
+                        provider,
+                        account,
+                        amount,
+                        period,
+                        tag,
+                    } => {
+                        let account = config.contacts.get(&account).cloned().unwrap_or(account);
+                        do_pay_recurring(&client, provider, account, amount, period, tag).await?
+                    }
                     other => unimplemented!("{:?}", other),
                 }
             }
@@ -0,0 +1,312 @@
+mod common;
+
+use {
+    common::*,
+    qiwi_mock_server::{Control, Fixture},
+    serde_json::json,
+};
+
+#[test]
+fn balance() {
+    let harness = Harness::new();
+
+    harness.cmd().arg("balance").assert().success().stdout(
+        "qw_wallet_rub: 12345.67 643\nqw_wallet_usd: 150.00 840\nqw_wallet_eur: 20.50 978\n",
+    );
+
+    let output = harness
+        .cmd()
+        .args(&["balance", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        json_lines(&output.stdout),
+        vec![
+            json!({ "alias": "qw_wallet_rub", "balance": { "amount": "12345.67", "currency": "643" } }),
+            json!({ "alias": "qw_wallet_usd", "balance": { "amount": "150.00", "currency": "840" } }),
+            json!({ "alias": "qw_wallet_eur", "balance": { "amount": "20.50", "currency": "978" } }),
+        ]
+    );
+}
+
+#[test]
+fn payment_history_limit() {
+    let harness = Harness::new();
+
+    let output = harness
+        .cmd()
+        .args(&["payment-history", "--limit", "3", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let entries = json_lines(&output.stdout);
+    let txn_ids = entries
+        .iter()
+        .map(|entry| entry["txnId"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        txn_ids,
+        vec![10_000_000_120, 10_000_000_119, 10_000_000_118]
+    );
+
+    // The first page covers the limit, no further pages are fetched.
+    let history_requests = harness
+        .requests()
+        .into_iter()
+        .filter(|request| request["path"].as_str().unwrap().ends_with("/payments"))
+        .count();
+    assert_eq!(history_requests, 1);
+}
+
+#[test]
+fn stats_pass_sources() {
+    let harness = Harness::new();
+    harness.control(Control {
+        fixture: Some(Fixture {
+            method: "GET".to_string(),
+            path: "payment-history/v2/persons/79991234567/payments/total".to_string(),
+            body: json!({
+                "incomingTotal": [{ "amount": "100.00", "currency": "643" }],
+                "outgoingTotal": [{ "amount": "40.50", "currency": "643" }],
+            }),
+        }),
+        ..Default::default()
+    });
+
+    harness
+        .cmd()
+        .args(&[
+            "stats", "--days", "7", "--source", "qw_rub", "--source", "card",
+        ])
+        .assert()
+        .success()
+        .stdout("in: 100.00 643\nout: 40.50 643\n");
+    let request = harness.requests().pop().unwrap();
+    assert_eq!(request["query"]["sources[0]"], json!("QW_RUB"));
+    assert_eq!(request["query"]["sources[1]"], json!("CARD"));
+
+    let output = harness
+        .cmd()
+        .args(&["stats", "--source", "card", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let totals = json_lines(&output.stdout).remove(0);
+    assert_eq!(totals["sources"], json!(["CARD"]));
+    assert_eq!(totals["incomingTotal"][0]["amount"], json!("100.00"));
+}
+
+#[test]
+fn transfer_dry_run() {
+    let harness = Harness::new();
+
+    let output = harness
+        .cmd()
+        .args(&[
+            "transfer",
+            "+79035550101",
+            "--amount",
+            "10",
+            "--dry-run",
+            "--output",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let result = &json_lines(&output.stdout)[0];
+    assert_eq!(
+        result["requested"],
+        json!({ "amount": "10", "currency": "643" })
+    );
+    assert_eq!(
+        result["debited"],
+        json!({ "amount": "10", "currency": "643" })
+    );
+    assert_eq!(result["transfer"], json!(null));
+    assert!(harness.payments().is_empty());
+
+    harness
+        .cmd()
+        .args(&["transfer", "+79035550101", "--amount", "10", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Dry run, nothing transferred"));
+    assert!(harness.payments().is_empty());
+}
+
+#[test]
+fn failed_auth() {
+    let harness = Harness::new();
+    harness.write_config("ffffffffffffffffffffffffffffffff", "");
+
+    harness
+        .cmd()
+        .arg("balance")
+        .assert()
+        .failure()
+        .code(1)
+        .stdout("")
+        .stderr(predicates::str::contains("Unauthorized"));
+    assert!(harness.payments().is_empty());
+}
+
+#[test]
+fn doctor() {
+    let harness = Harness::new();
+
+    let output = harness
+        .cmd()
+        .args(&["doctor", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let results = results.as_array().unwrap();
+    assert!(results.iter().all(|result| result["passed"] == json!(true)));
+    assert!(results
+        .iter()
+        .any(|result| result["name"] == json!("authenticated request")));
+}
+
+#[test]
+fn doctor_failed_auth() {
+    let harness = Harness::new();
+    harness.write_config("ffffffffffffffffffffffffffffffff", "");
+
+    let output = harness
+        .cmd()
+        .args(&["doctor", "--output", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let results = serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap();
+    let failed = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|result| result["passed"] == json!(false))
+        .map(|result| result["name"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(failed, vec![json!("authenticated request")]);
+}
+
+#[test]
+fn usd_transfer() {
+    let harness = Harness::new();
+
+    let output = harness
+        .cmd()
+        .args(&[
+            "transfer",
+            "+79035550101",
+            "--amount",
+            "10.50",
+            "--currency",
+            "usd",
+            "--yes",
+            "--output",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let result = &json_lines(&output.stdout)[0];
+    assert_eq!(
+        result["requested"],
+        json!({ "amount": "10.50", "currency": "840" })
+    );
+    assert_eq!(
+        result["debited"],
+        json!({ "amount": "10.50", "currency": "840" })
+    );
+    assert_eq!(result["rate"], json!("1"));
+    assert!(result["transfer"].is_object());
+
+    let payments = harness.payments();
+    assert_eq!(payments.len(), 1);
+    assert_eq!(
+        payments[0]["sum"],
+        json!({ "amount": "10.50", "currency": "840" })
+    );
+    assert_eq!(payments[0]["paymentMethod"]["accountId"], json!("840"));
+    assert_eq!(payments[0]["fields"]["account"], json!("+79035550101"));
+}
+
+#[test]
+fn usd_transfer_confirmation() {
+    let harness = Harness::new();
+
+    harness
+        .cmd()
+        .args(&[
+            "transfer",
+            "+79035550101",
+            "--amount",
+            "10.50",
+            "--currency",
+            "usd",
+        ])
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Amount: 10.50 840"))
+        .stdout(predicates::str::contains("Rate: 1"))
+        .stdout(predicates::str::contains(
+            "Debit from qw_wallet_usd: 10.50 840",
+        ))
+        .stdout(predicates::str::contains("Cancelled"));
+    assert!(harness.payments().is_empty());
+}
+
+#[test]
+fn usd_mobile_top_up_is_rejected_before_quote() {
+    let harness = Harness::new();
+
+    harness
+        .cmd()
+        .args(&[
+            "transfer",
+            "+79035550101",
+            "--amount",
+            "10",
+            "--currency",
+            "usd",
+            "--mobile",
+            "--yes",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("mobile top-ups are in"));
+    assert!(harness.requests().is_empty());
+    assert!(harness.payments().is_empty());
+}
+
+#[test]
+fn json_payment_requires_yes() {
+    let harness = Harness::new();
+
+    harness
+        .cmd()
+        .args(&[
+            "pay",
+            "26476",
+            "--amount",
+            "350",
+            "--field",
+            "account=12345",
+            "--output",
+            "json",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("pass --yes"));
+    assert!(harness.requests().is_empty());
+    assert!(harness.payments().is_empty());
+}
@@ -0,0 +1,97 @@
+//! Runs the built `qiwi-cli` against a mock server with a temporary config.
+
+#![allow(dead_code)]
+
+use {
+    assert_cmd::Command,
+    qiwi_mock_server::{Config, Control, MockServer},
+    serde_json::Value,
+    std::path::PathBuf,
+    tempfile::TempDir,
+    tokio::runtime::Runtime,
+};
+
+pub const PHONE: &str = "+79991234567";
+pub const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+/// Mock server running on its own runtime and a config pointing at it, removed on drop.
+pub struct Harness {
+    pub server: MockServer,
+    // Dropped after the server, which shuts down on it.
+    runtime: Runtime,
+    dir: TempDir,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let runtime = Runtime::new().unwrap();
+        let config = Config::new(PHONE.parse().unwrap(), TOKEN);
+        let server = runtime
+            .enter(|| MockServer::start("127.0.0.1:0".parse().unwrap(), config))
+            .unwrap();
+        let harness = Self {
+            server,
+            runtime,
+            dir: tempfile::tempdir().unwrap(),
+        };
+        harness.write_config(TOKEN, "");
+        harness
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.dir.path().join("config.toml")
+    }
+
+    /// Writes a config for the mock wallet with `token`, followed by `extra` TOML, e.g. tables.
+    pub fn write_config(&self, token: &str, extra: &str) {
+        let config = format!(
+            "phone = \"{}\"\ntoken = \"{}\"\ntoken_issued = \"{}\"\n{}",
+            PHONE,
+            token,
+            chrono::Utc::now().to_rfc3339(),
+            extra
+        );
+        std::fs::write(self.config_path(), config).unwrap();
+    }
+
+    /// `qiwi-cli` with the config and the mock server set.
+    pub fn cmd(&self) -> Command {
+        let mut cmd = Command::cargo_bin("qiwi-cli").unwrap();
+        cmd.arg("--config")
+            .arg(self.config_path())
+            .arg("--base-url")
+            .arg(self.server.url());
+        cmd
+    }
+
+    /// Changes the behaviour of the server, see [`MockServer::control`].
+    pub fn control(&self, control: Control) {
+        Runtime::new()
+            .unwrap()
+            .block_on(self.server.control(control));
+    }
+
+    /// Requests received by the server so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.server.report()["requests"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Payments accepted by the server so far.
+    pub fn payments(&self) -> Vec<Value> {
+        self.server.report()["payments"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Lines of `stdout` parsed as JSON.
+pub fn json_lines(stdout: &[u8]) -> Vec<Value> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
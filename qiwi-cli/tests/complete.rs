@@ -0,0 +1,107 @@
+mod common;
+
+use common::*;
+
+const KNOWN_PROVIDERS: &[&str] = &[
+    "99", "1963", "1960", "21013", "21012", "31652", "466", "464", "821", "815", "1717",
+];
+
+const SEEDED: &str = r#"
+[contacts]
+mom = "+79035550101"
+home = "+79991234567"
+
+[providers]
+"card.mir" = 31652
+"card-detection" = 26476
+"#;
+
+/// Candidates printed by `__complete` for `words`.
+fn complete(harness: &Harness, words: &[&str]) -> Vec<String> {
+    let output = harness
+        .cmd()
+        .arg("__complete")
+        .args(words)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+fn seeded() -> Harness {
+    let harness = Harness::new();
+    harness.write_config(TOKEN, SEEDED);
+    harness
+}
+
+#[test]
+fn providers_include_configured_ones() {
+    let harness = seeded();
+    let mut expected = KNOWN_PROVIDERS.to_vec();
+    expected.push("26476");
+
+    assert_eq!(complete(&harness, &["pay"]), expected);
+    assert_eq!(complete(&harness, &["commission-info"]), expected);
+    assert_eq!(
+        complete(&harness, &["pay-recurring", "--provider"]),
+        expected
+    );
+    assert!(harness.requests().is_empty());
+}
+
+#[test]
+fn contacts() {
+    let harness = seeded();
+
+    assert_eq!(complete(&harness, &["transfer"]), vec!["home", "mom"]);
+    assert_eq!(
+        complete(
+            &harness,
+            &["pay-recurring", "--provider", "99", "--account"]
+        ),
+        vec!["home", "mom"]
+    );
+    assert!(harness.requests().is_empty());
+}
+
+#[test]
+fn static_positions_have_no_candidates() {
+    let harness = seeded();
+
+    for words in &[
+        &[][..],
+        &["balance"][..],
+        &["transfer", "home"][..],
+        &["pay", "99"][..],
+        &["pay-recurring", "--provider", "99"][..],
+    ] {
+        assert!(complete(&harness, words).is_empty(), "{:?}", words);
+    }
+}
+
+#[test]
+fn without_config() {
+    let harness = Harness::new();
+    std::fs::remove_file(harness.config_path()).unwrap();
+
+    assert_eq!(complete(&harness, &["pay"]), KNOWN_PROVIDERS);
+    assert!(complete(&harness, &["transfer"]).is_empty());
+    assert!(harness.requests().is_empty());
+}
+
+#[test]
+fn bash_script_calls_complete() {
+    let harness = seeded();
+
+    harness
+        .cmd()
+        .args(&["completions", "bash"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("qiwi-cli __complete"))
+        .stdout(predicates::str::contains("complete -F _qiwi-cli_dynamic"));
+}
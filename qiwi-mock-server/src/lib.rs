@@ -0,0 +1,669 @@
+//! HTTP server emulating QIWI API with the canned responses of [`qiwi::fixtures`].
+//!
+//! Unlike [`qiwi::OfflineTransport`] it is reached over real HTTP, so requests go through the
+//! client's HTTP stack and clients in other languages can use the same fixtures. Point the client
+//! to [`MockServer::url`] with `ClientBuilder::base_url`, or the CLI with `--base-url`.
+//!
+//! Besides the fixtures the server
+//! - answers 401 with QIWI's `internal.invalid.token` error to requests without `Authorization: Bearer <token>`,
+//! - pages a synthetic history of configurable length like QIWI does,
+//! - accepts payments, adding them to the history,
+//! - quotes commission-free payments to any provider,
+//! - answers 429 once the requests of the current minute exceed the rate limit,
+//! - answers 503 in maintenance mode,
+//! - sends `ETag` or `Last-Modified` with `GET` responses if asked to, answering 304 to requests
+//!   that carry the current one,
+//! - posts unsigned webhook notifications of simulated incoming payments.
+//!
+//! Scenarios are scripted by posting [`Control`] to `/__control` or with [`MockServer::control`].
+//! `GET /__control` reports the requests received so far with their headers and the status they
+//! were answered with, and the payments.
+
+use {
+    hyper::{
+        header,
+        service::{make_service_fn, service_fn},
+        Body, Method, Request, Response, Server, StatusCode,
+    },
+    log::*,
+    qiwi::{
+        deps::{chrono, phonenumber::PhoneNumber},
+        fixtures,
+    },
+    serde::Deserialize,
+    serde_json::{json, Value},
+    std::{
+        collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+        convert::Infallible,
+        hash::{Hash, Hasher},
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    tokio::sync::oneshot,
+};
+
+/// Most history entries QIWI returns per page.
+const MAX_ROWS: usize = 50;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// `Last-Modified` of the initial history, moved a second forward by each change.
+const MODIFIED_EPOCH: &str = "2020-02-01T00:00:00Z";
+
+/// Id of the first transaction created by the server.
+const FIRST_TXN_ID: u64 = 20_000_000_001;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub phone: PhoneNumber,
+    /// Token expected in `Authorization` headers.
+    pub token: String,
+    /// Entries of the synthetic history, see [`fixtures::history_entries`].
+    pub history_len: usize,
+    /// Requests answered per minute before responding with 429.
+    pub rate_limit: Option<u32>,
+    /// Where notifications of incoming payments are posted.
+    pub webhook_url: Option<String>,
+}
+
+impl Config {
+    pub fn new<T: Into<String>>(phone: PhoneNumber, token: T) -> Self {
+        Self {
+            phone,
+            token: token.into(),
+            history_len: fixtures::TYPICAL_HISTORY_LEN,
+            rate_limit: None,
+            webhook_url: None,
+        }
+    }
+}
+
+/// Status and body to answer API requests with.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Failure {
+    pub status: u16,
+    /// Answered with an empty body if not set.
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// Number of requests to fail.
+    #[serde(default = "one")]
+    pub count: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+/// Cache validators sent with successful `GET` responses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Validators {
+    None,
+    /// Hash of the body, matched against `If-None-Match`.
+    Etag,
+    /// Time of the last change of the history, matched against `If-Modified-Since`.
+    LastModified,
+}
+
+/// Validators sent by the client with a `GET` request.
+#[derive(Debug)]
+struct Conditions {
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+}
+
+/// Payment received by the wallet.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IncomingPayment {
+    /// Payer, e.g. `+79035550101`.
+    pub account: String,
+    /// Amount in rubles, e.g. `100.50`.
+    pub amount: String,
+    #[serde(default)]
+    pub comment: String,
+}
+
+/// Response to add to the fixtures, e.g. of an endpoint the client has no method for.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Fixture {
+    /// `GET` if not set.
+    #[serde(default = "get")]
+    pub method: String,
+    /// Path without the leading slash, e.g. `person-profile/v1/profile/current`.
+    pub path: String,
+    pub body: Value,
+}
+
+fn get() -> String {
+    "GET".to_string()
+}
+
+/// Changes to the scenario, fields that are not set are left as they are.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Control {
+    /// Restore the configuration the server was started with first.
+    #[serde(default)]
+    pub reset: bool,
+    pub maintenance: Option<bool>,
+    /// Requests per minute, `0` disables the limit.
+    pub rate_limit: Option<u32>,
+    /// Regenerates the history, dropping payments made so far.
+    pub history_len: Option<usize>,
+    /// Expect another token from now on, e.g. to simulate its rotation.
+    pub token: Option<String>,
+    /// Validators to send with `GET` responses from now on.
+    pub validators: Option<Validators>,
+    pub webhook_url: Option<String>,
+    /// Fail the next API requests, whatever the endpoint.
+    pub fail_next: Option<Failure>,
+    /// Answer requests to another endpoint, or replace the response of a fixture.
+    pub fixture: Option<Fixture>,
+    /// Add a payment to the history and post its notification to the webhook.
+    pub incoming_payment: Option<IncomingPayment>,
+}
+
+struct State {
+    initial: Config,
+    config: Config,
+    fixtures: HashMap<(Method, String), Value>,
+    history_endpoint: String,
+    /// Newest first.
+    history: Vec<Value>,
+    maintenance: bool,
+    validators: Validators,
+    /// Seconds after [`MODIFIED_EPOCH`] of the last change of the history.
+    version: i64,
+    failure: Option<Failure>,
+    window: (Instant, u32),
+    next_txn_id: u64,
+    requests: Vec<Value>,
+    payments: Vec<Value>,
+}
+
+impl State {
+    fn new(config: Config) -> Self {
+        let mut fixtures = HashMap::new();
+        let mut history_endpoint = String::new();
+        for (method, endpoint, body) in fixtures::typical_wallet(&config.phone) {
+            if endpoint.ends_with("/payments") {
+                history_endpoint = endpoint;
+            } else {
+                fixtures.insert((method, endpoint), body);
+            }
+        }
+
+        Self {
+            history: fixtures::history_entries(config.history_len),
+            initial: config.clone(),
+            config,
+            fixtures,
+            history_endpoint,
+            maintenance: false,
+            validators: Validators::None,
+            version: 0,
+            failure: None,
+            window: (Instant::now(), 0),
+            next_txn_id: FIRST_TXN_ID,
+            requests: Vec::new(),
+            payments: Vec::new(),
+        }
+    }
+
+    /// Applies `control`, returning the webhook notification to post, if any.
+    fn apply(&mut self, control: Control) -> Option<(String, Value)> {
+        if control.reset {
+            *self = Self::new(self.initial.clone());
+        }
+        if let Some(maintenance) = control.maintenance {
+            self.maintenance = maintenance;
+        }
+        if let Some(limit) = control.rate_limit {
+            self.config.rate_limit = Some(limit).filter(|limit| *limit > 0);
+        }
+        if let Some(len) = control.history_len {
+            self.config.history_len = len;
+            self.history = fixtures::history_entries(len);
+            self.version += 1;
+        }
+        if let Some(token) = control.token {
+            self.config.token = token;
+        }
+        if let Some(validators) = control.validators {
+            self.validators = validators;
+        }
+        if let Some(url) = control.webhook_url {
+            self.config.webhook_url = Some(url);
+        }
+        if let Some(failure) = control.fail_next {
+            self.failure = Some(failure);
+        }
+        if let Some(fixture) = control.fixture {
+            match fixture.method.parse::<Method>() {
+                Ok(method) => {
+                    self.fixtures.insert((method, fixture.path), fixture.body);
+                }
+                Err(_) => warn!("Ignoring fixture with method {}", fixture.method),
+            }
+        }
+        let payment = control.incoming_payment?;
+        let sum = json!({ "amount": payment.amount, "currency": "643" });
+        let entry = self.add_entry("IN", &payment.account, sum, &payment.comment, None);
+        let url = self.config.webhook_url.clone()?;
+        Some((url, fixtures::webhook_payment(&entry)))
+    }
+
+    /// Adds a successful transaction to the top of the history, an hour after the newest one.
+    fn add_entry(
+        &mut self,
+        txn_type: &str,
+        account: &str,
+        sum: Value,
+        comment: &str,
+        trm_txn_id: Option<&str>,
+    ) -> Value {
+        let date = self
+            .history
+            .first()
+            .and_then(|entry| entry["date"].as_str())
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+            .map_or_else(chrono::Utc::now, |date| {
+                date.with_timezone(&chrono::Utc) + chrono::Duration::hours(1)
+            });
+        let txn_id = self.next_txn_id;
+        self.next_txn_id += 1;
+
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["txnId"] = json!(txn_id);
+        entry["date"] = json!(date.to_rfc3339());
+        entry["type"] = json!(txn_type);
+        entry["status"] = json!("SUCCESS");
+        entry["statusText"] = json!("SUCCESS");
+        entry["account"] = json!(account);
+        entry["sum"] = sum.clone();
+        entry["total"] = sum;
+        entry["comment"] = json!(comment);
+        entry["provider"]["id"] = json!(99);
+        entry["trmTxnId"] = json!(trm_txn_id.map_or_else(|| txn_id.to_string(), str::to_string));
+        self.history.insert(0, entry.clone());
+        self.version += 1;
+        entry
+    }
+
+    fn last_modified(&self) -> String {
+        let modified = chrono::DateTime::parse_from_rfc3339(MODIFIED_EPOCH).unwrap()
+            + chrono::Duration::seconds(self.version);
+        modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+    }
+
+    /// Successful response to a `GET` request, or 304 if the client has it already.
+    fn conditional(&self, conditions: &Conditions, body: &Value) -> Response<Body> {
+        let (name, condition, value) = match self.validators {
+            Validators::None => return json_response(StatusCode::OK, body),
+            Validators::Etag => {
+                let mut hasher = DefaultHasher::new();
+                body.to_string().hash(&mut hasher);
+                let etag = format!("\"{:016x}\"", hasher.finish());
+                (header::ETAG, &conditions.if_none_match, etag)
+            }
+            Validators::LastModified => (
+                header::LAST_MODIFIED,
+                &conditions.if_modified_since,
+                self.last_modified(),
+            ),
+        };
+        let mut rsp = if condition.as_deref() == Some(value.as_str()) {
+            text(StatusCode::NOT_MODIFIED, "")
+        } else {
+            json_response(StatusCode::OK, body)
+        };
+        rsp.headers_mut()
+            .insert(name, header::HeaderValue::from_str(&value).unwrap());
+        rsp
+    }
+
+    fn rate_limited(&mut self) -> bool {
+        let limit = match self.config.rate_limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        let (started, count) = &mut self.window;
+        if started.elapsed() >= RATE_WINDOW {
+            *started = Instant::now();
+            *count = 0;
+        }
+        *count += 1;
+        *count > limit
+    }
+
+    fn history_page(&self, query: &HashMap<String, String>) -> Value {
+        let rows = query
+            .get("rows")
+            .and_then(|rows| rows.parse().ok())
+            .unwrap_or(MAX_ROWS)
+            .min(MAX_ROWS);
+        let start = query
+            .get("nextTxnId")
+            .and_then(|id| id.parse::<u64>().ok())
+            .map_or(0, |id| {
+                self.history
+                    .iter()
+                    .position(|entry| entry["txnId"] == json!(id))
+                    .unwrap_or_else(|| self.history.len())
+            });
+        let end = (start + rows).min(self.history.len());
+        let next = self.history.get(end);
+
+        json!({
+            "data": &self.history[start..end],
+            "nextTxnId": next.map(|entry| entry["txnId"].clone()),
+            "nextTxnDate": next.map(|entry| entry["date"].clone()),
+        })
+    }
+
+    fn accept_payment(&mut self, provider: &str, body: &[u8]) -> Response<Body> {
+        let request = match serde_json::from_slice::<Value>(body) {
+            Ok(request) => request,
+            Err(e) => return text(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        self.payments.push(request.clone());
+
+        let account = request["fields"]["account"].as_str().unwrap_or_default();
+        let comment = request["comment"].as_str().unwrap_or_default();
+        let entry = self.add_entry(
+            "OUT",
+            account,
+            request["sum"].clone(),
+            comment,
+            request["id"].as_str(),
+        );
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "id": request["id"],
+                "terms": provider,
+                "fields": request["fields"],
+                "sum": request["sum"],
+                "transaction": {
+                    "id": entry["txnId"].to_string(),
+                    "state": { "code": "Accepted" },
+                },
+                "source": format!("account_{}", request["paymentMethod"]["accountId"].as_str().unwrap_or("643")),
+            }),
+        )
+    }
+
+    /// Quote without commission in the currency of the payment.
+    fn quote_commission(&self, body: &[u8]) -> Response<Body> {
+        let request = match serde_json::from_slice::<Value>(body) {
+            Ok(request) => request,
+            Err(e) => return text(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        let total = &request["purchaseTotals"]["total"];
+        let zero = json!({ "amount": "0", "currency": total["currency"] });
+        json_response(
+            StatusCode::OK,
+            &json!({
+                "withdrawSum": total,
+                "enrollmentSum": total,
+                "qwCommission": zero,
+                "fundingSourceCommission": zero,
+                "withdrawToEnrollmentRate": "1",
+            }),
+        )
+    }
+
+    fn api(
+        &mut self,
+        method: Method,
+        path: String,
+        query: HashMap<String, String>,
+        authorization: Option<String>,
+        conditions: &Conditions,
+        body: &[u8],
+    ) -> Response<Body> {
+        let expected = format!("Bearer {}", self.config.token);
+        let authorized = authorization.as_deref() == Some(expected.as_str());
+        self.requests.push(json!({
+            "method": method.as_str(),
+            "path": &path,
+            "query": &query,
+            "authorized": authorized,
+        }));
+
+        if self.maintenance {
+            return text(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Service is under maintenance",
+            );
+        }
+        if !authorized {
+            return json_response(
+                StatusCode::UNAUTHORIZED,
+                &json!({
+                    "serviceName": "api-gateway",
+                    "errorCode": "internal.invalid.token",
+                    "description": "Invalid token",
+                    "userMessage": "Неверный токен",
+                }),
+            );
+        }
+        if self.rate_limited() {
+            let mut rsp = text(StatusCode::TOO_MANY_REQUESTS, "Too many requests");
+            rsp.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from_static("60"));
+            return rsp;
+        }
+        if let Some(failure) = &mut self.failure {
+            failure.count = failure.count.saturating_sub(1);
+            let failure = failure.clone();
+            if failure.count == 0 {
+                self.failure = None;
+            }
+            let status =
+                StatusCode::from_u16(failure.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            return match &failure.body {
+                Some(body) => json_response(status, body),
+                None => text(status, ""),
+            };
+        }
+
+        if method == Method::GET && path == self.history_endpoint {
+            return self.conditional(conditions, &self.history_page(&query));
+        }
+        if method == Method::POST {
+            let segments = path.split('/').collect::<Vec<_>>();
+            match segments.as_slice() {
+                ["sinap", "api", "v2", "terms", provider, "payments"] => {
+                    return self.accept_payment(provider, body)
+                }
+                ["sinap", "providers", _, "onlineCommission"] => {
+                    return self.quote_commission(body)
+                }
+                _ => {}
+            }
+        }
+        match self.fixtures.get(&(method.clone(), path)) {
+            Some(body) if method == Method::GET => self.conditional(conditions, body),
+            Some(body) => json_response(StatusCode::OK, body),
+            None => text(StatusCode::NOT_FOUND, "Not found"),
+        }
+    }
+
+    fn report(&self) -> Value {
+        json!({
+            "maintenance": self.maintenance,
+            "rateLimit": self.config.rate_limit,
+            "historyLen": self.history.len(),
+            "webhookUrl": self.config.webhook_url,
+            "requests": self.requests,
+            "payments": self.payments,
+        })
+    }
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    let mut rsp = Response::new(Body::from(body.to_string()));
+    *rsp.status_mut() = status;
+    rsp
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    let mut rsp = text(status, &body.to_string());
+    rsp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    rsp
+}
+
+async fn post_webhook(url: String, payload: Value) {
+    let req = Request::post(url.as_str())
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(payload.to_string()));
+    let result = match req {
+        Ok(req) => hyper::Client::new()
+            .request(req)
+            .await
+            .map(|rsp| rsp.status()),
+        Err(e) => return warn!("Invalid webhook URL {}: {}", url, e),
+    };
+    match result {
+        Ok(status) => debug!("Webhook {} answered {}", url, status),
+        Err(e) => warn!("Failed to post webhook to {}: {}", url, e),
+    }
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().trim_start_matches('/').to_string();
+    let query = url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect::<HashMap<_, _>>();
+    let header_value = |name: header::HeaderName| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let authorization = header_value(header::AUTHORIZATION);
+    let mut headers = BTreeMap::<_, Vec<_>>::new();
+    for (name, value) in req.headers() {
+        headers
+            .entry(name.as_str().to_string())
+            .or_default()
+            .push(value.to_str().unwrap_or_default().to_string());
+    }
+    let conditions = Conditions {
+        if_none_match: header_value(header::IF_NONE_MATCH),
+        if_modified_since: header_value(header::IF_MODIFIED_SINCE),
+    };
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(text(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    trace!("{} /{}", method, path);
+
+    if path != "__control" {
+        let mut state = state.lock().unwrap();
+        let rsp = state.api(method, path, query, authorization, &conditions, &body);
+        if let Some(request) = state.requests.last_mut() {
+            request["status"] = json!(rsp.status().as_u16());
+            request["headers"] = json!(headers);
+        }
+        return Ok(rsp);
+    }
+
+    match method {
+        Method::GET => Ok(json_response(
+            StatusCode::OK,
+            &state.lock().unwrap().report(),
+        )),
+        Method::POST => match serde_json::from_slice::<Control>(&body) {
+            Ok(control) => {
+                let webhook = state.lock().unwrap().apply(control);
+                if let Some((url, payload)) = webhook {
+                    post_webhook(url, payload).await;
+                }
+                Ok(json_response(
+                    StatusCode::OK,
+                    &state.lock().unwrap().report(),
+                ))
+            }
+            Err(e) => Ok(text(StatusCode::BAD_REQUEST, &e.to_string())),
+        },
+        _ => Ok(text(StatusCode::METHOD_NOT_ALLOWED, "")),
+    }
+}
+
+/// Server running in the background of the current tokio runtime until dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Listens on `addr`, e.g. `127.0.0.1:0` for a free port.
+    pub fn start(addr: SocketAddr, config: Config) -> Result<Self, hyper::Error> {
+        let state = Arc::new(Mutex::new(State::new(config)));
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+        });
+
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        let addr = server.local_addr();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = server.with_graceful_shutdown(async {
+                let _ = stopped.await;
+            });
+            if let Err(e) = server.await {
+                error!("Mock server failed: {}", e);
+            }
+        });
+        info!("Mock server listening on {}", addr);
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Base URL for `ClientBuilder::base_url`, e.g. `http://127.0.0.1:34567`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Same as posting `control` to `/__control`.
+    pub async fn control(&self, control: Control) {
+        let webhook = self.state.lock().unwrap().apply(control);
+        if let Some((url, payload)) = webhook {
+            post_webhook(url, payload).await;
+        }
+    }
+
+    /// Same as the response to `GET /__control`.
+    pub fn report(&self) -> Value {
+        self.state.lock().unwrap().report()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use {
+    qiwi::deps::phonenumber::PhoneNumber,
+    qiwi_mock_server::{Config, MockServer},
+    std::net::SocketAddr,
+    structopt::StructOpt,
+};
+
+/// Serve QIWI API fixtures over HTTP until interrupted
+#[derive(Debug, StructOpt)]
+struct Opts {
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    addr: SocketAddr,
+    /// Wallet the fixtures are generated for
+    #[structopt(long, default_value = "+79991234567")]
+    phone: PhoneNumber,
+    /// Token expected in requests
+    #[structopt(long, default_value = "test-token")]
+    token: String,
+    /// Entries of the synthetic payment history
+    #[structopt(long)]
+    history_len: Option<usize>,
+    /// Requests per minute answered before responding with 429
+    #[structopt(long)]
+    rate_limit: Option<u32>,
+    /// Where notifications of simulated incoming payments are posted
+    #[structopt(long)]
+    webhook_url: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    env_logger::init();
+
+    let opts = Opts::from_args();
+    let mut config = Config::new(opts.phone, opts.token);
+    if let Some(len) = opts.history_len {
+        config.history_len = len;
+    }
+    config.rate_limit = opts.rate_limit;
+    config.webhook_url = opts.webhook_url;
+
+    let server = MockServer::start(opts.addr, config)?;
+    println!("Listening on {}", server.url());
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Masking of personal data in `Debug` output.
+//!
+//! Models holding phone numbers, emails or card numbers mask them when debug-printed.
+//! Wrap a value into [`Unmasked`] to print it in full.
+
+use {
+    regex::Regex,
+    std::{
+        borrow::Cow,
+        cell::Cell,
+        fmt::{self, Debug},
+    },
+};
+
+thread_local! {
+    static UNMASKED: Cell<bool> = Cell::new(false);
+}
+
+/// Whether masking is currently disabled by [`Unmasked`].
+pub fn is_unmasked() -> bool {
+    UNMASKED.with(Cell::get)
+}
+
+/// Prints the wrapped value without masking personal data.
+pub struct Unmasked<'a, T: ?Sized>(pub &'a T);
+
+impl<T: Debug + ?Sized> Debug for Unmasked<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Restore(bool);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let prev = self.0;
+                UNMASKED.with(|v| v.set(prev));
+            }
+        }
+
+        let _restore = Restore(UNMASKED.with(|v| v.replace(true)));
+        self.0.fmt(f)
+    }
+}
+
+const MASK: &str = "***";
+
+/// Masks a phone number, e.g. `+79991234567` becomes `+7999***4567`.
+///
+/// Numbers of 8 digits or less are masked completely.
+pub fn mask_phone(phone: &str) -> Cow<str> {
+    if is_unmasked() {
+        return Cow::Borrowed(phone);
+    }
+
+    let (plus, digits) = match phone.strip_prefix('+') {
+        Some(digits) => ("+", digits),
+        None => ("", phone),
+    };
+    if digits.len() <= 8 || !digits.is_ascii() {
+        return Cow::Borrowed(MASK);
+    }
+
+    Cow::Owned(format!(
+        "{}{}{}{}",
+        plus,
+        &digits[..4],
+        MASK,
+        &digits[digits.len() - 4..]
+    ))
+}
+
+/// Masks the local part of an email, e.g. `alice@example.com` becomes `a***@example.com`.
+pub fn mask_email(email: &str) -> Cow<str> {
+    if is_unmasked() {
+        return Cow::Borrowed(email);
+    }
+
+    match email.find('@') {
+        Some(at) if at > 0 => {
+            let first = email[..at].chars().next().unwrap();
+            Cow::Owned(format!("{}{}{}", first, MASK, &email[at..]))
+        }
+        _ => Cow::Borrowed(MASK),
+    }
+}
+
+/// Masks all but the last four digits of a card number, e.g. `4276123412341234` becomes `***1234`.
+pub fn mask_card_number(pan: &str) -> Cow<str> {
+    if is_unmasked() {
+        return Cow::Borrowed(pan);
+    }
+
+    match pan.len().checked_sub(4).and_then(|i| pan.get(i..)) {
+        Some(last) => Cow::Owned(format!("{}{}", MASK, last)),
+        None => Cow::Borrowed(MASK),
+    }
+}
+
+/// Masks card number-like runs of 12 to 19 digits, keeping the first and the last four digits.
+pub fn mask_pans(text: &str) -> Cow<str> {
+    if is_unmasked() {
+        return Cow::Borrowed(text);
+    }
+
+    Regex::new(r"\d{12,19}")
+        .unwrap()
+        .replace_all(text, |caps: &regex::Captures| {
+            let pan = &caps[0];
+            format!("{}{}{}", &pan[..4], MASK, &pan[pan.len() - 4..])
+        })
+}
+
+/// Masks a payment account, which may be a phone number, an email, a card number or something else.
+pub fn mask_account(account: &str) -> Cow<str> {
+    let digits = account.strip_prefix('+').unwrap_or(account);
+    if account.contains('@') {
+        mask_email(account)
+    } else if (10..=15).contains(&digits.len()) && digits.bytes().all(|b| b.is_ascii_digit()) {
+        mask_phone(account)
+    } else {
+        mask_pans(account)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phone_boundary_lengths() {
+        assert_eq!(mask_phone(""), "***");
+        assert_eq!(mask_phone("+"), "***");
+        assert_eq!(mask_phone("+12345678"), "***");
+        assert_eq!(mask_phone("+123456789"), "+1234***6789");
+        assert_eq!(mask_phone("+79991234567"), "+7999***4567");
+        assert_eq!(mask_phone("79991234567"), "7999***4567");
+        assert_eq!(mask_phone("+7999123456٧"), "***");
+    }
+
+    #[test]
+    fn email_boundary_lengths() {
+        assert_eq!(mask_email("a@example.com"), "a***@example.com");
+        assert_eq!(mask_email("alice@example.com"), "a***@example.com");
+        assert_eq!(mask_email("ёж@example.com"), "ё***@example.com");
+        assert_eq!(mask_email("@example.com"), "***");
+        assert_eq!(mask_email("alice"), "***");
+        assert_eq!(mask_email(""), "***");
+    }
+
+    #[test]
+    fn card_number_boundary_lengths() {
+        assert_eq!(mask_card_number(""), "***");
+        assert_eq!(mask_card_number("123"), "***");
+        assert_eq!(mask_card_number("1234"), "***1234");
+        assert_eq!(mask_card_number("4276123412341234"), "***1234");
+    }
+
+    #[test]
+    fn pan_boundary_lengths() {
+        assert_eq!(mask_pans("12345678901"), "12345678901");
+        assert_eq!(mask_pans("123456789012"), "1234***9012");
+        assert_eq!(mask_pans("1234567890123456789"), "1234***6789");
+        assert_eq!(
+            mask_pans("Card 4276123412341234 paid, order 8001"),
+            "Card 4276***1234 paid, order 8001"
+        );
+    }
+
+    #[test]
+    fn account_kinds() {
+        assert_eq!(mask_account("alice@example.com"), "a***@example.com");
+        assert_eq!(mask_account("+79991234567"), "+7999***4567");
+        // Too short for a phone or a card.
+        assert_eq!(mask_account("123456789"), "123456789");
+        assert_eq!(mask_account("123456789012345"), "1234***2345");
+        // Too long for a phone.
+        assert_eq!(mask_account("4276123412341234"), "4276***1234");
+    }
+
+    struct Contact(&'static str);
+
+    impl Debug for Contact {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_tuple("Contact").field(&mask_phone(self.0)).finish()
+        }
+    }
+
+    #[test]
+    fn unmasked_is_scoped_to_the_value() {
+        let contact = Contact("+79991234567");
+        assert_eq!(format!("{:?}", contact), r#"Contact("+7999***4567")"#);
+        assert_eq!(
+            format!("{:?}", Unmasked(&contact)),
+            r#"Contact("+79991234567")"#
+        );
+        assert!(!is_unmasked());
+        assert_eq!(format!("{:?}", contact), r#"Contact("+7999***4567")"#);
+    }
+}
@@ -0,0 +1,10 @@
+//! Data types of QIWI API, free of any transport or async runtime dependencies.
+//!
+//! These are re-exported by the `qiwi` crate and are normally used through it.
+
+pub mod display;
+mod models;
+#[cfg(feature = "webhooks")]
+pub mod signature;
+
+pub use models::*;
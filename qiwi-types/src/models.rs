@@ -0,0 +1,3542 @@
+use {
+    crate::display::*,
+    bigdecimal::*,
+    chrono::prelude::*,
+    derive_more::{Display, From, FromStr},
+    phonenumber::PhoneNumber,
+    regex::Regex,
+    serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer},
+    serde_json::Value,
+    std::{
+        borrow::Cow,
+        cmp::Ordering,
+        collections::{BTreeMap, HashMap},
+        convert::TryFrom,
+        fmt::{self, Debug},
+        net::IpAddr,
+        str::FromStr,
+    },
+};
+
+#[derive(Clone, Debug, Display, From)]
+#[display(fmt = "{}{}", self.0.code().value(), self.0.national())]
+pub struct QiwiUser(PhoneNumber);
+
+impl QiwiUser {
+    /// Parses a wallet number written with or without the leading `+`.
+    pub fn parse(input: &str) -> Option<Self> {
+        let digits = input.trim().trim_start_matches('+');
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        phonenumber::parse(None, format!("+{}", digits))
+            .ok()
+            .map(Self)
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "invalid wallet number: {}", _0)]
+pub struct InvalidQiwiUser(pub String);
+
+impl std::error::Error for InvalidQiwiUser {}
+
+impl FromStr for QiwiUser {
+    type Err = InvalidQiwiUser;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s).ok_or_else(|| InvalidQiwiUser(s.to_string()))
+    }
+}
+
+impl TryFrom<&str> for QiwiUser {
+    type Error = InvalidQiwiUser;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Serialize for QiwiUser {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[derive(Clone, Debug, Display, From, PartialEq, Eq)]
+#[display(fmt = "{}", self.0.info().number())]
+pub struct QiwiCurrency(penny::Currency);
+
+impl From<QiwiCurrency> for penny::Currency {
+    fn from(currency: QiwiCurrency) -> Self {
+        currency.0
+    }
+}
+
+impl QiwiCurrency {
+    /// Currencies of QIWI wallet balances.
+    pub const SUPPORTED: [penny::Currency; 4] = [
+        penny::Currency::RUB,
+        penny::Currency::USD,
+        penny::Currency::EUR,
+        penny::Currency::KZT,
+    ];
+
+    pub fn currency(&self) -> penny::Currency {
+        self.0
+    }
+
+    /// Parses ISO 4217 numeric code, e.g. `643`.
+    pub fn from_numeric_code(code: &str) -> Option<Self> {
+        Some(Self(match code {
+            "643" => penny::Currency::RUB,
+            "840" => penny::Currency::USD,
+            "978" => penny::Currency::EUR,
+            "398" => penny::Currency::KZT,
+            _ => return None,
+        }))
+    }
+
+    /// Parses ISO 4217 alphabetic code, e.g. `rub`.
+    pub fn from_alpha_code(code: &str) -> Option<Self> {
+        Some(Self(match code.to_ascii_uppercase().as_str() {
+            "RUB" => penny::Currency::RUB,
+            "USD" => penny::Currency::USD,
+            "EUR" => penny::Currency::EUR,
+            "KZT" => penny::Currency::KZT,
+            _ => return None,
+        }))
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown currency code: {}", _0)]
+pub struct UnknownCurrency(pub String);
+
+impl std::error::Error for UnknownCurrency {}
+
+/// Accepts both numeric and alphabetic codes, e.g. `643` and `RUB`.
+impl FromStr for QiwiCurrency {
+    type Err = UnknownCurrency;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        Self::from_numeric_code(code)
+            .or_else(|| Self::from_alpha_code(code))
+            .ok_or_else(|| UnknownCurrency(s.to_string()))
+    }
+}
+
+impl TryFrom<&str> for QiwiCurrency {
+    type Error = UnknownCurrency;
+
+    fn try_from(code: &str) -> Result<Self, Self::Error> {
+        code.parse()
+    }
+}
+
+/// ISO 4217 numeric code, e.g. `643`.
+impl TryFrom<u16> for QiwiCurrency {
+    type Error = UnknownCurrency;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        let code = code.to_string();
+        Self::from_numeric_code(&code).ok_or(UnknownCurrency(code))
+    }
+}
+
+impl Serialize for QiwiCurrency {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for QiwiCurrency {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Code {
+            String(String),
+            Number(u16),
+        }
+
+        let code = match Code::deserialize(deserializer)? {
+            Code::String(v) => v,
+            Code::Number(v) => v.to_string(),
+        };
+
+        code.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Amount of money in a specific currency.
+#[derive(Clone, Debug, Display, Serialize, Deserialize)]
+#[display(fmt = "{} {}", amount, currency)]
+pub struct Money {
+    pub amount: BigDecimal,
+    pub currency: QiwiCurrency,
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "currency mismatch: {} and {}", left, right)]
+pub struct MismatchedCurrencies {
+    pub left: QiwiCurrency,
+    pub right: QiwiCurrency,
+}
+
+impl std::error::Error for MismatchedCurrencies {}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "invalid amount of money: {}", _0)]
+pub struct InvalidMoney(pub String);
+
+impl std::error::Error for InvalidMoney {}
+
+/// Parses an amount followed by a currency code, e.g. `100.50 RUB` or `100.50 643`.
+impl FromStr for Money {
+    type Err = InvalidMoney;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(amount), Some(currency), None) => Ok(Self {
+                amount: amount.parse().map_err(|_| InvalidMoney(s.to_string()))?,
+                currency: currency.parse().map_err(|_| InvalidMoney(s.to_string()))?,
+            }),
+            _ => Err(InvalidMoney(s.to_string())),
+        }
+    }
+}
+
+impl TryFrom<&str> for Money {
+    type Error = InvalidMoney;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Takes the shortest decimal representation of the float, so `0.1` becomes exactly `0.1`.
+/// Fails for infinities and NaN.
+impl TryFrom<(f64, QiwiCurrency)> for Money {
+    type Error = InvalidMoney;
+
+    fn try_from((amount, currency): (f64, QiwiCurrency)) -> Result<Self, Self::Error> {
+        if !amount.is_finite() {
+            return Err(InvalidMoney(amount.to_string()));
+        }
+
+        Ok(Self {
+            amount: amount
+                .to_string()
+                .parse()
+                .map_err(|_| InvalidMoney(amount.to_string()))?,
+            currency,
+        })
+    }
+}
+
+impl Money {
+    pub fn new(amount: BigDecimal, currency: penny::Currency) -> Self {
+        Self {
+            amount,
+            currency: QiwiCurrency(currency),
+        }
+    }
+
+    fn ensure_same_currency(&self, other: &Self) -> Result<(), MismatchedCurrencies> {
+        if self.currency != other.currency {
+            return Err(MismatchedCurrencies {
+                left: self.currency.clone(),
+                right: other.currency.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self, MismatchedCurrencies> {
+        self.ensure_same_currency(other)?;
+        Ok(Self {
+            amount: &self.amount + &other.amount,
+            currency: self.currency.clone(),
+        })
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<Self, MismatchedCurrencies> {
+        self.ensure_same_currency(other)?;
+        Ok(Self {
+            amount: &self.amount - &other.amount,
+            currency: self.currency.clone(),
+        })
+    }
+
+    /// Compares amounts, failing if the currencies differ.
+    pub fn cmp_same_currency(&self, other: &Self) -> Result<Ordering, MismatchedCurrencies> {
+        self.ensure_same_currency(other)?;
+        Ok(self.amount.cmp(&other.amount))
+    }
+
+    fn zero(currency: QiwiCurrency) -> Self {
+        Self {
+            amount: BigDecimal::from(0),
+            currency,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "no cross rate from {} to {}", from, to)]
+pub struct MissingCrossRate {
+    pub from: QiwiCurrency,
+    pub to: QiwiCurrency,
+}
+
+impl std::error::Error for MissingCrossRate {}
+
+/// Sums of money in several currencies.
+///
+/// Amounts are added as is, so negative ones, e.g. refunds, reduce the sum. A currency once
+/// added stays in the bag even if its sum is zero. Serialized as a list of [`Money`] ordered by
+/// currency code.
+#[derive(Clone, Debug, Default)]
+pub struct MoneyBag {
+    /// Keyed by numeric currency code.
+    sums: BTreeMap<String, Money>,
+}
+
+impl MoneyBag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, money: Money) {
+        match self.sums.get_mut(&money.currency.to_string()) {
+            Some(sum) => sum.amount += money.amount,
+            None => {
+                self.sums.insert(money.currency.to_string(), money);
+            }
+        }
+    }
+
+    /// Sum in `currency`, zero if there is none.
+    pub fn get(&self, currency: &QiwiCurrency) -> Money {
+        self.sums
+            .get(&currency.to_string())
+            .cloned()
+            .unwrap_or_else(|| Money::zero(currency.clone()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sums.is_empty()
+    }
+
+    /// Sums ordered by currency code.
+    pub fn iter(&self) -> impl Iterator<Item = &Money> {
+        self.sums.values()
+    }
+
+    /// Sums ordered by currency code.
+    pub fn into_sorted_vec(self) -> Vec<Money> {
+        self.sums.into_iter().map(|(_, money)| money).collect()
+    }
+
+    /// Total in `to`, converting other currencies at `rates`.
+    ///
+    /// The exact total is rounded half away from zero to two decimal places once, after all sums
+    /// are added, e.g. `75.125` to `75.13` and `-75.125` to `-75.13`.
+    pub fn convert_all(
+        &self,
+        to: &QiwiCurrency,
+        rates: &[CrossRate],
+    ) -> Result<Money, MissingCrossRate> {
+        let mut total = BigDecimal::from(0);
+        for sum in self.sums.values() {
+            if sum.currency == *to {
+                total += &sum.amount;
+            } else {
+                let rate =
+                    CrossRate::find(rates, &sum.currency, to).ok_or_else(|| MissingCrossRate {
+                        from: sum.currency.clone(),
+                        to: to.clone(),
+                    })?;
+                total += &sum.amount * rate;
+            }
+        }
+
+        Ok(Money {
+            amount: round_cents(&total),
+            currency: to.clone(),
+        })
+    }
+}
+
+/// Rounds half away from zero to two decimal places.
+fn round_cents(amount: &BigDecimal) -> BigDecimal {
+    let half = BigDecimal::new(5.into(), 3);
+    if *amount < BigDecimal::from(0) {
+        (amount - half).with_scale(2)
+    } else {
+        (amount + half).with_scale(2)
+    }
+}
+
+impl Extend<Money> for MoneyBag {
+    fn extend<I: IntoIterator<Item = Money>>(&mut self, iter: I) {
+        for money in iter {
+            self.add(money);
+        }
+    }
+}
+
+impl std::iter::FromIterator<Money> for MoneyBag {
+    fn from_iter<I: IntoIterator<Item = Money>>(iter: I) -> Self {
+        let mut bag = Self::new();
+        bag.extend(iter);
+        bag
+    }
+}
+
+impl Serialize for MoneyBag {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.sums.values())
+    }
+}
+
+impl<'de> Deserialize<'de> for MoneyBag {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<Money>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+/// Alias of one of the wallet's balances.
+///
+/// History and funding sources use `qw_wallet_<currency>` form,
+/// while payment responses refer to balances as `account_<numeric code>`.
+/// Both are understood by [`AccountAlias::currency`].
+#[derive(Clone, Debug, Display, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct AccountAlias(Cow<'static, str>);
+
+impl AccountAlias {
+    pub const QW_WALLET_RUB: Self = Self(Cow::Borrowed("qw_wallet_rub"));
+    pub const QW_WALLET_USD: Self = Self(Cow::Borrowed("qw_wallet_usd"));
+    pub const QW_WALLET_EUR: Self = Self(Cow::Borrowed("qw_wallet_eur"));
+    pub const QW_WALLET_KZT: Self = Self(Cow::Borrowed("qw_wallet_kzt"));
+
+    /// Wallet balance alias for the given currency.
+    pub fn from_currency(currency: penny::Currency) -> Option<Self> {
+        Some(match currency {
+            penny::Currency::RUB => Self::QW_WALLET_RUB,
+            penny::Currency::USD => Self::QW_WALLET_USD,
+            penny::Currency::EUR => Self::QW_WALLET_EUR,
+            penny::Currency::KZT => Self::QW_WALLET_KZT,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Currency of the balance, if this alias refers to a wallet balance.
+    pub fn currency(&self) -> Option<QiwiCurrency> {
+        if let Some(code) = self.0.strip_prefix("qw_wallet_") {
+            QiwiCurrency::from_alpha_code(code)
+        } else if let Some(code) = self.0.strip_prefix("account_") {
+            QiwiCurrency::from_numeric_code(code)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "invalid account alias: {}", _0)]
+pub struct InvalidAccountAlias(pub String);
+
+impl std::error::Error for InvalidAccountAlias {}
+
+impl FromStr for AccountAlias {
+    type Err = InvalidAccountAlias;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let alias = s.trim().to_ascii_lowercase();
+        if alias.is_empty() || !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(InvalidAccountAlias(s.to_string()));
+        }
+
+        Ok(Self(Cow::Owned(alias)))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountType {
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+}
+
+/// Balance of the wallet in one currency.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Account {
+    pub alias: AccountAlias,
+    #[serde(default)]
+    pub title: String,
+    pub currency: QiwiCurrency,
+    #[serde(rename = "type")]
+    pub account_type: AccountType,
+    pub has_balance: bool,
+    /// Absent for accounts without a balance, such as bound cards.
+    #[serde(default)]
+    pub balance: Option<Money>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MobilePinInfo {
+    pub mobile_pin_used: bool,
+    pub last_mobile_pin_change: String,
+    pub next_mobile_pin_change: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PassInfo {
+    pub password_used: bool,
+    pub last_pass_change: String,
+    pub next_pass_change: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinInfo {
+    pub pin_used: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum IdentificationLevel {
+    Anonymous,
+    Simple,
+    Verified,
+    Full,
+}
+
+impl IdentificationLevel {
+    /// All levels, from the lowest to the highest.
+    pub const ALL: [Self; 4] = [Self::Anonymous, Self::Simple, Self::Verified, Self::Full];
+
+    /// Wallet capabilities at this level according to QIWI's published limits.
+    pub fn capabilities(self) -> Capabilities {
+        match self {
+            Self::Anonymous => Capabilities {
+                max_balance: Some(BigDecimal::from(15_000)),
+                max_monthly_turnover: Some(BigDecimal::from(40_000)),
+                p2p_transfers_allowed: false,
+                card_transfers_allowed: false,
+                international_providers_allowed: false,
+            },
+            Self::Simple | Self::Verified => Capabilities {
+                max_balance: Some(BigDecimal::from(60_000)),
+                max_monthly_turnover: Some(BigDecimal::from(200_000)),
+                p2p_transfers_allowed: true,
+                card_transfers_allowed: true,
+                international_providers_allowed: true,
+            },
+            Self::Full => Capabilities {
+                max_balance: Some(BigDecimal::from(600_000)),
+                max_monthly_turnover: None,
+                p2p_transfers_allowed: true,
+                card_transfers_allowed: true,
+                international_providers_allowed: true,
+            },
+        }
+    }
+}
+
+/// Operations gated by identification level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    P2pTransfer,
+    CardTransfer,
+    InternationalPayment,
+}
+
+/// What a wallet is allowed to do. Amounts are in rubles, `None` means unlimited.
+#[derive(Clone, Debug, Serialize)]
+pub struct Capabilities {
+    pub max_balance: Option<BigDecimal>,
+    pub max_monthly_turnover: Option<BigDecimal>,
+    pub p2p_transfers_allowed: bool,
+    pub card_transfers_allowed: bool,
+    pub international_providers_allowed: bool,
+}
+
+impl Capabilities {
+    pub fn allows(&self, op: Operation) -> bool {
+        match op {
+            Operation::P2pTransfer => self.p2p_transfers_allowed,
+            Operation::CardTransfer => self.card_transfers_allowed,
+            Operation::InternationalPayment => self.international_providers_allowed,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CapabilityCheck {
+    pub level: IdentificationLevel,
+    pub allowed: bool,
+    /// Lowest identification level that allows the operation, if it is denied at the current one.
+    pub required_level: Option<IdentificationLevel>,
+    pub reason: Option<String>,
+}
+
+impl CapabilityCheck {
+    pub fn new(level: IdentificationLevel, op: Operation) -> Self {
+        if level.capabilities().allows(op) {
+            return Self {
+                level,
+                allowed: true,
+                required_level: None,
+                reason: None,
+            };
+        }
+
+        let required_level = IdentificationLevel::ALL
+            .iter()
+            .copied()
+            .find(|level| level.capabilities().allows(op));
+        let reason = Some(match required_level {
+            Some(required) => format!(
+                "{:?} is not allowed at {:?} identification level, {:?} is required",
+                op, level, required
+            ),
+            None => format!("{:?} is not allowed", op),
+        });
+
+        Self {
+            level,
+            allowed: false,
+            required_level,
+            reason,
+        }
+    }
+}
+
+/// Bank that performed the identification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum BankAlias {
+    #[serde(rename = "QIWI")]
+    Qiwi,
+    #[serde(rename = "RSB")]
+    Rsb,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Identification of the wallet by a single bank.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentificationRecord {
+    pub bank_alias: BankAlias,
+    pub identification_level: IdentificationLevel,
+    /// Set when the identification is based on an expired passport and is about to lapse.
+    pub passport_expired: Option<bool>,
+}
+
+/// Identification type as reported by the identification API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+#[non_exhaustive]
+pub enum IdentificationType {
+    Simple,
+    Verified,
+    Full,
+    #[serde(other)]
+    Unknown,
+}
+
+impl IdentificationType {
+    pub fn level(self) -> Option<IdentificationLevel> {
+        match self {
+            Self::Simple => Some(IdentificationLevel::Simple),
+            Self::Verified => Some(IdentificationLevel::Verified),
+            Self::Full => Some(IdentificationLevel::Full),
+            Self::Unknown => None,
+        }
+    }
+}
+
+/// Personal data of the wallet owner on file with QIWI.
+///
+/// Document numbers are masked by QIWI, only their last digits are returned.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identification {
+    /// Wallet number.
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub identification_type: IdentificationType,
+    #[serde(default)]
+    pub birth_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub first_name: Option<String>,
+    #[serde(default)]
+    pub middle_name: Option<String>,
+    #[serde(default)]
+    pub last_name: Option<String>,
+    #[serde(default)]
+    pub passport: Option<String>,
+    #[serde(default)]
+    pub inn: Option<String>,
+    #[serde(default)]
+    pub snils: Option<String>,
+    #[serde(default)]
+    pub oms: Option<String>,
+}
+
+/// Personal data submitted for simple identification.
+///
+/// Besides the passport, QIWI needs at least one of INN, SNILS and OMS policy numbers.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentificationRequest {
+    pub birth_date: NaiveDate,
+    pub first_name: String,
+    pub middle_name: String,
+    pub last_name: String,
+    /// Series and number without spaces.
+    pub passport: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snils: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oms: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    pub default_pay_currency: u64,
+    pub default_pay_source: u64,
+    pub email: String,
+    #[serde(alias = "firstTransactionId", alias = "first_txn_id")]
+    pub first_txn_id: u64,
+    pub language: String,
+    pub operator: String,
+    pub phone_hash: String,
+    pub promo_enabled: String,
+}
+
+impl Debug for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserInfo")
+            .field("default_pay_currency", &self.default_pay_currency)
+            .field("default_pay_source", &self.default_pay_source)
+            .field("email", &mask_email(&self.email))
+            .field("first_txn_id", &self.first_txn_id)
+            .field("language", &self.language)
+            .field("operator", &self.operator)
+            .field("phone_hash", &self.phone_hash)
+            .field("promo_enabled", &self.promo_enabled)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractInfo {
+    pub blocked: bool,
+    pub contract_id: u64,
+    pub creation_date: DateTime<Utc>,
+    pub features: Vec<Value>,
+    pub identification_info: Vec<IdentificationRecord>,
+    pub user_info: UserInfo,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthInfo {
+    pub person_id: u64,
+    pub registration_date: DateTime<Utc>,
+    pub bound_email: Option<String>,
+    pub ip: IpAddr,
+    pub last_login_date: Option<DateTime<Utc>>,
+    pub mobile_pin_info: MobilePinInfo,
+    pub pass_info: PassInfo,
+    pub pin_info: PinInfo,
+    pub contract_info: Option<ContractInfo>,
+}
+
+impl Debug for AuthInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AuthInfo")
+            .field("person_id", &mask_phone(&self.person_id.to_string()))
+            .field("registration_date", &self.registration_date)
+            .field("bound_email", &self.bound_email.as_deref().map(mask_email))
+            .field("ip", &self.ip)
+            .field("last_login_date", &self.last_login_date)
+            .field("mobile_pin_info", &self.mobile_pin_info)
+            .field("pass_info", &self.pass_info)
+            .field("pin_info", &self.pin_info)
+            .field("contract_info", &self.contract_info)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileInfo {
+    pub auth_info: AuthInfo,
+}
+
+impl ProfileInfo {
+    pub fn identification_records(&self) -> &[IdentificationRecord] {
+        self.auth_info
+            .contract_info
+            .as_ref()
+            .map(|contract| contract.identification_info.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Identification record by QIWI bank itself.
+    pub fn qiwi_identification(&self) -> Option<&IdentificationRecord> {
+        self.identification_records()
+            .iter()
+            .find(|record| record.bank_alias == BankAlias::Qiwi)
+    }
+
+    /// Highest identification level among all banks, `Anonymous` if there are no records.
+    pub fn effective_level(&self) -> IdentificationLevel {
+        self.identification_records()
+            .iter()
+            .map(|record| record.identification_level)
+            .max()
+            .unwrap_or(IdentificationLevel::Anonymous)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PaymentType {
+    In,
+    Out,
+    QiwiCard,
+}
+
+/// Direction of a transaction as identified by `Client::transaction_info`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TransactionType {
+    In,
+    Out,
+}
+
+impl TransactionType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::In => "IN",
+            Self::Out => "OUT",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PaymentStatus {
+    Waiting,
+    Success,
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentSumData {
+    pub amount: BigDecimal,
+    pub currency: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderData {
+    pub id: u64,
+    pub short_name: String,
+    pub long_name: String,
+    pub logo_url: String,
+    pub description: String,
+    pub keys: String,
+    pub site_url: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentHistoryEntry {
+    #[serde(alias = "transactionId", alias = "txn_id")]
+    pub txn_id: u64,
+    pub person_id: u64,
+    pub date: DateTime<Utc>,
+    #[serde(alias = "error_code")]
+    pub error_code: u64,
+    pub error: String,
+    #[serde(rename = "type")]
+    pub payment_type: PaymentType,
+    pub status: PaymentStatus,
+    pub status_text: String,
+    #[serde(alias = "terminalTransactionId", alias = "trm_txn_id")]
+    pub trm_txn_id: String,
+    pub account: String,
+    pub sum: PaymentSumData,
+    pub commission: PaymentSumData,
+    pub total: PaymentSumData,
+    pub provider: ProviderData,
+    pub comment: String,
+    pub currency_rate: BigDecimal,
+    pub extras: HashMap<String, Value>,
+    pub cheque_ready: bool,
+    pub bank_document_available: bool,
+    pub bank_document_ready: bool,
+    pub repeat_payment_enabled: bool,
+    pub favorite_payment_enabled: bool,
+    pub regular_payment_enabled: bool,
+}
+
+impl Debug for PaymentHistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PaymentHistoryEntry")
+            .field("txn_id", &self.txn_id)
+            .field("person_id", &mask_phone(&self.person_id.to_string()))
+            .field("date", &self.date)
+            .field("error_code", &self.error_code)
+            .field("error", &self.error)
+            .field("payment_type", &self.payment_type)
+            .field("status", &self.status)
+            .field("status_text", &self.status_text)
+            .field("trm_txn_id", &self.trm_txn_id)
+            .field("account", &mask_account(&self.account))
+            .field("sum", &self.sum)
+            .field("commission", &self.commission)
+            .field("total", &self.total)
+            .field("provider", &self.provider)
+            .field("comment", &mask_pans(&self.comment))
+            .field("currency_rate", &self.currency_rate)
+            .field("extras", &self.extras)
+            .field("cheque_ready", &self.cheque_ready)
+            .field("bank_document_available", &self.bank_document_available)
+            .field("bank_document_ready", &self.bank_document_ready)
+            .field("repeat_payment_enabled", &self.repeat_payment_enabled)
+            .field("favorite_payment_enabled", &self.favorite_payment_enabled)
+            .field("regular_payment_enabled", &self.regular_payment_enabled)
+            .finish()
+    }
+}
+
+/// Bill issued to the wallet, e.g. by a shop, which the wallet owner may pay.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bill {
+    pub id: u64,
+    /// Bill id assigned by its issuer.
+    pub external_id: String,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub creation_datetime: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub expiration_datetime: DateTime<Utc>,
+    pub sum: Money,
+    pub status: BillStatus,
+    #[serde(default)]
+    pub comment: String,
+    pub pay_url: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BillStatus {
+    /// E.g. `READY_FOR_PAY`.
+    pub value: String,
+    #[serde(with = "chrono::serde::ts_milliseconds")]
+    pub changed_datetime: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BillList {
+    pub bills: Vec<Bill>,
+}
+
+/// Whether a history entry put money into the wallet or took it out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl PaymentHistoryEntry {
+    /// `QIWI_CARD` entries are spendings with a QIWI card and leave the wallet like `OUT` ones.
+    ///
+    /// Reversals of outgoing payments are reported as `OUT` or `QIWI_CARD` entries with a negative
+    /// `sum`, they return money to the wallet.
+    pub fn direction(&self) -> Direction {
+        match self.payment_type {
+            PaymentType::In => Direction::In,
+            PaymentType::Out | PaymentType::QiwiCard if self.sum.amount < BigDecimal::from(0) => {
+                Direction::In
+            }
+            PaymentType::Out | PaymentType::QiwiCard => Direction::Out,
+        }
+    }
+
+    /// Change of the wallet balance: positive for money entering it, negative for money leaving it,
+    /// zero for failed payments.
+    ///
+    /// Taken from `total`, which includes commission and is in the currency of the debited or
+    /// credited balance, e.g. the source currency for the outgoing half of a conversion or for a
+    /// QIWI card spending abroad. Its sign is ignored, as card top-ups are sometimes reported with
+    /// a negative `total`, the sign comes from [`PaymentHistoryEntry::direction`] instead.
+    /// Falls back to `sum` for entries without a `total`.
+    pub fn signed_amount(&self) -> Result<Money, UnknownCurrency> {
+        let data = if self.total.currency.is_empty() {
+            &self.sum
+        } else {
+            &self.total
+        };
+        let currency = data.currency.parse::<QiwiCurrency>()?;
+        let amount = if self.status == PaymentStatus::Error {
+            BigDecimal::from(0)
+        } else {
+            match self.direction() {
+                Direction::In => data.amount.abs(),
+                Direction::Out => -data.amount.abs(),
+            }
+        };
+        Ok(Money { amount, currency })
+    }
+}
+
+/// Kind of payments to list in history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryOperation {
+    In,
+    Out,
+    QiwiCard,
+    All,
+}
+
+impl HistoryOperation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::In => "IN",
+            Self::Out => "OUT",
+            Self::QiwiCard => "QIWI_CARD",
+            Self::All => "ALL",
+        }
+    }
+}
+
+/// Where the money of a payment came from or went to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum HistorySource {
+    /// Ruble wallet account
+    QwRub,
+    /// Dollar wallet account
+    QwUsd,
+    /// Euro wallet account
+    QwEur,
+    /// Bank cards, QIWI ones included
+    Card,
+    /// Mobile phone account
+    Mk,
+}
+
+impl HistorySource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::QwRub => "QW_RUB",
+            Self::QwUsd => "QW_USD",
+            Self::QwEur => "QW_EUR",
+            Self::Card => "CARD",
+            Self::Mk => "MK",
+        }
+    }
+
+    /// Source of `entry` as far as the entry tells it, for filtering where QIWI does not.
+    ///
+    /// Payments from or to a card are told by the account, others by the currency of the sum.
+    /// `None` for balances in other currencies, mobile account payments are not told apart.
+    pub fn of(entry: &PaymentHistoryEntry) -> Option<Self> {
+        if let NormalizedAccount::Card { .. } =
+            NormalizedAccount::new(&entry.account, Region::Russia)
+        {
+            return Some(Self::Card);
+        }
+        match entry.sum.currency.as_str() {
+            "643" => Some(Self::QwRub),
+            "840" => Some(Self::QwUsd),
+            "978" => Some(Self::QwEur),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown history source: {}", _0)]
+pub struct UnknownHistorySource(pub String);
+
+impl std::error::Error for UnknownHistorySource {}
+
+/// Accepts the QIWI names in any case, e.g. `qw_rub` or `CARD`.
+impl FromStr for HistorySource {
+    type Err = UnknownHistorySource;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "QW_RUB" => Ok(Self::QwRub),
+            "QW_USD" => Ok(Self::QwUsd),
+            "QW_EUR" => Ok(Self::QwEur),
+            "CARD" => Ok(Self::Card),
+            "MK" => Ok(Self::Mk),
+            _ => Err(UnknownHistorySource(s.to_string())),
+        }
+    }
+}
+
+/// Narrows down payments returned by `Client::payment_history_filtered`.
+#[derive(Clone, Debug, Default)]
+pub struct PaymentHistoryFilter {
+    /// Payments made at or after this time. QIWI requires the end as well, it defaults to now.
+    pub start_date: Option<DateTime<FixedOffset>>,
+    /// Payments made before this time. Requires the start.
+    pub end_date: Option<DateTime<FixedOffset>>,
+    /// All kinds if not set.
+    pub operation: Option<HistoryOperation>,
+    /// Payments from or to any of these, everything if empty.
+    pub sources: Vec<HistorySource>,
+}
+
+impl PaymentHistoryFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start_date(mut self, date: DateTime<FixedOffset>) -> Self {
+        self.start_date = Some(date);
+        self
+    }
+
+    pub fn end_date(mut self, date: DateTime<FixedOffset>) -> Self {
+        self.end_date = Some(date);
+        self
+    }
+
+    pub fn operation(mut self, operation: HistoryOperation) -> Self {
+        self.operation = Some(operation);
+        self
+    }
+
+    pub fn source(mut self, source: HistorySource) -> Self {
+        if !self.sources.contains(&source) {
+            self.sources.push(source);
+        }
+        self
+    }
+
+    /// Whether `entry` is from or to one of the sources, see [`HistorySource::of`]. Always if there are none.
+    pub fn matches_sources(&self, entry: &PaymentHistoryEntry) -> bool {
+        self.sources.is_empty()
+            || HistorySource::of(entry).map_or(false, |source| self.sources.contains(&source))
+    }
+}
+
+/// Sums of payments over a period, one per currency.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentTotals {
+    pub incoming_total: Vec<Money>,
+    pub outgoing_total: Vec<Money>,
+    /// Sources the sums are limited to, set by `Client::payment_totals`. Empty if they cover all.
+    #[serde(default)]
+    pub sources: Vec<HistorySource>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentHistoryData {
+    pub data: Vec<PaymentHistoryEntry>,
+    #[serde(alias = "nextTransactionId", alias = "next_txn_id")]
+    pub next_txn_id: Option<u64>,
+    #[serde(alias = "nextTransactionDate", alias = "next_txn_date")]
+    pub next_txn_date: Option<String>,
+}
+
+#[derive(
+    Clone, Copy, Debug, Display, PartialEq, Eq, Hash, From, FromStr, Serialize, Deserialize,
+)]
+pub struct ProviderId(u64);
+
+impl ProviderId {
+    pub const QIWI: Self = Self(99);
+    pub const VISA_RU: Self = Self(1963);
+    pub const VISA_CIS: Self = Self(1960);
+    pub const MASTERCARD_RU: Self = Self(21013);
+    pub const MASTERCARD_CIS: Self = Self(21012);
+    pub const MIR: Self = Self(31652);
+    pub const TINKOFF: Self = Self(466);
+    pub const ALFABANK: Self = Self(464);
+    pub const PROMSVYAZBANK: Self = Self(821);
+    pub const RUSSIAN_STANDARD: Self = Self(815);
+    pub const OTHER_BANK: Self = Self(1717);
+    pub const MTS: Self = Self(1);
+    pub const BEELINE: Self = Self(2);
+    pub const MEGAFON: Self = Self(3);
+    pub const TELE2: Self = Self(42);
+    /// QIWI Master package, required to issue cards other than `qvc-cpa`.
+    pub const MASTER_PACKAGE: Self = Self(28004);
+    /// Payment for a [`CardOrder`].
+    pub const CARD_ORDER: Self = Self(32064);
+
+    /// Whether accounts of the provider are phone numbers: QIWI wallets and carriers.
+    pub fn takes_phone(self) -> bool {
+        [
+            Self::QIWI,
+            Self::MTS,
+            Self::BEELINE,
+            Self::MEGAFON,
+            Self::TELE2,
+        ]
+        .contains(&self)
+    }
+}
+
+/// Payment system of a bank card, told by the first digits of the number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CardNetwork {
+    Visa,
+    Mastercard,
+    Mir,
+}
+
+impl CardNetwork {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Visa => "visa",
+            Self::Mastercard => "mastercard",
+            Self::Mir => "mir",
+        }
+    }
+}
+
+/// Kind of transfer whose SINAP provider is looked up in [`ProviderMap`].
+///
+/// Written as `wallet`, `card-detection` or `card.<network>`, e.g. `card.visa`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum TransferRoute {
+    /// QIWI wallet.
+    Wallet,
+    /// Provider whose containers detect the provider of a card, see `Client::card_provider`.
+    CardDetection,
+    /// Card of the network, used instead of detection if mapped.
+    Card(CardNetwork),
+}
+
+impl fmt::Display for TransferRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Wallet => f.write_str("wallet"),
+            Self::CardDetection => f.write_str("card-detection"),
+            Self::Card(network) => write!(f, "card.{}", network.as_str()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown transfer route: {}", _0)]
+pub struct UnknownTransferRoute(pub String);
+
+impl std::error::Error for UnknownTransferRoute {}
+
+impl FromStr for TransferRoute {
+    type Err = UnknownTransferRoute;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "wallet" => Self::Wallet,
+            "card-detection" => Self::CardDetection,
+            "card.visa" => Self::Card(CardNetwork::Visa),
+            "card.mastercard" => Self::Card(CardNetwork::Mastercard),
+            "card.mir" => Self::Card(CardNetwork::Mir),
+            _ => return Err(UnknownTransferRoute(s.to_string())),
+        })
+    }
+}
+
+impl From<TransferRoute> for String {
+    fn from(route: TransferRoute) -> Self {
+        route.to_string()
+    }
+}
+
+impl TryFrom<String> for TransferRoute {
+    type Error = UnknownTransferRoute;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// SINAP providers of transfers, see `ClientBuilder::provider_overrides`.
+///
+/// | Route            | Default                         |
+/// |------------------|---------------------------------|
+/// | `wallet`         | 99                              |
+/// | `card-detection` | 1717                            |
+/// | `card.<network>` | none, the provider is detected  |
+///
+/// Cellular and bank account transfers name their provider themselves. Serialized as a map of
+/// routes to provider ids, e.g. `{"card.visa": 1963}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProviderMap(BTreeMap<TransferRoute, ProviderId>);
+
+impl Default for ProviderMap {
+    fn default() -> Self {
+        Self::empty()
+            .set(TransferRoute::Wallet, ProviderId::QIWI)
+            .set(TransferRoute::CardDetection, ProviderId::OTHER_BANK)
+    }
+}
+
+impl ProviderMap {
+    /// Map without any routes, e.g. to collect overrides.
+    pub fn empty() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    pub fn set(mut self, route: TransferRoute, provider: ProviderId) -> Self {
+        self.0.insert(route, provider);
+        self
+    }
+
+    pub fn remove(mut self, route: TransferRoute) -> Self {
+        self.0.remove(&route);
+        self
+    }
+
+    pub fn get(&self, route: TransferRoute) -> Option<ProviderId> {
+        self.0.get(&route).copied()
+    }
+
+    /// Routes of `overrides` replace the ones of this map.
+    pub fn merge(mut self, overrides: &ProviderMap) -> Self {
+        self.0.extend(overrides.0.iter().map(|(k, v)| (*k, *v)));
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TransferRoute, ProviderId)> + '_ {
+        self.0.iter().map(|(k, v)| (*k, *v))
+    }
+}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionRange {
+    pub bound: BigDecimal,
+    pub rate: BigDecimal,
+    pub min: BigDecimal,
+    pub max: BigDecimal,
+    pub fixed: BigDecimal,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionLimit {
+    pub currency: u16,
+    pub min: BigDecimal,
+    pub max: BigDecimal,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionInfo {
+    pub ranges: Vec<CommissionRange>,
+    pub limits: Vec<CommissionLimit>,
+}
+
+impl CommissionInfo {
+    /// Commission for paying `amount`, rounded down to kopecks.
+    ///
+    /// The range with the highest bound not above `amount` applies: its fixed part plus `rate`
+    /// of the amount, at least `min` and at most `max` unless they are zero.
+    pub fn commission_for(&self, amount: &BigDecimal) -> BigDecimal {
+        let zero = BigDecimal::from(0);
+        let range = match self
+            .ranges
+            .iter()
+            .filter(|range| range.bound <= *amount)
+            .max_by(|a, b| a.bound.cmp(&b.bound))
+        {
+            Some(range) => range,
+            None => return zero,
+        };
+
+        let mut commission = &range.fixed + amount * &range.rate;
+        if range.min > zero && commission < range.min {
+            commission = range.min.clone();
+        }
+        if range.max > zero && commission > range.max {
+            commission = range.max.clone();
+        }
+        commission.with_scale(2)
+    }
+
+    /// Whether `amount` is within the limits listed for its currency. Amounts in currencies
+    /// without limits are allowed only if no limits are listed at all.
+    pub fn allows(&self, amount: &Money) -> bool {
+        if self.limits.is_empty() {
+            return true;
+        }
+        let zero = BigDecimal::from(0);
+        self.limits.iter().any(|limit| {
+            QiwiCurrency::try_from(limit.currency).ok().as_ref() == Some(&amount.currency)
+                && amount.amount >= limit.min
+                && (limit.max == zero || amount.amount <= limit.max)
+        })
+    }
+}
+
+/// Commission terms of a provider as fetched at `fetched_at`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderCommission {
+    pub provider: ProviderId,
+    pub fetched_at: DateTime<Utc>,
+    pub info: CommissionInfo,
+}
+
+impl ProviderCommission {
+    pub fn is_stale(&self, max_age: chrono::Duration) -> bool {
+        self.is_stale_at(max_age, Utc::now())
+    }
+
+    /// Whether the terms were older than `max_age` at `now`.
+    pub fn is_stale_at(&self, max_age: chrono::Duration, now: DateTime<Utc>) -> bool {
+        now - self.fetched_at > max_age
+    }
+}
+
+/// Commission terms of several providers for estimating fees offline, see
+/// `Client::snapshot_commissions`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionSnapshot {
+    pub providers: Vec<ProviderCommission>,
+}
+
+impl CommissionSnapshot {
+    pub fn get(&self, provider: ProviderId) -> Option<&ProviderCommission> {
+        self.providers
+            .iter()
+            .find(|entry| entry.provider == provider)
+    }
+
+    /// Replaces the terms of `provider`, if any.
+    pub fn insert(
+        &mut self,
+        provider: ProviderId,
+        info: CommissionInfo,
+        fetched_at: DateTime<Utc>,
+    ) {
+        let entry = ProviderCommission {
+            provider,
+            fetched_at,
+            info,
+        };
+        match self
+            .providers
+            .iter_mut()
+            .find(|entry| entry.provider == provider)
+        {
+            Some(existing) => *existing = entry,
+            None => self.providers.push(entry),
+        }
+    }
+
+    /// Commission for paying `amount` to `provider`, in the currency of `amount`.
+    ///
+    /// `None` if the snapshot has no terms of the provider or the amount is outside its limits.
+    pub fn estimate(&self, provider: ProviderId, amount: &Money) -> Option<Money> {
+        let info = &self.get(provider)?.info;
+        if !info.allows(amount) {
+            return None;
+        }
+        Some(Money {
+            amount: info.commission_for(&amount.amount),
+            currency: amount.currency.clone(),
+        })
+    }
+
+    /// Whether the terms of any provider are older than `max_age`.
+    pub fn is_stale(&self, max_age: chrono::Duration) -> bool {
+        self.is_stale_at(max_age, Utc::now())
+    }
+
+    /// Whether the terms of any provider were older than `max_age` at `now`.
+    pub fn is_stale_at(&self, max_age: chrono::Duration, now: DateTime<Utc>) -> bool {
+        self.providers
+            .iter()
+            .any(|entry| entry.is_stale_at(max_age, now))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidatorPredicate {
+    pub pattern: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldValidator {
+    #[serde(rename = "type")]
+    pub validator_type: String,
+    pub predicate: Option<ValidatorPredicate>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldView {
+    pub title: Option<String>,
+    pub prompt: Option<String>,
+    pub widget: Option<FieldWidget>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldWidget {
+    #[serde(rename = "type")]
+    pub widget_type: String,
+    #[serde(default)]
+    pub choices: Vec<FieldChoice>,
+}
+
+/// Allowed value of a select field.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChoice {
+    pub title: String,
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormElement {
+    #[serde(rename = "type")]
+    pub element_type: String,
+    pub name: Option<String>,
+    pub value: Option<Value>,
+    pub validator: Option<FieldValidator>,
+    pub view: Option<FieldView>,
+}
+
+impl FormElement {
+    pub fn is_field(&self) -> bool {
+        self.element_type == "field" && self.name.is_some()
+    }
+
+    /// Fields without a predefined value have to be supplied by the payer.
+    pub fn is_required(&self) -> bool {
+        self.is_field()
+            && match &self.value {
+                None | Some(Value::Null) => true,
+                Some(Value::String(v)) => v.is_empty(),
+                Some(_) => false,
+            }
+    }
+
+    pub fn pattern(&self) -> Option<&str> {
+        self.validator
+            .as_ref()
+            .filter(|validator| validator.validator_type == "regex")?
+            .predicate
+            .as_ref()?
+            .pattern
+            .as_deref()
+    }
+
+    /// Checks the value against field's regex validator, if any.
+    pub fn validate(&self, value: &str) -> bool {
+        match self.pattern().map(Regex::new) {
+            Some(Ok(regex)) => regex.is_match(value),
+            _ => true,
+        }
+    }
+
+    /// Human-readable field name, falling back to the field's own name.
+    pub fn title(&self) -> Option<&str> {
+        self.view
+            .as_ref()
+            .and_then(|view| view.title.as_deref())
+            .or_else(|| self.name.as_deref())
+    }
+
+    /// Validation failure message.
+    pub fn hint(&self) -> Option<&str> {
+        self.validator
+            .as_ref()?
+            .predicate
+            .as_ref()?
+            .message
+            .as_deref()
+    }
+
+    pub fn choices(&self) -> &[FieldChoice] {
+        self.view
+            .as_ref()
+            .and_then(|view| view.widget.as_ref())
+            .map(|widget| widget.choices.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// Everything needed to ask the payer for a form field.
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldDescriptor {
+    pub name: String,
+    pub title: String,
+    pub regex: Option<String>,
+    pub hint: Option<String>,
+    pub required: bool,
+    /// Allowed values, any value is allowed if empty.
+    pub choices: Vec<FieldChoice>,
+    /// Predefined value.
+    pub default: Option<String>,
+}
+
+impl FieldDescriptor {
+    pub fn from_element(element: &FormElement) -> Option<Self> {
+        if !element.is_field() {
+            return None;
+        }
+
+        let name = element.name.clone()?;
+        Some(Self {
+            title: element.title().unwrap_or(&name).to_string(),
+            regex: element.pattern().map(ToString::to_string),
+            hint: element.hint().map(ToString::to_string),
+            required: element.is_required(),
+            choices: element.choices().to_vec(),
+            default: match &element.value {
+                Some(Value::String(v)) if !v.is_empty() => Some(v.clone()),
+                _ => None,
+            },
+            name,
+        })
+    }
+
+    pub fn validate(&self, value: &str) -> Result<(), InvalidField> {
+        if let Some(Ok(regex)) = self.regex.as_deref().map(Regex::new) {
+            if !regex.is_match(value) {
+                return Err(InvalidField::PatternMismatch {
+                    name: self.name.clone(),
+                    hint: self.hint.clone().unwrap_or_default(),
+                });
+            }
+        }
+
+        if !self.choices.is_empty() && !self.choices.iter().any(|choice| choice.value == value) {
+            return Err(InvalidField::NotAllowed {
+                name: self.name.clone(),
+                value: value.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+pub enum InvalidField {
+    #[display(fmt = "unknown field: {}", _0)]
+    UnknownField(String),
+    #[display(fmt = "invalid value for field {}: {}", name, hint)]
+    PatternMismatch { name: String, hint: String },
+    #[display(fmt = "value {} is not allowed for field {}", value, name)]
+    NotAllowed { name: String, value: String },
+}
+
+impl std::error::Error for InvalidField {}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "missing required fields: {}", "_0.join(\", \")")]
+pub struct MissingFields(pub Vec<String>);
+
+impl std::error::Error for MissingFields {}
+
+/// Payment fields checked against the provider's form as they are set.
+#[derive(Clone, Debug)]
+pub struct TypedFields {
+    descriptors: Vec<FieldDescriptor>,
+    values: BTreeMap<String, String>,
+}
+
+impl TypedFields {
+    pub fn from_form(form: &ProviderForm) -> Self {
+        Self {
+            descriptors: form.descriptors(),
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub fn descriptors(&self) -> &[FieldDescriptor] {
+        &self.descriptors
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Validates and sets the field value.
+    pub fn set<N, V>(&mut self, name: N, value: V) -> Result<&mut Self, InvalidField>
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let name = name.into();
+        let value = value.into();
+        self.descriptors
+            .iter()
+            .find(|descriptor| descriptor.name == name)
+            .ok_or_else(|| InvalidField::UnknownField(name.clone()))?
+            .validate(&value)?;
+        self.values.insert(name, value);
+
+        Ok(self)
+    }
+
+    /// Required fields that are not set yet.
+    pub fn missing_required(&self) -> Vec<&FieldDescriptor> {
+        self.descriptors
+            .iter()
+            .filter(|descriptor| descriptor.required && !self.values.contains_key(&descriptor.name))
+            .collect()
+    }
+
+    /// Fields for [`PaymentRequest`], once all required ones are set.
+    pub fn into_fields(self) -> Result<BTreeMap<String, String>, MissingFields> {
+        let missing = self
+            .missing_required()
+            .into_iter()
+            .map(|descriptor| descriptor.name.clone())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(MissingFields(missing));
+        }
+
+        Ok(self.values)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderFormContent {
+    pub elements: Vec<FormElement>,
+}
+
+/// Payment form of a SINAP provider.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderForm {
+    pub content: ProviderFormContent,
+}
+
+impl ProviderForm {
+    pub fn fields(&self) -> impl Iterator<Item = &FormElement> {
+        self.content
+            .elements
+            .iter()
+            .filter(|element| element.is_field())
+    }
+
+    pub fn descriptors(&self) -> Vec<FieldDescriptor> {
+        self.fields()
+            .filter_map(FieldDescriptor::from_element)
+            .collect()
+    }
+}
+
+/// `paymentMethod` object of SINAP requests.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentMethod {
+    #[serde(rename = "type")]
+    pub method_type: &'static str,
+    pub account_id: QiwiCurrency,
+}
+
+impl PaymentMethod {
+    /// SINAP identifies the funding balance by its numeric currency code rather than by alias.
+    pub fn from_account(alias: &AccountAlias) -> Option<Self> {
+        Some(Self::account(alias.currency()?))
+    }
+
+    /// Wallet balance in `currency`.
+    pub fn account(currency: QiwiCurrency) -> Self {
+        Self {
+            method_type: "Account",
+            account_id: currency,
+        }
+    }
+}
+
+/// Body of a SINAP payment request.
+///
+/// Fields are serialized in declaration order and `fields` is sorted by name,
+/// so the same request always produces the same bytes.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRequest {
+    #[serde(skip)]
+    pub provider: ProviderId,
+    pub id: String,
+    pub sum: Money,
+    pub payment_method: PaymentMethod,
+    pub fields: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl PaymentRequest {
+    pub fn canonical_json(&self) -> String {
+        serde_json::to_string(self).expect("payment request is always serializable")
+    }
+}
+
+/// Country the wallet is registered in. Determines defaults for phone parsing,
+/// balance currency and date boundaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Region {
+    Russia,
+    Kazakhstan,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self::Russia
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown region: {}", _0)]
+pub struct UnknownRegion(pub String);
+
+impl std::error::Error for UnknownRegion {}
+
+impl FromStr for Region {
+    type Err = UnknownRegion;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ru" | "russia" => Ok(Self::Russia),
+            "kz" | "kazakhstan" => Ok(Self::Kazakhstan),
+            _ => Err(UnknownRegion(s.to_string())),
+        }
+    }
+}
+
+impl Region {
+    /// Region of the phone number, if it is a supported one.
+    pub fn of(phone: &PhoneNumber) -> Option<Self> {
+        match phone.country().id()? {
+            phonenumber::country::Id::RU => Some(Self::Russia),
+            phonenumber::country::Id::KZ => Some(Self::Kazakhstan),
+            _ => None,
+        }
+    }
+
+    pub fn country(self) -> phonenumber::country::Id {
+        match self {
+            Self::Russia => phonenumber::country::Id::RU,
+            Self::Kazakhstan => phonenumber::country::Id::KZ,
+        }
+    }
+
+    /// Currency of the wallet's default balance.
+    pub fn currency(self) -> penny::Currency {
+        match self {
+            Self::Russia => penny::Currency::RUB,
+            Self::Kazakhstan => penny::Currency::KZT,
+        }
+    }
+
+    pub fn default_account(self) -> AccountAlias {
+        match self {
+            Self::Russia => AccountAlias::QW_WALLET_RUB,
+            Self::Kazakhstan => AccountAlias::QW_WALLET_KZT,
+        }
+    }
+
+    /// Offset used for day and month boundaries.
+    pub fn utc_offset(self) -> FixedOffset {
+        FixedOffset::east_opt(match self {
+            Self::Russia => 3 * 3600,
+            Self::Kazakhstan => 5 * 3600,
+        })
+        .unwrap()
+    }
+
+    /// Start of the month `now` falls into, local time.
+    pub fn month_start(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let offset = self.utc_offset();
+        let now = now.with_timezone(&offset);
+        offset
+            .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    /// Parses a phone number, accepting national format of this region, e.g. `8 701 ...` for Kazakhstan.
+    pub fn parse_phone(self, input: &str) -> Option<PhoneNumber> {
+        phonenumber::parse(Some(self.country()), input.trim())
+            .ok()
+            .filter(phonenumber::is_valid)
+    }
+}
+
+/// Payment destination account: a phone number for wallets and carriers,
+/// or an arbitrary provider-specific string, e.g. a contract number.
+#[derive(Clone, Debug, From)]
+pub enum AccountId {
+    Phone(PhoneNumber),
+    Raw(String),
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "invalid account: {}", _0)]
+pub struct InvalidAccountId(pub String);
+
+impl std::error::Error for InvalidAccountId {}
+
+impl AccountId {
+    /// Parses user input for `provider`, with national numbers in Russian format.
+    ///
+    /// Phone numbers are preferred for [providers that take them](ProviderId::takes_phone), any other
+    /// input, or any input for other providers, is accepted as is if it passes the form field's validator.
+    /// So a contract number `89161234567` of an internet provider is not mistaken for a phone.
+    pub fn parse(
+        input: &str,
+        provider: ProviderId,
+        field: Option<&FormElement>,
+    ) -> Result<Self, InvalidAccountId> {
+        Self::parse_in(input, provider, field, Region::default())
+    }
+
+    /// Same as [`AccountId::parse`], with national numbers in the format of `region`.
+    pub fn parse_in(
+        input: &str,
+        provider: ProviderId,
+        field: Option<&FormElement>,
+        region: Region,
+    ) -> Result<Self, InvalidAccountId> {
+        let input = input.trim();
+        if provider.takes_phone() {
+            if let Some(phone) = input
+                .parse::<PhoneNumber>()
+                .ok()
+                .or_else(|| region.parse_phone(input))
+            {
+                return Ok(Self::Phone(phone));
+            }
+        }
+
+        if input.is_empty() || !field.map(|field| field.validate(input)).unwrap_or(true) {
+            return Err(InvalidAccountId(input.to_string()));
+        }
+
+        Ok(Self::Raw(input.to_string()))
+    }
+
+    /// Formats the account the way the provider expects it in `fields.account`.
+    ///
+    /// QIWI wallets are addressed by full international number, carriers by national number only.
+    pub fn format_for(&self, provider: ProviderId) -> String {
+        match self {
+            Self::Phone(phone) if provider == ProviderId::QIWI => {
+                QiwiUser(phone.clone()).to_string()
+            }
+            Self::Phone(phone) => phone.national().to_string(),
+            Self::Raw(v) => v.clone(),
+        }
+    }
+}
+
+/// Account in a form that does not depend on how it was written,
+/// e.g. `+7 (999) 123-45-67` in a request and `89991234567` in payment history.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NormalizedAccount {
+    /// International number without the leading `+`.
+    Phone(String),
+    /// Card number, possibly masked as in payment history, identified by its first and last four digits.
+    Card {
+        first: String,
+        last: String,
+    },
+    /// Lowercase nickname without the leading `@`.
+    Nickname(String),
+    Other(String),
+}
+
+impl NormalizedAccount {
+    /// Normalizes `input`, reading national phone numbers in the format of `region`.
+    pub fn new(input: &str, region: Region) -> Self {
+        let compact = input
+            .chars()
+            .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '(' | ')'))
+            .collect::<String>();
+
+        if compact.starts_with('@') {
+            return Self::Nickname(compact[1..].to_lowercase());
+        }
+
+        let digits = compact.bytes().filter(u8::is_ascii_digit).count();
+        if compact.bytes().all(|b| b.is_ascii_digit() || b == b'*')
+            && digits >= 8
+            && (compact.contains('*') || (13..=19).contains(&digits))
+        {
+            return Self::Card {
+                first: compact[..4].to_string(),
+                last: compact[compact.len() - 4..].to_string(),
+            };
+        }
+
+        if let Some(phone) = QiwiUser::parse(&compact)
+            .filter(|_| compact.starts_with('+'))
+            .map(|user| user.0)
+            .or_else(|| region.parse_phone(&compact))
+        {
+            return Self::Phone(QiwiUser(phone).to_string());
+        }
+
+        if compact.chars().any(char::is_alphabetic) {
+            return Self::Nickname(compact.to_lowercase());
+        }
+
+        Self::Other(compact)
+    }
+}
+
+impl From<&PhoneNumber> for NormalizedAccount {
+    fn from(phone: &PhoneNumber) -> Self {
+        Self::Phone(QiwiUser(phone.clone()).to_string())
+    }
+}
+
+/// Bank card number with a valid Luhn checksum, masked when debug-printed.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct CardNumber(String);
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "invalid card number")]
+pub struct InvalidCardNumber;
+
+impl std::error::Error for InvalidCardNumber {}
+
+impl CardNumber {
+    /// Digits of the number.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn last_digits(&self) -> &str {
+        &self.0[self.0.len() - 4..]
+    }
+
+    /// Payment system by the number's prefix, if it is one of the known ones.
+    pub fn network(&self) -> Option<CardNetwork> {
+        let prefix = self.0[..4].parse::<u16>().ok()?;
+        match prefix {
+            2200..=2204 => Some(CardNetwork::Mir),
+            2221..=2720 | 5100..=5599 => Some(CardNetwork::Mastercard),
+            4000..=4999 => Some(CardNetwork::Visa),
+            _ => None,
+        }
+    }
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let sum = digits
+        .bytes()
+        .rev()
+        .map(|b| u32::from(b - b'0'))
+        .enumerate()
+        .map(|(i, d)| match d * 2 {
+            doubled if i % 2 == 1 && doubled > 9 => doubled - 9,
+            doubled if i % 2 == 1 => doubled,
+            _ => d,
+        })
+        .sum::<u32>();
+    sum % 10 == 0
+}
+
+/// Accepts 13 to 19 digits, optionally separated with spaces or dashes.
+impl FromStr for CardNumber {
+    type Err = InvalidCardNumber;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-'))
+            .collect::<String>();
+        if (13..=19).contains(&digits.len())
+            && digits.bytes().all(|b| b.is_ascii_digit())
+            && luhn_valid(&digits)
+        {
+            Ok(Self(digits))
+        } else {
+            Err(InvalidCardNumber)
+        }
+    }
+}
+
+impl Debug for CardNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CardNumber")
+            .field(&mask_card_number(&self.0))
+            .finish()
+    }
+}
+
+/// Result of provider detection by card or phone number, see `Client::card_provider` and
+/// `Client::detect_mobile_provider`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProviderDetection {
+    pub code: ProviderDetectionCode,
+    /// Provider id if detected, otherwise the reason.
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProviderDetectionCode {
+    /// `0` if the provider was detected.
+    pub value: String,
+}
+
+impl ProviderDetection {
+    pub fn provider(&self) -> Option<ProviderId> {
+        match self.code.value.as_str() {
+            "0" => self.message.parse::<u64>().ok().map(ProviderId),
+            _ => None,
+        }
+    }
+}
+
+/// Requisites of a transfer to a Russian bank account, sent as SINAP payment fields.
+///
+/// Banks accept different sets of fields, those required by all of them are checked by
+/// [`BankTransferFields::missing`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BankTransferFields {
+    /// Account number.
+    pub account: Option<String>,
+    /// BIK of the bank.
+    pub mfo: Option<String>,
+    /// `1` for an account number.
+    pub account_type: Option<String>,
+    /// Last name of the recipient.
+    pub lname: Option<String>,
+    /// First name of the recipient.
+    pub fname: Option<String>,
+    /// Middle name of the recipient.
+    pub mname: Option<String>,
+    /// Fields of specific banks, e.g. `urgent`.
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "missing bank transfer fields: {}", "_0.join(\", \")")]
+pub struct MissingBankFields(pub Vec<&'static str>);
+
+impl std::error::Error for MissingBankFields {}
+
+impl BankTransferFields {
+    /// Names of the fields reported by [`MissingBankFields`].
+    pub const MANDATORY_FIELDS: [&'static str; 6] =
+        ["account", "mfo", "account_type", "lname", "fname", "mname"];
+
+    /// Fields of an account number transfer, `account_type` is `1`.
+    pub fn new<A: Into<String>, M: Into<String>>(account: A, mfo: M) -> Self {
+        Self {
+            account: Some(account.into()),
+            mfo: Some(mfo.into()),
+            account_type: Some("1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn account_type<T: Into<String>>(mut self, account_type: T) -> Self {
+        self.account_type = Some(account_type.into());
+        self
+    }
+
+    pub fn name<L, F, M>(mut self, lname: L, fname: F, mname: M) -> Self
+    where
+        L: Into<String>,
+        F: Into<String>,
+        M: Into<String>,
+    {
+        self.lname = Some(lname.into());
+        self.fname = Some(fname.into());
+        self.mname = Some(mname.into());
+        self
+    }
+
+    pub fn extra<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.extra.insert(name.into(), value.into());
+        self
+    }
+
+    fn mandatory(&self) -> [(&'static str, &Option<String>); 6] {
+        let [account, mfo, account_type, lname, fname, mname] = Self::MANDATORY_FIELDS;
+        [
+            (account, &self.account),
+            (mfo, &self.mfo),
+            (account_type, &self.account_type),
+            (lname, &self.lname),
+            (fname, &self.fname),
+            (mname, &self.mname),
+        ]
+    }
+
+    /// Fails with the names of mandatory fields that are not set or empty.
+    pub fn missing(&self) -> Result<(), MissingBankFields> {
+        let missing = self
+            .mandatory()
+            .iter()
+            .filter(|(_, value)| value.as_deref().map_or(true, |v| v.trim().is_empty()))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingBankFields(missing))
+        }
+    }
+
+    /// Fields of the payment body.
+    pub fn to_fields(&self) -> BTreeMap<String, String> {
+        let mut fields = self.extra.clone();
+        for (name, value) in self.mandatory().iter() {
+            if let Some(value) = value {
+                fields.insert(name.to_string(), value.clone());
+            }
+        }
+        fields
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum TransferDirection {
+    Qiwi {
+        to_phone: PhoneNumber,
+        to_currency: penny::Currency,
+    },
+    Cellular {
+        carrier: u64,
+        to_phone: PhoneNumber,
+    },
+    /// Phone account with the carrier detected by the client.
+    CellularAuto {
+        to_phone: PhoneNumber,
+    },
+    /// Bank card, in rubles.
+    Card {
+        pan: CardNumber,
+        /// Detected by the client from the number if not set.
+        provider: Option<ProviderId>,
+    },
+    /// Bank account by requisites, in rubles, through the bank's provider, e.g. 1466.
+    BankAccount {
+        provider: ProviderId,
+        fields: BankTransferFields,
+    },
+}
+
+impl TransferDirection {
+    /// Card by number, with the provider detected when the transfer is sent.
+    pub fn card(pan: CardNumber) -> Self {
+        Self::Card {
+            pan,
+            provider: None,
+        }
+    }
+
+    /// Provider to send the transfer to, unknown for cards until it is detected.
+    pub fn provider(&self) -> Option<ProviderId> {
+        match self {
+            Self::Qiwi { .. } => Some(ProviderId::QIWI),
+            Self::Cellular { carrier, .. } => Some(ProviderId(*carrier)),
+            Self::Card { provider, .. } => *provider,
+            Self::BankAccount { provider, .. } => Some(*provider),
+            Self::CellularAuto { .. } => None,
+        }
+    }
+
+    pub fn currency(&self) -> penny::Currency {
+        match self {
+            Self::Qiwi { to_currency, .. } => *to_currency,
+            Self::Cellular { .. }
+            | Self::CellularAuto { .. }
+            | Self::Card { .. }
+            | Self::BankAccount { .. } => penny::Currency::RUB,
+        }
+    }
+
+    pub fn phone(&self) -> Option<&PhoneNumber> {
+        match self {
+            Self::Qiwi { to_phone, .. }
+            | Self::Cellular { to_phone, .. }
+            | Self::CellularAuto { to_phone } => Some(to_phone),
+            Self::Card { .. } | Self::BankAccount { .. } => None,
+        }
+    }
+
+    pub fn account_id(&self) -> AccountId {
+        match self {
+            Self::Qiwi { to_phone, .. }
+            | Self::Cellular { to_phone, .. }
+            | Self::CellularAuto { to_phone } => AccountId::Phone(to_phone.clone()),
+            Self::Card { pan, .. } => AccountId::Raw(pan.as_str().to_string()),
+            Self::BankAccount { fields, .. } => {
+                AccountId::Raw(fields.account.clone().unwrap_or_default())
+            }
+        }
+    }
+
+    pub fn normalized_account(&self) -> NormalizedAccount {
+        match self {
+            Self::Qiwi { to_phone, .. }
+            | Self::Cellular { to_phone, .. }
+            | Self::CellularAuto { to_phone } => to_phone.into(),
+            Self::Card { pan, .. } => NormalizedAccount::Card {
+                first: pan.as_str()[..4].to_string(),
+                last: pan.last_digits().to_string(),
+            },
+            Self::BankAccount { fields, .. } => {
+                NormalizedAccount::Other(fields.account.clone().unwrap_or_default())
+            }
+        }
+    }
+
+    /// Fields of the payment body sent to `provider`.
+    pub fn payment_fields(&self, provider: ProviderId) -> BTreeMap<String, String> {
+        // Wallets are addressed the same way whatever their provider id is mapped to.
+        let format = match self {
+            Self::Qiwi { .. } => ProviderId::QIWI,
+            Self::BankAccount { fields, .. } => return fields.to_fields(),
+            _ => provider,
+        };
+        let mut fields = BTreeMap::new();
+        fields.insert("account".to_string(), self.account_id().format_for(format));
+        fields
+    }
+
+    /// Fails if the direction is known to be rejected, e.g. a bank account transfer without mandatory fields.
+    pub fn validate(&self) -> Result<(), MissingBankFields> {
+        match self {
+            Self::BankAccount { fields, .. } => fields.missing(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Generates a new client-side payment id from the current time in milliseconds.
+///
+/// Clients mint ids with their own generator instead, see `Client::transfer_request`.
+pub fn new_payment_id() -> u64 {
+    u64::try_from(Utc::now().timestamp_millis()).unwrap()
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferRequest {
+    id: u64,
+    pub amount: BigDecimal,
+    pub direction: TransferDirection,
+    pub comment: String,
+    /// Balance the transfer is funded from, the default balance of the client's region if not set.
+    pub source: Option<AccountAlias>,
+    /// Send even if a similar payment was made recently, see `ClientBuilder::duplicate_guard`.
+    pub force: bool,
+}
+
+impl TransferRequest {
+    /// Creates a transfer funded from the default balance with an id from [`new_payment_id`].
+    pub fn new<T: Into<String>>(
+        amount: BigDecimal,
+        direction: TransferDirection,
+        comment: T,
+    ) -> Self {
+        Self::with_id(new_payment_id(), amount, direction, comment)
+    }
+
+    /// Creates a transfer funded from the default balance, submitted with `id`.
+    pub fn with_id<T: Into<String>>(
+        id: u64,
+        amount: BigDecimal,
+        direction: TransferDirection,
+        comment: T,
+    ) -> Self {
+        Self {
+            id,
+            amount,
+            direction,
+            comment: comment.into(),
+            source: None,
+            force: false,
+        }
+    }
+
+    pub fn id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    pub fn source(mut self, source: AccountAlias) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn force(mut self) -> Self {
+        self.force = true;
+        self
+    }
+
+    /// Id the payment is submitted with. QIWI rejects repeated payments with the same id,
+    /// and the id is reported as `trmTxnId` in payment history.
+    pub fn idempotency_id(&self) -> u64 {
+        self.id
+    }
+}
+
+/// Usage of the monthly commission-free P2P volume.
+#[derive(Clone, Debug, Serialize)]
+pub struct FreeLimitStatus {
+    pub used: Money,
+    /// Known only if the free limit was configured for the client.
+    pub remaining_estimate: Option<Money>,
+}
+
+/// Result of `onlineCommission` call.
+///
+/// Commissions may be in a different currency than the payment sum for conversion payments.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommissionQuote {
+    /// Amount debited from the wallet.
+    pub withdraw_sum: Money,
+    /// Amount credited to the recipient.
+    pub enrollment_sum: Money,
+    pub qw_commission: Money,
+    pub funding_source_commission: Money,
+    pub withdraw_to_enrollment_rate: BigDecimal,
+}
+
+/// Kind of wallet limit, see `Client::limits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum LimitType {
+    /// Top-ups of the wallet.
+    Refill,
+    /// Turnover of the wallet.
+    Turnover,
+    /// Transfers to other wallets.
+    PaymentsP2p,
+    /// Payments to foreign providers.
+    PaymentsProviderInternationals,
+    /// Transfers to bank accounts and cards.
+    PaymentsProviderPayout,
+    /// Cash withdrawals.
+    WithdrawCash,
+    #[serde(other)]
+    Unknown,
+}
+
+impl LimitType {
+    /// All documented types.
+    pub const ALL: [Self; 6] = [
+        Self::Refill,
+        Self::Turnover,
+        Self::PaymentsP2p,
+        Self::PaymentsProviderInternationals,
+        Self::PaymentsProviderPayout,
+        Self::WithdrawCash,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Refill => "REFILL",
+            Self::Turnover => "TURNOVER",
+            Self::PaymentsP2p => "PAYMENTS_P2P",
+            Self::PaymentsProviderInternationals => "PAYMENTS_PROVIDER_INTERNATIONALS",
+            Self::PaymentsProviderPayout => "PAYMENTS_PROVIDER_PAYOUT",
+            Self::WithdrawCash => "WITHDRAW_CASH",
+            Self::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Period a limit applies to, in Moscow time as reported, e.g. `2020-01-01 00:00:00`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitInterval {
+    pub date_from: String,
+    pub date_till: String,
+}
+
+/// Wallet limit of one type in one currency.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Limit {
+    #[serde(rename = "type")]
+    pub limit_type: LimitType,
+    pub currency: QiwiCurrency,
+    /// Absent for unlimited operations.
+    #[serde(default)]
+    pub max: Option<BigDecimal>,
+    #[serde(default)]
+    pub spent: BigDecimal,
+    pub rest: BigDecimal,
+    #[serde(default)]
+    pub interval: Option<LimitInterval>,
+}
+
+/// Wallet limits grouped by currency.
+///
+/// Serialized as a map from numeric currency code to the limits in that currency.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Limits {
+    by_currency: BTreeMap<String, Vec<Limit>>,
+}
+
+impl Limits {
+    pub fn new<I: IntoIterator<Item = Limit>>(limits: I) -> Self {
+        let mut by_currency = BTreeMap::<_, Vec<_>>::new();
+        for limit in limits {
+            by_currency
+                .entry(limit.currency.to_string())
+                .or_default()
+                .push(limit);
+        }
+        Self { by_currency }
+    }
+
+    pub fn currency(&self, currency: &QiwiCurrency) -> &[Limit] {
+        self.by_currency
+            .get(&currency.to_string())
+            .map_or(&[][..], Vec::as_slice)
+    }
+
+    pub fn get(&self, limit_type: LimitType, currency: &QiwiCurrency) -> Option<&Limit> {
+        self.currency(currency)
+            .iter()
+            .find(|limit| limit.limit_type == limit_type)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Limit> {
+        self.by_currency.values().flatten()
+    }
+}
+
+/// Value kept out of `Debug` output, e.g. a full card number.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(secret: T) -> Self {
+        Self(secret)
+    }
+
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[non_exhaustive]
+pub enum CardStatus {
+    Active,
+    Blocked,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Kind of QIWI card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+#[non_exhaustive]
+pub enum QiwiCardType {
+    /// Virtual card.
+    Qvc,
+    /// Plastic card.
+    Qvp,
+    /// Virtual card for a single purchase.
+    Qvv,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Id of a QIWI card, see [`CardInfo::id`].
+#[derive(
+    Clone, Copy, Debug, Display, PartialEq, Eq, Hash, From, FromStr, Serialize, Deserialize,
+)]
+pub struct CardId(u64);
+
+/// Card as listed by `cards/v1/cards`, where it is split into `qvx`, `info` and `balance` objects.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", from = "CardRecord")]
+pub struct CardInfo {
+    pub id: CardId,
+    /// E.g. `533669******0901`.
+    pub masked_pan: String,
+    pub status: CardStatus,
+    /// Product alias, e.g. `qvc-cpa`.
+    pub alias: Option<String>,
+    pub card_type: QiwiCardType,
+    pub expiry: Option<DateTime<FixedOffset>>,
+    /// Balance the card spends from.
+    pub account: Option<AccountAlias>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardQvx {
+    id: CardId,
+    masked_pan: String,
+    status: CardStatus,
+    card_alias: QiwiCardType,
+    #[serde(default)]
+    card_expire: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Deserialize)]
+struct CardProductInfo {
+    #[serde(default)]
+    alias: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CardRecord {
+    qvx: CardQvx,
+    #[serde(default)]
+    info: Option<CardProductInfo>,
+    /// Shape is not documented, only the alias is used.
+    #[serde(default)]
+    balance: Option<Value>,
+}
+
+impl From<CardRecord> for CardInfo {
+    fn from(record: CardRecord) -> Self {
+        Self {
+            id: record.qvx.id,
+            masked_pan: record.qvx.masked_pan,
+            status: record.qvx.status,
+            alias: record.info.and_then(|info| info.alias),
+            card_type: record.qvx.card_alias,
+            expiry: record.qvx.card_expire,
+            account: record
+                .balance
+                .as_ref()
+                .and_then(|balance| balance.get("alias")?.as_str()?.parse().ok()),
+        }
+    }
+}
+
+/// Full requisites of a QIWI card.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardRequisites {
+    pub pan: Secret<String>,
+    pub cvv: Secret<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Result of blocking or unblocking a card.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardActionResult {
+    pub status: String,
+    /// Set when QIWI asks to confirm the action, the shape is not documented.
+    #[serde(default)]
+    pub next_confirmation_request: Option<Value>,
+}
+
+/// Status of a [`CardOrder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum CardOrderStatus {
+    /// Created, not yet confirmed.
+    Draft,
+    /// Confirmed, the card is issued once the order is paid.
+    PaymentRequired,
+    Completed,
+    #[serde(other)]
+    Unknown,
+}
+
+/// Order of a QIWI card, as listed by `cards/v2/persons/{personId}/orders`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CardOrder {
+    pub id: String,
+    /// Product ordered, e.g. `qvc-cpa`.
+    pub card_alias: String,
+    pub status: CardOrderStatus,
+    /// Set once the order is confirmed, zero for free cards.
+    #[serde(default)]
+    pub price: Option<Money>,
+    /// Set once the card is issued.
+    #[serde(default)]
+    pub card_id: Option<CardId>,
+}
+
+/// Restriction on the wallet's operations.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Restriction {
+    pub restriction_code: String,
+    pub restriction_description: String,
+}
+
+/// Exchange rate between wallet currencies, as listed by `sinap/crossRates`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrossRate {
+    pub from: QiwiCurrency,
+    pub to: QiwiCurrency,
+    /// Units of `to` per unit of `from`.
+    pub rate: BigDecimal,
+}
+
+impl CrossRate {
+    /// Units of `to` per unit of `from`, using the reverse rate if only that one is listed.
+    pub fn find(rates: &[Self], from: &QiwiCurrency, to: &QiwiCurrency) -> Option<BigDecimal> {
+        rates
+            .iter()
+            .find(|rate| rate.from == *from && rate.to == *to)
+            .map(|rate| rate.rate.clone())
+            .or_else(|| {
+                rates
+                    .iter()
+                    .find(|rate| {
+                        rate.from == *to && rate.to == *from && rate.rate != BigDecimal::from(0)
+                    })
+                    .map(|rate| BigDecimal::from(1) / &rate.rate)
+            })
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TransferQuote {
+    pub commission: CommissionQuote,
+    /// Present for transfers to QIWI wallets only.
+    pub free_limit: Option<FreeLimitStatus>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferState {
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferTransactionData {
+    pub id: String,
+    pub state: TransferState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferData {
+    pub transaction: TransferTransactionData,
+    pub source: Option<AccountAlias>,
+}
+
+/// Which transactions a webhook is notified about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookTxnType {
+    #[serde(rename = "IN")]
+    Incoming,
+    #[serde(rename = "OUT")]
+    Outgoing,
+    #[serde(rename = "BOTH")]
+    Both,
+}
+
+impl WebhookTxnType {
+    /// Numeric code used when registering a webhook.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Incoming => 0,
+            Self::Outgoing => 1,
+            Self::Both => 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookParameters {
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookInfo {
+    pub hook_id: String,
+    pub hook_type: String,
+    pub txn_type: WebhookTxnType,
+    pub hook_parameters: WebhookParameters,
+}
+
+impl WebhookInfo {
+    pub fn url(&self) -> &str {
+        &self.hook_parameters.url
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookKey {
+    pub key: String,
+}
+
+/// Payment in a webhook notification.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPaymentData {
+    pub txn_id: String,
+    #[serde(default)]
+    pub date: Option<DateTime<FixedOffset>>,
+    #[serde(rename = "type")]
+    pub payment_type: PaymentType,
+    #[serde(default)]
+    pub status: Option<PaymentStatus>,
+    #[serde(default)]
+    pub error_code: Option<String>,
+    #[serde(default)]
+    pub person_id: Option<u64>,
+    pub account: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub provider: Option<u64>,
+    pub sum: Money,
+    #[serde(default)]
+    pub commission: Option<Money>,
+    #[serde(default)]
+    pub total: Option<Money>,
+    /// Comma separated fields covered by the hash, e.g. `sum.currency,sum.amount,type,account,txnId`.
+    #[serde(default)]
+    pub sign_fields: Option<String>,
+}
+
+/// Notification QIWI sends to a webhook.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPayment {
+    pub message_id: String,
+    pub hook_id: String,
+    /// Absent in test notifications.
+    #[serde(default)]
+    pub payment: Option<WebhookPaymentData>,
+    /// Hex HMAC-SHA256 of the signed fields, see `verify_webhook_signature`.
+    #[serde(default)]
+    pub hash: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub test: bool,
+}
+
+/// Field of a webhook notification that differs from the history entry.
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldMismatch {
+    /// E.g. `sum.amount`.
+    pub field: &'static str,
+    pub webhook: String,
+    pub history: String,
+}
+
+/// Result of [`WebhookPayment::matches_entry`].
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MatchReport {
+    pub mismatches: Vec<FieldMismatch>,
+}
+
+impl MatchReport {
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    fn check<W: fmt::Display, H: fmt::Display>(
+        &mut self,
+        field: &'static str,
+        matches: bool,
+        webhook: W,
+        history: H,
+    ) {
+        if !matches {
+            self.mismatches.push(FieldMismatch {
+                field,
+                webhook: webhook.to_string(),
+                history: history.to_string(),
+            });
+        }
+    }
+
+    fn check_money(
+        &mut self,
+        field: (&'static str, &'static str),
+        webhook: &Money,
+        history: &PaymentSumData,
+        tolerance: &BigDecimal,
+    ) {
+        let currency = QiwiCurrency::from_str(&history.currency).ok();
+        self.check(
+            field.0,
+            (&webhook.amount - &history.amount).abs() <= *tolerance,
+            &webhook.amount,
+            &history.amount,
+        );
+        self.check(
+            field.1,
+            currency.as_ref() == Some(&webhook.currency),
+            &webhook.currency,
+            &history.currency,
+        );
+    }
+}
+
+impl WebhookPayment {
+    /// Amounts are allowed to differ by a kopeck, see [`WebhookPayment::matches_entry_within`].
+    pub fn matches_entry(&self, entry: &PaymentHistoryEntry) -> MatchReport {
+        self.matches_entry_within(entry, &BigDecimal::new(1.into(), 2))
+    }
+
+    /// Compares the notification with the history entry of the same transaction.
+    ///
+    /// Amounts differing by at most `tolerance` match, since webhooks and history may round them
+    /// differently. Commission, total and status are compared if the notification has them.
+    pub fn matches_entry_within(
+        &self,
+        entry: &PaymentHistoryEntry,
+        tolerance: &BigDecimal,
+    ) -> MatchReport {
+        let mut report = MatchReport::default();
+        let payment = match &self.payment {
+            Some(payment) => payment,
+            None => {
+                report.check("payment", false, "none", entry.txn_id);
+                return report;
+            }
+        };
+
+        report.check(
+            "txnId",
+            payment.txn_id == entry.txn_id.to_string(),
+            &payment.txn_id,
+            entry.txn_id,
+        );
+        report.check(
+            "type",
+            payment.payment_type == entry.payment_type,
+            format_args!("{:?}", payment.payment_type),
+            format_args!("{:?}", entry.payment_type),
+        );
+        if let Some(status) = &payment.status {
+            report.check(
+                "status",
+                *status == entry.status,
+                format_args!("{:?}", status),
+                format_args!("{:?}", entry.status),
+            );
+        }
+        report.check_money(
+            ("sum.amount", "sum.currency"),
+            &payment.sum,
+            &entry.sum,
+            tolerance,
+        );
+        if let Some(commission) = &payment.commission {
+            report.check_money(
+                ("commission.amount", "commission.currency"),
+                commission,
+                &entry.commission,
+                tolerance,
+            );
+        }
+        if let Some(total) = &payment.total {
+            report.check_money(
+                ("total.amount", "total.currency"),
+                total,
+                &entry.total,
+                tolerance,
+            );
+        }
+
+        report
+    }
+}
+
+/// Confirmation returned by webhook calls without data, e.g. `Hook deleted`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookResponse {
+    pub response: String,
+}
+
+/// Result of `Client::ensure_webhook`.
+#[derive(Clone, Debug, Serialize)]
+pub enum EnsureOutcome {
+    /// Active webhook already has the requested URL and transaction type.
+    AlreadyCorrect,
+    /// Previous webhook was deleted and the new one registered.
+    Replaced { old_url: String, key: String },
+    /// There was no webhook, the new one has been registered.
+    Created { key: String },
+}
+
+/// Period of a recurring payment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown period: {}", _0)]
+pub struct UnknownPeriod(pub String);
+
+impl std::error::Error for UnknownPeriod {}
+
+impl FromStr for Period {
+    type Err = UnknownPeriod;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Month),
+            _ => Err(UnknownPeriod(s.to_string())),
+        }
+    }
+}
+
+impl Period {
+    /// Start of the period `now` falls into, local time of the region.
+    pub fn start(self, now: DateTime<Utc>, region: Region) -> DateTime<Utc> {
+        let offset = region.utc_offset();
+        let date = now.with_timezone(&offset).date_naive();
+        let date = match self {
+            Self::Day => date,
+            Self::Week => {
+                date - chrono::Duration::days(i64::from(date.weekday().num_days_from_monday()))
+            }
+            Self::Month => date.with_day(1).unwrap(),
+        };
+
+        offset
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    /// Stable name of the period `now` falls into, e.g. `2020-01` for a month.
+    pub fn bucket(self, now: DateTime<Utc>, region: Region) -> String {
+        let local = self.start(now, region).with_timezone(&region.utc_offset());
+        match self {
+            Self::Day => local.format("%Y-%m-%d").to_string(),
+            Self::Week => {
+                let week = local.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Self::Month => local.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Document format of `Client::account_statement`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum StatementFormat {
+    Pdf,
+    Xlsx,
+}
+
+impl StatementFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pdf => "PDF",
+            Self::Xlsx => "XLSX",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown statement format: {}", _0)]
+pub struct UnknownStatementFormat(pub String);
+
+impl std::error::Error for UnknownStatementFormat {}
+
+impl FromStr for StatementFormat {
+    type Err = UnknownStatementFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pdf" => Ok(Self::Pdf),
+            "xlsx" => Ok(Self::Xlsx),
+            _ => Err(UnknownStatementFormat(s.to_string())),
+        }
+    }
+}
+
+/// Document format of `Client::cheque`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ChequeFormat {
+    Pdf,
+    Jpeg,
+}
+
+impl ChequeFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pdf => "PDF",
+            Self::Jpeg => "JPEG",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "unknown cheque format: {}", _0)]
+pub struct UnknownChequeFormat(pub String);
+
+impl std::error::Error for UnknownChequeFormat {}
+
+impl FromStr for ChequeFormat {
+    type Err = UnknownChequeFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pdf" => Ok(Self::Pdf),
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            _ => Err(UnknownChequeFormat(s.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERNET_PROVIDER: ProviderId = ProviderId(26_476);
+
+    fn is_phone(account: &AccountId, international: &str) -> bool {
+        matches!(account, AccountId::Phone(phone) if phone.to_string() == international)
+    }
+
+    #[test]
+    fn phone_accounts_for_wallets_and_carriers() {
+        for provider in &[ProviderId::QIWI, ProviderId::MTS, ProviderId::TELE2] {
+            for input in &["+79161234567", "89161234567", " 8 (916) 123-45-67 "] {
+                let account = AccountId::parse(input, *provider, None).unwrap();
+                assert!(is_phone(&account, "+79161234567"), "{:?}", account);
+            }
+        }
+
+        let account =
+            AccountId::parse_in("87011234567", ProviderId::QIWI, None, Region::Kazakhstan).unwrap();
+        assert!(is_phone(&account, "+77011234567"), "{:?}", account);
+    }
+
+    #[test]
+    fn numeric_account_of_other_provider_is_raw() {
+        for input in &["89161234567", "+79161234567", "8001"] {
+            match AccountId::parse(input, INTERNET_PROVIDER, None).unwrap() {
+                AccountId::Raw(account) => assert_eq!(account, *input),
+                other => panic!("expected raw account, got {:?}", other),
+            }
+        }
+        assert_eq!(
+            AccountId::parse("89161234567", INTERNET_PROVIDER, None)
+                .unwrap()
+                .format_for(INTERNET_PROVIDER),
+            "89161234567"
+        );
+    }
+
+    fn money(amount: &str, currency: penny::Currency) -> Money {
+        Money::new(amount.parse().unwrap(), currency)
+    }
+
+    fn usd_rub(rate: &str) -> Vec<CrossRate> {
+        vec![CrossRate {
+            from: penny::Currency::USD.into(),
+            to: penny::Currency::RUB.into(),
+            rate: rate.parse().unwrap(),
+        }]
+    }
+
+    fn assert_money(money: &Money, amount: &str, currency: penny::Currency) {
+        assert_eq!(money.currency, QiwiCurrency::from(currency));
+        assert_eq!(money.amount.to_string(), amount);
+    }
+
+    #[test]
+    fn bag_keeps_zero_sums() {
+        let mut bag = MoneyBag::new();
+        assert!(bag.is_empty());
+        assert_money(
+            &bag.get(&penny::Currency::RUB.into()),
+            "0",
+            penny::Currency::RUB,
+        );
+
+        bag.add(money("0", penny::Currency::USD));
+        bag.add(money("10.00", penny::Currency::RUB));
+        bag.add(money("-10.00", penny::Currency::RUB));
+        assert!(!bag.is_empty());
+        assert_eq!(bag.iter().count(), 2);
+
+        let total = bag
+            .convert_all(&penny::Currency::RUB.into(), &usd_rub("75"))
+            .unwrap();
+        assert_money(&total, "0.00", penny::Currency::RUB);
+    }
+
+    #[test]
+    fn bag_subtracts_refunds() {
+        let bag = vec![
+            money("100.00", penny::Currency::RUB),
+            money("-30.50", penny::Currency::RUB),
+            money("2.00", penny::Currency::USD),
+            money("-0.50", penny::Currency::USD),
+        ]
+        .into_iter()
+        .collect::<MoneyBag>();
+        assert_money(
+            &bag.get(&penny::Currency::RUB.into()),
+            "69.50",
+            penny::Currency::RUB,
+        );
+        assert_money(
+            &bag.get(&penny::Currency::USD.into()),
+            "1.50",
+            penny::Currency::USD,
+        );
+
+        let total = bag
+            .convert_all(&penny::Currency::RUB.into(), &usd_rub("75"))
+            .unwrap();
+        assert_money(&total, "182.00", penny::Currency::RUB);
+
+        let refunds = vec![money("-1.00", penny::Currency::USD)]
+            .into_iter()
+            .collect::<MoneyBag>();
+        let total = refunds
+            .convert_all(&penny::Currency::RUB.into(), &usd_rub("75.125"))
+            .unwrap();
+        assert_money(&total, "-75.13", penny::Currency::RUB);
+    }
+
+    #[test]
+    fn conversion_rounds_half_away_from_zero() {
+        let convert = |amount: &str, rate: &str| {
+            vec![money(amount, penny::Currency::USD)]
+                .into_iter()
+                .collect::<MoneyBag>()
+                .convert_all(&penny::Currency::RUB.into(), &usd_rub(rate))
+                .unwrap()
+        };
+        assert_money(&convert("1", "75.125"), "75.13", penny::Currency::RUB);
+        assert_money(&convert("1", "75.1249"), "75.12", penny::Currency::RUB);
+        assert_money(&convert("-1", "75.1249"), "-75.12", penny::Currency::RUB);
+        assert_money(&convert("0.001", "1"), "0.00", penny::Currency::RUB);
+    }
+
+    #[test]
+    fn conversion_by_reverse_rate_is_not_truncated() {
+        // 75 RUB at 1 / 75 USD per RUB is just below 1 USD.
+        let bag = vec![money("75", penny::Currency::RUB)]
+            .into_iter()
+            .collect::<MoneyBag>();
+        let total = bag
+            .convert_all(&penny::Currency::USD.into(), &usd_rub("75"))
+            .unwrap();
+        assert_money(&total, "1.00", penny::Currency::USD);
+    }
+
+    #[test]
+    fn conversion_rounds_once() {
+        let bag = vec![
+            money("0.004", penny::Currency::RUB),
+            money("0.004", penny::Currency::EUR),
+        ]
+        .into_iter()
+        .collect::<MoneyBag>();
+        let rates = vec![CrossRate {
+            from: penny::Currency::EUR.into(),
+            to: penny::Currency::RUB.into(),
+            rate: "1".parse().unwrap(),
+        }];
+        let total = bag
+            .convert_all(&penny::Currency::RUB.into(), &rates)
+            .unwrap();
+        assert_money(&total, "0.01", penny::Currency::RUB);
+
+        assert!(bag
+            .convert_all(&penny::Currency::USD.into(), &rates)
+            .is_err());
+    }
+
+    #[test]
+    fn empty_account_is_rejected() {
+        assert!(AccountId::parse("  ", INTERNET_PROVIDER, None).is_err());
+        assert!(AccountId::parse("", ProviderId::QIWI, None).is_err());
+    }
+
+    fn currency(json: &str) -> Result<QiwiCurrency, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    #[test]
+    fn currency_from_numeric_codes() {
+        for (json, expected) in &[
+            ("643", penny::Currency::RUB),
+            ("840", penny::Currency::USD),
+            ("978", penny::Currency::EUR),
+            ("398", penny::Currency::KZT),
+            ("\"643\"", penny::Currency::RUB),
+            ("\" 840 \"", penny::Currency::USD),
+        ] {
+            assert_eq!(currency(json).unwrap().currency(), *expected, "{}", json);
+        }
+        assert!(currency("0").is_err());
+        assert!(currency("\"999\"").is_err());
+        assert!(currency("70000").is_err());
+        assert!(currency("-643").is_err());
+    }
+
+    #[test]
+    fn currency_from_alphabetic_codes() {
+        for (json, expected) in &[
+            ("\"RUB\"", penny::Currency::RUB),
+            ("\"usd\"", penny::Currency::USD),
+            ("\"Eur\"", penny::Currency::EUR),
+            ("\"KZT\"", penny::Currency::KZT),
+        ] {
+            assert_eq!(currency(json).unwrap().currency(), *expected, "{}", json);
+        }
+        // Real currencies that wallets do not hold.
+        assert!(currency("\"GBP\"").is_err());
+        assert!(currency("\"\"").is_err());
+        assert!(currency("null").is_err());
+    }
+
+    #[test]
+    fn currency_is_serialized_as_numeric_code() {
+        for code in &QiwiCurrency::SUPPORTED {
+            let currency = QiwiCurrency::from(*code);
+            let json = serde_json::to_string(&currency).unwrap();
+            assert_eq!(json, format!("\"{}\"", code.info().number()));
+            assert_eq!(
+                serde_json::from_str::<QiwiCurrency>(&json).unwrap(),
+                currency
+            );
+        }
+    }
+
+    #[test]
+    fn currency_round_trips_through_penny() {
+        for (code, numeric, alpha) in &[
+            (penny::Currency::RUB, 643u16, "RUB"),
+            (penny::Currency::USD, 840, "USD"),
+            (penny::Currency::EUR, 978, "EUR"),
+            (penny::Currency::KZT, 398, "KZT"),
+        ] {
+            let currency = QiwiCurrency::from(*code);
+            assert_eq!(penny::Currency::from(currency.clone()), *code);
+            assert_eq!(currency.currency(), *code);
+            assert_eq!(currency.to_string(), numeric.to_string());
+
+            let numeric = QiwiCurrency::try_from(*numeric).unwrap();
+            let alpha = QiwiCurrency::from_alpha_code(alpha).unwrap();
+            assert_eq!(penny::Currency::from(numeric), *code);
+            assert_eq!(penny::Currency::from(alpha), *code);
+        }
+        assert!(QiwiCurrency::SUPPORTED
+            .iter()
+            .all(|code| QiwiCurrency::from(*code).currency() == *code));
+    }
+
+    #[test]
+    fn money_arithmetic_refuses_mixed_currencies() {
+        let rub = money("100.50", penny::Currency::RUB);
+        let usd = money("1.40", penny::Currency::USD);
+
+        let sum = rub
+            .checked_add(&money("0.50", penny::Currency::RUB))
+            .unwrap();
+        assert_eq!(sum.amount, "101.00".parse::<BigDecimal>().unwrap());
+        assert_eq!(sum.currency, rub.currency);
+        assert_eq!(rub.checked_sub(&rub).unwrap().amount, BigDecimal::from(0));
+        assert_eq!(
+            usd.cmp_same_currency(&money("2", penny::Currency::USD))
+                .unwrap(),
+            Ordering::Less
+        );
+
+        for result in vec![
+            rub.checked_add(&usd).map(|_| ()),
+            rub.checked_sub(&usd).map(|_| ()),
+            rub.cmp_same_currency(&usd).map(|_| ()),
+        ] {
+            let error = result.unwrap_err();
+            assert_eq!(error.left, rub.currency);
+            assert_eq!(error.right, usd.currency);
+        }
+    }
+
+    /// Same payment with its fields added in the `run`th rotation of their order.
+    fn payment_request(run: usize) -> PaymentRequest {
+        let mut names = vec!["account", "rem1", "rec_city", "mfo", "name", "account_type"];
+        names.rotate_left(run % names.len());
+        if run % 2 == 1 {
+            names.reverse();
+        }
+        PaymentRequest {
+            provider: INTERNET_PROVIDER,
+            id: "1500000000000".to_string(),
+            sum: money("350.50", penny::Currency::RUB),
+            payment_method: PaymentMethod::account(penny::Currency::RUB.into()),
+            fields: names
+                .into_iter()
+                .map(|name| (name.to_string(), format!("{}-value", name)))
+                .collect(),
+            comment: Some("Оплата".to_string()),
+        }
+    }
+
+    #[test]
+    fn payment_request_canonical_json_is_stable() {
+        let bodies = (0..100)
+            .map(|run| payment_request(run).canonical_json())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(bodies.len(), 1, "{:?}", bodies);
+        assert_eq!(
+            bodies.into_iter().next().unwrap(),
+            concat!(
+                r#"{"id":"1500000000000","sum":{"amount":"350.50","currency":"643"},"#,
+                r#""paymentMethod":{"type":"Account","accountId":"643"},"#,
+                r#""fields":{"account":"account-value","account_type":"account_type-value","#,
+                r#""mfo":"mfo-value","name":"name-value","rec_city":"rec_city-value","#,
+                r#""rem1":"rem1-value"},"comment":"Оплата"}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn default_providers() {
+        let defaults = ProviderMap::default();
+        for (route, provider) in &[
+            (TransferRoute::Wallet, Some(ProviderId::QIWI)),
+            (TransferRoute::CardDetection, Some(ProviderId::OTHER_BANK)),
+            (TransferRoute::Card(CardNetwork::Visa), None),
+            (TransferRoute::Card(CardNetwork::Mastercard), None),
+            (TransferRoute::Card(CardNetwork::Mir), None),
+        ] {
+            assert_eq!(defaults.get(*route), *provider, "{}", route);
+        }
+        assert_eq!(defaults.iter().count(), 2);
+        assert_eq!(ProviderId::QIWI, ProviderId(99));
+        assert_eq!(ProviderId::OTHER_BANK, ProviderId(1717));
+    }
+
+    #[test]
+    fn transfer_route_names() {
+        for (route, name) in &[
+            (TransferRoute::Wallet, "wallet"),
+            (TransferRoute::CardDetection, "card-detection"),
+            (TransferRoute::Card(CardNetwork::Visa), "card.visa"),
+            (
+                TransferRoute::Card(CardNetwork::Mastercard),
+                "card.mastercard",
+            ),
+            (TransferRoute::Card(CardNetwork::Mir), "card.mir"),
+        ] {
+            assert_eq!(route.to_string(), *name);
+            assert_eq!(name.parse::<TransferRoute>().unwrap(), *route);
+        }
+        assert!("card.amex".parse::<TransferRoute>().is_err());
+        assert!("Wallet".parse::<TransferRoute>().is_err());
+    }
+
+    #[test]
+    fn provider_overrides_from_config() {
+        let overrides: ProviderMap =
+            serde_json::from_str(r#"{"card.visa": 1963, "wallet": 100}"#).unwrap();
+        let providers = ProviderMap::default().merge(&overrides);
+        assert_eq!(providers.get(TransferRoute::Wallet), Some(ProviderId(100)));
+        assert_eq!(
+            providers.get(TransferRoute::Card(CardNetwork::Visa)),
+            Some(ProviderId::VISA_RU)
+        );
+        assert_eq!(
+            providers.get(TransferRoute::CardDetection),
+            Some(ProviderId::OTHER_BANK)
+        );
+        assert_eq!(
+            serde_json::to_string(&providers).unwrap(),
+            r#"{"wallet":100,"card-detection":1717,"card.visa":1963}"#
+        );
+
+        let removed = providers.remove(TransferRoute::Wallet);
+        assert_eq!(removed.get(TransferRoute::Wallet), None);
+        assert!(serde_json::from_str::<ProviderMap>(r#"{"card.amex": 1}"#).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Verification of webhook notifications, without the client.
+
+use {
+    crate::*,
+    bigdecimal::{BigDecimal, ToPrimitive},
+    derive_more::Display,
+    hmac::{Hmac, Mac, NewMac},
+};
+
+/// Fields signed by QIWI when the notification does not list them.
+pub const DEFAULT_SIGN_FIELDS: &str = "sum.currency,sum.amount,type,account,txnId";
+
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "webhook key is not valid base64: {}", _0)]
+pub struct InvalidWebhookKey(pub base64::DecodeError);
+
+impl std::error::Error for InvalidWebhookKey {}
+
+/// Amounts enter the hash with two decimal places, e.g. `1.00` for `1` or `1.0` in the notification.
+///
+/// Notifications carry amounts as floating point numbers, so they are rounded rather than
+/// written the way they were parsed.
+fn sign_amount(amount: &BigDecimal) -> String {
+    format!("{:.2}", amount.to_f64().unwrap_or_default())
+}
+
+/// Value of a signed field as it enters the hash, e.g. `sum.amount` or `txnId`.
+///
+/// `None` if the field is unknown or absent from the notification.
+pub fn sign_field(payment: &WebhookPaymentData, field: &str) -> Option<String> {
+    let money = |money: &Option<Money>, part: &str| {
+        money.as_ref().and_then(|money| match part {
+            "amount" => Some(sign_amount(&money.amount)),
+            "currency" => Some(money.currency.to_string()),
+            _ => None,
+        })
+    };
+
+    Some(match field {
+        "sum.amount" => sign_amount(&payment.sum.amount),
+        "sum.currency" => payment.sum.currency.to_string(),
+        "type" => match payment.payment_type {
+            PaymentType::In => "IN",
+            PaymentType::Out => "OUT",
+            PaymentType::QiwiCard => "QIWI_CARD",
+        }
+        .to_string(),
+        "account" => payment.account.clone(),
+        "txnId" => payment.txn_id.clone(),
+        "personId" => payment.person_id?.to_string(),
+        "provider" => payment.provider?.to_string(),
+        "comment" => payment.comment.clone()?,
+        "errorCode" => payment.error_code.clone()?,
+        _ => {
+            let (object, part) = field.split_at(field.find('.')?);
+            match object {
+                "commission" => money(&payment.commission, &part[1..])?,
+                "total" => money(&payment.total, &part[1..])?,
+                _ => return None,
+            }
+        }
+    })
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks that `payload` was signed with the webhook key, see `Client::webhook_key`.
+///
+/// The hash is HMAC-SHA256 of the signed fields joined with `|`, e.g.
+/// `{currency}|{amount}|{type}|{account}|{txnId}`, keyed with the base64 decoded key.
+/// Notifications without payment, such as test ones, never verify.
+pub fn verify_webhook_signature(
+    payload: &WebhookPayment,
+    base64_key: &str,
+) -> Result<bool, InvalidWebhookKey> {
+    let key = base64::decode(base64_key).map_err(InvalidWebhookKey)?;
+    let payment = match &payload.payment {
+        Some(payment) => payment,
+        None => return Ok(false),
+    };
+    let fields = payment
+        .sign_fields
+        .as_deref()
+        .filter(|fields| !fields.is_empty())
+        .unwrap_or(DEFAULT_SIGN_FIELDS);
+    let values = fields
+        .split(',')
+        .map(|field| sign_field(payment, field.trim()))
+        .collect::<Option<Vec<_>>>();
+    let (values, hash) = match (values, decode_hex(&payload.hash)) {
+        (Some(values), Some(hash)) => (values, hash),
+        _ => return Ok(false),
+    };
+
+    let mut mac = Hmac::<sha2::Sha256>::new_varkey(&key).expect("HMAC accepts keys of any length");
+    mac.update(values.join("|").as_bytes());
+    Ok(mac.verify(&hash).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Known vector: the key and payload below, with the default fields, sign
+    /// `643|1.00|IN|+78008000600|13353941550`.
+    const KEY: &str = "JcyVhjHCvHQwufz+IHXolyqHgEc5MoayBfParl6Guoc=";
+    const HASH: &str = "74751061c1fe21fd4f9407bb66461ba96c2f4d8e540101476805537232f3e646";
+
+    fn payload(amount: &str, hash: &str) -> WebhookPayment {
+        serde_json::from_str(&format!(
+            r#"{{
+                "messageId": "7814c49d-2d29-4b14-b2dc-36b377c76156",
+                "hookId": "5e2027d1-f5f3-4ad1-b409-058b8b8a8c22",
+                "payment": {{
+                    "txnId": "13353941550",
+                    "date": "2018-06-27T13:39:00+03:00",
+                    "type": "IN",
+                    "status": "SUCCESS",
+                    "errorCode": "0",
+                    "personId": 78000008000,
+                    "account": "+78008000600",
+                    "comment": "",
+                    "provider": 7,
+                    "sum": {{ "amount": {}, "currency": 643 }},
+                    "commission": {{ "amount": 0, "currency": 643 }},
+                    "total": {{ "amount": {}, "currency": 643 }},
+                    "signFields": "sum.currency,sum.amount,type,account,txnId"
+                }},
+                "hash": "{}",
+                "version": "1.0.0",
+                "test": false
+            }}"#,
+            amount, amount, hash
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn known_vector() {
+        let payload = payload("1", HASH);
+        let payment = payload.payment.as_ref().unwrap();
+        assert_eq!(sign_field(payment, "sum.amount").unwrap(), "1.00");
+        assert_eq!(sign_field(payment, "sum.currency").unwrap(), "643");
+        assert!(verify_webhook_signature(&payload, KEY).unwrap());
+    }
+
+    #[test]
+    fn amount_formatting_is_pinned() {
+        for (amount, signed) in &[
+            ("1", "1.00"),
+            ("1.0", "1.00"),
+            ("1.00", "1.00"),
+            ("0.29", "0.29"),
+            ("10.5", "10.50"),
+            ("1.0000001", "1.00"),
+            ("12345.67", "12345.67"),
+        ] {
+            let payload = payload(amount, HASH);
+            let payment = payload.payment.as_ref().unwrap();
+            assert_eq!(
+                sign_field(payment, "sum.amount").unwrap(),
+                *signed,
+                "{}",
+                amount
+            );
+            assert_eq!(
+                sign_field(payment, "total.amount").unwrap(),
+                *signed,
+                "{}",
+                amount
+            );
+        }
+
+        // Same amount written differently verifies with the same hash.
+        assert!(verify_webhook_signature(&payload("1.0", HASH), KEY).unwrap());
+        assert!(!verify_webhook_signature(&payload("1.01", HASH), KEY).unwrap());
+    }
+
+    #[test]
+    fn invalid_key() {
+        assert!(verify_webhook_signature(&payload("1", HASH), "not base64!").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! Known confusing history entries and how they change the balance.
+
+use {
+    bigdecimal::BigDecimal,
+    qiwi_types::{Direction, PaymentHistoryEntry, QiwiCurrency},
+    serde_json::Value,
+    std::str::FromStr,
+};
+
+const CASES: &str = include_str!("data/history-signs.json");
+
+#[test]
+fn signed_amounts() {
+    let cases = serde_json::from_str::<Vec<Value>>(CASES).unwrap();
+    assert!(!cases.is_empty());
+
+    for case in cases {
+        let name = case["name"].as_str().unwrap();
+        let entry = serde_json::from_value::<PaymentHistoryEntry>(case["entry"].clone())
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+        let direction = match case["direction"].as_str().unwrap() {
+            "IN" => Direction::In,
+            _ => Direction::Out,
+        };
+        assert_eq!(entry.direction(), direction, "{}", name);
+
+        let expected = &case["signedAmount"];
+        let signed = entry.signed_amount().unwrap();
+        assert_eq!(
+            signed.amount,
+            BigDecimal::from_str(expected["amount"].as_str().unwrap()).unwrap(),
+            "{}",
+            name
+        );
+        assert_eq!(
+            signed.currency,
+            expected["currency"]
+                .as_str()
+                .unwrap()
+                .parse::<QiwiCurrency>()
+                .unwrap(),
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! Signature corpus shared with the `qiwi` crate, which has to verify it identically.
+#![cfg(feature = "webhooks")]
+
+use {
+    qiwi_types::{signature::*, WebhookPayment},
+    serde_json::Value,
+};
+
+const CORPUS: &str = include_str!("data/webhook-signatures.json");
+
+#[test]
+fn corpus() {
+    let cases = serde_json::from_str::<Vec<Value>>(CORPUS).unwrap();
+    assert!(!cases.is_empty());
+
+    for case in cases {
+        let name = case["name"].as_str().unwrap();
+        let payload = serde_json::from_value::<WebhookPayment>(case["payload"].clone())
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+        let outcome = match verify_webhook_signature(&payload, case["key"].as_str().unwrap()) {
+            Ok(true) => "valid",
+            Ok(false) => "invalid",
+            Err(_) => "invalid-key",
+        };
+        assert_eq!(outcome, case["expected"], "{}", name);
+
+        if let (Some(signed), Some(payment)) = (case["signed"].as_str(), &payload.payment) {
+            let fields = payment
+                .sign_fields
+                .as_deref()
+                .unwrap_or(DEFAULT_SIGN_FIELDS);
+            let values = fields
+                .split(',')
+                .map(|field| sign_field(payment, field.trim()).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(values.join("|"), signed, "{}", name);
+        }
+    }
+}
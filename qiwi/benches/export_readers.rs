@@ -0,0 +1,165 @@
+//! Reads generated NDJSON and CSV exports of growing size, checking that memory use stays the same.
+//!
+//! Run with `cargo bench -p qiwi --bench export_readers --features test-util`.
+
+use {
+    qiwi::{
+        export::{self, CsvReader, NdjsonReader},
+        fixtures, PaymentHistoryEntry,
+    },
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        io::{self, BufReader, Read},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    },
+};
+
+/// System allocator keeping track of the bytes in use and their peak.
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(in_use, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Export of a header and `lines` copies of one entry, produced as it is read rather than held
+/// in memory.
+struct Export {
+    head: io::Cursor<Vec<u8>>,
+    line: Vec<u8>,
+    pos: usize,
+    lines: usize,
+}
+
+impl Read for Export {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.head.read(buf)?;
+        if n > 0 || self.lines == 0 {
+            return Ok(n);
+        }
+        let n = (self.line.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        if self.pos == self.line.len() {
+            self.pos = 0;
+            self.lines -= 1;
+        }
+        Ok(n)
+    }
+}
+
+/// Export format, with the header and the line of one entry.
+struct Format {
+    name: &'static str,
+    head: Vec<u8>,
+    line: Vec<u8>,
+    read: fn(Export) -> usize,
+}
+
+fn ndjson(entry: &PaymentHistoryEntry) -> Format {
+    let mut line = serde_json::to_vec(entry).unwrap();
+    line.push(b'\n');
+    Format {
+        name: "NDJSON",
+        head: Vec::new(),
+        line,
+        read: |export| {
+            let mut reader = NdjsonReader::new(BufReader::new(export));
+            let read = reader.by_ref().count();
+            assert!(reader.finish().unwrap().is_empty());
+            read
+        },
+    }
+}
+
+fn csv(entry: &PaymentHistoryEntry) -> Format {
+    let history = tokio::stream::iter(vec![Ok(entry.clone())]);
+    let mut export = Vec::new();
+    tokio::runtime::Builder::new()
+        .basic_scheduler()
+        .build()
+        .unwrap()
+        .block_on(export::history_to_csv_writer(history, &mut export, 1))
+        .unwrap();
+    let header_len = export.iter().position(|&b| b == b'\n').unwrap() + 1;
+    let line = export.split_off(header_len);
+    Format {
+        name: "CSV",
+        head: export,
+        line,
+        read: |export| {
+            let mut reader = CsvReader::new(export);
+            let read = reader.by_ref().count();
+            assert!(reader.finish().unwrap().is_empty());
+            read
+        },
+    }
+}
+
+/// Peak bytes in use above the start of the run.
+fn run(format: &Format, lines: usize) -> usize {
+    let export = Export {
+        head: io::Cursor::new(format.head.clone()),
+        line: format.line.clone(),
+        pos: 0,
+        lines,
+    };
+    let size = format.line.len() * lines;
+    let base = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+
+    let started = Instant::now();
+    assert_eq!((format.read)(export), lines);
+    let elapsed = started.elapsed();
+
+    let peak = PEAK.load(Ordering::Relaxed) - base;
+    println!(
+        "{:>6} {:>9} lines, {:>6} MiB: {:>8.1?}, {:>5.0} MiB/s, peak {} KiB",
+        format.name,
+        lines,
+        size >> 20,
+        elapsed,
+        size as f64 / elapsed.as_secs_f64() / (1 << 20) as f64,
+        peak >> 10
+    );
+    peak
+}
+
+fn main() {
+    let entry = serde_json::from_value(fixtures::history_entries(1).remove(0)).unwrap();
+
+    for format in &[ndjson(&entry), csv(&entry)] {
+        let peaks = [1_000, 10_000, 100_000, 1_000_000]
+            .iter()
+            .map(|&lines| run(format, lines))
+            .collect::<Vec<_>>();
+        // Only the read buffers and the entry being parsed are held, whatever the size of the export.
+        let smallest = peaks[0];
+        for peak in &peaks {
+            assert!(
+                *peak <= smallest + 16 * 1024,
+                "{} memory grows with the export: {:?}",
+                format.name,
+                peaks
+            );
+        }
+    }
+}
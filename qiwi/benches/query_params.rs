@@ -0,0 +1,115 @@
+//! Builds the requests of 10k history pages with [`QueryParams`], and the way they were built
+//! before it: a fresh endpoint and a `HashMap<&str, String>` of formatted values per page.
+//!
+//! Run with `cargo bench -p qiwi --bench query_params`.
+
+use {
+    qiwi::QueryParams,
+    std::{
+        alloc::{GlobalAlloc, Layout, System},
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    },
+};
+
+/// System allocator counting allocations.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const PAGES: u64 = 10_000;
+const USER: u64 = 79_991_234_567;
+const NEXT_TXN_DATE: &str = "2020-01-01T00:00:00+03:00";
+
+/// Requests are consumed like a transport does, by reading every key and value.
+fn consume<'a>(endpoint: &str, params: impl Iterator<Item = (&'a str, &'a str)>) -> usize {
+    endpoint.len()
+        + params
+            .map(|(key, value)| key.len() + value.len())
+            .sum::<usize>()
+}
+
+fn hash_map() -> usize {
+    let mut total = 0;
+    for page in 0..PAGES {
+        let endpoint = format!("payment-history/v2/persons/{}/payments", USER);
+        let mut args = HashMap::new();
+        args.insert("rows", 50.to_string());
+        args.insert("operation", "ALL".to_string());
+        args.insert("nextTxnDate", NEXT_TXN_DATE.to_string());
+        args.insert("nextTxnId", (20_000_000_000 - page).to_string());
+        total += consume(&endpoint, args.iter().map(|(k, v)| (*k, v.as_str())));
+    }
+    total
+}
+
+fn query_params() -> usize {
+    let mut total = 0;
+    let endpoint = format!("payment-history/v2/persons/{}/payments", USER);
+    let mut args = QueryParams::new();
+    for page in 0..PAGES {
+        args.clear();
+        args.push("rows", 50u16);
+        args.push("operation", "ALL");
+        args.push("nextTxnDate", NEXT_TXN_DATE);
+        args.push("nextTxnId", 20_000_000_000 - page);
+        total += consume(&endpoint, args.iter());
+    }
+    total
+}
+
+/// Best time of a few runs, and allocations of one.
+fn measure(build: fn() -> usize) -> (Duration, usize, usize) {
+    let mut best = Duration::MAX;
+    let mut allocations = 0;
+    let mut total = 0;
+    for _ in 0..5 {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        let started = Instant::now();
+        total = build();
+        best = best.min(started.elapsed());
+        allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    }
+    (best, allocations, total)
+}
+
+fn main() {
+    let (old_time, old_allocations, old_total) = measure(hash_map);
+    let (new_time, new_allocations, new_total) = measure(query_params);
+    assert_eq!(old_total, new_total);
+
+    println!(
+        "HashMap<&str, String>: {:>8.1?}, {:>6} allocations",
+        old_time, old_allocations
+    );
+    println!(
+        "QueryParams:           {:>8.1?}, {:>6} allocations",
+        new_time, new_allocations
+    );
+    println!(
+        "{:.1}x faster",
+        old_time.as_secs_f64() / new_time.as_secs_f64()
+    );
+    // Only the endpoint and the first page allocate, the other pages reuse the buffer.
+    assert!(
+        new_allocations < 10,
+        "{} allocations for {} pages",
+        new_allocations,
+        PAGES
+    );
+}
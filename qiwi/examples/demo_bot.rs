@@ -0,0 +1,197 @@
+//! Donation bot running against canned responses.
+//!
+//! Waits for a donation carrying a code, thanks the donor with a small transfer within the
+//! payment policy and exports the history of the day as NDJSON. Exits with a panic if any step
+//! does not end up as expected.
+//!
+//! Run with `cargo run -p qiwi --example demo_bot --features test-util`, the final state is
+//! checked by `cargo test -p qiwi --examples --features test-util`.
+
+use {
+    bigdecimal::BigDecimal,
+    phonenumber::PhoneNumber,
+    qiwi::{
+        fixtures,
+        policy::{PolicyViolation, SimplePolicy},
+        *,
+    },
+    serde_json::{json, Value},
+    std::{io::Write, str::FromStr, sync::Arc, time::Duration},
+    tokio::stream::StreamExt,
+};
+
+const DONATION_CODE: &str = "AB12CD";
+const DONOR: &str = "+79035550101";
+
+fn rub(amount: &str) -> Money {
+    Money::new(BigDecimal::from_str(amount).unwrap(), penny::Currency::RUB)
+}
+
+/// Typical wallet history with the newest entry turned into a donation.
+fn history_with_donation() -> Value {
+    let mut entries = fixtures::history_entries(fixtures::TYPICAL_HISTORY_LEN);
+    let donation = &mut entries[0];
+    donation["type"] = json!("IN");
+    donation["status"] = json!("SUCCESS");
+    donation["provider"]["id"] = json!(99);
+    donation["account"] = json!(DONOR);
+    donation["comment"] = json!("for coffee, code ab-12 cd");
+    donation["sum"] = json!({ "amount": "500.00", "currency": "643" });
+    fixtures::history_page(entries)
+}
+
+type BotResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// State the bot leaves behind.
+struct Outcome {
+    donation: PaymentHistoryEntry,
+    thank_you: TransferData,
+    /// Why the large thank-you was refused.
+    refusal: PolicyViolation,
+    /// History of the day, as NDJSON.
+    export: String,
+    /// Payments that reached the transport.
+    payments: Vec<OfflineRequest>,
+}
+
+async fn run() -> BotResult<Outcome> {
+    let phone = PhoneNumber::from_str("+79991234567")?;
+    let user = QiwiUser::from(phone.clone());
+
+    let transport = Arc::new(OfflineTransport::typical_wallet(&phone));
+    transport.insert(
+        Method::GET,
+        format!("payment-history/v2/persons/{}/payments", user),
+        &history_with_donation(),
+    );
+    transport.insert(
+        Method::POST,
+        "sinap/api/v2/terms/99/payments",
+        &json!({
+            "id": "1",
+            "terms": "99",
+            "fields": { "account": DONOR },
+            "sum": { "amount": 10, "currency": "643" },
+            "transaction": { "id": "20000000001", "state": { "code": "Accepted" } },
+            "source": "account_643",
+        }),
+    );
+
+    let client = Client::builder(phone, "")
+        .transport(transport.clone())
+        .payment_policy(SimplePolicy::new().transfer_limit(rub("50")))
+        .build();
+
+    // Login and snapshot.
+    let profile = client.profile_info().await?;
+    println!("Logged in, identification {:?}", profile.effective_level());
+    let accounts = client.accounts().await?;
+    assert_eq!(accounts.len(), 3);
+    for account in &accounts {
+        println!("Balance {}: {:?}", account.alias, account.balance);
+    }
+
+    // Donation.
+    let donation = client
+        .watch_for_code(DONATION_CODE, Duration::from_secs(30))
+        .await?
+        .expect("donation arrives");
+    assert_eq!(donation.account, DONOR);
+    println!("Donation {} from {}", donation.txn_id, donation.account);
+
+    // Thank-you transfers, the large one is stopped by the policy.
+    let thanks = |amount: &str| {
+        client.transfer_request(
+            BigDecimal::from_str(amount).unwrap(),
+            TransferDirection::Qiwi {
+                to_phone: DONOR.parse().unwrap(),
+                to_currency: penny::Currency::RUB,
+            },
+            "Thank you!",
+        )
+    };
+    let thank_you = client.transfer(&thanks("10")).await?;
+    let refusal = match client.transfer(&thanks("100")).await {
+        Err(Error::PolicyViolation { source }) => source,
+        other => panic!("policy should refuse the transfer, got {:?}", other),
+    };
+
+    // Export of the day.
+    let day = donation.date.date_naive();
+    let mut export = Vec::new();
+    let mut history = client.payment_history();
+    while let Some(entry) = history.next().await.transpose()? {
+        if entry.date.date_naive() < day {
+            break;
+        }
+        serde_json::to_writer(&mut export, &entry)?;
+        export.push(b'\n');
+    }
+    std::io::stdout().write_all(&export)?;
+
+    Ok(Outcome {
+        donation,
+        thank_you,
+        refusal,
+        export: String::from_utf8(export)?,
+        payments: transport
+            .recorded()
+            .into_iter()
+            .filter(|request| request.method == Method::POST)
+            .collect(),
+    })
+}
+
+#[tokio::main(basic_scheduler)]
+async fn main() -> BotResult<()> {
+    let outcome = run().await?;
+    assert_eq!(outcome.export.lines().count(), 3);
+    assert_eq!(outcome.payments.len(), 1);
+    println!(
+        "Thanked for donation {} with transfer {}, large thank-you refused: {}",
+        outcome.donation.txn_id, outcome.thank_you.transaction.id, outcome.refusal
+    );
+    println!("Demo finished");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn final_state() {
+        let outcome = run().await.unwrap();
+
+        assert_eq!(outcome.donation.account, DONOR);
+        assert_eq!(outcome.donation.sum.amount, rub("500").amount);
+        assert_eq!(outcome.thank_you.transaction.id, "20000000001");
+        match &outcome.refusal {
+            PolicyViolation::TransferLimitExceeded { amount, limit } => {
+                assert_eq!(amount.amount, rub("100").amount);
+                assert_eq!(limit.amount, rub("50").amount);
+            }
+            other => panic!("unexpected refusal {:?}", other),
+        }
+
+        // The export holds the day of the donation, newest first.
+        let exported = outcome
+            .export
+            .lines()
+            .map(|line| serde_json::from_str::<PaymentHistoryEntry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(exported.len(), 3);
+        assert_eq!(exported[0].txn_id, outcome.donation.txn_id);
+        assert!(exported
+            .iter()
+            .all(|entry| entry.date.date_naive() == outcome.donation.date.date_naive()));
+
+        // Only the small thank-you was paid.
+        assert_eq!(outcome.payments.len(), 1);
+        let payment = &outcome.payments[0];
+        assert_eq!(payment.endpoint, "sinap/api/v2/terms/99/payments");
+        let body = serde_json::from_str::<Value>(payment.body.as_deref().unwrap()).unwrap();
+        assert_eq!(body["fields"]["account"], json!(DONOR));
+        assert_eq!(body["sum"]["amount"], json!("10"));
+    }
+}
@@ -0,0 +1,433 @@
+//! Audit trail of payments, see [`ClientBuilder::audit_sink`](crate::ClientBuilder::audit_sink).
+
+use {
+    crate::*,
+    async_trait::async_trait,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    },
+    tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex as AsyncMutex},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditStage {
+    /// The payment is about to be sent.
+    Intent,
+    /// The payment was sent, successfully or not.
+    Outcome,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum AuditOutcome {
+    Accepted {
+        txn_id: String,
+        state: String,
+        response: Value,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Record of a payment attempt.
+///
+/// Card numbers in fields and comment are masked, amounts, accounts and ids are kept as sent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub at: DateTime<Utc>,
+    pub stage: AuditStage,
+    /// Id the payment is submitted with.
+    pub payment_id: String,
+    pub provider: ProviderId,
+    pub sum: Money,
+    /// Currency of the funding balance.
+    pub source: QiwiCurrency,
+    pub fields: BTreeMap<String, String>,
+    pub comment: Option<String>,
+    /// Set for failed calls only, successful responses do not carry it.
+    pub correlation_id: Option<String>,
+    /// Set at the outcome stage.
+    pub outcome: Option<AuditOutcome>,
+}
+
+#[cfg(feature = "payments")]
+impl AuditEvent {
+    pub(crate) fn intent(request: &PaymentRequest, at: DateTime<Utc>) -> Self {
+        Self {
+            at,
+            stage: AuditStage::Intent,
+            payment_id: request.id.clone(),
+            provider: request.provider,
+            sum: request.sum.clone(),
+            source: request.payment_method.account_id.clone(),
+            fields: mask_fields(&request.fields),
+            comment: request
+                .comment
+                .as_deref()
+                .map(|comment| display::mask_pans(comment).into_owned()),
+            correlation_id: None,
+            outcome: None,
+        }
+    }
+
+    pub(crate) fn outcome(mut self, result: &QiwiResult<TransferData>, at: DateTime<Utc>) -> Self {
+        self.at = at;
+        self.stage = AuditStage::Outcome;
+        self.outcome = Some(match result {
+            Ok(data) => AuditOutcome::Accepted {
+                txn_id: data.transaction.id.clone(),
+                state: data.transaction.state.code.clone(),
+                response: serde_json::to_value(data).unwrap_or(Value::Null),
+            },
+            Err(e) => {
+                self.correlation_id = e.correlation_id().map(str::to_string);
+                AuditOutcome::Failed {
+                    error: e.to_string(),
+                }
+            }
+        });
+        self
+    }
+}
+
+/// Payment fields with values that are card numbers masked.
+#[cfg(feature = "payments")]
+pub(crate) fn mask_fields(fields: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    fields
+        .iter()
+        .map(|(name, value)| {
+            let value = match value.parse::<CardNumber>() {
+                Ok(_) => display::mask_card_number(value).into_owned(),
+                Err(_) => value.clone(),
+            };
+            (name.clone(), value)
+        })
+        .collect()
+}
+
+/// Destination of audit events.
+///
+/// Events are recorded one at a time and in order for each payment: the intent before the
+/// payment is sent and the outcome after.
+#[async_trait]
+pub trait AuditSink: Debug + Send + Sync + 'static {
+    async fn record(&self, event: AuditEvent) -> Result<(), StdError>;
+}
+
+#[async_trait]
+impl<S: AuditSink + ?Sized> AuditSink for Arc<S> {
+    async fn record(&self, event: AuditEvent) -> Result<(), StdError> {
+        (**self).record(event).await
+    }
+}
+
+/// Appends events to a file, one JSON object per line, syncing it to disk after every event.
+///
+/// Once the file would grow beyond the size limit it is renamed with the time appended, e.g.
+/// `audit.jsonl.20200131T120000.000Z`, and a new one is started. Rotated files are never removed.
+#[derive(Debug)]
+pub struct FileAuditSink {
+    path: PathBuf,
+    max_size: u64,
+    lock: AsyncMutex<()>,
+}
+
+impl FileAuditSink {
+    /// Rotates at 100 MiB by default.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            max_size: 100 << 20,
+            lock: Default::default(),
+        }
+    }
+
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    async fn rotate_if_full(&self, line_len: u64) -> Result<(), StdError> {
+        let size = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if size == 0 || size + line_len <= self.max_size {
+            return Ok(());
+        }
+
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ")));
+        tokio::fs::rename(&self.path, &rotated).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileAuditSink {
+    async fn record(&self, event: AuditEvent) -> Result<(), StdError> {
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        self.rotate_if_full(line.len() as u64).await?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// Events read back from files written by [`FileAuditSink`].
+#[derive(Clone, Debug, Default)]
+pub struct AuditLogReader {
+    events: Vec<AuditEvent>,
+}
+
+impl AuditLogReader {
+    pub fn new(events: Vec<AuditEvent>) -> Self {
+        Self { events }
+    }
+
+    /// Parses JSON lines, skipping blank ones.
+    pub fn from_slice(data: &[u8]) -> Result<Self, StdError> {
+        let mut events = Vec::new();
+        for (i, line) in data.split(|&b| b == b'\n').enumerate() {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            events.push(
+                serde_json::from_slice(line)
+                    .map_err(|e| format!("invalid audit event on line {}: {}", i + 1, e))?,
+            );
+        }
+        Ok(Self::new(events))
+    }
+
+    /// Reads one file, rotated files have to be read separately.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, StdError> {
+        Self::from_slice(&std::fs::read(path)?)
+    }
+
+    pub fn events(&self) -> &[AuditEvent] {
+        &self.events
+    }
+
+    /// The last event of every payment id, in order of the first one.
+    ///
+    /// An intent without an outcome means the payment was sent but its result is unknown.
+    pub fn attempts(&self) -> Vec<&AuditEvent> {
+        let mut order = Vec::new();
+        let mut last = HashMap::new();
+        for event in &self.events {
+            if last.insert(event.payment_id.as_str(), event).is_none() {
+                order.push(event.payment_id.as_str());
+            }
+        }
+        order.into_iter().map(|id| last[id]).collect()
+    }
+}
+
+#[cfg(all(test, feature = "payments"))]
+mod tests {
+    use {super::*, crate::clock::ManualClock, std::sync::Mutex};
+
+    const PAN: &str = "4111111111111111";
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap()
+    }
+
+    fn payments_endpoint() -> String {
+        format!("sinap/api/v2/terms/{}/payments", ProviderId::VISA_RU)
+    }
+
+    /// Sink keeping events with the number of requests sent before each of them.
+    #[derive(Debug)]
+    struct RecordingSink {
+        transport: Arc<OfflineTransport>,
+        events: Mutex<Vec<(AuditEvent, usize)>>,
+        failing: bool,
+    }
+
+    #[async_trait]
+    impl AuditSink for RecordingSink {
+        async fn record(&self, event: AuditEvent) -> Result<(), StdError> {
+            if self.failing {
+                return Err("disk full".into());
+            }
+            let sent = self.transport.requests().len();
+            self.events.lock().unwrap().push((event, sent));
+            Ok(())
+        }
+    }
+
+    fn client(
+        failing: bool,
+        required: bool,
+    ) -> (Client, Arc<OfflineTransport>, Arc<RecordingSink>) {
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::POST,
+            payments_endpoint(),
+            &json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } }),
+        ));
+        let sink = Arc::new(RecordingSink {
+            transport: transport.clone(),
+            events: Default::default(),
+            failing,
+        });
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(ManualClock::new(now()))
+            .id_generator(ids::SequentialIdGenerator::new(1_000))
+            .audit_sink(sink.clone())
+            .audit_required(required)
+            .build();
+        (client, transport, sink)
+    }
+
+    fn card_transfer(client: &Client) -> TransferRequest {
+        client.transfer_request(
+            BigDecimal::from(250),
+            TransferDirection::Card {
+                pan: PAN.parse().unwrap(),
+                provider: Some(ProviderId::VISA_RU),
+            },
+            &format!("refund to {}", PAN),
+        )
+    }
+
+    #[tokio::test]
+    async fn intent_is_recorded_before_sending() {
+        let (client, transport, sink) = client(false, true);
+        client.transfer(&card_transfer(&client)).await.unwrap();
+        assert_eq!(transport.requests().len(), 1);
+
+        let events = sink.events.lock().unwrap();
+        let stages = events
+            .iter()
+            .map(|(event, sent)| (event.stage, *sent))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stages,
+            vec![(AuditStage::Intent, 0), (AuditStage::Outcome, 1)]
+        );
+
+        let (intent, _) = &events[0];
+        assert_eq!(intent.at, now());
+        assert_eq!(intent.payment_id, "1000");
+        assert_eq!(intent.provider, ProviderId::VISA_RU);
+        assert_eq!(intent.sum.amount, BigDecimal::from(250));
+        assert!(intent.outcome.is_none());
+
+        let (outcome, _) = &events[1];
+        assert_eq!(outcome.payment_id, "1000");
+        match &outcome.outcome {
+            Some(AuditOutcome::Accepted { txn_id, state, .. }) => {
+                assert_eq!(txn_id, "20000000001");
+                assert_eq!(state, "Accepted");
+            }
+            other => panic!("expected Accepted, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn card_numbers_are_masked() {
+        let (client, _, sink) = client(false, true);
+        client.transfer(&card_transfer(&client)).await.unwrap();
+
+        for (event, _) in sink.events.lock().unwrap().iter() {
+            assert_eq!(event.fields["account"], "***1111");
+            assert_eq!(event.comment.as_deref(), Some("refund to 4111***1111"));
+            let line = serde_json::to_string(event).unwrap();
+            assert!(!line.contains(PAN), "{}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_payment_outcome() {
+        let (client, transport, sink) = client(false, true);
+        transport.push_error(
+            Method::POST,
+            payments_endpoint(),
+            HttpStatusError::new(500, ""),
+        );
+        assert!(client.transfer(&card_transfer(&client)).await.is_err());
+
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        match &events[1].0.outcome {
+            Some(AuditOutcome::Failed { error }) => assert!(!error.is_empty()),
+            other => panic!("expected Failed, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn payment_is_not_sent_without_intent() {
+        let (client, transport, _) = client(true, true);
+        match client.transfer(&card_transfer(&client)).await {
+            Err(Error::AuditFailed { .. }) => {}
+            other => panic!("expected AuditFailed, got {:?}", other),
+        }
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn optional_audit_does_not_block_payments() {
+        let (client, transport, _) = client(true, false);
+        client.transfer(&card_transfer(&client)).await.unwrap();
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn file_sink_appends_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let (client, _, sink) = client(false, true);
+        client.transfer(&card_transfer(&client)).await.unwrap();
+        let events = sink
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(event, _)| event.clone())
+            .collect::<Vec<_>>();
+
+        let file = FileAuditSink::new(&path);
+        for event in &events {
+            file.record(event.clone()).await.unwrap();
+        }
+        let log = AuditLogReader::from_file(&path).unwrap();
+        assert_eq!(log.events().len(), 2);
+        let attempts = log.attempts();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].stage, AuditStage::Outcome);
+
+        // Every event goes to a new file once the limit is below the size of one.
+        let file = FileAuditSink::new(&path).max_size(1);
+        file.record(events[0].clone()).await.unwrap();
+        let files = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, 2);
+        assert_eq!(AuditLogReader::from_file(&path).unwrap().events().len(), 1);
+    }
+
+    #[test]
+    fn reader_rejects_broken_lines() {
+        let error = AuditLogReader::from_slice(b"\n{\"at\":1}\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+        assert!(AuditLogReader::from_slice(b"\n \n")
+            .unwrap()
+            .events()
+            .is_empty());
+    }
+}
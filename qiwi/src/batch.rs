@@ -0,0 +1,442 @@
+//! Planning of batch payouts and comparison with a previous run, see [`plan`].
+
+use {
+    crate::{audit::*, *},
+    serde::Serialize,
+    std::collections::HashMap,
+};
+
+/// Payee of a payment as recorded in audit events, with card numbers masked.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Recipient {
+    pub provider: ProviderId,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Recipient {
+    fn of_event(event: &AuditEvent) -> Self {
+        Self {
+            provider: event.provider,
+            fields: event.fields.clone(),
+        }
+    }
+}
+
+impl Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "provider {}", self.provider)?;
+        for (name, value) in &self.fields {
+            write!(f, ", {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Transfer of a batch with its payee and quote.
+#[derive(Clone, Debug)]
+pub struct PlannedItem {
+    /// Position in the batch.
+    pub index: usize,
+    pub request: TransferRequest,
+    pub recipient: Recipient,
+    pub sum: Money,
+    pub quote: TransferQuote,
+}
+
+/// Transfers of a batch resolved and quoted without sending anything.
+#[derive(Clone, Debug)]
+pub struct BatchPlan {
+    pub items: Vec<PlannedItem>,
+}
+
+/// Resolves the provider and quotes the commission of every transfer, in batch order.
+///
+/// Only provider detection and commission quotes are requested, so a plan is safe to build
+/// against the live API.
+pub async fn plan(client: &Client, items: Vec<TransferRequest>) -> QiwiResult<BatchPlan> {
+    let mut planned = Vec::with_capacity(items.len());
+    for (index, request) in items.into_iter().enumerate() {
+        let quote = client
+            .quote_transfer(&request.direction, request.amount.clone())
+            .await?;
+        let provider = client.transfer_provider(&request.direction).await?;
+        planned.push(PlannedItem {
+            index,
+            recipient: Recipient {
+                provider,
+                fields: mask_fields(&request.direction.payment_fields(provider)),
+            },
+            sum: Money::new(request.amount.clone(), request.direction.currency()),
+            request,
+            quote,
+        });
+    }
+
+    Ok(BatchPlan { items: planned })
+}
+
+/// Planned transfer with the previous attempt it was matched to, if any.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffItem {
+    /// Position in the batch.
+    pub index: usize,
+    pub recipient: Recipient,
+    pub planned: Money,
+    pub previous: Option<Money>,
+    pub previous_payment_id: Option<String>,
+    /// Set if the previous attempt was accepted.
+    pub previous_txn_id: Option<String>,
+}
+
+/// Planned transfers sorted by what the previous run did for their payee, each in batch order.
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchDiff {
+    /// Accepted in the previous run with the same sum.
+    pub already_paid: Vec<DiffItem>,
+    /// Accepted in the previous run with a different sum.
+    pub amount_changed: Vec<DiffItem>,
+    /// Sent in the previous run without a recorded outcome, e.g. the run was killed midway.
+    /// Check history before paying these.
+    pub unresolved: Vec<DiffItem>,
+    /// No previous attempt, or only failed ones.
+    pub to_pay: Vec<DiffItem>,
+}
+
+impl BatchDiff {
+    /// Whether any planned transfer may repeat a payment of the previous run.
+    pub fn has_overlaps(&self) -> bool {
+        !(self.already_paid.is_empty()
+            && self.amount_changed.is_empty()
+            && self.unresolved.is_empty())
+    }
+}
+
+/// Payment attempt of the previous run.
+#[derive(Clone, Copy)]
+struct Attempt<'a> {
+    event: &'a AuditEvent,
+    txn_id: Option<&'a str>,
+}
+
+fn same_sum(left: &Money, right: &Money) -> bool {
+    left.currency == right.currency && left.amount == right.amount
+}
+
+impl BatchPlan {
+    /// Matches transfers to attempts of a previous run by payee and sum.
+    ///
+    /// Every attempt is matched at most once, so a payee paid twice before covers two planned
+    /// transfers. Attempts with the same sum are matched first, accepted ones before unresolved,
+    /// then the attempts left are matched regardless of sum. Failed attempts are ignored.
+    pub fn diff(&self, log: &AuditLogReader) -> BatchDiff {
+        let mut pool = HashMap::<Recipient, Vec<Attempt>>::new();
+        for event in log.attempts() {
+            let txn_id = match &event.outcome {
+                Some(AuditOutcome::Accepted { txn_id, .. }) => Some(txn_id.as_str()),
+                Some(AuditOutcome::Failed { .. }) => continue,
+                None => None,
+            };
+            pool.entry(Recipient::of_event(event))
+                .or_default()
+                .push(Attempt { event, txn_id });
+        }
+
+        let mut matched = vec![None; self.items.len()];
+        let passes: [&dyn Fn(&PlannedItem, &Attempt) -> bool; 3] = [
+            &|item, attempt| attempt.txn_id.is_some() && same_sum(&item.sum, &attempt.event.sum),
+            &|item, attempt| same_sum(&item.sum, &attempt.event.sum),
+            &|_, _| true,
+        ];
+        for pass in passes.iter() {
+            for (item, slot) in self.items.iter().zip(matched.iter_mut()) {
+                if slot.is_some() {
+                    continue;
+                }
+                if let Some(attempts) = pool.get_mut(&item.recipient) {
+                    if let Some(i) = attempts.iter().position(|attempt| pass(item, attempt)) {
+                        *slot = Some(attempts.remove(i));
+                    }
+                }
+            }
+        }
+
+        let mut diff = BatchDiff::default();
+        for (item, attempt) in self.items.iter().zip(matched) {
+            let entry = DiffItem {
+                index: item.index,
+                recipient: item.recipient.clone(),
+                planned: item.sum.clone(),
+                previous: attempt.as_ref().map(|a| a.event.sum.clone()),
+                previous_payment_id: attempt.as_ref().map(|a| a.event.payment_id.clone()),
+                previous_txn_id: attempt.as_ref().and_then(|a| a.txn_id.map(str::to_string)),
+            };
+            match attempt {
+                None => diff.to_pay.push(entry),
+                Some(Attempt { txn_id: None, .. }) => diff.unresolved.push(entry),
+                Some(Attempt { event, .. }) if same_sum(&item.sum, &event.sum) => {
+                    diff.already_paid.push(entry)
+                }
+                Some(_) => diff.amount_changed.push(entry),
+            }
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, std::str::FromStr};
+
+    const CARDS: [&str; 4] = [
+        "4111111111111111",
+        "4012888888881881",
+        "4242424242424242",
+        "4000056655665556",
+    ];
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap()
+    }
+
+    fn card(pan: &str) -> TransferDirection {
+        TransferDirection::Card {
+            pan: pan.parse().unwrap(),
+            provider: Some(ProviderId::VISA_RU),
+        }
+    }
+
+    fn rub(amount: &str) -> Money {
+        Money::new(BigDecimal::from_str(amount).unwrap(), penny::Currency::RUB)
+    }
+
+    /// Client quoting and accepting card transfers.
+    fn builder(transport: Arc<OfflineTransport>) -> ClientBuilder {
+        transport.insert(
+            Method::POST,
+            format!("sinap/providers/{}/onlineCommission", ProviderId::VISA_RU),
+            &json!({
+                "withdrawSum": { "amount": "101", "currency": "643" },
+                "enrollmentSum": { "amount": "100", "currency": "643" },
+                "qwCommission": { "amount": "1", "currency": "643" },
+                "fundingSourceCommission": { "amount": "0", "currency": "643" },
+                "withdrawToEnrollmentRate": "1",
+            }),
+        );
+        transport.insert(
+            Method::POST,
+            format!("sinap/api/v2/terms/{}/payments", ProviderId::VISA_RU),
+            &json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } }),
+        );
+        Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport)
+            .clock(ManualClock::new(now()))
+            .id_generator(ids::SequentialIdGenerator::new(1_000))
+    }
+
+    fn client(transport: Arc<OfflineTransport>) -> Client {
+        builder(transport).build()
+    }
+
+    async fn batch_plan(items: &[(&str, &str)]) -> BatchPlan {
+        let client = client(Arc::new(OfflineTransport::new()));
+        let items = items
+            .iter()
+            .map(|(pan, amount)| {
+                client.transfer_request(BigDecimal::from_str(amount).unwrap(), card(pan), "")
+            })
+            .collect();
+        plan(&client, items).await.unwrap()
+    }
+
+    fn event(id: &str, pan: &str, amount: &str, outcome: Option<AuditOutcome>) -> AuditEvent {
+        AuditEvent {
+            at: now(),
+            stage: match outcome {
+                Some(_) => AuditStage::Outcome,
+                None => AuditStage::Intent,
+            },
+            payment_id: id.to_string(),
+            provider: ProviderId::VISA_RU,
+            sum: rub(amount),
+            source: penny::Currency::RUB.into(),
+            fields: mask_fields(&card(pan).payment_fields(ProviderId::VISA_RU)),
+            comment: None,
+            correlation_id: None,
+            outcome,
+        }
+    }
+
+    fn accepted(txn_id: &str) -> Option<AuditOutcome> {
+        Some(AuditOutcome::Accepted {
+            txn_id: txn_id.to_string(),
+            state: "Accepted".to_string(),
+            response: Value::Null,
+        })
+    }
+
+    fn failed() -> Option<AuditOutcome> {
+        Some(AuditOutcome::Failed {
+            error: "HTTP status 500".to_string(),
+        })
+    }
+
+    fn indices(items: &[DiffItem]) -> Vec<usize> {
+        items.iter().map(|item| item.index).collect()
+    }
+
+    #[tokio::test]
+    async fn plan_sends_no_payments() {
+        let transport = Arc::new(OfflineTransport::new());
+        let client = client(transport.clone());
+        let items = vec![
+            client.transfer_request(BigDecimal::from(100), card(CARDS[0]), ""),
+            client.transfer_request(BigDecimal::from(200), card(CARDS[1]), ""),
+        ];
+        let plan = plan(&client, items).await.unwrap();
+
+        assert_eq!(plan.items.len(), 2);
+        assert_eq!(plan.items[1].index, 1);
+        assert_eq!(plan.items[1].sum.amount, BigDecimal::from(200));
+        assert_eq!(plan.items[1].recipient.provider, ProviderId::VISA_RU);
+        assert_eq!(plan.items[1].recipient.fields["account"], "***1881");
+        assert!(transport
+            .requests()
+            .iter()
+            .all(|(_, endpoint)| endpoint.ends_with("/onlineCommission")));
+    }
+
+    #[tokio::test]
+    async fn partially_overlapping_runs() {
+        let plan = batch_plan(&[
+            (CARDS[0], "100"),
+            (CARDS[1], "250"),
+            (CARDS[2], "300"),
+            (CARDS[3], "400"),
+        ])
+        .await;
+        let log = AuditLogReader::new(vec![
+            event("1", CARDS[0], "100", None),
+            event("1", CARDS[0], "100", accepted("10")),
+            event("2", CARDS[1], "200", None),
+            event("2", CARDS[1], "200", accepted("20")),
+            event("3", CARDS[2], "300", None),
+            event("3", CARDS[2], "300", failed()),
+            // Killed before the outcome of the last one was recorded.
+            event("4", CARDS[3], "400", None),
+        ]);
+
+        let diff = plan.diff(&log);
+        assert!(diff.has_overlaps());
+        assert_eq!(indices(&diff.already_paid), vec![0]);
+        assert_eq!(indices(&diff.amount_changed), vec![1]);
+        assert_eq!(indices(&diff.to_pay), vec![2]);
+        assert_eq!(indices(&diff.unresolved), vec![3]);
+
+        let changed = &diff.amount_changed[0];
+        assert_eq!(changed.planned.amount, BigDecimal::from(250));
+        assert_eq!(
+            changed.previous.as_ref().unwrap().amount,
+            BigDecimal::from(200)
+        );
+        assert_eq!(changed.previous_payment_id.as_deref(), Some("2"));
+        assert_eq!(changed.previous_txn_id.as_deref(), Some("20"));
+        assert_eq!(diff.unresolved[0].previous_txn_id, None);
+    }
+
+    #[tokio::test]
+    async fn no_previous_run() {
+        let plan = batch_plan(&[(CARDS[0], "100"), (CARDS[1], "200")]).await;
+        let diff = plan.diff(&AuditLogReader::default());
+        assert!(!diff.has_overlaps());
+        assert_eq!(indices(&diff.to_pay), vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn sums_are_compared_by_value() {
+        let plan = batch_plan(&[(CARDS[0], "100.00")]).await;
+        let log = AuditLogReader::new(vec![event("1", CARDS[0], "100", accepted("10"))]);
+        assert_eq!(indices(&plan.diff(&log).already_paid), vec![0]);
+
+        // Other currencies are other sums.
+        let mut dollars = event("1", CARDS[0], "100", accepted("10"));
+        dollars.sum = Money::new(BigDecimal::from(100), penny::Currency::USD);
+        let diff = plan.diff(&AuditLogReader::new(vec![dollars]));
+        assert_eq!(indices(&diff.amount_changed), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn attempts_are_matched_once() {
+        // Paid twice before, three times now: the third one is new.
+        let plan = batch_plan(&[(CARDS[0], "100"), (CARDS[0], "100"), (CARDS[0], "100")]).await;
+        let log = AuditLogReader::new(vec![
+            event("1", CARDS[0], "100", accepted("10")),
+            event("2", CARDS[0], "100", accepted("20")),
+        ]);
+
+        let diff = plan.diff(&log);
+        assert_eq!(indices(&diff.already_paid), vec![0, 1]);
+        assert_eq!(indices(&diff.to_pay), vec![2]);
+        let txns = diff
+            .already_paid
+            .iter()
+            .map(|item| item.previous_txn_id.clone().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(txns, vec!["10", "20"]);
+    }
+
+    #[tokio::test]
+    async fn same_sums_are_matched_first() {
+        // The attempt of 200 belongs to the second transfer even though the first one comes first.
+        let plan = batch_plan(&[(CARDS[0], "150"), (CARDS[0], "200")]).await;
+        let log = AuditLogReader::new(vec![
+            event("1", CARDS[0], "200", accepted("10")),
+            event("2", CARDS[0], "100", None),
+        ]);
+
+        let diff = plan.diff(&log);
+        assert_eq!(indices(&diff.already_paid), vec![1]);
+        assert_eq!(indices(&diff.unresolved), vec![0]);
+        assert_eq!(diff.unresolved[0].previous_payment_id.as_deref(), Some("2"));
+
+        // Accepted attempts are matched before unresolved ones of the same sum.
+        let plan = batch_plan(&[(CARDS[0], "200")]).await;
+        let log = AuditLogReader::new(vec![
+            event("1", CARDS[0], "200", None),
+            event("2", CARDS[0], "200", accepted("20")),
+        ]);
+        let diff = plan.diff(&log);
+        assert_eq!(indices(&diff.already_paid), vec![0]);
+        assert_eq!(diff.already_paid[0].previous_txn_id.as_deref(), Some("20"));
+    }
+
+    #[tokio::test]
+    async fn diff_against_audited_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let transport = Arc::new(OfflineTransport::new());
+        let previous = builder(transport.clone())
+            .audit_sink(FileAuditSink::new(&path))
+            .build();
+        transport.push_error(
+            Method::POST,
+            format!("sinap/api/v2/terms/{}/payments", ProviderId::VISA_RU),
+            HttpStatusError::new(500, ""),
+        );
+        for (pan, amount) in &[(CARDS[1], 200), (CARDS[0], 100)] {
+            let request = previous.transfer_request(BigDecimal::from(*amount), card(pan), "");
+            let _ = previous.transfer(&request).await;
+        }
+
+        let plan = batch_plan(&[(CARDS[0], "100"), (CARDS[1], "200"), (CARDS[2], "300")]).await;
+        let diff = plan.diff(&AuditLogReader::from_file(&path).unwrap());
+        assert_eq!(indices(&diff.already_paid), vec![0]);
+        assert_eq!(indices(&diff.to_pay), vec![1, 2]);
+        assert!(diff.unresolved.is_empty());
+        assert_eq!(
+            diff.already_paid[0].previous_txn_id.as_deref(),
+            Some("20000000001")
+        );
+    }
+}
@@ -0,0 +1,206 @@
+//! Bills issued to the wallet.
+
+use {crate::*, async_stream::stream, log::*, std::collections::HashSet};
+
+/// Bills requested by [`Client::unpaid_bills`].
+const BILL_ROWS: u16 = 50;
+
+#[derive(Clone, Debug)]
+pub enum BillWatchEvent {
+    Bill(Bill),
+    Status(WatcherStatus),
+}
+
+async fn unpaid_bills(caller: &CallerWrapper) -> QiwiResult<Vec<Bill>> {
+    let list: BillList = caller
+        .call(
+            "checkout-api/api/bill/search",
+            Method::GET,
+            &QueryParams::new()
+                .with("statuses", "READY_FOR_PAY")
+                .with("rows", BILL_ROWS),
+            None,
+        )
+        .await?
+        .into_result()?;
+    Ok(list.bills)
+}
+
+impl Client {
+    /// Bills issued to the wallet which are ready to be paid.
+    pub async fn unpaid_bills(&self) -> QiwiResult<Vec<Bill>> {
+        unpaid_bills(&self.caller).await
+    }
+
+    /// Polls unpaid bills and yields bills not seen before, all of them on the first poll.
+    ///
+    /// A bill is yielded once while it stays unpaid. Failed polls are reported as
+    /// [`WatcherStatus::Degraded`] and retried with backoff.
+    pub fn watch_bills(
+        &self,
+        options: WatchOptions,
+    ) -> Pin<Box<dyn Stream<Item = BillWatchEvent> + Send>> {
+        let caller = self.caller.clone();
+        let polls = self.polls.clone();
+        Box::pin(stream! {
+            let slots = polls.register("watch-bills", options.interval);
+            let mut backoff = Backoff::new(options.interval, options.max_interval);
+            let mut seen = HashSet::new();
+            loop {
+                let delay = match unpaid_bills(&caller).await {
+                    Ok(bills) => {
+                        let current = bills.iter().map(|bill| bill.id).collect::<HashSet<_>>();
+                        for bill in bills {
+                            if !seen.contains(&bill.id) {
+                                yield BillWatchEvent::Bill(bill);
+                            }
+                        }
+                        // Bills paid or expired meanwhile are forgotten.
+                        seen = current;
+
+                        backoff.on_success()
+                    }
+                    Err(e) => {
+                        let next_attempt_in = backoff.on_failure();
+                        warn!("Failed to poll bills: {}", e);
+                        yield BillWatchEvent::Status(WatcherStatus::Degraded {
+                            consecutive_failures: backoff.consecutive_failures(),
+                            next_attempt_in,
+                        });
+
+                        next_attempt_in
+                    }
+                };
+
+                slots.set_interval(delay);
+                slots.next_slot().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, crate::clock::ManualClock, serde_json::json, std::time::Duration,
+        tokio::stream::StreamExt,
+    };
+
+    const ENDPOINT: &str = "checkout-api/api/bill/search";
+
+    fn bill(id: u64) -> Value {
+        json!({
+            "id": id,
+            "external_id": format!("shop-{}", id),
+            "creation_datetime": 1_577_836_800_000u64,
+            "expiration_datetime": 1_577_923_200_000u64,
+            "sum": { "currency": 643, "amount": 100 },
+            "status": { "value": "READY_FOR_PAY", "changed_datetime": 1_577_836_800_000u64 },
+            "comment": "Order",
+            "pay_url": format!("https://oplata.qiwi.com/form?invoice_uid={}", id),
+        })
+    }
+
+    fn bills(ids: &[u64]) -> Value {
+        json!({ "bills": ids.iter().copied().map(bill).collect::<Vec<_>>() })
+    }
+
+    fn client() -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let transport = Arc::new(OfflineTransport::new());
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .build();
+        (client, transport, clock)
+    }
+
+    fn options() -> WatchOptions {
+        WatchOptions {
+            interval: Duration::from_secs(30),
+            max_interval: Duration::from_secs(100),
+        }
+    }
+
+    async fn next_bill(events: &mut Pin<Box<dyn Stream<Item = BillWatchEvent> + Send>>) -> u64 {
+        match events.next().await {
+            Some(BillWatchEvent::Bill(bill)) => bill.id,
+            other => panic!("expected bill, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_unpaid_bills() {
+        let (client, transport, _) = client();
+        transport.insert(Method::GET, ENDPOINT, &bills(&[1, 2]));
+
+        let bills = client.unpaid_bills().await.unwrap();
+        assert_eq!(bills.len(), 2);
+        assert_eq!(bills[0].external_id, "shop-1");
+        assert_eq!(bills[0].sum.amount, BigDecimal::from(100));
+        assert_eq!(bills[0].status.value, "READY_FOR_PAY");
+        assert_eq!(
+            bills[0].creation_datetime,
+            Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()
+        );
+
+        let params = &transport.recorded()[0].params;
+        assert!(params.contains(&("statuses".to_string(), "READY_FOR_PAY".to_string())));
+    }
+
+    #[tokio::test]
+    async fn new_bills_are_yielded_once() {
+        let (client, transport, _) = client();
+        transport.push(Method::GET, ENDPOINT, &bills(&[1, 2]));
+        transport.push(Method::GET, ENDPOINT, &bills(&[2, 3]));
+        // Bill 1 is issued again after it was paid.
+        transport.insert(Method::GET, ENDPOINT, &bills(&[1, 3]));
+
+        let mut events = client.watch_bills(options());
+        let ids = vec![
+            next_bill(&mut events).await,
+            next_bill(&mut events).await,
+            next_bill(&mut events).await,
+            next_bill(&mut events).await,
+        ];
+        assert_eq!(ids, vec![1, 2, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn failures_back_off_and_success_resets() {
+        let (client, transport, _) = client();
+        for _ in 0..3 {
+            transport.push_error(Method::GET, ENDPOINT, HttpStatusError::new(502, ""));
+        }
+        transport.push(Method::GET, ENDPOINT, &bills(&[1]));
+        transport.push_error(Method::GET, ENDPOINT, HttpStatusError::new(502, ""));
+        transport.insert(Method::GET, ENDPOINT, &bills(&[1, 2]));
+
+        let mut events = client.watch_bills(options());
+        let mut items = Vec::new();
+        for _ in 0..6 {
+            items.push(match events.next().await {
+                Some(BillWatchEvent::Status(status)) => Err(status),
+                Some(BillWatchEvent::Bill(bill)) => Ok(bill.id),
+                None => panic!("stream ended"),
+            });
+        }
+        let status = |consecutive_failures, secs| {
+            Err(WatcherStatus::Degraded {
+                consecutive_failures,
+                next_attempt_in: Duration::from_secs(secs),
+            })
+        };
+        assert_eq!(
+            items,
+            vec![
+                status(1, 60),
+                status(2, 100),
+                status(3, 100),
+                Ok(1),
+                status(1, 60),
+                Ok(2),
+            ]
+        );
+    }
+}
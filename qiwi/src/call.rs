@@ -0,0 +1,68 @@
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+};
+
+impl Client {
+    /// Placeholder in endpoints passed to [`Client::call`] that is replaced with the wallet number.
+    pub const WALLET_PLACEHOLDER: &'static str = "{wallet}";
+
+    fn expand_endpoint(&self, endpoint: &str) -> String {
+        endpoint.replace(Self::WALLET_PLACEHOLDER, &self.user.to_string())
+    }
+
+    fn encode_body<B: Serialize>(body: Option<B>) -> QiwiResult<Option<Value>> {
+        Ok(body
+            .map(|body| serde_json::to_value(body).map_err(transport::Error::from_parse_error))
+            .transpose()?)
+    }
+
+    /// Calls an endpoint the client has no dedicated method for.
+    ///
+    /// The request goes through the client's transport and authorization, and QIWI errors are
+    /// reported the same way as for other methods. `{wallet}` in the endpoint is replaced with
+    /// the wallet number, e.g. `payment-history/v2/persons/{wallet}/payments`.
+    pub async fn call<T, B>(
+        &self,
+        endpoint: &str,
+        method: Method,
+        params: QueryParams,
+        body: Option<B>,
+    ) -> QiwiResult<T>
+    where
+        T: for<'de> Deserialize<'de> + Send + 'static,
+        B: Serialize,
+    {
+        let body = Self::encode_body(body)?;
+        Ok(self
+            .caller
+            .call::<_, T>(
+                self.expand_endpoint(endpoint),
+                method,
+                &params,
+                body.as_ref(),
+            )
+            .await?
+            .into_result()?)
+    }
+
+    /// Same as [`Client::call`], but returns the response body without parsing it.
+    pub async fn call_raw<B: Serialize>(
+        &self,
+        endpoint: &str,
+        method: Method,
+        params: QueryParams,
+        body: Option<B>,
+    ) -> QiwiResult<String> {
+        let body = Self::encode_body(body)?;
+        Ok(self
+            .caller
+            .call_raw(
+                self.expand_endpoint(endpoint),
+                method,
+                &params,
+                body.as_ref(),
+            )
+            .await?)
+    }
+}
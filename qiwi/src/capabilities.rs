@@ -0,0 +1,22 @@
+use crate::*;
+
+impl Client {
+    /// Highest identification level among the wallet's identification records.
+    ///
+    /// Fetched once and cached for the lifetime of the client.
+    pub async fn identification_level(&self) -> QiwiResult<IdentificationLevel> {
+        if let Some(level) = *self.identification_level.lock().unwrap() {
+            return Ok(level);
+        }
+
+        let level = self.profile_info().await?.effective_level();
+        *self.identification_level.lock().unwrap() = Some(level);
+
+        Ok(level)
+    }
+
+    /// Checks whether the wallet's identification level allows the operation.
+    pub async fn can_perform(&self, op: Operation) -> QiwiResult<CapabilityCheck> {
+        Ok(CapabilityCheck::new(self.identification_level().await?, op))
+    }
+}
@@ -0,0 +1,674 @@
+use {
+    crate::{
+        oplog::{self, Operation},
+        *,
+    },
+    serde::{Deserialize, Serialize},
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardOrderInput {
+    card_alias: String,
+    package_price: Option<Money>,
+}
+
+impl Client {
+    /// QIWI cards of the wallet.
+    pub async fn cards(&self) -> QiwiResult<Vec<CardInfo>> {
+        Ok(self
+            .caller
+            .call(
+                "cards/v1/cards",
+                Method::GET,
+                &QueryParams::new().with("vas-alias", "qvc-master"),
+                None,
+            )
+            .await?
+            .into_result()?)
+    }
+
+    /// Full card number and CVV of card `card_id`.
+    ///
+    /// `operation_id` identifies the confirmed operation of revealing the requisites, as
+    /// obtained through QIWI's operation confirmation flow.
+    pub async fn card_requisites(
+        &self,
+        card_id: CardId,
+        operation_id: &str,
+    ) -> QiwiResult<CardRequisites> {
+        let url = format!("cards/v2/persons/{}/cards/{}/details", self.user, card_id);
+        Ok(self
+            .caller
+            .call(
+                url,
+                Method::PUT,
+                &Default::default(),
+                Some(&json!({ "operationId": operation_id })),
+            )
+            .await?
+            .into_result()?)
+    }
+
+    /// Blocks card `card_id`. Fails with [`Error::QiwiError`] if QIWI refuses, e.g. if the card
+    /// is already blocked.
+    pub async fn block_card(&self, card_id: CardId) -> QiwiResult<CardActionResult> {
+        self.card_action(card_id, "block").await
+    }
+
+    /// Unblocks card `card_id`, see [`Client::block_card`].
+    pub async fn unblock_card(&self, card_id: CardId) -> QiwiResult<CardActionResult> {
+        self.card_action(card_id, "unblock").await
+    }
+
+    /// Renames card `card_id` as shown in the wallet.
+    pub async fn rename_card(&self, card_id: CardId, alias: &str) -> QiwiResult<()> {
+        let url = format!("cards/v1/cards/{}/alias", card_id);
+        self.caller
+            .call::<_, CardAliasResponse>(
+                url,
+                Method::PUT,
+                &Default::default(),
+                Some(&json!({ "alias": alias })),
+            )
+            .await?
+            .into_result()?
+            .into_result()
+    }
+
+    /// Card orders of the wallet, in any status.
+    pub async fn card_orders(&self) -> QiwiResult<Vec<CardOrder>> {
+        let url = format!("cards/v2/persons/{}/orders", self.user);
+        Ok(self
+            .caller
+            .call(url, Method::GET, &Default::default(), None)
+            .await?
+            .into_result()?)
+    }
+
+    /// Orders and pays for a card `card_alias`, e.g. `qvc-cpa`, buying the QIWI Master package
+    /// for `package_price` first if it is set.
+    ///
+    /// Steps are recorded in the [operation log](Client::operation_log). If the call fails
+    /// midway, continue with [`Client::resume_operation`] rather than calling this again.
+    pub async fn issue_virtual_card(
+        &self,
+        card_alias: &str,
+        package_price: Option<Money>,
+    ) -> QiwiResult<CardOrder> {
+        let input = CardOrderInput {
+            card_alias: card_alias.to_string(),
+            package_price,
+        };
+        let mut op = self
+            .operation_log()
+            .start(oplog::CARD_ORDER, &input)
+            .await?;
+        self.run_card_order(&mut op, input).await
+    }
+
+    /// Continues operation `id` of [`Client::pending_operations`] from the step it was
+    /// interrupted at, checking the orders on QIWI side for steps that went through
+    /// without being recorded.
+    pub async fn resume_operation(&self, id: &str) -> QiwiResult<CardOrder> {
+        let mut op = match self.operation_log().get(id).await? {
+            Some(op) if op.kind == oplog::CARD_ORDER => op,
+            _ => return UnknownOperation { id }.fail(),
+        };
+        let input = match serde_json::from_value(op.input.clone()) {
+            Ok(input) => input,
+            Err(_) => return UnknownOperation { id }.fail(),
+        };
+        self.run_card_order(&mut op, input).await
+    }
+
+    async fn run_card_order(
+        &self,
+        op: &mut Operation,
+        input: CardOrderInput,
+    ) -> QiwiResult<CardOrder> {
+        let log = self.operation_log();
+        let account = self.user.to_string();
+
+        if let Some(price) = input.package_price {
+            if op.step("package").is_none() {
+                let fields = vec![
+                    ("account".to_string(), account.clone()),
+                    ("vas_alias".to_string(), "qvc-master".to_string()),
+                ]
+                .into_iter()
+                .collect();
+                self.pay_step(op, "package", ProviderId::MASTER_PACKAGE, price, fields)
+                    .await?;
+            }
+        }
+
+        let mut order = match recorded::<CardOrder>(op, "order") {
+            // The order may have moved on since, e.g. if the step after it was not recorded.
+            Some(order) => self
+                .card_orders()
+                .await?
+                .into_iter()
+                .find(|listed| listed.id == order.id)
+                .unwrap_or(order),
+            None => {
+                let orders = self.card_orders().await?;
+                // An order not known before creating one is the one created before an interruption.
+                let created = match recorded::<Vec<String>>(op, "known-orders") {
+                    Some(known) => orders.into_iter().find(|order| {
+                        order.card_alias == input.card_alias && !known.contains(&order.id)
+                    }),
+                    None => {
+                        let known = orders.iter().map(|order| &order.id).collect::<Vec<_>>();
+                        log.record_step(op, "known-orders", "", &known).await?;
+                        None
+                    }
+                };
+                let order = match created {
+                    Some(order) => order,
+                    None => self.create_card_order(&input.card_alias).await?,
+                };
+                log.record_step(op, "order", &order.id, &order).await?;
+                order
+            }
+        };
+
+        if order.status == CardOrderStatus::Draft {
+            order = self.submit_card_order(&order.id).await?;
+            log.record_step(op, "confirm", &order.id, &order).await?;
+        }
+
+        if order.status == CardOrderStatus::PaymentRequired && op.step("pay").is_none() {
+            let price = order
+                .price
+                .clone()
+                .ok_or_else(|| Error::ContractViolation {
+                    endpoint: format!("cards/v2/persons/{}/orders/{}/submit", self.user, order.id),
+                    details: "order requires payment but has no price".to_string(),
+                    correlation_id: None,
+                })?;
+            let fields = vec![
+                ("account".to_string(), account),
+                ("order_id".to_string(), order.id.clone()),
+            ]
+            .into_iter()
+            .collect();
+            self.pay_step(op, "pay", ProviderId::CARD_ORDER, price, fields)
+                .await?;
+            order = self
+                .card_orders()
+                .await?
+                .into_iter()
+                .find(|listed| listed.id == order.id)
+                .unwrap_or(order);
+        }
+
+        log.complete(op).await?;
+        Ok(order)
+    }
+
+    /// Pays for step `name` of `op` once: with the id saved before an interruption if there
+    /// is one, or not at all if QIWI has the payment with that id already.
+    async fn pay_step(
+        &self,
+        op: &mut Operation,
+        name: &str,
+        provider: ProviderId,
+        amount: Money,
+        fields: BTreeMap<String, String>,
+    ) -> QiwiResult<()> {
+        let log = self.operation_log();
+        let pending = op
+            .pending_request_id
+            .as_ref()
+            .and_then(|id| id.parse::<u64>().ok());
+
+        let id = match pending {
+            Some(id) => {
+                if let Some(entry) = self.find_transfer_by_client_id(id).await? {
+                    return log.record_step(op, name, &id.to_string(), &entry).await;
+                }
+                id
+            }
+            None => {
+                let id = self.next_payment_id();
+                log.prepare_step(op, &id.to_string()).await?;
+                id
+            }
+        };
+
+        let data = self.pay(provider, amount, fields, None, Some(id)).await?;
+        log.record_step(op, name, &id.to_string(), &data).await
+    }
+
+    async fn create_card_order(&self, card_alias: &str) -> QiwiResult<CardOrder> {
+        let url = format!("cards/v2/persons/{}/orders", self.user);
+        Ok(self
+            .caller
+            .call(
+                url,
+                Method::POST,
+                &Default::default(),
+                Some(&json!({ "cardAlias": card_alias })),
+            )
+            .await?
+            .into_result()?)
+    }
+
+    async fn submit_card_order(&self, order_id: &str) -> QiwiResult<CardOrder> {
+        let url = format!("cards/v2/persons/{}/orders/{}/submit", self.user, order_id);
+        Ok(self
+            .caller
+            .call(url, Method::PUT, &Default::default(), None)
+            .await?
+            .into_result()?)
+    }
+
+    async fn card_action(&self, card_id: CardId, action: &str) -> QiwiResult<CardActionResult> {
+        let url = format!(
+            "cards/v2/persons/{}/cards/{}/{}",
+            self.user, card_id, action
+        );
+        Ok(self
+            .caller
+            .call(url, Method::PUT, &Default::default(), None)
+            .await?
+            .into_result()?)
+    }
+}
+
+/// Response of step `name`, if it was recorded.
+fn recorded<T: serde::de::DeserializeOwned>(op: &Operation, name: &str) -> Option<T> {
+    serde_json::from_value(op.step(name)?.response.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{clock::ManualClock, transport::HttpStatusError},
+        serde_json::json,
+    };
+
+    const ORDERS: &str = "cards/v2/persons/79991234567/orders";
+
+    fn phone() -> PhoneNumber {
+        "+79991234567".parse().unwrap()
+    }
+
+    fn payments(provider: ProviderId) -> String {
+        format!("sinap/api/v2/terms/{}/payments", provider)
+    }
+
+    fn submit(order_id: &str) -> String {
+        format!("{}/{}/submit", ORDERS, order_id)
+    }
+
+    fn history() -> String {
+        ApiVersions::default().history_endpoint(&QiwiUser::from(phone()))
+    }
+
+    fn order(id: &str, status: &str) -> Value {
+        let mut order = json!({ "id": id, "cardAlias": "qvc-cpa", "status": status });
+        if status != "DRAFT" {
+            order["price"] = json!({ "amount": 199, "currency": 643 });
+        }
+        if status == "COMPLETED" {
+            order["cardId"] = json!(11_111_111);
+        }
+        order
+    }
+
+    fn rub(amount: u32) -> Money {
+        Money::new(BigDecimal::from(amount), penny::Currency::RUB)
+    }
+
+    /// Server side of the card order flow, shared by a client and its restarts.
+    struct World {
+        transport: Arc<OfflineTransport>,
+        clock: Arc<ManualClock>,
+        store: Arc<state::MemoryStateStore>,
+    }
+
+    impl World {
+        /// Orders are created as `ord-1` and paid for through both providers, history is empty.
+        fn new() -> Self {
+            let accepted =
+                json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } });
+            let transport = OfflineTransport::new()
+                .with(Method::POST, ORDERS, &order("ord-1", "DRAFT"))
+                .with(
+                    Method::PUT,
+                    submit("ord-1"),
+                    &order("ord-1", "PAYMENT_REQUIRED"),
+                )
+                .with(
+                    Method::POST,
+                    payments(ProviderId::MASTER_PACKAGE),
+                    &accepted,
+                )
+                .with(Method::POST, payments(ProviderId::CARD_ORDER), &accepted)
+                .with(Method::GET, history(), &fixtures::history_page(vec![]));
+            Self {
+                transport: Arc::new(transport),
+                clock: Arc::new(ManualClock::new(
+                    Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap(),
+                )),
+                store: Default::default(),
+            }
+        }
+
+        /// Orders listed, one response per listing, in order.
+        fn list(&self, orders: &[Vec<Value>]) {
+            for listed in orders {
+                self.transport.push(Method::GET, ORDERS, &json!(listed));
+            }
+        }
+
+        fn fail(&self, method: Method, endpoint: String) {
+            self.transport
+                .push_error(method, endpoint, HttpStatusError::new(502, ""));
+        }
+
+        /// Client minting payment ids from `first_id`, different for each restart.
+        fn client(&self, first_id: u64) -> Client {
+            Client::builder(phone(), "")
+                .transport(self.transport.clone())
+                .clock(self.clock.clone())
+                .state_store(self.store.clone())
+                .id_generator(ids::SequentialIdGenerator::new(first_id))
+                .build()
+        }
+
+        /// Starts an order that fails on the request failed with [`World::fail`], returning
+        /// the id of the operation left pending.
+        async fn interrupted(&self, package: bool) -> String {
+            let client = self.client(1_000);
+            let package = if package { Some(rub(2_999)) } else { None };
+            assert!(client.issue_virtual_card("qvc-cpa", package).await.is_err());
+
+            let pending = client.pending_operations().await.unwrap();
+            assert_eq!(pending.len(), 1);
+            pending[0].id.clone()
+        }
+
+        /// Restarts the client and resumes operation `id`, checking that it completes.
+        async fn resume(&self, id: &str) -> CardOrder {
+            let client = self.client(2_000);
+            let order = client.resume_operation(id).await.unwrap();
+            assert!(client.pending_operations().await.unwrap().is_empty());
+            let op = client.operation_log().get(id).await.unwrap().unwrap();
+            assert!(op.completed);
+            order
+        }
+
+        fn count(&self, method: Method, endpoint: &str) -> usize {
+            self.transport
+                .requests()
+                .into_iter()
+                .filter(|request| *request == (method.clone(), endpoint.to_string()))
+                .count()
+        }
+
+        /// Ids of the payments sent to `provider`, in order.
+        fn payment_ids(&self, provider: ProviderId) -> Vec<String> {
+            self.transport
+                .recorded()
+                .into_iter()
+                .filter(|request| request.endpoint == payments(provider))
+                .map(|request| {
+                    let body: Value = serde_json::from_str(&request.body.unwrap()).unwrap();
+                    body["id"].as_str().unwrap().to_string()
+                })
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn issues_card_with_package() {
+        let world = World::new();
+        world.list(&[vec![], vec![order("ord-1", "COMPLETED")]]);
+        let client = world.client(1_000);
+
+        let order = client
+            .issue_virtual_card("qvc-cpa", Some(rub(2_999)))
+            .await
+            .unwrap();
+        assert_eq!(order.status, CardOrderStatus::Completed);
+        assert_eq!(order.card_id, Some(CardId::from(11_111_111)));
+
+        let package = payments(ProviderId::MASTER_PACKAGE);
+        let pay = payments(ProviderId::CARD_ORDER);
+        assert_eq!(
+            world.transport.requests(),
+            vec![
+                (Method::POST, package.clone()),
+                (Method::GET, ORDERS.to_string()),
+                (Method::POST, ORDERS.to_string()),
+                (Method::PUT, submit("ord-1")),
+                (Method::POST, pay.clone()),
+                (Method::GET, ORDERS.to_string()),
+            ]
+        );
+        let bodies = world
+            .transport
+            .recorded()
+            .into_iter()
+            .filter_map(|request| request.body)
+            .map(|body| serde_json::from_str::<Value>(&body).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bodies[0]["fields"],
+            json!({ "account": "79991234567", "vas_alias": "qvc-master" })
+        );
+        assert_eq!(bodies[1], json!({ "cardAlias": "qvc-cpa" }));
+        assert_eq!(
+            bodies[2]["fields"],
+            json!({ "account": "79991234567", "order_id": "ord-1" })
+        );
+        assert_eq!(bodies[2]["sum"]["amount"], json!("199"));
+        assert!(client.pending_operations().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn package_payment_lost_is_sent_again_with_the_same_id() {
+        let world = World::new();
+        world.fail(Method::POST, payments(ProviderId::MASTER_PACKAGE));
+        let id = world.interrupted(true).await;
+
+        world.list(&[vec![], vec![order("ord-1", "COMPLETED")]]);
+        world.resume(&id).await;
+        assert_eq!(
+            world.payment_ids(ProviderId::MASTER_PACKAGE),
+            vec!["1000", "1000"]
+        );
+        assert_eq!(world.payment_ids(ProviderId::CARD_ORDER), vec!["2000"]);
+    }
+
+    #[tokio::test]
+    async fn package_paid_without_response_is_not_paid_again() {
+        let world = World::new();
+        world.fail(Method::POST, payments(ProviderId::MASTER_PACKAGE));
+        let id = world.interrupted(true).await;
+
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["date"] = json!("2020-01-31T14:00:00+03:00");
+        entry["type"] = json!("OUT");
+        entry["trmTxnId"] = json!("1000");
+        world
+            .transport
+            .insert(Method::GET, history(), &fixtures::history_page(vec![entry]));
+        world.list(&[vec![], vec![order("ord-1", "COMPLETED")]]);
+        world.resume(&id).await;
+        assert_eq!(world.payment_ids(ProviderId::MASTER_PACKAGE), vec!["1000"]);
+        assert_eq!(world.count(Method::POST, ORDERS), 1);
+    }
+
+    #[tokio::test]
+    async fn interrupted_before_listing_orders() {
+        let world = World::new();
+        world.fail(Method::GET, ORDERS.to_string());
+        let id = world.interrupted(false).await;
+
+        world.list(&[vec![], vec![order("ord-1", "COMPLETED")]]);
+        assert_eq!(world.resume(&id).await.status, CardOrderStatus::Completed);
+        assert_eq!(world.count(Method::POST, ORDERS), 1);
+        assert_eq!(world.payment_ids(ProviderId::CARD_ORDER), vec!["2000"]);
+    }
+
+    #[tokio::test]
+    async fn created_order_is_adopted() {
+        let world = World::new();
+        world.list(&[vec![order("ord-0", "DRAFT")]]);
+        world.fail(Method::POST, ORDERS.to_string());
+        let id = world.interrupted(false).await;
+
+        // `ord-0` was there before, `ord-1` is the one created before the response was lost.
+        world.list(&[
+            vec![order("ord-0", "DRAFT"), order("ord-1", "DRAFT")],
+            vec![order("ord-1", "COMPLETED")],
+        ]);
+        world.resume(&id).await;
+        assert_eq!(world.count(Method::POST, ORDERS), 1);
+        assert_eq!(world.count(Method::PUT, &submit("ord-0")), 0);
+        assert_eq!(world.count(Method::PUT, &submit("ord-1")), 1);
+    }
+
+    #[tokio::test]
+    async fn order_not_created_is_created_on_resume() {
+        let world = World::new();
+        world.list(&[vec![order("ord-0", "DRAFT")]]);
+        world.fail(Method::POST, ORDERS.to_string());
+        let id = world.interrupted(false).await;
+
+        world.list(&[
+            vec![order("ord-0", "DRAFT")],
+            vec![order("ord-1", "COMPLETED")],
+        ]);
+        world.resume(&id).await;
+        assert_eq!(world.count(Method::POST, ORDERS), 2);
+        assert_eq!(world.count(Method::PUT, &submit("ord-0")), 0);
+    }
+
+    #[tokio::test]
+    async fn confirmed_order_is_not_confirmed_again() {
+        let world = World::new();
+        world.list(&[vec![]]);
+        world.fail(Method::PUT, submit("ord-1"));
+        let id = world.interrupted(false).await;
+
+        world.list(&[
+            vec![order("ord-1", "PAYMENT_REQUIRED")],
+            vec![order("ord-1", "COMPLETED")],
+        ]);
+        world.resume(&id).await;
+        assert_eq!(world.count(Method::PUT, &submit("ord-1")), 1);
+        assert_eq!(world.payment_ids(ProviderId::CARD_ORDER), vec!["2000"]);
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_order_is_confirmed_on_resume() {
+        let world = World::new();
+        world.list(&[vec![]]);
+        world.fail(Method::PUT, submit("ord-1"));
+        let id = world.interrupted(false).await;
+
+        world.list(&[
+            vec![order("ord-1", "DRAFT")],
+            vec![order("ord-1", "COMPLETED")],
+        ]);
+        world.resume(&id).await;
+        assert_eq!(world.count(Method::PUT, &submit("ord-1")), 2);
+        assert_eq!(world.payment_ids(ProviderId::CARD_ORDER), vec!["2000"]);
+    }
+
+    #[tokio::test]
+    async fn order_payment_lost_is_sent_again_with_the_same_id() {
+        let world = World::new();
+        world.list(&[vec![]]);
+        world.fail(Method::POST, payments(ProviderId::CARD_ORDER));
+        let id = world.interrupted(false).await;
+
+        world.list(&[
+            vec![order("ord-1", "PAYMENT_REQUIRED")],
+            vec![order("ord-1", "COMPLETED")],
+        ]);
+        world.resume(&id).await;
+        assert_eq!(world.count(Method::POST, ORDERS), 1);
+        assert_eq!(world.count(Method::PUT, &submit("ord-1")), 1);
+        assert_eq!(
+            world.payment_ids(ProviderId::CARD_ORDER),
+            vec!["1000", "1000"]
+        );
+    }
+
+    #[tokio::test]
+    async fn paid_order_is_not_paid_again() {
+        let world = World::new();
+        world.list(&[vec![]]);
+        world.fail(Method::GET, ORDERS.to_string());
+        let id = world.interrupted(false).await;
+
+        world.list(&[vec![order("ord-1", "COMPLETED")]]);
+        let order = world.resume(&id).await;
+        assert_eq!(order.card_id, Some(CardId::from(11_111_111)));
+        assert_eq!(world.payment_ids(ProviderId::CARD_ORDER), vec!["1000"]);
+    }
+
+    /// Renames card 11111111 to `Travel` with the server answering `response`.
+    async fn rename(response: Value) -> QiwiResult<()> {
+        let endpoint = "cards/v1/cards/11111111/alias";
+        let transport = Arc::new(OfflineTransport::new().with(Method::PUT, endpoint, &response));
+        let client = Client::builder(phone(), "")
+            .transport(transport.clone())
+            .build();
+        let result = client.rename_card(CardId::from(11_111_111), "Travel").await;
+
+        let recorded = transport.recorded();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].endpoint, endpoint);
+        let body: Value = serde_json::from_str(recorded[0].body.as_deref().unwrap()).unwrap();
+        assert_eq!(body, json!({ "alias": "Travel" }));
+        result
+    }
+
+    fn rename_error_code(result: QiwiResult<()>) -> String {
+        match result {
+            Err(Error::QiwiError { error }) => error.error_code,
+            other => panic!("expected QiwiError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn card_is_renamed() {
+        rename(json!({ "status": "OK", "error": null, "errorCode": null }))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn card_renaming_failures_are_qiwi_errors() {
+        let with_message =
+            json!({ "status": "ERROR", "error": "Card not found", "errorCode": null });
+        assert_eq!(
+            rename_error_code(rename(with_message).await),
+            "Card not found"
+        );
+
+        let with_code = json!({ "status": "ERROR", "error": null, "errorCode": "card.not.found" });
+        assert_eq!(rename_error_code(rename(with_code).await), "card.not.found");
+
+        // Neither a message nor a code, only the status tells that renaming failed.
+        let status_only = json!({ "status": "FAIL", "error": null, "errorCode": null });
+        assert_eq!(rename_error_code(rename(status_only).await), "FAIL");
+    }
+
+    #[tokio::test]
+    async fn unknown_operation_is_rejected() {
+        let world = World::new();
+        match world.client(1_000).resume_operation("missing").await {
+            Err(Error::UnknownOperation { id }) => assert_eq!(id, "missing"),
+            other => panic!("expected UnknownOperation, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Sources of the current time, for deadlines, rolling windows and expiry.
+
+use {
+    async_trait::async_trait,
+    chrono::prelude::*,
+    std::{
+        fmt::Debug,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Tells the time to the client, see [`ClientBuilder::clock`](crate::ClientBuilder::clock).
+#[async_trait]
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// Monotonic time for deadlines and intervals.
+    fn now(&self) -> Instant;
+    /// Wall-clock time for rolling windows and expiry.
+    fn utc_now(&self) -> DateTime<Utc>;
+    /// Waits until `now()` reaches `deadline`.
+    async fn sleep_until(&self, deadline: Instant);
+}
+
+#[async_trait]
+impl<C: Clock> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        (**self).utc_now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        (**self).sleep_until(deadline).await
+    }
+}
+
+/// System time and tokio timers.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::delay_until(tokio::time::Instant::from_std(deadline)).await
+    }
+}
+
+/// Time that only moves when told to, for reproducible tests.
+///
+/// Sleeping returns at once and moves the time forward to the deadline, so that waits
+/// and timeouts of minutes take no real time.
+#[derive(Debug)]
+pub struct ManualClock {
+    time: Mutex<(Instant, DateTime<Utc>)>,
+}
+
+impl ManualClock {
+    /// Starts at `utc_now`, the monotonic time starts at the moment of creation.
+    pub fn new(utc_now: DateTime<Utc>) -> Self {
+        Self {
+            time: Mutex::new((Instant::now(), utc_now)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.0 += by;
+        time.1 = time.1 + chrono::Duration::from_std(by).unwrap();
+    }
+}
+
+#[async_trait]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.time.lock().unwrap().0
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        self.time.lock().unwrap().1
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        let now = self.now();
+        if deadline > now {
+            self.advance(deadline - now);
+        }
+    }
+}
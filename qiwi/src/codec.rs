@@ -0,0 +1,230 @@
+//! Serialization of persisted state, see [`FileStateStore::with_codec`](crate::state::FileStateStore::with_codec).
+//!
+//! JSON is written as is. Other codecs prefix their output with an envelope of a zero byte, the
+//! codec tag and the format version. Since JSON never starts with a zero byte, data written by
+//! any codec, including state saved before codecs existed, is read regardless of the codec in use.
+
+use {
+    crate::StdError,
+    serde::{de::DeserializeOwned, Serialize},
+    serde_json::Value,
+    std::fmt::Debug,
+};
+
+const ENVELOPE_MARKER: u8 = 0;
+const ENVELOPE_VERSION: u8 = 1;
+
+#[cfg(feature = "cbor")]
+const CBOR_TAG: u8 = b'C';
+
+/// Format of persisted values.
+pub trait Codec: Debug + Send + Sync + 'static {
+    /// Identifies the codec in the envelope, `None` for untagged JSON.
+    fn tag(&self) -> Option<u8>;
+    /// File name extension for [`FileStateStore`](crate::state::FileStateStore).
+    fn extension(&self) -> &'static str;
+    fn encode_value(&self, value: &Value) -> Result<Vec<u8>, StdError>;
+    fn decode_value(&self, data: &[u8]) -> Result<Value, StdError>;
+}
+
+/// Plain JSON, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn tag(&self) -> Option<u8> {
+        None
+    }
+
+    fn extension(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode_value(&self, value: &Value) -> Result<Vec<u8>, StdError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode_value(&self, data: &[u8]) -> Result<Value, StdError> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+/// CBOR, several times more compact for large histories.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn tag(&self) -> Option<u8> {
+        Some(CBOR_TAG)
+    }
+
+    fn extension(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn encode_value(&self, value: &Value) -> Result<Vec<u8>, StdError> {
+        Ok(serde_cbor::to_vec(value)?)
+    }
+
+    fn decode_value(&self, data: &[u8]) -> Result<Value, StdError> {
+        Ok(serde_cbor::from_slice(data)?)
+    }
+}
+
+/// Serializes `value` with `codec`, in an envelope unless it is JSON.
+pub fn encode<T: Serialize + ?Sized>(codec: &dyn Codec, value: &T) -> Result<Vec<u8>, StdError> {
+    let value = serde_json::to_value(value)?;
+    let body = codec.encode_value(&value)?;
+    Ok(match codec.tag() {
+        Some(tag) => {
+            let mut data = Vec::with_capacity(body.len() + 3);
+            data.extend_from_slice(&[ENVELOPE_MARKER, tag, ENVELOPE_VERSION]);
+            data.extend_from_slice(&body);
+            data
+        }
+        None => body,
+    })
+}
+
+/// Deserializes data written by [`encode`] with any codec.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, StdError> {
+    let value = match data {
+        [ENVELOPE_MARKER, tag, version, body @ ..] => {
+            if *version != ENVELOPE_VERSION {
+                return Err(format!("unsupported state format version {}", version).into());
+            }
+            codec_for(*tag)?.decode_value(body)?
+        }
+        [ENVELOPE_MARKER, ..] => return Err("truncated state envelope".into()),
+        _ => JsonCodec.decode_value(data)?,
+    };
+    Ok(serde_json::from_value(value)?)
+}
+
+fn codec_for(tag: u8) -> Result<&'static dyn Codec, StdError> {
+    match tag {
+        #[cfg(feature = "cbor")]
+        CBOR_TAG => Ok(&CborCodec),
+        #[cfg(not(feature = "cbor"))]
+        b'C' => Err("state is encoded with CBOR, enable the `cbor` feature to read it".into()),
+        tag => Err(format!("unknown state codec {:?}", char::from(tag)).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::fixtures, serde_json::json};
+
+    #[cfg(feature = "cbor")]
+    use crate::state::{self, FileStateStore, StateStore};
+
+    fn sample() -> Value {
+        json!({
+            "history": fixtures::history_entries(20),
+            "cursor": { "txnId": 10_000_000_020u64, "date": "2020-01-31T12:00:00+03:00" },
+            "rate": 1.5,
+            "balance": -12.25,
+            "comment": "Перевод 🙂",
+            "tags": [],
+            "nothing": null,
+            "flag": true,
+        })
+    }
+
+    #[test]
+    fn json_is_written_without_envelope() {
+        let data = encode(&JsonCodec, &sample()).unwrap();
+        assert_eq!(data, serde_json::to_vec(&sample()).unwrap());
+        assert_eq!(decode::<Value>(&data).unwrap(), sample());
+    }
+
+    #[test]
+    fn unsupported_envelopes_are_rejected() {
+        for (data, error) in &[
+            (&[ENVELOPE_MARKER][..], "truncated state envelope"),
+            (&[ENVELOPE_MARKER, b'C'][..], "truncated state envelope"),
+            (
+                &[ENVELOPE_MARKER, b'C', ENVELOPE_VERSION + 1, 0xa0][..],
+                "unsupported state format version 2",
+            ),
+            (
+                &[ENVELOPE_MARKER, b'X', ENVELOPE_VERSION, 0xa0][..],
+                "unknown state codec 'X'",
+            ),
+        ] {
+            assert_eq!(decode::<Value>(data).unwrap_err().to_string(), *error);
+        }
+    }
+
+    #[cfg(not(feature = "cbor"))]
+    #[test]
+    fn cbor_needs_the_feature() {
+        let error = decode::<Value>(&[ENVELOPE_MARKER, b'C', ENVELOPE_VERSION, 0xa0]).unwrap_err();
+        assert!(error.to_string().contains("`cbor` feature"));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_round_trip() {
+        let data = encode(&CborCodec, &sample()).unwrap();
+        assert_eq!(data[..3], [ENVELOPE_MARKER, CBOR_TAG, ENVELOPE_VERSION]);
+        assert_eq!(decode::<Value>(&data).unwrap(), sample());
+        assert!(data.len() < encode(&JsonCodec, &sample()).unwrap().len());
+
+        // Either format converts to the other without loss.
+        let json = encode(&JsonCodec, &decode::<Value>(&data).unwrap()).unwrap();
+        assert_eq!(json, encode(&JsonCodec, &sample()).unwrap());
+        let cbor = encode(&CborCodec, &decode::<Value>(&json).unwrap()).unwrap();
+        assert_eq!(cbor, data);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn json_state_migrates_to_cbor() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Saved before codecs existed, as plain JSON.
+        let json_store = FileStateStore::new(dir.path());
+        state::save(&json_store, "sync.cursor", &sample())
+            .await
+            .unwrap();
+        let json_path = dir.path().join("sync.cursor.json");
+        assert!(json_path.exists());
+
+        let store = FileStateStore::new(dir.path()).with_codec(CborCodec);
+        let loaded = state::load::<Value>(&store, "sync.cursor").await.unwrap();
+        assert_eq!(loaded, Some(sample()));
+
+        // Saving rewrites the value as CBOR and drops the JSON file.
+        state::save(&store, "sync.cursor", &sample()).await.unwrap();
+        assert!(!json_path.exists());
+        let data = std::fs::read(dir.path().join("sync.cursor.cbor")).unwrap();
+        assert_eq!(data[..3], [ENVELOPE_MARKER, CBOR_TAG, ENVELOPE_VERSION]);
+        let loaded = state::load::<Value>(&store, "sync.cursor").await.unwrap();
+        assert_eq!(loaded, Some(sample()));
+
+        // Switching back to JSON still reads the CBOR value through its envelope.
+        let data = store.get("sync.cursor").await.unwrap().unwrap();
+        json_store.put("sync.cursor", data).await.unwrap();
+        let loaded = state::load::<Value>(&json_store, "sync.cursor")
+            .await
+            .unwrap();
+        assert_eq!(loaded, Some(sample()));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[tokio::test]
+    async fn taking_migrated_value_leaves_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        state::save(&FileStateStore::new(dir.path()), "key", &sample())
+            .await
+            .unwrap();
+
+        let store = FileStateStore::new(dir.path()).with_codec(CborCodec);
+        let taken = store.take("key").await.unwrap().unwrap();
+        assert_eq!(decode::<Value>(&taken).unwrap(), sample());
+        assert_eq!(store.get("key").await.unwrap(), None);
+    }
+}
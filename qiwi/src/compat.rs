@@ -0,0 +1,288 @@
+//! Tolerance for renamed response fields, see [`ClientBuilder::lenient_parsing`](crate::ClientBuilder::lenient_parsing).
+
+use {
+    log::*,
+    serde::Serialize,
+    serde_json::{Map, Value},
+    std::{collections::BTreeSet, sync::Mutex},
+};
+
+/// Former field names with the current ones.
+///
+/// Former names are not used by any current model, so they are renamed wherever they appear.
+/// Models accept the former names as serde aliases as well, this table makes their use visible.
+pub const RENAMED_FIELDS: &[(&str, &str)] = &[
+    ("transactionId", "txnId"),
+    ("txn_id", "txnId"),
+    ("terminalTransactionId", "trmTxnId"),
+    ("trm_txn_id", "trmTxnId"),
+    ("firstTransactionId", "firstTxnId"),
+    ("first_txn_id", "firstTxnId"),
+    ("nextTransactionId", "nextTxnId"),
+    ("next_txn_id", "nextTxnId"),
+    ("nextTransactionDate", "nextTxnDate"),
+    ("next_txn_date", "nextTxnDate"),
+    ("error_code", "errorCode"),
+];
+
+/// Former field name found in a response.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct CompatNote {
+    /// Object holding the field, e.g. `data[].sum`, empty for the top level.
+    pub path: String,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+/// Former field names found in the response of one call.
+#[derive(Clone, Debug, Serialize)]
+pub struct CompatReport {
+    pub endpoint: String,
+    pub correlation_id: String,
+    pub notes: Vec<CompatNote>,
+}
+
+/// Renames former fields in `value` to the current names.
+pub(crate) fn normalize(value: &mut Value) -> Vec<CompatNote> {
+    let mut notes = BTreeSet::new();
+    normalize_at(value, String::new(), &mut notes);
+    notes.into_iter().collect()
+}
+
+fn normalize_at(value: &mut Value, path: String, notes: &mut BTreeSet<CompatNote>) {
+    match value {
+        Value::Object(map) => {
+            rename_fields(map, &path, notes);
+            for (key, v) in map.iter_mut() {
+                let path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                normalize_at(v, path, notes);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                normalize_at(item, format!("{}[]", path), notes);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn rename_fields(map: &mut Map<String, Value>, path: &str, notes: &mut BTreeSet<CompatNote>) {
+    for &(from, to) in RENAMED_FIELDS {
+        if map.contains_key(to) {
+            continue;
+        }
+        if let Some(v) = map.remove(from) {
+            map.insert(to.to_string(), v);
+            notes.insert(CompatNote {
+                path: path.to_string(),
+                from,
+                to,
+            });
+        }
+    }
+}
+
+/// Keeps the last report with notes.
+#[derive(Debug, Default)]
+pub(crate) struct CompatLog {
+    last: Mutex<Option<CompatReport>>,
+}
+
+impl CompatLog {
+    pub(crate) fn record(&self, report: CompatReport) {
+        for note in &report.notes {
+            warn!(
+                "[{}] {} returned former field {} at `{}`, now {}",
+                report.correlation_id, report.endpoint, note.from, note.path, note.to
+            );
+        }
+        *self.last.lock().unwrap() = Some(report);
+    }
+
+    pub(crate) fn last(&self) -> Option<CompatReport> {
+        self.last.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::*, serde::de::DeserializeOwned, serde_json::json};
+
+    /// Renames `to` back to `from` everywhere, as an older API version would send it.
+    fn former(value: &Value, from: &str, to: &str) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, v)| {
+                        let key = if key == to { from } else { key.as_str() };
+                        (key.to_string(), former(v, from, to))
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => {
+                Value::Array(items.iter().map(|v| former(v, from, to)).collect())
+            }
+            v => v.clone(),
+        }
+    }
+
+    fn reparsed<T: DeserializeOwned + Serialize>(value: Value) -> Result<Value, serde_json::Error> {
+        Ok(serde_json::to_value(serde_json::from_value::<T>(value)?).unwrap())
+    }
+
+    type Parse = fn(Value) -> Result<Value, serde_json::Error>;
+
+    fn history_page() -> Value {
+        let mut page = fixtures::history_page(fixtures::history_entries(2));
+        page["nextTxnId"] = json!(10_000_000_000u64);
+        page["nextTxnDate"] = json!("2020-01-31T12:00:00+03:00");
+        page
+    }
+
+    /// Responses of models with former field names, with their parsers.
+    fn documents() -> Vec<(&'static str, Value, Parse)> {
+        vec![
+            (
+                "UserInfo",
+                fixtures::profile()["authInfo"]["contractInfo"]["userInfo"].clone(),
+                reparsed::<UserInfo> as Parse,
+            ),
+            (
+                "PaymentHistoryData",
+                history_page(),
+                reparsed::<PaymentHistoryData> as Parse,
+            ),
+            (
+                "QiwiApiError",
+                json!({ "errorCode": "payment.blocked", "description": "Blocked" }),
+                reparsed::<QiwiApiError> as Parse,
+            ),
+        ]
+    }
+
+    fn contains_key(value: &Value, key: &str) -> bool {
+        match value {
+            Value::Object(map) => {
+                map.contains_key(key) || map.values().any(|v| contains_key(v, key))
+            }
+            Value::Array(items) => items.iter().any(|v| contains_key(v, key)),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn aliases() {
+        for &(from, to) in RENAMED_FIELDS {
+            let mut covered = false;
+            for (model, current, parse) in documents() {
+                if !contains_key(&current, to) {
+                    continue;
+                }
+                covered = true;
+                let old = former(&current, from, to);
+                assert!(contains_key(&old, from) && !contains_key(&old, to));
+
+                // Both spellings parse to the same model.
+                assert_eq!(
+                    parse(old.clone()).unwrap_or_else(|e| panic!("{} as {}: {}", model, from, e)),
+                    parse(current.clone()).unwrap(),
+                    "{} as {}",
+                    model,
+                    from
+                );
+
+                let mut normalized = old;
+                let notes = normalize(&mut normalized);
+                assert_eq!(normalized, current, "{} as {}", model, from);
+                assert!(!notes.is_empty());
+                assert!(notes.iter().all(|note| note.from == from && note.to == to));
+            }
+            assert!(covered, "{} -> {} is not in any model", from, to);
+        }
+    }
+
+    #[test]
+    fn notes_name_the_path() {
+        let mut page = former(&history_page(), "transactionId", "txnId");
+        page = former(&page, "next_txn_id", "nextTxnId");
+
+        assert_eq!(
+            normalize(&mut page),
+            vec![
+                CompatNote {
+                    path: String::new(),
+                    from: "next_txn_id",
+                    to: "nextTxnId",
+                },
+                CompatNote {
+                    path: "data[]".to_string(),
+                    from: "transactionId",
+                    to: "txnId",
+                },
+            ]
+        );
+        assert_eq!(page, history_page());
+        assert!(normalize(&mut page).is_empty());
+    }
+
+    #[test]
+    fn current_names_win() {
+        let mut value = json!({ "txnId": 2, "transactionId": 1 });
+        assert!(normalize(&mut value).is_empty());
+        assert_eq!(value["txnId"], json!(2));
+    }
+
+    #[cfg(feature = "history")]
+    mod client {
+        use {super::*, std::sync::Arc, tokio::stream::StreamExt};
+
+        fn client(lenient: bool) -> Client {
+            let phone: PhoneNumber = "+79991234567".parse().unwrap();
+            let page = former(&history_page(), "trm_txn_id", "trmTxnId");
+            let transport = Arc::new(OfflineTransport::new().with(
+                Method::GET,
+                ApiVersions::default().history_endpoint(&QiwiUser::from(phone.clone())),
+                &former(&page, "nextTransactionDate", "nextTxnDate"),
+            ));
+            Client::builder(phone, "")
+                .transport(transport)
+                .lenient_parsing(lenient)
+                .build()
+        }
+
+        #[tokio::test]
+        async fn lenient_client_reports_former_names() {
+            let client = client(true);
+            assert!(client.last_compat_report().is_none());
+
+            let entry = client.payment_history().next().await.unwrap().unwrap();
+            assert_eq!(entry.trm_txn_id, history_page()["data"][0]["trmTxnId"]);
+            let report = client.last_compat_report().unwrap();
+            assert!(
+                report.endpoint.ends_with("/payments"),
+                "{}",
+                report.endpoint
+            );
+            assert_eq!(
+                report
+                    .notes
+                    .iter()
+                    .map(|note| (note.path.as_str(), note.from))
+                    .collect::<Vec<_>>(),
+                vec![("", "nextTransactionDate"), ("data[]", "trm_txn_id")]
+            );
+        }
+
+        #[tokio::test]
+        async fn strict_client_parses_aliases_silently() {
+            let client = client(false);
+            client.payment_history().next().await.unwrap().unwrap();
+            assert!(client.last_compat_report().is_none());
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+};
+
+/// How long a prepared transfer can be confirmed.
+const CONFIRMATION_TTL_MINUTES: i64 = 5;
+
+/// Transfer awaiting confirmation, see [`Client::prepare_transfer`].
+#[derive(Clone, Debug)]
+pub struct PreparedTransfer {
+    /// Pass to [`Client::confirm_transfer`] to send the transfer.
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    pub request: TransferRequest,
+    pub quote: TransferQuote,
+    /// Amount debited from the wallet, commission included.
+    pub total: Money,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum StoredDirection {
+    #[serde(rename_all = "camelCase")]
+    Qiwi {
+        to_phone: String,
+        to_currency: QiwiCurrency,
+    },
+    #[serde(rename_all = "camelCase")]
+    Cellular { carrier: u64, to_phone: String },
+    #[serde(rename_all = "camelCase")]
+    CellularAuto { to_phone: String },
+    #[serde(rename_all = "camelCase")]
+    Card {
+        pan: String,
+        provider: Option<ProviderId>,
+    },
+    #[serde(rename_all = "camelCase")]
+    BankAccount {
+        provider: ProviderId,
+        fields: BankTransferFields,
+    },
+}
+
+/// Prepared transfer as kept in the state store.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingTransfer {
+    id: u64,
+    sum: Money,
+    direction: StoredDirection,
+    comment: String,
+    source: Option<AccountAlias>,
+    force: bool,
+    expires_at: DateTime<Utc>,
+}
+
+fn format_phone(phone: &PhoneNumber) -> String {
+    format!("+{}", QiwiUser::from(phone.clone()))
+}
+
+impl PendingTransfer {
+    fn new(req: &TransferRequest, expires_at: DateTime<Utc>) -> Self {
+        Self {
+            id: req.idempotency_id(),
+            sum: Money::new(req.amount.clone(), req.direction.currency()),
+            direction: match &req.direction {
+                TransferDirection::Qiwi {
+                    to_phone,
+                    to_currency,
+                } => StoredDirection::Qiwi {
+                    to_phone: format_phone(to_phone),
+                    to_currency: QiwiCurrency::from(*to_currency),
+                },
+                TransferDirection::Cellular { carrier, to_phone } => StoredDirection::Cellular {
+                    carrier: *carrier,
+                    to_phone: format_phone(to_phone),
+                },
+                TransferDirection::CellularAuto { to_phone } => StoredDirection::CellularAuto {
+                    to_phone: format_phone(to_phone),
+                },
+                TransferDirection::Card { pan, provider } => StoredDirection::Card {
+                    pan: pan.as_str().to_string(),
+                    provider: *provider,
+                },
+                TransferDirection::BankAccount { provider, fields } => {
+                    StoredDirection::BankAccount {
+                        provider: *provider,
+                        fields: fields.clone(),
+                    }
+                }
+            },
+            comment: req.comment.clone(),
+            source: req.source.clone(),
+            force: req.force,
+            expires_at,
+        }
+    }
+
+    fn into_request(self) -> Result<TransferRequest, StdError> {
+        let direction = match self.direction {
+            StoredDirection::Qiwi {
+                to_phone,
+                to_currency,
+            } => TransferDirection::Qiwi {
+                to_phone: to_phone.parse()?,
+                to_currency: to_currency.currency(),
+            },
+            StoredDirection::Cellular { carrier, to_phone } => TransferDirection::Cellular {
+                carrier,
+                to_phone: to_phone.parse()?,
+            },
+            StoredDirection::CellularAuto { to_phone } => TransferDirection::CellularAuto {
+                to_phone: to_phone.parse()?,
+            },
+            StoredDirection::Card { pan, provider } => TransferDirection::Card {
+                pan: pan.parse()?,
+                provider,
+            },
+            StoredDirection::BankAccount { provider, fields } => {
+                TransferDirection::BankAccount { provider, fields }
+            }
+        };
+
+        let mut req = TransferRequest::with_id(self.id, self.sum.amount, direction, self.comment);
+        req.source = self.source;
+        req.force = self.force;
+        Ok(req)
+    }
+}
+
+fn pending_key(token: &str) -> String {
+    format!("pending-transfer.{}", token)
+}
+
+impl Client {
+    /// Quotes the transfer and keeps it until confirmed with [`Client::confirm_transfer`].
+    ///
+    /// Prepared transfers are held in the [state store](ClientBuilder::state_store), or in memory
+    /// if there is none, and expire after five minutes.
+    pub async fn prepare_transfer(&self, req: TransferRequest) -> QiwiResult<PreparedTransfer> {
+        let quote = self
+            .quote_transfer(&req.direction, req.amount.clone())
+            .await?;
+        let token = self.ids.next_token();
+        let expires_at = self.clock.utc_now() + chrono::Duration::minutes(CONFIRMATION_TTL_MINUTES);
+
+        state::save(
+            &*self.local_state,
+            &pending_key(&token),
+            &PendingTransfer::new(&req, expires_at),
+        )
+        .await
+        .context(StateStoreError)?;
+
+        Ok(PreparedTransfer {
+            token,
+            expires_at,
+            request: req,
+            total: quote.commission.withdraw_sum.clone(),
+            quote,
+        })
+    }
+
+    /// Sends a transfer prepared with [`Client::prepare_transfer`].
+    ///
+    /// A token can be used once, concurrent calls with the same token send the transfer at most once.
+    pub async fn confirm_transfer(&self, token: &str) -> QiwiResult<TransferData> {
+        ensure!(
+            !token.is_empty() && token.chars().all(|c| c.is_ascii_hexdigit() || c == '-'),
+            InvalidConfirmation
+        );
+
+        let data = self
+            .local_state
+            .take(&pending_key(token))
+            .await
+            .context(StateStoreError)?
+            .context(InvalidConfirmation)?;
+        let pending = codec::decode::<PendingTransfer>(&data).context(StateStoreError)?;
+        ensure!(
+            pending.expires_at > self.clock.utc_now(),
+            ConfirmationExpired {
+                expired_at: pending.expires_at,
+            }
+        );
+
+        let req = pending.into_request().context(StateStoreError)?;
+        self.transfer(&req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, serde_json::json, std::time::Duration};
+
+    const CARRIER: u64 = 1;
+
+    fn payments_endpoint() -> String {
+        format!("sinap/api/v2/terms/{}/payments", CARRIER)
+    }
+
+    fn client() -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let rub = |amount: &str| json!({ "amount": amount, "currency": "643" });
+        let transport = Arc::new(
+            OfflineTransport::new()
+                .with(
+                    Method::POST,
+                    format!("sinap/providers/{}/onlineCommission", CARRIER),
+                    &json!({
+                        "withdrawSum": rub("100"),
+                        "enrollmentSum": rub("100"),
+                        "qwCommission": rub("0"),
+                        "fundingSourceCommission": rub("0"),
+                        "withdrawToEnrollmentRate": "1",
+                    }),
+                )
+                .with(
+                    Method::POST,
+                    payments_endpoint(),
+                    &json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } }),
+                ),
+        );
+        let clock = Arc::new(ManualClock::new(
+            Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap(),
+        ));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .id_generator(ids::SequentialIdGenerator::new(1_000))
+            .build();
+        (client, transport, clock)
+    }
+
+    fn request(client: &Client) -> TransferRequest {
+        client.transfer_request(
+            BigDecimal::from(100),
+            TransferDirection::Cellular {
+                carrier: CARRIER,
+                to_phone: "+79035550101".parse().unwrap(),
+            },
+            String::new(),
+        )
+    }
+
+    fn payments(transport: &OfflineTransport) -> usize {
+        transport
+            .requests()
+            .into_iter()
+            .filter(|(method, endpoint)| {
+                *method == Method::POST && *endpoint == payments_endpoint()
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn confirms_before_expiry() {
+        let (client, transport, clock) = client();
+        let prepared = client.prepare_transfer(request(&client)).await.unwrap();
+        assert_eq!(
+            prepared.expires_at,
+            clock.utc_now() + chrono::Duration::minutes(5)
+        );
+        assert_eq!(payments(&transport), 0);
+
+        clock.advance(Duration::from_secs(4 * 60 + 59));
+        client.confirm_transfer(&prepared.token).await.unwrap();
+        assert_eq!(payments(&transport), 1);
+    }
+
+    #[tokio::test]
+    async fn prepared_transfer_golden() {
+        let (client, transport, _) = client();
+        let prepared = client.prepare_transfer(request(&client)).await.unwrap();
+        assert_eq!(prepared.token, "00000000-0000-0000-0000-0000000003e8");
+        assert_eq!(prepared.request.idempotency_id(), 1_000);
+        assert_eq!(
+            prepared.expires_at,
+            Utc.with_ymd_and_hms(2020, 1, 31, 12, 5, 0).unwrap()
+        );
+
+        let stored = client
+            .local_state
+            .get(&pending_key(&prepared.token))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stored).unwrap(),
+            concat!(
+                r#"{"comment":"","direction":{"carrier":1,"kind":"cellular","toPhone":"+79035550101"},"#,
+                r#""expiresAt":"2020-01-31T12:05:00Z","force":false,"id":1000,"source":null,"#,
+                r#""sum":{"amount":"100","currency":"643"}}"#,
+            )
+        );
+
+        client.confirm_transfer(&prepared.token).await.unwrap();
+        assert_eq!(
+            transport.recorded().pop().unwrap().body.unwrap(),
+            concat!(
+                r#"{"comment":"","fields":{"account":"9035550101"},"id":"1000","#,
+                r#""paymentMethod":{"accountId":"643","type":"Account"},"#,
+                r#""sum":{"amount":"100","currency":"643"}}"#,
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn expires() {
+        let (client, transport, clock) = client();
+        let prepared = client.prepare_transfer(request(&client)).await.unwrap();
+
+        clock.advance(Duration::from_secs(5 * 60));
+        match client.confirm_transfer(&prepared.token).await {
+            Err(Error::ConfirmationExpired { expired_at }) => {
+                assert_eq!(expired_at, prepared.expires_at)
+            }
+            other => panic!("expected ConfirmationExpired, got {:?}", other),
+        }
+        // An expired token is used up as well.
+        assert!(matches!(
+            client.confirm_transfer(&prepared.token).await,
+            Err(Error::InvalidConfirmation)
+        ));
+        assert_eq!(payments(&transport), 0);
+    }
+
+    #[tokio::test]
+    async fn single_use() {
+        let (client, transport, _) = client();
+        let prepared = client.prepare_transfer(request(&client)).await.unwrap();
+
+        client.confirm_transfer(&prepared.token).await.unwrap();
+        assert!(matches!(
+            client.confirm_transfer(&prepared.token).await,
+            Err(Error::InvalidConfirmation)
+        ));
+        assert_eq!(payments(&transport), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_confirms_send_once() {
+        let (client, transport, _) = client();
+        let prepared = client.prepare_transfer(request(&client)).await.unwrap();
+
+        let (first, second) = tokio::join!(
+            client.confirm_transfer(&prepared.token),
+            client.confirm_transfer(&prepared.token)
+        );
+        let results = vec![first, second];
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        assert_eq!(
+            results
+                .iter()
+                .filter(|result| matches!(result, Err(Error::InvalidConfirmation)))
+                .count(),
+            1
+        );
+        assert_eq!(payments(&transport), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_and_malformed_tokens() {
+        let (client, transport, _) = client();
+        client.prepare_transfer(request(&client)).await.unwrap();
+
+        for token in &["", "../pending", "00000000-0000-0000-0000-000000000099"] {
+            assert!(
+                matches!(
+                    client.confirm_transfer(token).await,
+                    Err(Error::InvalidConfirmation)
+                ),
+                "{:?}",
+                token
+            );
+        }
+        assert_eq!(payments(&transport), 0);
+    }
+}
@@ -0,0 +1,140 @@
+//! Validation of responses against expected contracts, see [`ClientBuilder::response_validator`](crate::ClientBuilder::response_validator).
+
+use {
+    crate::StdError,
+    serde::Deserialize,
+    serde_json::Value,
+    std::{collections::BTreeMap, fmt::Debug, path::Path},
+};
+
+/// Checks parsed response bodies before they are turned into models.
+///
+/// Called with the endpoint as sent, e.g. `payment-history/v2/persons/79991234567/payments`, and
+/// the body of every successful response parsed by the client. QIWI error bodies and raw calls,
+/// e.g. [`Client::call_raw`](crate::Client::call_raw), are not validated. Transports report the
+/// bodies of successful responses only, so there is no status to check.
+pub trait ResponseValidator: Debug + Send + Sync + 'static {
+    /// Warnings to log, or details of a violation that fails the call with
+    /// [`Error::ContractViolation`](crate::Error::ContractViolation).
+    fn validate(&self, endpoint: &str, value: &Value) -> Result<Vec<String>, String>;
+}
+
+impl<V: ResponseValidator + ?Sized> ResponseValidator for Box<V> {
+    fn validate(&self, endpoint: &str, value: &Value) -> Result<Vec<String>, String> {
+        (**self).validate(endpoint, value)
+    }
+}
+
+/// Field paths required in responses of matching endpoints.
+///
+/// The spec is a JSON object mapping endpoint patterns to lists of fields, e.g.
+///
+/// ```json
+/// {
+///     "payment-history/v2/persons/*/payments": ["data", "data[].txnId", "data[].sum.amount"]
+/// }
+/// ```
+///
+/// A `*` in a pattern matches one path segment. In field paths `.` separates object keys and
+/// `[]` applies the rest of the path to every element of an array. Responses of endpoints not
+/// in the spec are not checked.
+#[derive(Clone, Debug, Default)]
+pub struct RequiredFieldsValidator {
+    spec: BTreeMap<String, Vec<String>>,
+    warn_only: bool,
+}
+
+impl RequiredFieldsValidator {
+    pub fn new(spec: BTreeMap<String, Vec<String>>) -> Self {
+        Self {
+            spec,
+            warn_only: false,
+        }
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, StdError> {
+        #[derive(Deserialize)]
+        #[serde(transparent)]
+        struct Spec(BTreeMap<String, Vec<String>>);
+
+        Ok(Self::new(serde_json::from_slice::<Spec>(data)?.0))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, StdError> {
+        Self::from_json(&std::fs::read(path)?)
+    }
+
+    /// Report missing fields as warnings instead of failing the call.
+    pub fn warn_only(mut self, enabled: bool) -> Self {
+        self.warn_only = enabled;
+        self
+    }
+}
+
+fn matches(pattern: &str, endpoint: &str) -> bool {
+    let mut pattern = pattern.trim_matches('/').split('/');
+    let mut endpoint = endpoint.trim_matches('/').split('/');
+    loop {
+        match (pattern.next(), endpoint.next()) {
+            (None, None) => return true,
+            (Some(p), Some(e)) if p == "*" || p == e => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Places under `value` where `path` is missing, e.g. `data[3].sum`.
+fn missing(value: &Value, path: &str, at: String, found: &mut Vec<String>) {
+    let (key, rest) = match path.find('.') {
+        Some(i) => (&path[..i], Some(&path[i + 1..])),
+        None => (path, None),
+    };
+    let (key, each) = match key.strip_suffix("[]") {
+        Some(key) => (key, true),
+        None => (key, false),
+    };
+    let at = if at.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", at, key)
+    };
+    let v = match value.get(key) {
+        Some(v) if !v.is_null() => v,
+        _ => return found.push(at),
+    };
+    match (each, rest) {
+        (false, None) => {}
+        (false, Some(rest)) => missing(v, rest, at, found),
+        (true, rest) => match v.as_array() {
+            None => found.push(format!("{}[]", at)),
+            Some(items) => {
+                if let Some(rest) = rest {
+                    for (i, item) in items.iter().enumerate() {
+                        missing(item, rest, format!("{}[{}]", at, i), found);
+                    }
+                }
+            }
+        },
+    }
+}
+
+impl ResponseValidator for RequiredFieldsValidator {
+    fn validate(&self, endpoint: &str, value: &Value) -> Result<Vec<String>, String> {
+        let mut found = Vec::new();
+        for (_, fields) in self.spec.iter().filter(|(p, _)| matches(p, endpoint)) {
+            for field in fields {
+                missing(value, field, String::new(), &mut found);
+            }
+        }
+        if found.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let details = format!("missing required fields: {}", found.join(", "));
+        if self.warn_only {
+            Ok(vec![details])
+        } else {
+            Err(details)
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use {crate::*, std::fmt};
+
+/// How long cross rates are reused for checks that tolerate slightly stale rates.
+const CROSS_RATES_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Relative rate change tolerated by [`ConversionPlan::execute`] by default, 0.5%.
+fn default_rate_tolerance() -> BigDecimal {
+    BigDecimal::new(5.into(), 3)
+}
+
+/// Conversion between balances of the wallet, see [`Client::plan_conversion`].
+pub struct ConversionPlan<'a> {
+    client: &'a Client,
+    id: u64,
+    from: AccountAlias,
+    sum: Money,
+    rate_tolerance: BigDecimal,
+    /// Units of the target currency per unit of the source one.
+    pub rate: BigDecimal,
+    pub commission: Money,
+    /// Amount debited from the source balance, commission included.
+    pub debit: Money,
+    /// Amount credited to the target balance, as quoted by QIWI.
+    pub credit_estimate: Money,
+}
+
+impl<'a> fmt::Debug for ConversionPlan<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConversionPlan")
+            .field("id", &self.id)
+            .field("from", &self.from)
+            .field("rate", &self.rate)
+            .field("commission", &self.commission)
+            .field("debit", &self.debit)
+            .field("credit_estimate", &self.credit_estimate)
+            .field("rate_tolerance", &self.rate_tolerance)
+            .finish()
+    }
+}
+
+impl<'a> ConversionPlan<'a> {
+    /// Relative rate change after which [`ConversionPlan::execute`] refuses to convert, e.g. `0.01` for 1%.
+    pub fn rate_tolerance(mut self, tolerance: BigDecimal) -> Self {
+        self.rate_tolerance = tolerance;
+        self
+    }
+
+    /// Id the conversion is submitted with, see [`TransferRequest::idempotency_id`].
+    pub fn idempotency_id(&self) -> u64 {
+        self.id
+    }
+
+    /// Converts with the planned parameters.
+    ///
+    /// Fails with [`Error::RateMoved`] if the current rate differs from the planned one by more than the tolerance.
+    pub async fn execute(self) -> QiwiResult<TransferData> {
+        let client = self.client;
+        let payment_method =
+            PaymentMethod::from_account(&self.from).context(InvalidAccountAlias {
+                alias: self.from.clone(),
+            })?;
+        let current = client
+            .conversion_rate(&payment_method.account_id, &self.sum.currency)
+            .await?;
+        ensure!(
+            (&current - &self.rate).abs() <= &self.rate * &self.rate_tolerance,
+            RateMoved {
+                planned: self.rate,
+                current,
+            }
+        );
+
+        client
+            .send_payment(client.conversion_request(self.id, self.sum, payment_method))
+            .await
+    }
+}
+
+impl Client {
+    /// Conversion payment crediting `sum` to the wallet itself.
+    fn conversion_request(
+        &self,
+        id: u64,
+        sum: Money,
+        payment_method: PaymentMethod,
+    ) -> PaymentRequest {
+        let mut fields = BTreeMap::new();
+        fields.insert("account".to_string(), self.user.to_string());
+
+        PaymentRequest {
+            provider: ProviderId::from(reconcile::CONVERSION_PROVIDER),
+            id: id.to_string(),
+            sum,
+            payment_method,
+            fields,
+            comment: None,
+        }
+    }
+
+    /// Converts the balance in `from` into `amount` of `to` at whatever rate QIWI applies.
+    ///
+    /// Use [`Client::plan_conversion`] to see the rate and commission first and to guard
+    /// against rate changes.
+    pub async fn exchange(
+        &self,
+        amount: BigDecimal,
+        from: penny::Currency,
+        to: penny::Currency,
+    ) -> QiwiResult<TransferData> {
+        self.send_payment(self.conversion_request(
+            self.next_payment_id(),
+            Money::new(amount, to),
+            PaymentMethod::account(from.into()),
+        ))
+        .await
+    }
+
+    pub async fn cross_rates(&self) -> QiwiResult<Vec<CrossRate>> {
+        Ok(self
+            .caller
+            .call::<_, CrossRatesWrapper>(
+                "sinap/crossRates",
+                Method::GET,
+                &Default::default(),
+                None,
+            )
+            .await?
+            .into_result()?
+            .result)
+    }
+
+    /// Cross rates fetched within the last few minutes.
+    pub(crate) async fn cached_cross_rates(&self) -> QiwiResult<Vec<CrossRate>> {
+        if let Some((fetched_at, rates)) = &*self.cross_rates_cache.lock().unwrap() {
+            if fetched_at.elapsed() < CROSS_RATES_TTL {
+                return Ok(rates.clone());
+            }
+        }
+        let rates = self.cross_rates().await?;
+        *self.cross_rates_cache.lock().unwrap() = Some((std::time::Instant::now(), rates.clone()));
+        Ok(rates)
+    }
+
+    /// Units of `to` per unit of `from`.
+    async fn conversion_rate(
+        &self,
+        from: &QiwiCurrency,
+        to: &QiwiCurrency,
+    ) -> QiwiResult<BigDecimal> {
+        CrossRate::find(&self.cross_rates().await?, from, to).context(NoCrossRate {
+            from: from.clone(),
+            to: to.clone(),
+        })
+    }
+
+    /// `amount` of `from` converted into `to` at a recently fetched cross rate, rounded down
+    /// to kopecks. Fails with [`Error::NoCrossRate`] if the pair is not listed either way.
+    ///
+    /// QIWI's conversion commission is not included, see [`Client::plan_conversion`].
+    pub async fn convert_quote(
+        &self,
+        from: &QiwiCurrency,
+        to: &QiwiCurrency,
+        amount: BigDecimal,
+    ) -> QiwiResult<BigDecimal> {
+        if from == to {
+            return Ok(amount);
+        }
+        let rate =
+            CrossRate::find(&self.cached_cross_rates().await?, from, to).context(NoCrossRate {
+                from: from.clone(),
+                to: to.clone(),
+            })?;
+        Ok((amount * rate).with_scale(2))
+    }
+
+    /// Quotes converting `amount` from the `from` balance into `to_currency`.
+    ///
+    /// `amount` is either the sum to credit, in `to_currency`, or the sum to convert, in the currency of `from`.
+    /// The latter is converted at the current cross rate and rounded down to kopecks.
+    pub async fn plan_conversion(
+        &self,
+        from: AccountAlias,
+        to_currency: penny::Currency,
+        amount: Money,
+    ) -> QiwiResult<ConversionPlan<'_>> {
+        let payment_method = PaymentMethod::from_account(&from).context(InvalidAccountAlias {
+            alias: from.clone(),
+        })?;
+        let source = payment_method.account_id.clone();
+        let target = QiwiCurrency::from(to_currency);
+        let rate = self.conversion_rate(&source, &target).await?;
+
+        let credit = if amount.currency == target {
+            amount.amount
+        } else {
+            ensure!(
+                amount.currency == source,
+                AccountCurrencyMismatch {
+                    alias: from.clone(),
+                    currency: amount.currency,
+                }
+            );
+            (amount.amount * &rate).with_scale(2)
+        };
+        let sum = Money {
+            amount: credit,
+            currency: target,
+        };
+
+        let quote = self
+            .online_commission_from(
+                ProviderId::from(reconcile::CONVERSION_PROVIDER),
+                self.user.to_string(),
+                sum.clone(),
+                payment_method,
+            )
+            .await?;
+
+        Ok(ConversionPlan {
+            client: self,
+            id: self.next_payment_id(),
+            from,
+            sum,
+            rate_tolerance: default_rate_tolerance(),
+            rate,
+            commission: quote.qw_commission,
+            debit: quote.withdraw_sum,
+            credit_estimate: quote.enrollment_sum,
+        })
+    }
+}
@@ -0,0 +1,6 @@
+//! Crates whose types appear in the public API, in the versions this crate is built with.
+//!
+//! Use these instead of depending on the crates directly to avoid version mismatches,
+//! e.g. `qiwi::deps::bigdecimal::BigDecimal`.
+
+pub use {bigdecimal, chrono, penny, phonenumber};
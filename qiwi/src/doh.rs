@@ -0,0 +1,175 @@
+//! DNS-over-HTTPS resolution of API hosts, see [`ClientBuilder::doh_resolver`](crate::ClientBuilder::doh_resolver).
+
+use {
+    crate::{
+        clock::{Clock, SystemClock},
+        StdError,
+    },
+    log::*,
+    reqwest::dns::{Addrs, Name, Resolve, Resolving},
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        net::{IpAddr, SocketAddr},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Cloudflare's JSON endpoint, addressed by IP so that it does not need DNS itself.
+pub const CLOUDFLARE: &str = "https://1.1.1.1/dns-query";
+/// Google's JSON endpoint, addressed by IP so that it does not need DNS itself.
+pub const GOOGLE: &str = "https://8.8.8.8/resolve";
+
+/// Resolutions are reused for at least this long, whatever their TTL.
+const MIN_TTL: Duration = Duration::from_secs(30);
+/// Resolutions are reused for at most this long, whatever their TTL.
+const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `A` record type.
+const RECORD_A: u16 = 1;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DohResponse {
+    status: u32,
+    #[serde(default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Deserialize)]
+struct DohAnswer {
+    #[serde(rename = "type")]
+    record_type: u16,
+    #[serde(rename = "TTL")]
+    ttl: u64,
+    data: String,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Separate from the API client, which resolves through this resolver.
+    client: reqwest::Client,
+    url: String,
+    clock: Arc<dyn Clock>,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+/// Resolves IPv4 addresses with the JSON API of a DoH server, e.g. [`CLOUDFLARE`].
+///
+/// Resolutions are cached for their TTL. If a lookup fails, the host is resolved by the system
+/// and a warning is logged, so a blocked DoH server does not make the API unreachable.
+#[derive(Clone, Debug)]
+pub struct DohResolver {
+    inner: Arc<Inner>,
+}
+
+impl DohResolver {
+    pub fn new<U: Into<String>>(url: U) -> Self {
+        Self::with_clock(url, Arc::new(SystemClock))
+    }
+
+    /// Same as [`DohResolver::new`], expiring resolutions by `clock`.
+    pub fn with_clock<U: Into<String>>(url: U, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                client: reqwest::Client::builder()
+                    .timeout(LOOKUP_TIMEOUT)
+                    .build()
+                    .unwrap(),
+                url: url.into(),
+                clock,
+                cache: Default::default(),
+            }),
+        }
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let mut cache = self.inner.cache.lock().unwrap();
+        match cache.get(host) {
+            Some(entry) if entry.expires > self.inner.clock.now() => Some(entry.addrs.clone()),
+            Some(_) => {
+                cache.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Addresses of `host` from the DoH server, or from the cache while their TTL lasts.
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, StdError> {
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+
+        let rsp = self
+            .inner
+            .client
+            .get(&self.inner.url)
+            .query(&[("name", host), ("type", "A")])
+            .header(reqwest::header::ACCEPT, "application/dns-json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<DohResponse>()
+            .await?;
+        if rsp.status != 0 {
+            return Err(format!("lookup of {} failed with DNS status {}", host, rsp.status).into());
+        }
+
+        let mut ttl = MAX_TTL;
+        let mut addrs = Vec::new();
+        for answer in rsp.answer {
+            if answer.record_type != RECORD_A {
+                continue;
+            }
+            if let Ok(addr) = answer.data.parse() {
+                addrs.push(addr);
+                ttl = ttl.min(Duration::from_secs(answer.ttl));
+            }
+        }
+        if addrs.is_empty() {
+            return Err(format!("no addresses of {}", host).into());
+        }
+
+        trace!("Resolved {} over DoH to {:?} for {:?}", host, addrs, ttl);
+        self.inner.cache.lock().unwrap().insert(
+            host.to_string(),
+            CacheEntry {
+                addrs: addrs.clone(),
+                expires: self.inner.clock.now() + ttl.max(MIN_TTL),
+            },
+        );
+        Ok(addrs)
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs = match resolver.lookup(&host).await {
+                Ok(addrs) => addrs
+                    .into_iter()
+                    .map(|addr| SocketAddr::new(addr, 0))
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    warn!(
+                        "DoH lookup of {} failed, falling back to system DNS: {}",
+                        host, e
+                    );
+                    tokio::net::lookup_host((host.as_str(), 0)).await?.collect()
+                }
+            };
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
@@ -0,0 +1,358 @@
+//! Identifying payers of incoming transfers by a code they put in the comment.
+
+use {crate::*, log::*, std::time::Duration};
+
+/// Letters and digits without the easily confused `0`, `1`, `I`, `L` and `O`.
+pub const DEFAULT_ALPHABET: &str = "23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+const DEFAULT_LENGTH: usize = 6;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Generates payment codes and finds them in comments.
+///
+/// Comments are matched ignoring case and anything but letters and digits, so `ab-12 cd` matches
+/// code `AB12CD`. With confusions tolerated, which is the default, `O` is taken for `0`, `I` and
+/// `L` for `1`, and Cyrillic letters for Latin ones looking the same.
+#[derive(Clone, Debug)]
+pub struct CommentMatcher {
+    alphabet: Vec<char>,
+    length: usize,
+    tolerate_confusions: bool,
+}
+
+impl Default for CommentMatcher {
+    fn default() -> Self {
+        Self::new(DEFAULT_ALPHABET, DEFAULT_LENGTH)
+    }
+}
+
+fn unconfuse(c: char) -> char {
+    match c {
+        'O' | 'О' => '0',
+        'I' | 'L' | 'І' => '1',
+        'А' => 'A',
+        'В' => 'B',
+        'Е' => 'E',
+        'К' => 'K',
+        'М' => 'M',
+        'Н' => 'H',
+        'Р' => 'P',
+        'С' => 'C',
+        'Т' => 'T',
+        'У' => 'Y',
+        'Х' => 'X',
+        c => c,
+    }
+}
+
+impl CommentMatcher {
+    /// Panics if `alphabet` is empty or has more than 256 characters.
+    pub fn new(alphabet: &str, length: usize) -> Self {
+        let alphabet = alphabet.chars().collect::<Vec<_>>();
+        assert!(
+            !alphabet.is_empty() && alphabet.len() <= 256,
+            "alphabet must have from 1 to 256 characters"
+        );
+        Self {
+            alphabet,
+            length,
+            tolerate_confusions: true,
+        }
+    }
+
+    pub fn tolerate_confusions(mut self, enabled: bool) -> Self {
+        self.tolerate_confusions = enabled;
+        self
+    }
+
+    /// Random code of the configured length.
+    pub fn generate(&self) -> String {
+        self.generate_with(&ids::DefaultIdGenerator::default())
+    }
+
+    /// Same as [`CommentMatcher::generate`] taking random bytes from `ids`, e.g. [`Client::id_generator`].
+    pub fn generate_with(&self, ids: &dyn ids::IdGenerator) -> String {
+        let n = self.alphabet.len();
+        // Largest multiple of the alphabet size, bytes above it are dropped to keep the choice uniform.
+        let limit = 256 - 256 % n;
+        let mut code = String::with_capacity(self.length);
+        let mut count = 0;
+        let mut buf = [0; 16];
+        while count < self.length {
+            ids.fill_random(&mut buf);
+            for &b in buf.iter().filter(|&&b| usize::from(b) < limit) {
+                if count == self.length {
+                    break;
+                }
+                code.push(self.alphabet[usize::from(b) % n]);
+                count += 1;
+            }
+        }
+        code
+    }
+
+    fn normalize(&self, s: &str) -> String {
+        s.chars()
+            .flat_map(char::to_uppercase)
+            .filter(|c| c.is_alphanumeric())
+            .map(|c| {
+                if self.tolerate_confusions {
+                    unconfuse(c)
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, comment: &str, code: &str) -> bool {
+        let code = self.normalize(code);
+        !code.is_empty() && self.normalize(comment).contains(&code)
+    }
+}
+
+impl Client {
+    /// Waits for a successful incoming payment with `code` in the comment, `None` if none arrives before `expiry`.
+    ///
+    /// Payments made before the call are considered as well. Failed polls are retried with backoff,
+    /// the error is returned if the last poll before expiry has failed.
+    pub async fn watch_for_code(
+        &self,
+        code: &str,
+        expiry: Duration,
+    ) -> QiwiResult<Option<PaymentHistoryEntry>> {
+        self.watch_for_code_with(&CommentMatcher::default(), code, expiry)
+            .await
+    }
+
+    /// Same as [`Client::watch_for_code`] with custom matching rules.
+    pub async fn watch_for_code_with(
+        &self,
+        matcher: &CommentMatcher,
+        code: &str,
+        expiry: Duration,
+    ) -> QiwiResult<Option<PaymentHistoryEntry>> {
+        let deadline = self.clock.now() + expiry;
+        let endpoint = self.api_versions.history_endpoint(&self.user);
+        let args = QueryParams::new()
+            .with("rows", 50)
+            .with("operation", HistoryOperation::In.as_str());
+        let mut backoff = Backoff::new(POLL_INTERVAL, MAX_POLL_INTERVAL);
+        let slots = self
+            .polls
+            .register(&format!("watch-for-code.{}", code), POLL_INTERVAL);
+        loop {
+            let rsp = self
+                .caller
+                .call::<_, PaymentHistoryData>(&endpoint, Method::GET, &args, None)
+                .await
+                .map_err(versions::versioned_error(
+                    "payment-history",
+                    &self.api_versions.payment_history,
+                ))
+                .and_then(Rsp::into_result);
+
+            let delay = match rsp {
+                Ok(history) => {
+                    let found = history.data.into_iter().find(|entry| {
+                        matches!(entry.payment_type, PaymentType::In)
+                            && entry.status == PaymentStatus::Success
+                            && matcher.matches(&entry.comment, code)
+                    });
+                    if found.is_some() {
+                        return Ok(found);
+                    }
+                    if self.clock.now() >= deadline {
+                        return Ok(None);
+                    }
+                    backoff.on_success()
+                }
+                Err(e) => {
+                    if self.clock.now() >= deadline {
+                        return Err(e);
+                    }
+                    warn!("Failed to poll payment history: {}", e);
+                    backoff.on_failure()
+                }
+            };
+
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            slots.set_interval(delay.min(remaining));
+            slots.next_slot().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, serde_json::json};
+
+    const CODE: &str = "X7K9MQ";
+
+    #[test]
+    fn codes_are_taken_from_the_alphabet() {
+        let ids = ids::SequentialIdGenerator::new(0);
+        assert_eq!(CommentMatcher::default().generate_with(&ids), "234567");
+        assert_eq!(CommentMatcher::new("AB", 0).generate_with(&ids), "");
+
+        let code = CommentMatcher::default().generate();
+        assert_eq!(code.chars().count(), DEFAULT_LENGTH);
+        assert!(code.chars().all(|c| DEFAULT_ALPHABET.contains(c)));
+    }
+
+    #[test]
+    fn codes_are_reproducible_with_client_ids() {
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(OfflineTransport::new())
+            .id_generator(ids::SequentialIdGenerator::new(1_000))
+            .build();
+        let matcher = CommentMatcher::default();
+        let codes = (0..3)
+            .map(|_| matcher.generate_with(client.id_generator()))
+            .collect::<Vec<_>>();
+        // Each code takes 16 bytes, 248 to 255 of the second batch are dropped.
+        assert_eq!(codes, vec!["HJKMNP", "234567", "ABCDEF"]);
+    }
+
+    #[test]
+    fn bytes_beyond_a_multiple_of_the_alphabet_are_dropped() {
+        // 255 is dropped for an alphabet of 3, which would otherwise favour `A`.
+        let ids = ids::SequentialIdGenerator::new(254);
+        assert_eq!(CommentMatcher::new("ABC", 3).generate_with(&ids), "CAB");
+    }
+
+    #[test]
+    #[should_panic(expected = "alphabet must have from 1 to 256 characters")]
+    fn empty_alphabet_panics() {
+        CommentMatcher::new("", 6);
+    }
+
+    #[test]
+    fn messy_comments_match() {
+        let matcher = CommentMatcher::default();
+        for comment in &[
+            "X7K9MQ",
+            "x7k9mq",
+            "  x7k-9mq  ",
+            "Donation: X7K 9MQ, thanks!!",
+            "Спасибо за стрим 🙏\nкод: x7k_9mq",
+            "#x7k.9mq#",
+        ] {
+            assert!(matcher.matches(comment, CODE), "{:?}", comment);
+        }
+    }
+
+    #[test]
+    fn other_comments_do_not_match() {
+        let matcher = CommentMatcher::default();
+        for comment in &["", "X7K9M", "MQ9K7X", "Donation"] {
+            assert!(!matcher.matches(comment, CODE), "{:?}", comment);
+        }
+        // Codes without letters or digits match nothing.
+        assert!(!matcher.matches("anything", ""));
+        assert!(!matcher.matches("-- --", "--"));
+    }
+
+    #[test]
+    fn confusions_are_tolerated_by_default() {
+        let matcher = CommentMatcher::default();
+        assert!(matcher.matches("AO-BL-C2", "A0B1C2"));
+        assert!(matcher.matches("aoBIc2", "A0B1C2"));
+        // Cyrillic К, Х and М typed instead of Latin ones.
+        assert!(matcher.matches("КХ2М", "KX2M"));
+        assert!(matcher.matches("кх2м", "KX2M"));
+
+        let strict = CommentMatcher::default().tolerate_confusions(false);
+        assert!(!strict.matches("AO-BL-C2", "A0B1C2"));
+        assert!(!strict.matches("КХ2М", "KX2M"));
+        // Case and separators are still ignored.
+        assert!(strict.matches("  x7k-9mq ", CODE));
+    }
+
+    fn payment(txn_id: u64, kind: &str, status: &str, comment: &str) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["txnId"] = json!(txn_id);
+        entry["type"] = json!(kind);
+        entry["status"] = json!(status);
+        entry["comment"] = json!(comment);
+        entry
+    }
+
+    fn client() -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let transport = Arc::new(OfflineTransport::new());
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .build();
+        (client, transport, clock)
+    }
+
+    fn history_endpoint() -> String {
+        ApiVersions::default().history_endpoint(&QiwiUser::from(
+            "+79991234567".parse::<PhoneNumber>().unwrap(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_successful_incoming_payment() {
+        let (client, transport, _) = client();
+        transport.push(
+            Method::GET,
+            history_endpoint(),
+            &fixtures::history_page(vec![payment(1, "IN", "SUCCESS", "hello")]),
+        );
+        transport.insert(
+            Method::GET,
+            history_endpoint(),
+            &fixtures::history_page(vec![
+                payment(4, "IN", "SUCCESS", "for stream x7k-9mq"),
+                payment(3, "IN", "WAITING", "X7K9MQ"),
+                payment(2, "OUT", "SUCCESS", "X7K9MQ"),
+                payment(1, "IN", "SUCCESS", "hello"),
+            ]),
+        );
+
+        let entry = client
+            .watch_for_code(CODE, Duration::from_secs(600))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.txn_id, 4);
+        assert_eq!(transport.requests().len(), 2);
+        assert_eq!(
+            transport.recorded()[0],
+            OfflineRequest {
+                method: Method::GET,
+                endpoint: history_endpoint(),
+                params: vec![
+                    ("rows".to_string(), "50".to_string()),
+                    ("operation".to_string(), "IN".to_string()),
+                ],
+                body: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_is_found_before_expiry() {
+        let (client, transport, clock) = client();
+        let started = clock.now();
+        transport.insert(
+            Method::GET,
+            history_endpoint(),
+            &fixtures::history_page(vec![payment(1, "IN", "SUCCESS", "hello")]),
+        );
+
+        let found = client
+            .watch_for_code(CODE, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert!(found.is_none());
+        // Polled until the expiry, roughly every 10 seconds.
+        assert!(clock.now() >= started + Duration::from_secs(60));
+        assert!(transport.requests().len() >= 6);
+    }
+}
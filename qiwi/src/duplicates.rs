@@ -0,0 +1,176 @@
+use crate::*;
+
+impl Client {
+    /// Successful and pending outgoing payments of `amount` to the account of `direction` made within `window`,
+    /// newest first.
+    ///
+    /// Accounts are compared in normalized form, see [`NormalizedAccount`].
+    pub async fn find_recent_similar(
+        &self,
+        direction: &TransferDirection,
+        amount: &BigDecimal,
+        window: std::time::Duration,
+    ) -> QiwiResult<Vec<PaymentHistoryEntry>> {
+        let horizon = chrono::Duration::from_std(window)
+            .ok()
+            .and_then(|window| self.clock.utc_now().checked_sub_signed(window));
+        let account = direction.normalized_account();
+
+        let mut similar = Vec::new();
+        let mut history = self.payment_history();
+        while let Some(entry) = history.next().await.transpose()? {
+            if horizon.map(|horizon| entry.date < horizon).unwrap_or(false) {
+                break;
+            }
+
+            if matches!(entry.payment_type, PaymentType::Out)
+                && !matches!(entry.status, PaymentStatus::Error)
+                && entry.sum.amount == *amount
+                && NormalizedAccount::new(&entry.account, self.region) == account
+            {
+                similar.push(entry);
+            }
+        }
+
+        Ok(similar)
+    }
+
+    /// Refuses `req` if the duplicate guard is enabled and finds a similar recent payment.
+    pub(crate) async fn check_duplicate(&self, req: &TransferRequest) -> QiwiResult<()> {
+        let window = match self.duplicate_window {
+            Some(window) if !req.force => window,
+            _ => return Ok(()),
+        };
+
+        if let Some(existing) = self
+            .find_recent_similar(&req.direction, &req.amount, window)
+            .await?
+            .first()
+        {
+            return PossibleDuplicate {
+                existing_txn_id: existing.txn_id,
+            }
+            .fail();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, crate::clock::ManualClock, serde_json::json, std::str::FromStr,
+        std::time::Duration,
+    };
+
+    const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap()
+    }
+
+    fn entry(txn_id: u64, minutes_ago: i64, status: &str, account: &str, amount: &str) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["txnId"] = json!(txn_id);
+        entry["date"] = json!((now() - chrono::Duration::minutes(minutes_ago)).to_rfc3339());
+        entry["type"] = json!("OUT");
+        entry["status"] = json!(status);
+        entry["account"] = json!(account);
+        entry["sum"] = json!({ "amount": amount, "currency": "643" });
+        entry
+    }
+
+    /// Client at [`now`] with the duplicate guard enabled, accepting wallet transfers.
+    fn client(history: Vec<Value>) -> (Client, Arc<OfflineTransport>) {
+        let phone: PhoneNumber = "+79991234567".parse().unwrap();
+        let transport = Arc::new(
+            OfflineTransport::new()
+                .with(
+                    Method::GET,
+                    ApiVersions::default().history_endpoint(&QiwiUser::from(phone.clone())),
+                    &fixtures::history_page(history),
+                )
+                .with(
+                    Method::POST,
+                    "sinap/api/v2/terms/99/payments",
+                    &json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } }),
+                ),
+        );
+        let client = Client::builder(phone, "")
+            .transport(transport.clone())
+            .clock(ManualClock::new(now()))
+            .duplicate_guard(WINDOW)
+            .build();
+        (client, transport)
+    }
+
+    fn direction() -> TransferDirection {
+        TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        }
+    }
+
+    fn amount() -> BigDecimal {
+        BigDecimal::from_str("100.00").unwrap()
+    }
+
+    fn sent_payments(transport: &OfflineTransport) -> usize {
+        transport
+            .requests()
+            .into_iter()
+            .filter(|(method, _)| *method == Method::POST)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn similar_payments_match_normalized_account_within_window() {
+        let (client, _) = client(vec![
+            entry(5, 10, "SUCCESS", "+79035550102", "100.00"),
+            entry(4, 20, "SUCCESS", "+79035550101", "200.00"),
+            entry(3, 30, "SUCCESS", "(903) 555-01-01", "100.00"),
+            entry(2, 40, "WAITING", "+7 903 555 01 01", "100"),
+            entry(1, 90, "SUCCESS", "+79035550101", "100.00"),
+        ]);
+
+        let similar = client
+            .find_recent_similar(&direction(), &amount(), WINDOW)
+            .await
+            .unwrap();
+        assert_eq!(
+            similar.iter().map(|entry| entry.txn_id).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn guard_refuses_transfer_similar_to_recent_one() {
+        let (client, transport) = client(vec![entry(3, 30, "SUCCESS", "+79035550101", "100.00")]);
+
+        let req = client.transfer_request(amount(), direction(), "");
+        match client.transfer(&req).await {
+            Err(Error::PossibleDuplicate { existing_txn_id }) => assert_eq!(existing_txn_id, 3),
+            other => panic!("expected PossibleDuplicate, got {:?}", other),
+        }
+        assert_eq!(sent_payments(&transport), 0);
+    }
+
+    #[tokio::test]
+    async fn forced_transfer_bypasses_guard() {
+        let (client, transport) = client(vec![entry(3, 30, "SUCCESS", "+79035550101", "100.00")]);
+
+        let req = client.transfer_request(amount(), direction(), "").force();
+        client.transfer(&req).await.unwrap();
+        assert_eq!(sent_payments(&transport), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_payments_are_not_duplicates() {
+        let (client, transport) = client(vec![entry(3, 30, "ERROR", "+79035550101", "100.00")]);
+
+        let req = client.transfer_request(amount(), direction(), "");
+        client.transfer(&req).await.unwrap();
+        assert_eq!(sent_payments(&transport), 1);
+    }
+}
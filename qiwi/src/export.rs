@@ -0,0 +1,701 @@
+//! Lazy reading of exported payment history for offline analytics, e.g. [`link_internal_transfers`](crate::reconcile::link_internal_transfers) over an export of any size.
+
+use {
+    crate::{stream_ext::TryStreamExt, *},
+    log::*,
+    std::{
+        fs::File,
+        io::{self, BufRead, BufReader},
+        path::Path,
+    },
+    tokio::stream::StreamExt,
+};
+
+#[derive(Debug, Snafu)]
+pub enum ExportError {
+    #[snafu(display("failed to fetch history after {} entries: {}", written, source))]
+    HistoryFailed { written: usize, source: Error },
+    #[snafu(display("failed to write history: {}", source))]
+    WriteFailed { source: io::Error },
+}
+
+/// Columns of the CSV export, nested fields named by their paths.
+///
+/// Numbers, flags and `extras` are JSON literals, other columns are the values as is.
+pub const CSV_COLUMNS: [&str; 32] = [
+    "txnId",
+    "personId",
+    "date",
+    "errorCode",
+    "error",
+    "type",
+    "status",
+    "statusText",
+    "trmTxnId",
+    "account",
+    "sum.amount",
+    "sum.currency",
+    "commission.amount",
+    "commission.currency",
+    "total.amount",
+    "total.currency",
+    "provider.id",
+    "provider.shortName",
+    "provider.longName",
+    "provider.logoUrl",
+    "provider.description",
+    "provider.keys",
+    "provider.siteUrl",
+    "comment",
+    "currencyRate",
+    "extras",
+    "chequeReady",
+    "bankDocumentAvailable",
+    "bankDocumentReady",
+    "repeatPaymentEnabled",
+    "favoritePaymentEnabled",
+    "regularPaymentEnabled",
+];
+
+/// Columns of [`CSV_COLUMNS`] holding JSON literals rather than text.
+const CSV_LITERALS: [&str; 11] = [
+    "txnId",
+    "personId",
+    "errorCode",
+    "provider.id",
+    "extras",
+    "chequeReady",
+    "bankDocumentAvailable",
+    "bankDocumentReady",
+    "repeatPaymentEnabled",
+    "favoritePaymentEnabled",
+    "regularPaymentEnabled",
+];
+
+/// Writes the entries of `history` to `writer` as NDJSON, readable with [`NdjsonReader`].
+///
+/// Entries are written `chunk_size` at a time, each chunk in one write. If `history` fails,
+/// the entries received before the failure are written first. Returns the number of entries written.
+///
+/// # Panics
+///
+/// Panics if `chunk_size` is zero.
+pub async fn history_to_writer<S, W>(
+    history: S,
+    writer: W,
+    chunk_size: usize,
+) -> Result<usize, ExportError>
+where
+    S: Stream<Item = QiwiResult<PaymentHistoryEntry>> + Unpin,
+    W: io::Write,
+{
+    write_chunks(history, writer, chunk_size, |buf, chunk| {
+        for entry in chunk {
+            serde_json::to_writer(&mut *buf, entry).expect("history entry is always serializable");
+            buf.push(b'\n');
+        }
+    })
+    .await
+}
+
+/// Writes the entries of `history` to `writer` as CSV with a header of [`CSV_COLUMNS`],
+/// readable with [`CsvReader`]. Chunks and failures are handled like by [`history_to_writer`].
+///
+/// # Panics
+///
+/// Panics if `chunk_size` is zero.
+pub async fn history_to_csv_writer<S, W>(
+    history: S,
+    mut writer: W,
+    chunk_size: usize,
+) -> Result<usize, ExportError>
+where
+    S: Stream<Item = QiwiResult<PaymentHistoryEntry>> + Unpin,
+    W: io::Write,
+{
+    let mut header = csv::Writer::from_writer(Vec::new());
+    header
+        .write_record(CSV_COLUMNS.iter())
+        .expect("writing to memory does not fail");
+    let header = header
+        .into_inner()
+        .expect("writing to memory does not fail");
+    writer.write_all(&header).context(WriteFailed)?;
+
+    write_chunks(history, writer, chunk_size, |buf, chunk| {
+        let mut csv = csv::Writer::from_writer(buf);
+        for entry in chunk {
+            csv.write_record(csv_record(entry))
+                .expect("writing to memory does not fail");
+        }
+        csv.flush().expect("writing to memory does not fail");
+    })
+    .await
+}
+
+async fn write_chunks<S, W, F>(
+    history: S,
+    mut writer: W,
+    chunk_size: usize,
+    mut encode: F,
+) -> Result<usize, ExportError>
+where
+    S: Stream<Item = QiwiResult<PaymentHistoryEntry>> + Unpin,
+    W: io::Write,
+    F: FnMut(&mut Vec<u8>, &[PaymentHistoryEntry]),
+{
+    let mut chunks = history.try_chunks(chunk_size);
+    let mut buf = Vec::new();
+    let mut written = 0;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.context(HistoryFailed { written })?;
+        buf.clear();
+        encode(&mut buf, &chunk);
+        writer.write_all(&buf).context(WriteFailed)?;
+        written += chunk.len();
+    }
+    writer.flush().context(WriteFailed)?;
+
+    Ok(written)
+}
+
+/// Cells of `entry` in the order of [`CSV_COLUMNS`].
+fn csv_record(entry: &PaymentHistoryEntry) -> Vec<String> {
+    let entry = serde_json::to_value(entry).expect("history entry is always serializable");
+    CSV_COLUMNS
+        .iter()
+        .map(|column| {
+            let value = column.split('.').fold(&entry, |node, key| &node[key]);
+            match value {
+                Value::String(text) => text.clone(),
+                literal => literal.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Entry of a CSV `record` with columns named by `headers`.
+fn csv_entry(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+) -> Result<PaymentHistoryEntry, String> {
+    if record.len() != headers.len() {
+        return Err(format!(
+            "expected {} fields, found {}",
+            headers.len(),
+            record.len()
+        ));
+    }
+    let mut entry = json!({});
+    for (column, cell) in headers.iter().zip(record.iter()) {
+        let value = if CSV_LITERALS.contains(&column) {
+            serde_json::from_str(cell).map_err(|e| format!("{}: {}", column, e))?
+        } else {
+            Value::String(cell.to_string())
+        };
+        *column
+            .split('.')
+            .fold(&mut entry, |node, key| &mut node[key]) = value;
+    }
+    serde_json::from_value(entry).map_err(|e| e.to_string())
+}
+
+/// Line of an export that is not a history entry.
+#[derive(Clone, Debug)]
+pub struct MalformedLine {
+    /// Starting from 1.
+    pub line: usize,
+    pub error: String,
+}
+
+/// History entries of an NDJSON export, one JSON object per line as written by `payment-history --output json`
+/// or [`history_to_writer`].
+///
+/// Lines are read one at a time through a reused buffer. Blank lines are ignored, malformed
+/// ones are skipped with a warning and kept for [`NdjsonReader::finish`]. A read error ends
+/// the iteration and is returned by `finish` as well.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let mut reader = qiwi::export::NdjsonReader::open("history.ndjson")?;
+/// let links = qiwi::reconcile::link_internal_transfers(&mut reader);
+/// let malformed = reader.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct NdjsonReader<R> {
+    inner: R,
+    buf: String,
+    line: usize,
+    malformed: Vec<MalformedLine>,
+    error: Option<io::Error>,
+}
+
+impl NdjsonReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> NdjsonReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: String::new(),
+            line: 0,
+            malformed: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Lines skipped so far.
+    pub fn malformed(&self) -> &[MalformedLine] {
+        &self.malformed
+    }
+
+    /// Lines skipped, or the error that ended reading early.
+    pub fn finish(self) -> io::Result<Vec<MalformedLine>> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.malformed),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for NdjsonReader<R> {
+    type Item = PaymentHistoryEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        loop {
+            self.buf.clear();
+            match self.inner.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => {
+                    self.error = Some(e);
+                    return None;
+                }
+            }
+            self.line += 1;
+
+            let line = self.buf.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(entry) => return Some(entry),
+                Err(e) => {
+                    warn!("Skipping malformed history line {}: {}", self.line, e);
+                    self.malformed.push(MalformedLine {
+                        line: self.line,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// History entries of a CSV export with a header row, e.g. written by [`history_to_csv_writer`].
+///
+/// Columns are matched by the names in the header, in any order, see [`CSV_COLUMNS`]. Records
+/// are read one at a time into a reused buffer and skipped like the lines of [`NdjsonReader`],
+/// [`MalformedLine::line`] being the line the record starts on.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let mut reader = qiwi::export::CsvReader::open("history.csv")?;
+/// let links = qiwi::reconcile::link_internal_transfers(&mut reader);
+/// let malformed = reader.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CsvReader<R> {
+    inner: csv::Reader<R>,
+    headers: Option<csv::StringRecord>,
+    record: csv::StringRecord,
+    malformed: Vec<MalformedLine>,
+    error: Option<io::Error>,
+}
+
+impl CsvReader<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+}
+
+impl<R: io::Read> CsvReader<R> {
+    /// Reader of `inner`, which is buffered by the reader itself.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner: csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(inner),
+            headers: None,
+            record: csv::StringRecord::new(),
+            malformed: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Records skipped so far.
+    pub fn malformed(&self) -> &[MalformedLine] {
+        &self.malformed
+    }
+
+    /// Records skipped, or the error that ended reading early.
+    pub fn finish(self) -> io::Result<Vec<MalformedLine>> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.malformed),
+        }
+    }
+
+    fn skip(&mut self, line: usize, error: String) {
+        warn!(
+            "Skipping malformed history record on line {}: {}",
+            line, error
+        );
+        self.malformed.push(MalformedLine { line, error });
+    }
+}
+
+impl<R: io::Read> Iterator for CsvReader<R> {
+    type Item = PaymentHistoryEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        loop {
+            match self.inner.read_record(&mut self.record) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    let line = e.position().map_or(0, |pos| pos.line() as usize);
+                    let message = e.to_string();
+                    if let csv::ErrorKind::Io(e) = e.into_kind() {
+                        self.error = Some(e);
+                        return None;
+                    }
+                    self.skip(line, message);
+                    continue;
+                }
+            }
+
+            let entry = match &self.headers {
+                Some(headers) => csv_entry(headers, &self.record),
+                None => {
+                    self.headers = Some(self.record.clone());
+                    continue;
+                }
+            };
+            match entry {
+                Ok(entry) => return Some(entry),
+                Err(e) => {
+                    let line = self.record.position().map_or(0, |pos| pos.line() as usize);
+                    self.skip(line, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::fixtures, std::io::Write};
+
+    /// Writer keeping the written bytes and counting writes.
+    #[derive(Default)]
+    struct Recorder {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn entries(count: usize) -> Vec<PaymentHistoryEntry> {
+        fixtures::history_entries(count)
+            .into_iter()
+            .map(|entry| serde_json::from_value(entry).unwrap())
+            .collect()
+    }
+
+    fn txn_ids<'a>(entries: impl IntoIterator<Item = &'a PaymentHistoryEntry>) -> Vec<u64> {
+        entries.into_iter().map(|entry| entry.txn_id).collect()
+    }
+
+    #[tokio::test]
+    async fn history_is_written_in_chunks() {
+        let entries = entries(5);
+        let mut out = Recorder::default();
+
+        let written = history_to_writer(
+            tokio::stream::iter(entries.clone().into_iter().map(Ok)),
+            &mut out,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 5);
+        assert_eq!(out.writes, 3);
+
+        let read = NdjsonReader::new(out.data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(txn_ids(&read), txn_ids(&entries));
+    }
+
+    #[tokio::test]
+    async fn entries_before_failure_are_written() {
+        let entries = entries(3);
+        let history = entries
+            .iter()
+            .cloned()
+            .map(Ok)
+            .chain(std::iter::once(Err(Error::Offline {
+                endpoint: "payment-history".to_string(),
+            })));
+        let mut out = Recorder::default();
+
+        match history_to_writer(tokio::stream::iter(history), &mut out, 2).await {
+            Err(ExportError::HistoryFailed { written, .. }) => assert_eq!(written, 3),
+            other => panic!("expected HistoryFailed, got {:?}", other),
+        }
+        let read = NdjsonReader::new(out.data.as_slice()).collect::<Vec<_>>();
+        assert_eq!(txn_ids(&read), txn_ids(&entries));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped_and_reported() {
+        let entries = fixtures::history_entries(3);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.ndjson");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}", entries[0]).unwrap();
+        writeln!(file, "not json").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "{}", entries[1]).unwrap();
+        writeln!(file, "{{\"txnId\": 1}}").unwrap();
+        writeln!(file, "   ").unwrap();
+        // The last line has no newline.
+        write!(file, "{}", entries[2]).unwrap();
+        drop(file);
+
+        let mut reader = NdjsonReader::open(&path).unwrap();
+        let txn_ids = reader
+            .by_ref()
+            .map(|entry| entry.txn_id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            txn_ids,
+            entries
+                .iter()
+                .map(|entry| entry["txnId"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        );
+        let malformed = reader.finish().unwrap();
+        assert_eq!(
+            malformed.iter().map(|line| line.line).collect::<Vec<_>>(),
+            vec![2, 5]
+        );
+        assert!(malformed.iter().all(|line| !line.error.is_empty()));
+    }
+
+    #[test]
+    fn lines_are_read_on_demand() {
+        let first = fixtures::history_entries(1)[0].to_string();
+        let export = format!("{}\n{}\n", first, first).repeat(1000);
+        let mut cursor = io::Cursor::new(export.as_bytes());
+
+        let mut reader = NdjsonReader::new(&mut cursor);
+        assert!(reader.next().is_some());
+        drop(reader);
+        assert_eq!(cursor.position() as usize, first.len() + 1);
+    }
+
+    #[test]
+    fn read_error_ends_iteration() {
+        let first = fixtures::history_entries(1)[0].to_string();
+        let mut export = format!("{}\n", first).into_bytes();
+        export.extend_from_slice(b"\xff\xfe\n");
+        export.extend_from_slice(first.as_bytes());
+
+        let mut reader = NdjsonReader::new(export.as_slice());
+        assert_eq!(reader.by_ref().count(), 1);
+        assert!(reader.next().is_none());
+        assert_eq!(
+            reader.finish().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    /// CSV line of `cells`, without the line end.
+    fn csv_line<I: IntoIterator<Item = S>, S: AsRef<[u8]>>(cells: I) -> String {
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_writer(Vec::new());
+        writer.write_record(cells).unwrap();
+        let mut line = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        line.truncate(line.trim_end().len());
+        line
+    }
+
+    #[tokio::test]
+    async fn history_is_written_as_csv() {
+        let entries = entries(5);
+        let mut out = Recorder::default();
+
+        let written = history_to_csv_writer(
+            tokio::stream::iter(entries.clone().into_iter().map(Ok)),
+            &mut out,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 5);
+        // The header and three chunks.
+        assert_eq!(out.writes, 4);
+        let export = String::from_utf8(out.data.clone()).unwrap();
+        assert_eq!(export.lines().next(), Some(CSV_COLUMNS.join(",").as_str()));
+        assert_eq!(export.lines().count(), 6);
+
+        let mut reader = CsvReader::new(out.data.as_slice());
+        let read = reader.by_ref().collect::<Vec<_>>();
+        assert_eq!(txn_ids(&read), txn_ids(&entries));
+        assert!(reader.finish().unwrap().is_empty());
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&entries).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn empty_history_is_written_as_header() {
+        let mut out = Recorder::default();
+        let written = history_to_csv_writer(tokio::stream::empty(), &mut out, 2)
+            .await
+            .unwrap();
+        assert_eq!(written, 0);
+        assert_eq!(
+            String::from_utf8(out.data.clone()).unwrap(),
+            format!("{}\n", CSV_COLUMNS.join(","))
+        );
+        assert_eq!(CsvReader::new(out.data.as_slice()).count(), 0);
+    }
+
+    #[test]
+    fn malformed_records_are_skipped_and_reported() {
+        let mut entries = entries(3);
+        entries[1].comment = "rent, January\n\"thanks\"".to_string();
+        entries[1]
+            .extras
+            .insert("note".to_string(), json!({ "a": 1 }));
+        let mut broken = csv_record(&entries[2]);
+        broken[0] = "x".to_string();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.csv");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}", csv_line(CSV_COLUMNS.iter())).unwrap();
+        writeln!(file, "{}", csv_line(csv_record(&entries[0]))).unwrap();
+        writeln!(file, "not,enough").unwrap();
+        // The comment takes two lines.
+        writeln!(file, "{}", csv_line(csv_record(&entries[1]))).unwrap();
+        writeln!(file, "{}", csv_line(broken)).unwrap();
+        file.write_all(b"\xff\xfe,1\n").unwrap();
+        writeln!(file).unwrap();
+        // The last line has no newline.
+        write!(file, "{}", csv_line(csv_record(&entries[2]))).unwrap();
+        drop(file);
+
+        let mut reader = CsvReader::open(&path).unwrap();
+        let read = reader.by_ref().collect::<Vec<_>>();
+        assert_eq!(txn_ids(&read), txn_ids(&entries));
+        assert_eq!(read[1].comment, entries[1].comment);
+        assert_eq!(read[1].extras["note"], json!({ "a": 1 }));
+
+        let malformed = reader.finish().unwrap();
+        assert_eq!(
+            malformed.iter().map(|line| line.line).collect::<Vec<_>>(),
+            vec![3, 6, 7]
+        );
+        assert!(malformed[0].error.contains("expected 32 fields, found 2"));
+        assert!(malformed[1].error.starts_with("txnId: "));
+    }
+
+    #[test]
+    fn columns_are_matched_by_name() {
+        let entries = entries(2);
+        let mut header = CSV_COLUMNS.iter().rev().copied().collect::<Vec<_>>();
+        header.push("unknown");
+        let mut export = format!("{}\n", csv_line(&header));
+        for entry in &entries {
+            let mut cells = csv_record(entry);
+            cells.reverse();
+            cells.push("ignored".to_string());
+            export += &format!("{}\n", csv_line(cells));
+        }
+
+        let mut reader = CsvReader::new(export.as_bytes());
+        let read = reader.by_ref().collect::<Vec<_>>();
+        assert_eq!(txn_ids(&read), txn_ids(&entries));
+        assert!(reader.finish().unwrap().is_empty());
+
+        // Entries without a column of theirs are malformed.
+        let export = format!(
+            "{}\n{}\n",
+            csv_line(&CSV_COLUMNS[1..]),
+            csv_line(&csv_record(&entries[0])[1..])
+        );
+        let mut reader = CsvReader::new(export.as_bytes());
+        assert_eq!(reader.by_ref().count(), 0);
+        let malformed = reader.finish().unwrap();
+        assert_eq!(malformed.len(), 1);
+        assert!(
+            malformed[0].error.contains("txnId"),
+            "{}",
+            malformed[0].error
+        );
+    }
+
+    #[test]
+    fn csv_read_error_ends_iteration() {
+        struct Broken;
+
+        impl io::Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))
+            }
+        }
+
+        let entries = entries(1);
+        let export = format!(
+            "{}\n{}\n",
+            csv_line(CSV_COLUMNS.iter()),
+            csv_line(csv_record(&entries[0]))
+        );
+
+        let mut reader = CsvReader::new(io::Read::chain(export.as_bytes(), Broken));
+        assert_eq!(reader.by_ref().count(), 1);
+        assert!(reader.next().is_none());
+        assert_eq!(
+            reader.finish().unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+    }
+}
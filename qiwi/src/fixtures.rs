@@ -0,0 +1,214 @@
+//! Canned API responses.
+//!
+//! Everything here is deterministic, so the same fixtures come out on every run.
+
+use {crate::*, penny::Currency};
+
+/// Number of history entries in [`typical_wallet`].
+pub const TYPICAL_HISTORY_LEN: usize = 120;
+
+/// Time of the newest generated history entry.
+fn history_end() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap()
+}
+
+pub fn profile() -> Value {
+    json!({
+        "authInfo": {
+            "personId": 79_991_234_567u64,
+            "registrationDate": "2017-03-01T10:00:00Z",
+            "boundEmail": "demo@example.com",
+            "ip": "127.0.0.1",
+            "lastLoginDate": "2020-01-31T09:00:00Z",
+            "mobilePinInfo": {
+                "mobilePinUsed": true,
+                "lastMobilePinChange": "2019-06-01T10:00:00+03:00",
+                "nextMobilePinChange": "2020-06-01T10:00:00+03:00",
+            },
+            "passInfo": {
+                "passwordUsed": true,
+                "lastPassChange": "2019-06-01T10:00:00+03:00",
+                "nextPassChange": "2020-06-01T10:00:00+03:00",
+            },
+            "pinInfo": {
+                "pinUsed": true,
+            },
+            "contractInfo": {
+                "blocked": false,
+                "contractId": 79_991_234_567u64,
+                "creationDate": "2017-03-01T10:00:00Z",
+                "features": [],
+                "identificationInfo": [
+                    {
+                        "bankAlias": "QIWI",
+                        "identificationLevel": "VERIFIED",
+                        "passportExpired": false,
+                    },
+                ],
+                "userInfo": {
+                    "defaultPayCurrency": 643,
+                    "defaultPaySource": 7,
+                    "email": "demo@example.com",
+                    "firstTxnId": 10_000_000_000u64,
+                    "language": "ru",
+                    "operator": "Beeline",
+                    "phoneHash": "0000000000000000",
+                    "promoEnabled": "false",
+                },
+            },
+        },
+    })
+}
+
+/// RUB, USD and EUR balances.
+pub fn accounts() -> Value {
+    let account = |currency: Currency, amount: &str, default: bool| {
+        let alias = AccountAlias::from_currency(currency).unwrap();
+        let code = QiwiCurrency::from(currency).to_string();
+        json!({
+            "alias": alias.as_str(),
+            "fsAlias": "qb_wallet",
+            "bankAlias": "QIWI",
+            "title": "Qiwi Account",
+            "type": { "id": "WALLET", "title": "QIWI Wallet" },
+            "hasBalance": true,
+            "balance": { "amount": amount, "currency": code },
+            "currency": code,
+            "defaultAccount": default,
+        })
+    };
+
+    json!({
+        "accounts": [
+            account(Currency::RUB, "12345.67", true),
+            account(Currency::USD, "150.00", false),
+            account(Currency::EUR, "20.50", false),
+        ],
+    })
+}
+
+/// Procedurally generated history entries, newest first, six hours apart.
+pub fn history_entries(count: usize) -> Vec<Value> {
+    // Linear congruential generator, good enough for varied but reproducible amounts.
+    let mut seed = 0x5eed_u64;
+    let mut next = move || {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        seed >> 33
+    };
+
+    (0..count)
+        .map(|i| {
+            let date = history_end() - chrono::Duration::hours(6 * i as i64);
+            let incoming = next() % 3 == 0;
+            let (provider, account) = match next() % 4 {
+                0 | 1 => (99, format!("+7999{:07}", next() % 10_000_000)),
+                2 => (1963, format!("4276********{:04}", next() % 10_000)),
+                _ => (2, format!("+7903{:07}", next() % 10_000_000)),
+            };
+            let amount = BigDecimal::new((next() % 500_000 + 100).into(), 2);
+            let status = if next() % 20 == 0 { "ERROR" } else { "SUCCESS" };
+            let txn_id = 10_000_000_000u64 + (count - i) as u64;
+            let sum = json!({ "amount": amount.to_string(), "currency": "643" });
+
+            json!({
+                "txnId": txn_id,
+                "personId": 79_991_234_567u64,
+                "date": date.to_rfc3339(),
+                "errorCode": 0,
+                "error": "",
+                "type": if incoming { "IN" } else { "OUT" },
+                "status": status,
+                "statusText": status,
+                "trmTxnId": (1_500_000_000_000u64 + txn_id).to_string(),
+                "account": account,
+                "sum": sum,
+                "commission": { "amount": "0", "currency": "643" },
+                "total": sum,
+                "provider": {
+                    "id": provider,
+                    "shortName": "",
+                    "longName": "",
+                    "logoUrl": "",
+                    "description": "",
+                    "keys": "",
+                    "siteUrl": "",
+                },
+                "comment": "",
+                "currencyRate": "1",
+                "extras": {},
+                "chequeReady": true,
+                "bankDocumentAvailable": false,
+                "bankDocumentReady": false,
+                "repeatPaymentEnabled": false,
+                "favoritePaymentEnabled": false,
+                "regularPaymentEnabled": false,
+            })
+        })
+        .collect()
+}
+
+/// Webhook notification of the payment in a generated history `entry`.
+///
+/// Amounts are sent as floating point numbers, the way QIWI rounds them in notifications, so
+/// they may be off by a fraction of a kopeck from the history entry.
+pub fn webhook_payment(entry: &Value) -> Value {
+    let money = |sum: &Value| {
+        let amount = sum["amount"].as_str().unwrap_or("0");
+        json!({
+            "amount": amount.parse::<f64>().unwrap_or_default() * 1.000_000_1,
+            "currency": sum["currency"],
+        })
+    };
+    json!({
+        "messageId": format!("msg-{}", entry["txnId"]),
+        "hookId": "00000000-0000-0000-0000-000000000001",
+        "payment": {
+            "txnId": entry["txnId"].to_string(),
+            "date": entry["date"],
+            "type": entry["type"],
+            "status": entry["status"],
+            "personId": entry["personId"],
+            "account": entry["account"],
+            "comment": entry["comment"],
+            "provider": entry["provider"]["id"],
+            "sum": money(&entry["sum"]),
+            "commission": money(&entry["commission"]),
+            "total": money(&entry["total"]),
+            "signFields": "sum.currency,sum.amount,type,account,txnId",
+        },
+        "hash": "",
+        "version": "1.0.0",
+        "test": true,
+    })
+}
+
+/// Single page of history with all `entries`.
+pub fn history_page(entries: Vec<Value>) -> Value {
+    json!({
+        "data": entries,
+        "nextTxnId": null,
+        "nextTxnDate": null,
+    })
+}
+
+/// Responses for a typical wallet: profile, three balances and a single page of
+/// [`TYPICAL_HISTORY_LEN`] history entries.
+pub fn typical_wallet(phone: &PhoneNumber) -> Vec<(Method, String, Value)> {
+    let user = QiwiUser::from(phone.clone());
+    let versions = ApiVersions::default();
+    vec![
+        (Method::GET, versions.profile_endpoint(), profile()),
+        (
+            Method::GET,
+            format!("funding-sources/v2/persons/{}/accounts", user),
+            accounts(),
+        ),
+        (
+            Method::GET,
+            versions.history_endpoint(&user),
+            history_page(history_entries(TYPICAL_HISTORY_LEN)),
+        ),
+    ]
+}
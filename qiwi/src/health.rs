@@ -0,0 +1,487 @@
+use {
+    crate::*,
+    serde::Serialize,
+    std::time::{Duration, Instant},
+};
+
+/// How long probe results are reused, so that frequent scraping does not use up the quota.
+const PROBE_TTL: Duration = Duration::from_secs(60);
+
+/// Fewer requests in the last hour are not enough to judge the error rate.
+const MIN_REQUESTS_FOR_ERROR_RATE: u64 = 10;
+
+const P2P_LIMIT_TYPE: &str = "PAYMENTS_P2P";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warn,
+    Crit,
+}
+
+/// Value of a health check together with its severity.
+#[derive(Clone, Debug, Serialize)]
+pub struct Check<T> {
+    pub severity: Severity,
+    pub value: T,
+    /// Why the check is not `Ok`, or why its value is unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl<T> Check<T> {
+    fn new(severity: Severity, value: T) -> Self {
+        Self {
+            severity,
+            value,
+            detail: None,
+        }
+    }
+
+    fn detail<D: Display>(mut self, detail: D) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+/// Remaining P2P transfer limit in one currency.
+#[derive(Clone, Debug, Serialize)]
+pub struct LimitUsage {
+    pub rest: Money,
+    pub max: Option<Money>,
+}
+
+/// Requests sent in the last hour and how many of them failed.
+#[derive(Clone, Debug, Serialize)]
+pub struct ErrorRate {
+    pub requests: u64,
+    pub errors: u64,
+}
+
+/// Summary of the client's state for monitoring, see [`Client::health_report`].
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    pub generated_at: DateTime<Utc>,
+    /// When the token, restrictions and limits were last fetched.
+    pub probed_at: DateTime<Utc>,
+    /// Whether the last profile request succeeded.
+    pub token: Check<bool>,
+    pub restrictions: Check<Vec<Restriction>>,
+    /// See [`Client::is_read_only`].
+    pub read_only: Check<bool>,
+    pub p2p_limit: Check<Vec<LimitUsage>>,
+    pub error_rate: Check<ErrorRate>,
+    pub quota: Check<QuotaUsage>,
+    /// Estimated token expiry, see [`Client::token_expiry_estimate`].
+    pub token_expiry: Check<Option<DateTime<Utc>>>,
+}
+
+impl HealthReport {
+    /// The worst severity among the checks.
+    pub fn severity(&self) -> Severity {
+        *[
+            self.token.severity,
+            self.restrictions.severity,
+            self.read_only.severity,
+            self.p2p_limit.severity,
+            self.error_rate.severity,
+            self.quota.severity,
+            self.token_expiry.severity,
+        ]
+        .iter()
+        .max()
+        .unwrap()
+    }
+}
+
+/// Results of the requests made for the report.
+#[derive(Clone, Debug)]
+struct Probe {
+    at: Instant,
+    probed_at: DateTime<Utc>,
+    token: Result<(), String>,
+    restrictions: Result<Vec<Restriction>, String>,
+    p2p_limits: Result<Vec<LimitUsage>, String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct HealthCache {
+    /// Held while probing, so that concurrent reports share the requests.
+    probe: tokio::sync::Mutex<Option<Probe>>,
+}
+
+fn token_check(probe: &Probe) -> Check<bool> {
+    match &probe.token {
+        Ok(()) => Check::new(Severity::Ok, true),
+        Err(e) => Check::new(Severity::Crit, false).detail(e),
+    }
+}
+
+fn restrictions_check(probe: &Probe) -> Check<Vec<Restriction>> {
+    match &probe.restrictions {
+        Ok(restrictions) if restrictions.is_empty() => Check::new(Severity::Ok, vec![]),
+        Ok(restrictions) => Check::new(Severity::Crit, restrictions.clone()).detail(
+            restrictions
+                .iter()
+                .map(|restriction| restriction.restriction_description.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+        ),
+        Err(e) => Check::new(Severity::Warn, vec![]).detail(e),
+    }
+}
+
+/// `Warn` under a quarter of the limit left, `Crit` under a tenth.
+fn p2p_limit_check(probe: &Probe) -> Check<Vec<LimitUsage>> {
+    let limits = match &probe.p2p_limits {
+        Ok(limits) => limits,
+        Err(e) => return Check::new(Severity::Warn, vec![]).detail(e),
+    };
+
+    let severity = limits
+        .iter()
+        .filter_map(|limit| {
+            let max = &limit.max.as_ref()?.amount;
+            Some(if &limit.rest.amount * BigDecimal::from(10) < *max {
+                Severity::Crit
+            } else if &limit.rest.amount * BigDecimal::from(4) < *max {
+                Severity::Warn
+            } else {
+                Severity::Ok
+            })
+        })
+        .max()
+        .unwrap_or(Severity::Ok);
+
+    Check::new(severity, limits.clone())
+}
+
+/// `Warn` from a tenth of requests failing, `Crit` from a half.
+fn error_rate_check(usage: &QuotaUsage) -> Check<ErrorRate> {
+    let rate = ErrorRate {
+        requests: usage.total.last_hour,
+        errors: usage.errors.last_hour,
+    };
+    let severity = if rate.requests < MIN_REQUESTS_FOR_ERROR_RATE {
+        Severity::Ok
+    } else if rate.errors * 2 >= rate.requests {
+        Severity::Crit
+    } else if rate.errors * 10 >= rate.requests {
+        Severity::Warn
+    } else {
+        Severity::Ok
+    };
+
+    Check::new(severity, rate)
+}
+
+/// `Warn` once requests are delayed by the soft quota, `Crit` from 90% of it.
+fn quota_check(usage: QuotaUsage, soft_limit: Option<u32>) -> Check<QuotaUsage> {
+    let used = usage.total.last_minute;
+    let severity = match soft_limit.map(u64::from) {
+        Some(limit) if used * 10 >= limit * 9 => Severity::Crit,
+        Some(limit) if used * 2 >= limit => Severity::Warn,
+        _ => Severity::Ok,
+    };
+
+    Check::new(severity, usage)
+}
+
+/// `Warn` close to the estimated expiry, see [`ClientBuilder::token_expiry_warning`].
+fn token_expiry_check(
+    expiry: QiwiResult<Option<DateTime<Utc>>>,
+    warning: chrono::Duration,
+    now: DateTime<Utc>,
+) -> Check<Option<DateTime<Utc>>> {
+    match expiry {
+        Ok(Some(expiry)) if expiry - now < warning => Check::new(Severity::Warn, Some(expiry))
+            .detail("token is about to expire, issue a new one at qiwi.com/api"),
+        Ok(expiry) => Check::new(Severity::Ok, expiry),
+        Err(e) => Check::new(Severity::Warn, None).detail(e),
+    }
+}
+
+fn assemble(
+    probe: &Probe,
+    usage: QuotaUsage,
+    soft_limit: Option<u32>,
+    read_only: bool,
+    token_expiry: Check<Option<DateTime<Utc>>>,
+    now: DateTime<Utc>,
+) -> HealthReport {
+    HealthReport {
+        generated_at: now,
+        probed_at: probe.probed_at,
+        token: token_check(probe),
+        restrictions: restrictions_check(probe),
+        read_only: Check::new(
+            if read_only {
+                Severity::Crit
+            } else {
+                Severity::Ok
+            },
+            read_only,
+        ),
+        p2p_limit: p2p_limit_check(probe),
+        error_rate: error_rate_check(&usage),
+        quota: quota_check(usage, soft_limit),
+        token_expiry,
+    }
+}
+
+impl Client {
+    async fn probe_health(&self) -> Probe {
+        let profile = self.profile_info();
+        let restrictions = self.caller.call::<_, Vec<Restriction>>(
+            format!(
+                "person-profile/v1/persons/{}/status/restrictions",
+                self.user
+            ),
+            Method::GET,
+            &Default::default(),
+            None,
+        );
+        let limits = self.caller.call::<_, LimitRestsWrapper>(
+            format!("qw-limits/v1/persons/{}/actual-limits", self.user),
+            Method::GET,
+            &QueryParams::new().with("types[0]", P2P_LIMIT_TYPE),
+            None,
+        );
+        let (profile, restrictions, limits) = tokio::join!(profile, restrictions, limits);
+
+        Probe {
+            at: self.clock.now(),
+            probed_at: self.clock.utc_now(),
+            token: profile.map(|_| ()).map_err(|e| e.to_string()),
+            restrictions: restrictions
+                .map_err(Error::from)
+                .and_then(Rsp::into_result)
+                .map_err(|e| e.to_string()),
+            p2p_limits: limits
+                .map_err(Error::from)
+                .and_then(Rsp::into_result)
+                .map(|limits| {
+                    limits
+                        .limits
+                        .into_iter()
+                        .flat_map(|(_, limits)| limits)
+                        .filter(|limit| limit.limit_type == P2P_LIMIT_TYPE)
+                        .map(|limit| LimitUsage {
+                            max: limit.max.map(|max| Money {
+                                amount: max,
+                                currency: limit.currency.clone(),
+                            }),
+                            rest: Money {
+                                amount: limit.rest,
+                                currency: limit.currency,
+                            },
+                        })
+                        .collect()
+                })
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Token validity and expiry, wallet restrictions, P2P limit, error rate and quota usage with their severities.
+    ///
+    /// The token, restrictions and limit are fetched at most once a minute, so the report can be scraped often.
+    /// Failures to fetch them are reported in the checks rather than as an error.
+    pub async fn health_report(&self) -> QiwiResult<HealthReport> {
+        let probe = {
+            let mut cached = self.health.probe.lock().await;
+            let now = self.clock.now();
+            match cached
+                .as_ref()
+                .filter(|probe| now.saturating_duration_since(probe.at) < PROBE_TTL)
+            {
+                Some(probe) => probe.clone(),
+                None => {
+                    let probe = self.probe_health().await;
+                    *cached = Some(probe.clone());
+                    probe
+                }
+            }
+        };
+
+        let now = self.clock.utc_now();
+        let token_expiry = token_expiry_check(
+            self.token_expiry_estimate().await,
+            self.token_expiry_warning,
+            now,
+        );
+        Ok(assemble(
+            &probe,
+            self.quota_usage(),
+            self.caller.quota.soft_limit(),
+            self.is_read_only(),
+            token_expiry,
+            now,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, serde_json::json};
+
+    fn phone() -> PhoneNumber {
+        "+79991234567".parse().unwrap()
+    }
+
+    fn restrictions_endpoint() -> String {
+        format!(
+            "person-profile/v1/persons/{}/status/restrictions",
+            QiwiUser::from(phone())
+        )
+    }
+
+    fn limits_endpoint() -> String {
+        format!(
+            "qw-limits/v1/persons/{}/actual-limits",
+            QiwiUser::from(phone())
+        )
+    }
+
+    fn limits(rest: &str) -> Value {
+        json!({
+            "limits": {
+                "RU": [{ "type": P2P_LIMIT_TYPE, "currency": "643", "rest": rest, "max": "1000" }],
+            },
+        })
+    }
+
+    fn client(token_age_days: i64) -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let now = Utc.with_ymd_and_hms(2020, 2, 1, 12, 0, 0).unwrap();
+        let transport = Arc::new(
+            OfflineTransport::new()
+                .with(
+                    Method::GET,
+                    ApiVersions::default().profile_endpoint(),
+                    &fixtures::profile(),
+                )
+                .with(Method::GET, restrictions_endpoint(), &json!([]))
+                .with(Method::GET, limits_endpoint(), &limits("900")),
+        );
+        let clock = Arc::new(ManualClock::new(now));
+        let client = Client::builder(phone(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .token_issued_at(now - chrono::Duration::days(token_age_days))
+            .build();
+        (client, transport, clock)
+    }
+
+    #[tokio::test]
+    async fn healthy_wallet() {
+        let (client, _, clock) = client(30);
+
+        let report = client.health_report().await.unwrap();
+        assert_eq!(report.severity(), Severity::Ok);
+        assert_eq!(report.generated_at, clock.utc_now());
+        assert_eq!(report.probed_at, clock.utc_now());
+        assert!(report.token.value);
+        assert_eq!(report.p2p_limit.value[0].rest.amount, BigDecimal::from(900));
+        assert_eq!(
+            report.token_expiry.value,
+            Some(clock.utc_now() + chrono::Duration::days(150))
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_once_a_minute() {
+        let (client, transport, clock) = client(30);
+        let first = client.health_report().await.unwrap();
+        assert_eq!(transport.requests().len(), 3);
+
+        clock.advance(PROBE_TTL - Duration::from_secs(1));
+        let cached = client.health_report().await.unwrap();
+        assert_eq!(transport.requests().len(), 3);
+        assert_eq!(cached.probed_at, first.probed_at);
+        assert!(cached.generated_at > first.generated_at);
+
+        clock.advance(Duration::from_secs(1));
+        let fresh = client.health_report().await.unwrap();
+        assert_eq!(transport.requests().len(), 6);
+        assert_eq!(fresh.probed_at, clock.utc_now());
+    }
+
+    #[tokio::test]
+    async fn concurrent_reports_share_probe() {
+        let (client, transport, _) = client(30);
+        let (first, second) = tokio::join!(client.health_report(), client.health_report());
+        assert_eq!(first.unwrap().probed_at, second.unwrap().probed_at);
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn degraded_wallet() {
+        let (client, transport, _) = client(170);
+        transport.insert(Method::GET, limits_endpoint(), &limits("50"));
+        transport.insert(
+            Method::GET,
+            restrictions_endpoint(),
+            &json!([{ "restrictionCode": "OUTGOING_PAYMENTS", "restrictionDescription": "Payments are restricted" }]),
+        );
+
+        let report = client.health_report().await.unwrap();
+        assert_eq!(report.p2p_limit.severity, Severity::Crit);
+        assert_eq!(report.restrictions.severity, Severity::Crit);
+        assert_eq!(
+            report.restrictions.detail.as_deref(),
+            Some("Payments are restricted")
+        );
+        assert_eq!(report.token_expiry.severity, Severity::Warn);
+        assert_eq!(report.severity(), Severity::Crit);
+    }
+
+    #[tokio::test]
+    async fn failed_probes_are_reported_in_checks() {
+        let transport = OfflineTransport::new();
+        let client = Client::builder(phone(), "")
+            .transport(transport)
+            .clock(ManualClock::new(Utc::now()))
+            .build();
+
+        let report = client.health_report().await.unwrap();
+        assert_eq!(report.token.severity, Severity::Crit);
+        assert_eq!(report.restrictions.severity, Severity::Warn);
+        assert_eq!(report.p2p_limit.severity, Severity::Warn);
+        assert!(report.p2p_limit.detail.is_some());
+        assert_eq!(report.token_expiry.value, None);
+    }
+
+    fn usage(requests: u64, errors: u64) -> QuotaUsage {
+        QuotaUsage {
+            total: WindowUsage {
+                last_minute: requests,
+                last_hour: requests,
+            },
+            errors: WindowUsage {
+                last_minute: errors,
+                last_hour: errors,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn error_rate_thresholds() {
+        let severity = |requests, errors| error_rate_check(&usage(requests, errors)).severity;
+        assert_eq!(severity(9, 9), Severity::Ok);
+        assert_eq!(severity(100, 9), Severity::Ok);
+        assert_eq!(severity(100, 10), Severity::Warn);
+        assert_eq!(severity(100, 49), Severity::Warn);
+        assert_eq!(severity(100, 50), Severity::Crit);
+    }
+
+    #[test]
+    fn quota_thresholds() {
+        let severity = |used, limit| quota_check(usage(used, 0), limit).severity;
+        assert_eq!(severity(1000, None), Severity::Ok);
+        assert_eq!(severity(49, Some(100)), Severity::Ok);
+        assert_eq!(severity(50, Some(100)), Severity::Warn);
+        assert_eq!(severity(89, Some(100)), Severity::Warn);
+        assert_eq!(severity(90, Some(100)), Severity::Crit);
+    }
+}
@@ -0,0 +1,211 @@
+//! Conditional requests to endpoints returning cache validators, see [`ClientBuilder::http_cache`](crate::ClientBuilder::http_cache).
+
+use {
+    crate::{
+        state::{self, StateStore},
+        QueryParams,
+    },
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        sync::Arc,
+    },
+};
+
+const INDEX_KEY: &str = "http-cache-index";
+
+/// Bounds of the cache, least recently used responses are evicted beyond them.
+#[derive(Clone, Copy, Debug)]
+pub struct HttpCacheLimits {
+    pub max_entries: usize,
+    /// Larger responses are not cached.
+    pub max_entry_size: usize,
+    /// Total size of cached bodies.
+    pub max_total_size: usize,
+}
+
+impl Default for HttpCacheLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 256,
+            max_entry_size: 1 << 20,
+            max_total_size: 16 << 20,
+        }
+    }
+}
+
+/// Response body with its validators.
+///
+/// Bodies are kept as text, responses that are not UTF-8 are not cached.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CachedResponse {
+    url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    body: String,
+}
+
+impl CachedResponse {
+    pub fn into_body(self) -> Vec<u8> {
+        self.body.into_bytes()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct IndexEntry {
+    key: String,
+    size: usize,
+}
+
+/// Cached responses, least recently used first.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl CacheIndex {
+    fn total_size(&self) -> usize {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// Moves the entry to the end, if there is one.
+    fn touch(&mut self, key: &str) -> bool {
+        match self.entries.iter().position(|entry| entry.key == key) {
+            Some(i) => {
+                let entry = self.entries.remove(i);
+                self.entries.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Responses of `GET` requests with an `ETag` or `Last-Modified` header, kept in a [`StateStore`].
+///
+/// Cached validators are sent with later requests to the same URL, and the cached body is
+/// returned as is if the server answers `304 Not Modified`. Errors of the store are logged and
+/// treated as cache misses.
+#[derive(Debug)]
+pub struct HttpCache {
+    store: Arc<dyn StateStore>,
+    limits: HttpCacheLimits,
+    /// Loaded from the store on first use.
+    index: tokio::sync::Mutex<Option<CacheIndex>>,
+}
+
+impl HttpCache {
+    pub fn new(store: Arc<dyn StateStore>, limits: HttpCacheLimits) -> Self {
+        Self {
+            store,
+            limits,
+            index: Default::default(),
+        }
+    }
+
+    /// Key of the request in the cache, the endpoint with the query string.
+    pub(crate) fn url(endpoint: &str, params: &QueryParams) -> String {
+        let mut url = endpoint.to_string();
+        for (i, (key, value)) in params.iter().enumerate() {
+            url.push(if i == 0 { '?' } else { '&' });
+            url.push_str(key);
+            url.push('=');
+            url.push_str(value);
+        }
+        url
+    }
+
+    /// URLs are hashed to keep keys short, the URL is checked on load.
+    fn key(url: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        format!("http-cache-{:016x}", hasher.finish())
+    }
+
+    pub(crate) async fn get(&self, url: &str) -> Option<CachedResponse> {
+        match state::load::<CachedResponse>(&*self.store, &Self::key(url)).await {
+            Ok(entry) => entry.filter(|entry| entry.url == url),
+            Err(e) => {
+                warn!("Failed to read cached response of {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    /// Marks the response as recently used after it was served.
+    pub(crate) async fn touch(&self, url: &str) {
+        let mut index = self.index.lock().await;
+        let index = self.loaded(&mut index).await;
+        if index.touch(&Self::key(url)) {
+            self.save_index(index).await;
+        }
+    }
+
+    pub(crate) async fn insert(
+        &self,
+        url: &str,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        body: &[u8],
+    ) {
+        let body = match std::str::from_utf8(body) {
+            Ok(body) if body.len() <= self.limits.max_entry_size => body,
+            _ => return,
+        };
+        let key = Self::key(url);
+        let entry = CachedResponse {
+            url: url.to_string(),
+            etag,
+            last_modified,
+            body: body.to_string(),
+        };
+
+        let mut index = self.index.lock().await;
+        let index = self.loaded(&mut index).await;
+        if let Err(e) = state::save(&*self.store, &key, &entry).await {
+            warn!("Failed to cache response of {}: {}", url, e);
+            return;
+        }
+        index.entries.retain(|entry| entry.key != key);
+        index.entries.push(IndexEntry {
+            key,
+            size: body.len(),
+        });
+
+        while !index.entries.is_empty()
+            && (index.entries.len() > self.limits.max_entries
+                || index.total_size() > self.limits.max_total_size)
+        {
+            let evicted = index.entries.remove(0);
+            trace!("Evicting cached response {}", evicted.key);
+            if let Err(e) = self.store.take(&evicted.key).await {
+                warn!("Failed to evict cached response {}: {}", evicted.key, e);
+            }
+        }
+        self.save_index(index).await;
+    }
+
+    async fn loaded<'a>(&self, index: &'a mut Option<CacheIndex>) -> &'a mut CacheIndex {
+        if index.is_none() {
+            *index = Some(
+                match state::load::<CacheIndex>(&*self.store, INDEX_KEY).await {
+                    Ok(loaded) => loaded.unwrap_or_default(),
+                    Err(e) => {
+                        warn!("Failed to read HTTP cache index, starting anew: {}", e);
+                        CacheIndex::default()
+                    }
+                },
+            );
+        }
+        index.as_mut().unwrap()
+    }
+
+    async fn save_index(&self, index: &CacheIndex) {
+        if let Err(e) = state::save(&*self.store, INDEX_KEY, index).await {
+            warn!("Failed to save HTTP cache index: {}", e);
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use crate::*;
+
+impl Client {
+    /// Personal data of the wallet owner, with document numbers masked.
+    pub async fn identification(&self) -> QiwiResult<Identification> {
+        let url = format!("identification/v1/persons/{}/identification", self.user);
+        Ok(self
+            .caller
+            .call(url, Method::GET, &Default::default(), None)
+            .await?
+            .into_result()?)
+    }
+
+    /// Submits personal data for simple identification and returns the data on file.
+    ///
+    /// Forgets the cached [`Client::identification_level`], the level may change once QIWI
+    /// checks the data.
+    pub async fn submit_identification(
+        &self,
+        req: &IdentificationRequest,
+    ) -> QiwiResult<Identification> {
+        let url = format!("identification/v1/persons/{}/identification", self.user);
+        let identification = self
+            .caller
+            .call(url, Method::POST, &Default::default(), Some(&json!(req)))
+            .await?
+            .into_result()?;
+        *self.identification_level.lock().unwrap() = None;
+
+        Ok(identification)
+    }
+}
@@ -0,0 +1,110 @@
+//! Sources of payment ids, confirmation tokens and other generated values.
+
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Mints ids for the client, see [`ClientBuilder::id_generator`](crate::ClientBuilder::id_generator).
+pub trait IdGenerator: Debug + Send + Sync + 'static {
+    /// Client-side id of a payment, see [`TransferRequest::idempotency_id`](crate::TransferRequest::idempotency_id).
+    fn next_payment_id(&self) -> u64;
+    /// Opaque token of hex digits and dashes, e.g. for [`Client::prepare_transfer`](crate::Client::prepare_transfer).
+    fn next_token(&self) -> String;
+    /// Fills `buf` with random bytes, e.g. for donation codes.
+    fn fill_random(&self, buf: &mut [u8]) {
+        fill_from_uuids(buf)
+    }
+
+    /// Short id telling calls apart in logs and errors, see [`CallerWrapper`](crate::CallerWrapper).
+    fn next_correlation_id(&self) -> String {
+        let mut buf = [0; 6];
+        self.fill_random(&mut buf);
+        buf.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+fn fill_from_uuids(buf: &mut [u8]) {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let bytes = *uuid::Uuid::new_v4().as_bytes();
+        // Bytes 6 and 8 carry the UUID version and variant.
+        for (_, &b) in bytes.iter().enumerate().filter(|&(i, _)| i != 6 && i != 8) {
+            if filled == buf.len() {
+                break;
+            }
+            buf[filled] = b;
+            filled += 1;
+        }
+    }
+}
+
+/// Payment ids from the current time in milliseconds, see [`new_payment_id`](crate::new_payment_id),
+/// and random UUID tokens.
+///
+/// Payment ids are strictly increasing, even if requested within one millisecond.
+#[derive(Debug, Default)]
+pub struct DefaultIdGenerator {
+    last_payment_id: AtomicU64,
+}
+
+impl IdGenerator for DefaultIdGenerator {
+    fn next_payment_id(&self) -> u64 {
+        let now = crate::new_payment_id();
+        let mut last = self.last_payment_id.load(Ordering::Relaxed);
+        loop {
+            let id = now.max(last + 1);
+            match self.last_payment_id.compare_exchange_weak(
+                last,
+                id,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return id,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+
+    fn next_token(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// Generates `start`, `start + 1`, ... for reproducible requests in tests.
+///
+/// Payment ids, tokens and random bytes are taken from separate counters.
+#[derive(Debug, Default)]
+pub struct SequentialIdGenerator {
+    payment_id: AtomicU64,
+    token: AtomicU64,
+    random: AtomicU64,
+}
+
+impl SequentialIdGenerator {
+    pub fn new(start: u64) -> Self {
+        Self {
+            payment_id: AtomicU64::new(start),
+            token: AtomicU64::new(start),
+            random: AtomicU64::new(start),
+        }
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_payment_id(&self) -> u64 {
+        self.payment_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Formatted like a UUID, e.g. `00000000-0000-0000-0000-000000000001`.
+    fn next_token(&self) -> String {
+        let n = self.token.fetch_add(1, Ordering::Relaxed);
+        format!("00000000-0000-0000-0000-{:012x}", n)
+    }
+
+    fn fill_random(&self, buf: &mut [u8]) {
+        for b in buf {
+            *b = self.random.fetch_add(1, Ordering::Relaxed) as u8;
+        }
+    }
+}
@@ -1,50 +1,387 @@
 //! Client for QIWI API based on [its official documentation](https://developer.qiwi.com/ru/qiwi-wallet-personal).
 #![recursion_limit = "256"]
 
+pub mod audit;
+#[cfg(feature = "payments")]
+pub mod batch;
+#[cfg(feature = "bills")]
+mod bills;
+mod call;
+#[cfg(feature = "identification")]
+mod capabilities;
+#[cfg(feature = "cards")]
+mod cards;
+pub mod clock;
+pub mod codec;
+pub mod compat;
+#[cfg(feature = "payments")]
+mod confirm;
+pub mod contract;
+#[cfg(feature = "payments")]
+mod conversion;
+pub mod deps;
+#[cfg(feature = "doh")]
+pub mod doh;
+#[cfg(feature = "history")]
+pub mod donations;
+#[cfg(feature = "payments")]
+mod duplicates;
+#[cfg(feature = "history")]
+pub mod export;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixtures;
+mod health;
+pub mod http_cache;
+#[cfg(feature = "identification")]
+mod identification;
+pub mod ids;
+pub mod mfa;
 mod models;
+#[cfg(any(test, feature = "test-util"))]
+mod offline;
+pub mod oplog;
+pub mod policy;
+mod poll;
+pub mod portable;
+#[cfg(feature = "payments")]
+mod preflight;
+pub mod quick;
+mod quota;
+mod read_only;
+#[cfg(feature = "history")]
+mod receipts;
+#[cfg(feature = "history")]
+pub mod reconcile;
+#[cfg(feature = "payments")]
+mod recurring;
+#[cfg(feature = "history")]
+mod reports;
+mod sandbox;
+pub mod state;
+mod statement;
+pub mod stream_ext;
+#[cfg(feature = "history")]
+pub mod sync;
+mod token_age;
 mod transport;
+mod versions;
+#[cfg(feature = "history")]
+mod watch;
+#[cfg(feature = "webhooks")]
+mod webhooks;
 
-pub use {models::*, transport::*};
+pub use {
+    health::{Check, ErrorRate, HealthReport, LimitUsage, Severity},
+    http::Method,
+    poll::{Backoff, PollCoordinator, PollRegistration, WatchOptions, WatcherStatus},
+    portable::PortableError,
+    qiwi_types::*,
+    quota::{EndpointCategory, QuotaUsage, WindowUsage},
+    sandbox::*,
+    statement::MAX_STATEMENT_DAYS,
+    token_age::{DEFAULT_TOKEN_LIFETIME_DAYS, DEFAULT_TOKEN_WARNING_DAYS},
+    transport::*,
+    versions::ApiVersions,
+};
+
+#[cfg(feature = "bills")]
+pub use bills::BillWatchEvent;
+
+#[cfg(feature = "history")]
+pub use {reports::*, watch::*};
+
+#[cfg(feature = "payments")]
+pub use {confirm::PreparedTransfer, conversion::*, recurring::*};
+
+#[cfg(any(test, feature = "test-util"))]
+pub use offline::{OfflineRequest, OfflineTransport};
+
+#[cfg(feature = "webhooks")]
+pub use webhooks::verify_webhook_signature;
+
+use models::*;
 
 use {
-    async_stream::try_stream,
     bigdecimal::BigDecimal,
     chrono::prelude::*,
-    http::Method,
-    maplit::hashmap,
-    penny::Currency,
     phonenumber::PhoneNumber,
-    serde_json::json,
+    serde_json::{json, Value},
     snafu::*,
     std::{
-        collections::HashMap,
-        convert::TryFrom,
+        collections::BTreeMap,
         fmt::{Debug, Display},
         pin::Pin,
-        sync::Arc,
+        sync::{Arc, Mutex},
     },
-    tokio::stream::*,
 };
 
+#[cfg(feature = "history")]
+use async_stream::try_stream;
+
+#[cfg(any(feature = "bills", feature = "history"))]
+use tokio::stream::*;
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     TransportError {
         #[snafu(backtrace)]
         source: transport::Error,
     },
-    QiwiError {
-        description: String,
+    #[snafu(display("QIWI error {}", error))]
+    QiwiError { error: QiwiApiError },
+    /// HTTP 401 or 403, the token is wrong, expired or lacks the permissions for the call.
+    #[snafu(display("unauthorized ({}): {}", status, body))]
+    Unauthorized {
+        status: u16,
+        body: String,
+        /// QIWI error in the body, e.g. `internal.invalid.token`.
+        error: Option<QiwiApiError>,
+        correlation_id: Option<String>,
     },
     AuthorizationCallbackError {
         source: StdError,
         backtrace: Backtrace,
+        correlation_id: Option<String>,
+    },
+    #[snafu(display("{} does not refer to a wallet balance", alias))]
+    InvalidAccountAlias { alias: AccountAlias },
+    #[snafu(display("wallet is in read-only mode until {}: {}", until, reason))]
+    ReadOnlyMode {
+        until: DateTime<Utc>,
+        reason: String,
+    },
+    #[snafu(display("payment not sent, failed to audit it: {}", source))]
+    AuditFailed { source: StdError },
+    #[snafu(display("payment rejected by policy: {}", source))]
+    PolicyViolation { source: policy::PolicyViolation },
+    #[snafu(display(
+        "payment denied by second factor: {}",
+        reason.as_deref().unwrap_or("no reason given")
+    ))]
+    MfaDenied { reason: Option<String> },
+    #[snafu(display("payment was not approved by second factor in time"))]
+    MfaTimeout,
+    #[snafu(display("{}", source))]
+    MfaError { source: mfa::MfaError },
+    #[snafu(display("{}", source))]
+    CurrencyMismatch { source: MismatchedCurrencies },
+    #[snafu(display("insufficient funds: {} available, {} required", available, required))]
+    InsufficientFunds { available: Money, required: Money },
+    #[snafu(display("P2P transfer limit exceeded, {} remaining", remaining))]
+    LimitExceeded { remaining: Money },
+    #[snafu(display("balance {} cannot fund a payment in {}", alias, currency))]
+    AccountCurrencyMismatch {
+        alias: AccountAlias,
+        currency: QiwiCurrency,
+    },
+    #[snafu(display(
+        "webhook {} was deleted but the new one could not be registered: {}",
+        old_url,
+        source
+    ))]
+    WebhookLost { old_url: String, source: Box<Error> },
+    #[snafu(display("webhook key is not valid base64: {}", source))]
+    InvalidWebhookKey { source: StdError },
+    /// Offline transport has no response for the endpoint.
+    #[snafu(display("no offline response for {}", endpoint))]
+    Offline { endpoint: String },
+    /// Response rejected by the validator, see [`ClientBuilder::response_validator`].
+    #[snafu(display("response of {} violates the contract: {}", endpoint, details))]
+    ContractViolation {
+        endpoint: String,
+        details: String,
+        correlation_id: Option<String>,
+    },
+    #[snafu(display(
+        "unexpected response from {} API {}, check the configured API version: {}",
+        api,
+        version,
+        source
+    ))]
+    UnexpectedApiResponse {
+        api: &'static str,
+        version: String,
+        source: transport::Error,
     },
+    #[snafu(display(
+        "a similar payment {} was made recently, force the request to send it anyway",
+        existing_txn_id
+    ))]
+    PossibleDuplicate { existing_txn_id: u64 },
+    #[snafu(display("no cross rate from {} to {}", from, to))]
+    NoCrossRate {
+        from: QiwiCurrency,
+        to: QiwiCurrency,
+    },
+    #[snafu(display("conversion rate moved from {} to {}", planned, current))]
+    RateMoved {
+        planned: BigDecimal,
+        current: BigDecimal,
+    },
+    #[snafu(display("unknown or already used confirmation token"))]
+    InvalidConfirmation,
+    #[snafu(display("confirmation token expired at {}", expired_at))]
+    ConfirmationExpired { expired_at: DateTime<Utc> },
+    #[snafu(display("failed to access state store: {}", source))]
+    StateStoreError { source: StdError },
+    #[snafu(display("operation {} is not in the log or cannot be resumed", id))]
+    UnknownOperation { id: String },
+    #[snafu(display(
+        "statement period from {} till {} must be from 1 to {} days",
+        from,
+        till,
+        statement::MAX_STATEMENT_DAYS
+    ))]
+    InvalidStatementPeriod { from: NaiveDate, till: NaiveDate },
+    #[snafu(display("invalid date range: {:?} to {:?}", start, end))]
+    InvalidDateRange {
+        start: Option<DateTime<FixedOffset>>,
+        end: Option<DateTime<FixedOffset>>,
+    },
+    #[snafu(display(
+        "date range from {} to {} is longer than {} days",
+        start,
+        end,
+        max_days
+    ))]
+    DateRangeTooWide {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        max_days: i64,
+    },
+    #[snafu(display("failed to download receipt of transaction {}: {}", txn_id, source))]
+    ReceiptDownloadFailed { txn_id: u64, source: Box<Error> },
+    #[snafu(display("{}", source))]
+    IncompleteTransfer { source: MissingBankFields },
+    #[snafu(display("no provider is mapped for {} transfers", route))]
+    NoProviderMapping { route: TransferRoute },
+    #[snafu(display("no carrier accepts payments to the phone: {}", reason))]
+    UnknownMobileProvider { reason: String },
+    #[snafu(display("no provider accepts transfers to the card: {}", reason))]
+    UnknownCardProvider { reason: String },
+    #[snafu(display("transaction {} not found", txn_id))]
+    TransactionNotFound { txn_id: u64 },
+    /// Statement is being generated, retry in a few minutes.
+    #[snafu(display("statement is not ready yet"))]
+    StatementNotReady,
+    /// Payment order is issued once the bank transfer settles, retry later.
+    #[snafu(display("bank document for transaction {} is not ready yet", txn_id))]
+    DocumentNotReady { txn_id: u64 },
+    #[snafu(display("transaction {} has no bank document", txn_id))]
+    NoBankDocument { txn_id: u64 },
+    /// Error passed from another process that this version cannot rebuild, see [`Error::from_portable`].
+    #[snafu(display("{}", error))]
+    Remote { error: PortableError },
+}
+
+impl From<transport::Error> for Error {
+    fn from(source: transport::Error) -> Self {
+        match source {
+            transport::Error::TokenProviderError {
+                source,
+                backtrace,
+                correlation_id,
+            } => Self::AuthorizationCallbackError {
+                source,
+                backtrace,
+                correlation_id,
+            },
+            transport::Error::Offline { endpoint } => Self::Offline { endpoint },
+            transport::Error::NetworkError {
+                source,
+                backtrace,
+                correlation_id,
+            } => match source.downcast_ref::<HttpStatusError>() {
+                Some(error) if error.status == 401 || error.status == 403 => {
+                    Self::unauthorized(error.status, error.body.clone(), correlation_id)
+                }
+                _ => Self::TransportError {
+                    source: transport::Error::NetworkError {
+                        source,
+                        backtrace,
+                        correlation_id,
+                    },
+                },
+            },
+            transport::Error::ContractViolation {
+                endpoint,
+                details,
+                correlation_id,
+            } => Self::ContractViolation {
+                endpoint,
+                details,
+                correlation_id,
+            },
+            source => Self::TransportError { source },
+        }
+    }
+}
+
+impl Error {
+    /// [`Error::Unauthorized`] with the QIWI error parsed from `body`, if it has one.
+    pub(crate) fn unauthorized(status: u16, body: String, correlation_id: Option<String>) -> Self {
+        let error = serde_json::from_str::<QiwiApiError>(&body)
+            .ok()
+            .map(|error| QiwiApiError {
+                correlation_id: correlation_id.clone(),
+                ..error
+            });
+        Self::Unauthorized {
+            status,
+            body,
+            error,
+            correlation_id,
+        }
+    }
+
+    /// Id of the call that failed, see [`CallerWrapper`].
+    pub fn correlation_id(&self) -> Option<&str> {
+        match self {
+            Self::TransportError { source } | Self::UnexpectedApiResponse { source, .. } => {
+                source.correlation_id()
+            }
+            Self::QiwiError { error } => error.correlation_id.as_deref(),
+            Self::AuthorizationCallbackError { correlation_id, .. }
+            | Self::Unauthorized { correlation_id, .. }
+            | Self::ContractViolation { correlation_id, .. } => correlation_id.as_deref(),
+            Self::WebhookLost { source, .. } => source.correlation_id(),
+            Self::Remote { error } => error.correlation_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// How long to wait before repeating a call that failed because a document is still being generated.
+    pub fn retry_later(&self) -> Option<std::time::Duration> {
+        match self {
+            Self::StatementNotReady | Self::DocumentNotReady { .. } => Some(DOCUMENT_RETRY_DELAY),
+            _ => None,
+        }
+    }
+
+    /// Whether repeating the failed call may succeed, e.g. after a network failure.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::TransportError { source } => source.is_retryable(),
+            Self::WebhookLost { source, .. } | Self::ReceiptDownloadFailed { source, .. } => {
+                source.is_retryable()
+            }
+            Self::MfaTimeout | Self::StatementNotReady | Self::DocumentNotReady { .. } => true,
+            Self::Remote { error } => error.retryable,
+            _ => false,
+        }
+    }
+}
+
+impl From<MismatchedCurrencies> for Error {
+    fn from(source: MismatchedCurrencies) -> Self {
+        Self::CurrencyMismatch { source }
+    }
 }
 
 impl<T> Rsp<T> {
     pub fn into_result(self) -> Result<T, Error> {
         match self {
-            Self::Error { error } => Err(Error::QiwiError { description: error }),
+            Self::Error(error) => Err(Error::QiwiError { error }),
             Self::OK(v) => Ok(v),
         }
     }
@@ -52,56 +389,862 @@ impl<T> Rsp<T> {
 
 pub type QiwiResult<T> = Result<T, self::Error>;
 
+const DOCUMENT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// Longest period [`Client::payment_totals`] can cover.
+pub const MAX_TOTALS_DAYS: i64 = 90;
+
+/// Operation and sources of a history filter.
+#[cfg(feature = "history")]
+fn push_kind_filter(params: &mut QueryParams, filter: &PaymentHistoryFilter) {
+    if let Some(operation) = filter.operation {
+        params.push("operation", operation.as_str());
+    }
+    params.push_indexed("sources", filter.sources.iter().map(|s| s.as_str()));
+}
+
+/// ISO 8601 with offset, as QIWI requires for history filters.
+#[cfg(feature = "history")]
+fn format_history_date(date: &DateTime<FixedOffset>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+/// Most history entries QIWI returns per page.
+#[cfg(feature = "history")]
+const HISTORY_PAGE_ROWS: u16 = 50;
+
+/// Providers used for wallet-to-wallet transfers.
+#[cfg(feature = "history")]
+const P2P_PROVIDERS: &[u64] = &[99, 99999];
+
+/// How far back [`Client::find_transfer_by_client_id`] looks.
+#[cfg(feature = "history")]
+const RECONCILIATION_WINDOW_DAYS: i64 = 7;
+
 pub struct Client {
     caller: CallerWrapper,
+    /// Absent if the client was built with a custom transport.
+    remote: Option<Arc<RemoteCaller>>,
     user: QiwiUser,
+    region: Region,
+    api_versions: ApiVersions,
+    #[cfg(feature = "payments")]
+    p2p_free_limit: Option<Money>,
+    #[cfg(feature = "payments")]
+    payment_policy: Option<Arc<dyn policy::PaymentPolicy>>,
+    #[cfg(feature = "payments")]
+    mfa: Option<(Arc<dyn mfa::MfaProvider>, Money)>,
+    #[cfg(feature = "payments")]
+    audit: Option<Arc<dyn audit::AuditSink>>,
+    #[cfg(feature = "payments")]
+    audit_required: bool,
+    /// Last cross rates with the time they were fetched.
+    #[cfg(feature = "payments")]
+    cross_rates_cache: Mutex<Option<(std::time::Instant, Vec<CrossRate>)>>,
+    #[cfg(feature = "payments")]
+    auto_readonly: bool,
+    #[cfg(feature = "payments")]
+    read_only_ttl: std::time::Duration,
+    #[cfg(feature = "payments")]
+    read_only: Mutex<Option<read_only::ReadOnlyState>>,
+    #[cfg(feature = "payments")]
+    preflight: Option<preflight::PreflightCache>,
+    #[cfg(feature = "payments")]
+    duplicate_window: Option<std::time::Duration>,
+    state_store: Option<Arc<dyn state::StateStore>>,
+    /// The state store, or an in-memory one if there is none.
+    local_state: Arc<dyn state::StateStore>,
+    health: health::HealthCache,
+    /// Serializes updates of the list of pending operations.
+    operations_lock: tokio::sync::Mutex<()>,
+    #[cfg(feature = "identification")]
+    identification_level: Mutex<Option<IdentificationLevel>>,
+    ids: Arc<dyn ids::IdGenerator>,
+    clock: Arc<dyn clock::Clock>,
+    polls: Arc<PollCoordinator>,
+    token_issued_at: Mutex<Option<DateTime<Utc>>>,
+    token_lifetime: chrono::Duration,
+    token_expiry_warning: chrono::Duration,
+    providers: ProviderMap,
 }
 
-impl Client {
-    pub fn new<T: Display>(phone: PhoneNumber, token: T) -> Self {
-        let http_client = reqwest::Client::builder().build().unwrap();
-        Self {
-            caller: CallerWrapper {
-                transport: Arc::new(RemoteCaller {
+const DEFAULT_BASE_URL: &str = "https://edge.qiwi.com";
+
+/// How long a token from [`TokenProvider`] is reused by default.
+const DEFAULT_TOKEN_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+pub struct ClientBuilder {
+    phone: PhoneNumber,
+    token: Arc<dyn TokenProvider>,
+    token_ttl: std::time::Duration,
+    transport: Option<Arc<dyn Transport>>,
+    region: Option<Region>,
+    api_versions: ApiVersions,
+    #[cfg(feature = "payments")]
+    p2p_free_limit: Option<Money>,
+    #[cfg(feature = "payments")]
+    payment_policy: Option<Arc<dyn policy::PaymentPolicy>>,
+    #[cfg(feature = "payments")]
+    mfa: Option<(Arc<dyn mfa::MfaProvider>, Money)>,
+    #[cfg(feature = "payments")]
+    audit: Option<Arc<dyn audit::AuditSink>>,
+    #[cfg(feature = "payments")]
+    audit_required: bool,
+    #[cfg(feature = "payments")]
+    auto_readonly: bool,
+    #[cfg(feature = "payments")]
+    read_only_ttl: std::time::Duration,
+    #[cfg(feature = "payments")]
+    preflight_checks: bool,
+    sandbox: bool,
+    soft_quota: Option<u32>,
+    #[cfg(feature = "payments")]
+    duplicate_window: Option<std::time::Duration>,
+    state_store: Option<Arc<dyn state::StateStore>>,
+    base_url: Option<String>,
+    ids: Option<Arc<dyn ids::IdGenerator>>,
+    clock: Option<Arc<dyn clock::Clock>>,
+    fallback_hosts: Vec<reqwest::Url>,
+    poll_min_gap: std::time::Duration,
+    lenient_parsing: bool,
+    response_validator: Option<Arc<dyn contract::ResponseValidator>>,
+    token_issued_at: Option<DateTime<Utc>>,
+    token_lifetime: chrono::Duration,
+    token_expiry_warning: chrono::Duration,
+    providers: ProviderMap,
+    request_timeout: Option<std::time::Duration>,
+    http_cache: Option<http_cache::HttpCacheLimits>,
+    #[cfg(feature = "doh")]
+    doh_resolver: Option<String>,
+}
+
+impl ClientBuilder {
+    /// Fetch the token from `provider` instead of using a fixed one.
+    ///
+    /// The token is reused for [`ClientBuilder::token_ttl`] and re-fetched once if the API rejects it.
+    pub fn token_provider<P: TokenProvider>(mut self, provider: P) -> Self {
+        self.token = Arc::new(provider);
+        self
+    }
+
+    /// How long a token from the provider is cached. One minute by default.
+    pub fn token_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.token_ttl = ttl;
+        self
+    }
+
+    /// When the token was issued, e.g. as saved at login. Takes precedence over the time kept in
+    /// the state store, see [`Client::record_token_issued`].
+    pub fn token_issued_at(mut self, at: DateTime<Utc>) -> Self {
+        self.token_issued_at = Some(at);
+        self
+    }
+
+    /// How long tokens are assumed to be valid, [`DEFAULT_TOKEN_LIFETIME_DAYS`] by default.
+    pub fn token_lifetime(mut self, lifetime: chrono::Duration) -> Self {
+        self.token_lifetime = lifetime;
+        self
+    }
+
+    /// Warn about the token this long before its estimated expiry, [`DEFAULT_TOKEN_WARNING_DAYS`] by default.
+    pub fn token_expiry_warning(mut self, threshold: chrono::Duration) -> Self {
+        self.token_expiry_warning = threshold;
+        self
+    }
+
+    /// Send requests through `transport` instead of the QIWI API. The token is not used then.
+    pub fn transport<T: Transport>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Host to send requests to instead of `https://edge.qiwi.com`, e.g. a mock server.
+    /// Not used with a custom transport.
+    pub fn base_url<T: Into<String>>(mut self, url: T) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    /// Region of the wallet. Detected from the phone number by default, falling back to Russia.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// Endpoint versions to use instead of the default ones.
+    pub fn api_versions(mut self, versions: ApiVersions) -> Self {
+        self.api_versions = versions;
+        self
+    }
+
+    /// Use the providers of `overrides` instead of the defaults of [`ProviderMap`], e.g. after
+    /// QIWI changes the id of a card provider.
+    pub fn provider_overrides(mut self, overrides: &ProviderMap) -> Self {
+        self.providers = self.providers.merge(overrides);
+        self
+    }
+
+    /// Monthly volume of P2P transfers that QIWI does not charge commission for.
+    #[cfg(feature = "payments")]
+    pub fn p2p_free_limit(mut self, limit: Money) -> Self {
+        self.p2p_free_limit = Some(limit);
+        self
+    }
+
+    /// Record every payment attempt in `sink`, before it is sent and once it completes.
+    ///
+    /// If the intent can not be recorded the payment is not sent and fails with
+    /// [`Error::AuditFailed`], unless [`ClientBuilder::audit_required`] is disabled. Failures to
+    /// record outcomes are logged.
+    #[cfg(feature = "payments")]
+    pub fn audit_sink<S: audit::AuditSink>(mut self, sink: S) -> Self {
+        self.audit = Some(Arc::new(sink));
+        self
+    }
+
+    /// Send payments even if their intent could not be audited. Enabled by default.
+    #[cfg(feature = "payments")]
+    pub fn audit_required(mut self, required: bool) -> Self {
+        self.audit_required = required;
+        self
+    }
+
+    /// Accept former names of response fields listed in [`compat::RENAMED_FIELDS`], and report
+    /// their use through [`Client::last_compat_report`].
+    ///
+    /// Models accept known former names either way, lenient mode makes noticing upstream renames possible.
+    pub fn lenient_parsing(mut self, enabled: bool) -> Self {
+        self.lenient_parsing = enabled;
+        self
+    }
+
+    /// Check every parsed response with `validator`, e.g. a [`contract::RequiredFieldsValidator`]
+    /// in staging. Violations fail the call with [`Error::ContractViolation`].
+    pub fn response_validator<V: contract::ResponseValidator>(mut self, validator: V) -> Self {
+        self.response_validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Policy consulted before sending every payment.
+    #[cfg(feature = "payments")]
+    pub fn payment_policy<P: policy::PaymentPolicy>(mut self, policy: P) -> Self {
+        self.payment_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Ask `provider` to approve payments of more than `threshold` before sending them.
+    ///
+    /// Payments in other currencies are compared at the current cross rate, or need approval if there is none.
+    #[cfg(feature = "payments")]
+    pub fn mfa<P: mfa::MfaProvider>(mut self, provider: P, threshold: Money) -> Self {
+        self.mfa = Some((Arc::new(provider), threshold));
+        self
+    }
+
+    /// Refuse payments locally for a while after QIWI reports the wallet as restricted.
+    ///
+    /// Read-only calls keep working.
+    #[cfg(feature = "payments")]
+    pub fn auto_readonly_on_restriction(mut self, enabled: bool) -> Self {
+        self.auto_readonly = enabled;
+        self
+    }
+
+    /// How long the client stays read-only after a restriction error. One hour by default.
+    #[cfg(feature = "payments")]
+    pub fn read_only_ttl(mut self, ttl: std::time::Duration) -> Self {
+        self.read_only_ttl = ttl;
+        self
+    }
+
+    /// Check the balance and P2P limit locally before sending payments.
+    ///
+    /// Both are fetched concurrently and reused for a few seconds, so that a series of payments
+    /// does not double the number of requests.
+    #[cfg(feature = "payments")]
+    pub fn preflight_checks(mut self, enabled: bool) -> Self {
+        self.preflight_checks = enabled;
+        self
+    }
+
+    /// Simulate payments instead of sending them, see [`SandboxTransport`]. Reads still go to QIWI.
+    pub fn sandbox(mut self, enabled: bool) -> Self {
+        self.sandbox = enabled;
+        self
+    }
+
+    /// Delay requests as the number sent in the last minute approaches `per_minute`.
+    ///
+    /// Delays start at half of the quota and grow smoothly, so that at the quota requests are
+    /// spaced evenly over a minute instead of failing with `429 Too Many Requests`.
+    pub fn soft_quota(mut self, per_minute: u32) -> Self {
+        self.soft_quota = Some(per_minute);
+        self
+    }
+
+    /// Refuse transfers similar to a payment made within `window`, see [`Client::find_recent_similar`].
+    ///
+    /// Use [`TransferRequest::force`] to send such a transfer anyway.
+    #[cfg(feature = "payments")]
+    pub fn duplicate_guard(mut self, window: std::time::Duration) -> Self {
+        self.duplicate_window = Some(window);
+        self
+    }
+
+    /// Keep watcher and history cursor state in `store`, so that it survives restarts.
+    pub fn state_store<S: state::StateStore>(mut self, store: S) -> Self {
+        self.state_store = Some(Arc::new(store));
+        self
+    }
+
+    /// Mint payment ids and tokens with `ids`, e.g. [`ids::SequentialIdGenerator`] for reproducible requests.
+    pub fn id_generator<G: ids::IdGenerator>(mut self, ids: G) -> Self {
+        self.ids = Some(Arc::new(ids));
+        self
+    }
+
+    /// Tell the time with `clock`, e.g. [`clock::ManualClock`] for reproducible deadlines and windows.
+    pub fn clock<C: clock::Clock>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Hosts to switch to when the base URL cannot be connected to, see [`RemoteCaller::set_fallback_hosts`].
+    pub fn fallback_hosts(mut self, hosts: Vec<reqwest::Url>) -> Self {
+        self.fallback_hosts = hosts;
+        self
+    }
+
+    /// Keep at least `gap` between any two polls of the client's watchers, see [`Client::poll_coordinator`].
+    pub fn poll_min_gap(mut self, gap: std::time::Duration) -> Self {
+        self.poll_min_gap = gap;
+        self
+    }
+
+    /// Fail HTTP requests taking longer than `timeout`, including reading the body. No limit by default.
+    ///
+    /// Applies to the default transport only.
+    pub fn request_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Keep responses with `ETag` or `Last-Modified` headers, e.g. provider forms, in the state
+    /// store and fetch them again only when they change. Disabled by default.
+    ///
+    /// Applies to the default transport only, see [`http_cache::HttpCache`].
+    pub fn http_cache(mut self, enabled: bool) -> Self {
+        self.http_cache = if enabled {
+            Some(self.http_cache.unwrap_or_default())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Size bounds of the [HTTP cache](ClientBuilder::http_cache), enabling it.
+    pub fn http_cache_limits(mut self, limits: http_cache::HttpCacheLimits) -> Self {
+        self.http_cache = Some(limits);
+        self
+    }
+
+    /// Resolve API hosts with the DNS-over-HTTPS server at `url`, e.g. [`doh::CLOUDFLARE`],
+    /// falling back to the system resolver if it fails.
+    ///
+    /// Applies to the default transport only, see [`doh::DohResolver`].
+    #[cfg(feature = "doh")]
+    pub fn doh_resolver<U: Into<String>>(mut self, url: U) -> Self {
+        self.doh_resolver = Some(url.into());
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let local_state = self
+            .state_store
+            .clone()
+            .unwrap_or_else(|| Arc::new(state::MemoryStateStore::default()));
+        let clock = self.clock.unwrap_or_else(|| Arc::new(clock::SystemClock));
+        let (transport, remote) = match self.transport {
+            Some(transport) => (transport, None),
+            None => {
+                let mut http_client = reqwest::Client::builder();
+                if let Some(timeout) = self.request_timeout {
+                    http_client = http_client.timeout(timeout);
+                }
+                #[cfg(feature = "doh")]
+                {
+                    if let Some(url) = &self.doh_resolver {
+                        let resolver = doh::DohResolver::with_clock(url.clone(), clock.clone());
+                        http_client = http_client.dns_resolver(Arc::new(resolver));
+                    }
+                }
+                let http_client = http_client.build().unwrap();
+                let remote = Arc::new(RemoteCaller::new(
                     http_client,
-                    addr: "https://edge.qiwi.com".into(),
-                    bearer: Some(token.to_string()),
-                }),
+                    self.base_url
+                        .unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+                    Some(Arc::new(TokenCache::new(self.token, self.token_ttl))),
+                ));
+                remote.set_fallback_hosts(
+                    self.fallback_hosts
+                        .iter()
+                        .map(|url| url.as_str().trim_end_matches('/').to_string()),
+                );
+                if let Some(limits) = self.http_cache {
+                    remote.set_http_cache(Some(Arc::new(http_cache::HttpCache::new(
+                        local_state.clone(),
+                        limits,
+                    ))));
+                }
+                (remote.clone() as Arc<dyn Transport>, Some(remote))
+            }
+        };
+        let ids = self
+            .ids
+            .unwrap_or_else(|| Arc::new(ids::DefaultIdGenerator::default()));
+        let transport: Arc<dyn Transport> = if self.sandbox {
+            Arc::new(SandboxTransport::with_clock(transport, clock.clone()))
+        } else {
+            transport
+        };
+        Client {
+            caller: CallerWrapper {
+                transport,
+                quota: Arc::new(quota::QuotaTracker::new(self.soft_quota, clock.clone())),
+                ids: ids.clone(),
+                compat: if self.lenient_parsing {
+                    Some(Default::default())
+                } else {
+                    None
+                },
+                validator: self.response_validator,
             },
-            user: QiwiUser(phone),
+            remote,
+            region: self
+                .region
+                .or_else(|| Region::of(&self.phone))
+                .unwrap_or_default(),
+            user: QiwiUser::from(self.phone),
+            api_versions: self.api_versions,
+            #[cfg(feature = "payments")]
+            p2p_free_limit: self.p2p_free_limit,
+            #[cfg(feature = "payments")]
+            payment_policy: self.payment_policy,
+            #[cfg(feature = "payments")]
+            mfa: self.mfa,
+            #[cfg(feature = "payments")]
+            audit: self.audit,
+            #[cfg(feature = "payments")]
+            audit_required: self.audit_required,
+            #[cfg(feature = "payments")]
+            cross_rates_cache: Default::default(),
+            #[cfg(feature = "payments")]
+            auto_readonly: self.auto_readonly,
+            #[cfg(feature = "payments")]
+            read_only_ttl: self.read_only_ttl,
+            #[cfg(feature = "payments")]
+            read_only: Default::default(),
+            #[cfg(feature = "payments")]
+            preflight: if self.preflight_checks {
+                Some(Default::default())
+            } else {
+                None
+            },
+            #[cfg(feature = "payments")]
+            duplicate_window: self.duplicate_window,
+            local_state,
+            state_store: self.state_store,
+            health: Default::default(),
+            operations_lock: Default::default(),
+            #[cfg(feature = "identification")]
+            identification_level: Default::default(),
+            polls: Arc::new(PollCoordinator::new(
+                self.poll_min_gap,
+                ids.clone(),
+                clock.clone(),
+            )),
+            ids,
+            clock,
+            token_issued_at: Mutex::new(self.token_issued_at),
+            token_lifetime: self.token_lifetime,
+            token_expiry_warning: self.token_expiry_warning,
+            providers: self.providers,
+        }
+    }
+}
+
+impl Client {
+    pub fn new<T: Display>(phone: PhoneNumber, token: T) -> Self {
+        Self::builder(phone, token).build()
+    }
+
+    /// Client that sends all requests through `transport`, e.g. an offline one in tests.
+    pub fn with_transport<T: Transport>(phone: PhoneNumber, transport: T) -> Self {
+        Self::builder(phone, "").transport(transport).build()
+    }
+
+    /// Client that simulates payments, see [`ClientBuilder::sandbox`].
+    pub fn sandbox<T: Display>(phone: PhoneNumber, token: T) -> Self {
+        Self::builder(phone, token).sandbox(true).build()
+    }
+
+    pub fn builder<T: Display>(phone: PhoneNumber, token: T) -> ClientBuilder {
+        ClientBuilder {
+            phone,
+            token: Arc::new(token.to_string()),
+            token_ttl: DEFAULT_TOKEN_TTL,
+            transport: None,
+            region: None,
+            api_versions: Default::default(),
+            #[cfg(feature = "payments")]
+            p2p_free_limit: None,
+            #[cfg(feature = "payments")]
+            payment_policy: None,
+            #[cfg(feature = "payments")]
+            mfa: None,
+            #[cfg(feature = "payments")]
+            audit: None,
+            #[cfg(feature = "payments")]
+            audit_required: true,
+            #[cfg(feature = "payments")]
+            auto_readonly: false,
+            #[cfg(feature = "payments")]
+            read_only_ttl: read_only::DEFAULT_READ_ONLY_TTL,
+            #[cfg(feature = "payments")]
+            preflight_checks: false,
+            sandbox: false,
+            soft_quota: None,
+            #[cfg(feature = "payments")]
+            duplicate_window: None,
+            state_store: None,
+            base_url: None,
+            ids: None,
+            clock: None,
+            fallback_hosts: Vec::new(),
+            poll_min_gap: poll::DEFAULT_POLL_MIN_GAP,
+            lenient_parsing: false,
+            response_validator: None,
+            token_issued_at: None,
+            token_lifetime: chrono::Duration::days(DEFAULT_TOKEN_LIFETIME_DAYS),
+            token_expiry_warning: chrono::Duration::days(DEFAULT_TOKEN_WARNING_DAYS),
+            providers: Default::default(),
+            request_timeout: None,
+            http_cache: None,
+            #[cfg(feature = "doh")]
+            doh_resolver: None,
         }
     }
 }
 
 impl Client {
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Former field names found in the last response that had any, see [`ClientBuilder::lenient_parsing`].
+    pub fn last_compat_report(&self) -> Option<compat::CompatReport> {
+        self.caller.compat.as_ref().and_then(|log| log.last())
+    }
+
+    /// Id for a new payment, for use with [`TransferRequest::id`] and [`Client::pay`].
+    pub fn next_payment_id(&self) -> u64 {
+        self.ids.next_payment_id()
+    }
+
+    /// Transfer with an id from the client's [id generator](ClientBuilder::id_generator).
+    pub fn transfer_request<T: Into<String>>(
+        &self,
+        amount: BigDecimal,
+        direction: TransferDirection,
+        comment: T,
+    ) -> TransferRequest {
+        TransferRequest::with_id(self.next_payment_id(), amount, direction, comment)
+    }
+
+    pub fn id_generator(&self) -> &dyn ids::IdGenerator {
+        &*self.ids
+    }
+
+    pub fn clock(&self) -> &dyn clock::Clock {
+        &*self.clock
+    }
+
+    /// Schedules polls of the client's watchers, so that they do not burst. Custom pollers can register as well.
+    pub fn poll_coordinator(&self) -> &Arc<PollCoordinator> {
+        &self.polls
+    }
+
+    /// Requests sent by this client in the last minute and hour.
+    pub fn quota_usage(&self) -> QuotaUsage {
+        self.caller.quota.usage()
+    }
+
+    /// Uses `token` for all requests started after this call.
+    ///
+    /// Requests in flight finish with the previous token. Safe to call concurrently with other calls.
+    /// Has no effect on clients with a custom transport. Use [`Client::record_token_issued`] for
+    /// the token age to be tracked.
+    pub fn set_token<P: TokenProvider>(&self, token: P) {
+        if let Some(remote) = &self.remote {
+            let ttl = remote.token_ttl().unwrap_or(DEFAULT_TOKEN_TTL);
+            remote.set_token(Some(Arc::new(TokenCache::new(Arc::new(token), ttl))));
+        }
+    }
+
+    /// Sends requests started after this call to another host, e.g. `https://edge.qiwi.com`.
+    /// Has no effect on clients with a custom transport.
+    pub fn set_base_url<T: Into<String>>(&self, url: T) {
+        if let Some(remote) = &self.remote {
+            remote.set_addr(url);
+        }
+    }
+
     pub async fn profile_info(&self) -> QiwiResult<ProfileInfo> {
         Ok(self
             .caller
-            .call("person-profile/v1/profile/current", Method::GET, &hashmap! { "authInfoEnabled" => true.to_string(), "contractInfoEnabled" => true.to_string(), "userInfoEnabled" => true.to_string() }, None)
+            .call(
+                self.api_versions.profile_endpoint(),
+                Method::GET,
+                &QueryParams::new()
+                    .with("authInfoEnabled", true)
+                    .with("contractInfoEnabled", true)
+                    .with("userInfoEnabled", true),
+                None,
+            )
             .await
-            .context(TransportError)?
+            .map_err(versions::versioned_error(
+                "person-profile",
+                &self.api_versions.person_profile,
+            ))?
             .into_result()?)
     }
 
+    /// Balances of the wallet.
+    pub async fn accounts(&self) -> QiwiResult<Vec<Account>> {
+        Ok(self
+            .caller
+            .call::<_, AccountBalancesWrapper>(
+                format!("funding-sources/v2/persons/{}/accounts", self.user),
+                Method::GET,
+                &Default::default(),
+                None,
+            )
+            .await?
+            .into_result()?
+            .accounts)
+    }
+
+    /// Current limits of the given types, all documented ones if `types` is empty.
+    pub async fn limits(&self, types: &[LimitType]) -> QiwiResult<Limits> {
+        let types = if types.is_empty() {
+            &LimitType::ALL[..]
+        } else {
+            types
+        };
+        let mut params = QueryParams::new();
+        params.push_indexed("types", types.iter().map(|t| t.as_str()));
+
+        let limits = self
+            .caller
+            .call::<_, ActualLimitsWrapper>(
+                format!("qw-limits/v1/persons/{}/actual-limits", self.user),
+                Method::GET,
+                &params,
+                None,
+            )
+            .await?
+            .into_result()?
+            .limits;
+        Ok(Limits::new(
+            limits.into_iter().flat_map(|(_, limits)| limits),
+        ))
+    }
+
+    /// Restrictions on the wallet's operations, empty if there are none.
+    ///
+    /// Payments of a restricted wallet fail with opaque errors, check this first to report a
+    /// readable reason.
+    pub async fn restrictions(&self) -> QiwiResult<Vec<Restriction>> {
+        let restrictions = self
+            .caller
+            .call(
+                format!(
+                    "person-profile/v1/persons/{}/status/restrictions",
+                    self.user
+                ),
+                Method::GET,
+                &Default::default(),
+                None,
+            )
+            .await?
+            .into_result()?;
+        #[cfg(feature = "payments")]
+        self.note_restrictions(&restrictions);
+        Ok(restrictions)
+    }
+}
+
+#[cfg(feature = "history")]
+impl Client {
     pub fn payment_history(
         &self,
+    ) -> Pin<Box<dyn Stream<Item = QiwiResult<PaymentHistoryEntry>> + Send>> {
+        self.history_pages(None, QueryParams::new(), HISTORY_PAGE_ROWS)
+    }
+
+    /// Same as [`Client::payment_history`], limited to payments matching `filter`.
+    ///
+    /// Fails without sending a request if the end date precedes the start one, or there is an end without a start.
+    pub fn payment_history_filtered(
+        &self,
+        filter: PaymentHistoryFilter,
+    ) -> QiwiResult<Pin<Box<dyn Stream<Item = QiwiResult<PaymentHistoryEntry>> + Send>>> {
+        let mut params = QueryParams::new();
+        match (filter.start_date, filter.end_date) {
+            (None, None) => {}
+            (Some(start), end) => {
+                let end = end.unwrap_or_else(|| Utc::now().with_timezone(start.offset()));
+                ensure!(
+                    end >= start,
+                    InvalidDateRange {
+                        start: Some(start),
+                        end: Some(end),
+                    }
+                );
+                params.push("startDate", format_history_date(&start));
+                params.push("endDate", format_history_date(&end));
+            }
+            (None, end @ Some(_)) => return InvalidDateRange { start: None, end }.fail(),
+        }
+        push_kind_filter(&mut params, &filter);
+
+        Ok(self.history_pages(None, params, HISTORY_PAGE_ROWS))
+    }
+
+    /// Sums of incoming and outgoing payments from `start` to `end`, at most [`MAX_TOTALS_DAYS`] apart.
+    ///
+    /// Only the operation and sources of `filter` are used, its dates are ignored.
+    pub async fn payment_totals(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        filter: &PaymentHistoryFilter,
+    ) -> QiwiResult<PaymentTotals> {
+        ensure!(
+            end >= start,
+            InvalidDateRange {
+                start: Some(DateTime::<FixedOffset>::from(start)),
+                end: Some(DateTime::<FixedOffset>::from(end)),
+            }
+        );
+        ensure!(
+            end - start <= chrono::Duration::days(MAX_TOTALS_DAYS),
+            DateRangeTooWide {
+                start,
+                end,
+                max_days: MAX_TOTALS_DAYS,
+            }
+        );
+
+        let mut params = QueryParams::new()
+            .with("startDate", format_history_date(&start.into()))
+            .with("endDate", format_history_date(&end.into()));
+        push_kind_filter(&mut params, filter);
+
+        let mut totals: PaymentTotals = self
+            .caller
+            .call(
+                self.api_versions.totals_endpoint(&self.user),
+                Method::GET,
+                &params,
+                None,
+            )
+            .await
+            .map_err(versions::versioned_error(
+                "payment-history",
+                &self.api_versions.payment_history,
+            ))?
+            .into_result()?;
+        totals.sources = filter.sources.clone();
+
+        Ok(totals)
+    }
+
+    /// Same as [`Client::payment_history`], resuming from the position saved under `cursor_key`
+    /// in the [state store](ClientBuilder::state_store).
+    ///
+    /// The position is saved once all payments of a page have been consumed, so after a restart
+    /// the payments of the page that was being read are yielded again. Once history is exhausted,
+    /// the stream for `cursor_key` stays empty. Without a state store nothing is saved.
+    pub fn payment_history_from(
+        &self,
+        cursor_key: &str,
+    ) -> Pin<Box<dyn Stream<Item = QiwiResult<PaymentHistoryEntry>> + Send>> {
+        self.history_pages(
+            self.state_store
+                .clone()
+                .map(|store| (store, cursor_key.to_string())),
+            QueryParams::new(),
+            HISTORY_PAGE_ROWS,
+        )
+    }
+
+    /// Current state of a transaction, e.g. to check whether a transfer has reached `SUCCESS` or `ERROR`.
+    pub async fn transaction_info(
+        &self,
+        txn_id: u64,
+        txn_type: TransactionType,
+    ) -> QiwiResult<PaymentHistoryEntry> {
+        let rsp = self
+            .caller
+            .call(
+                self.api_versions.transaction_endpoint(txn_id),
+                Method::GET,
+                &QueryParams::new().with("type", txn_type.as_str()),
+                None,
+            )
+            .await;
+        match rsp {
+            Err(e) if e.http_status() == Some(404) => TransactionNotFound { txn_id }.fail(),
+            rsp => rsp
+                .map_err(versions::versioned_error(
+                    "payment-history",
+                    &self.api_versions.payment_history,
+                ))?
+                .into_result(),
+        }
+    }
+
+    fn history_pages(
+        &self,
+        cursor: Option<(Arc<dyn state::StateStore>, String)>,
+        filter: QueryParams,
+        rows: u16,
     ) -> Pin<Box<dyn Stream<Item = QiwiResult<PaymentHistoryEntry>> + Send>> {
         let caller = self.caller.clone();
-        let user_id = self.user.clone();
+        let endpoint = self.api_versions.history_endpoint(&self.user);
+        let version = self.api_versions.payment_history.clone();
         Box::pin(try_stream! {
             let mut next_txn: Option<(String, u64)> = None;
-            loop {
-                let endpoint = format!("payment-history/v2/persons/{}/payments", user_id);
-                let mut args = HashMap::new();
-                args.insert("rows", 50.to_string());
-                if let Some(next_txn) = next_txn.take() {
-                    args.insert("nextTxnDate", next_txn.0.to_string());
-                    args.insert("nextTxnId", next_txn.1.to_string());
+            let mut exhausted = false;
+            if let Some((store, key)) = &cursor {
+                match state::load(&**store, key).await.context(StateStoreError)? {
+                    Some(HistoryCursor::Next { date, id }) => next_txn = Some((date, id)),
+                    Some(HistoryCursor::Exhausted) => exhausted = true,
+                    None => {}
+                }
+            }
+            while !exhausted {
+                let mut args = filter.clone();
+                args.push("rows", rows);
+                if let Some((date, id)) = next_txn.take() {
+                    args.push("nextTxnDate", date);
+                    args.push("nextTxnId", id);
                 }
                 let rsp = caller
-                    .call(endpoint, Method::GET, &args, None)
+                    .call(&endpoint, Method::GET, &args, None)
                     .await
-                    .context(TransportError)?;
+                    .map_err(versions::versioned_error("payment-history", &version))?;
 
                 let history: PaymentHistoryData = rsp.into_result()?;
 
@@ -115,102 +1258,1425 @@ impl Client {
                     yield entry;
                 }
 
-                if next_txn.is_none() {
-                    break;
+                if let Some((store, key)) = &cursor {
+                    let position = match &next_txn {
+                        Some((date, id)) => HistoryCursor::Next {
+                            date: date.clone(),
+                            id: *id,
+                        },
+                        None => HistoryCursor::Exhausted,
+                    };
+                    state::save(&**store, key, &position)
+                        .await
+                        .context(StateStoreError)?;
                 }
+
+                exhausted = next_txn.is_none();
             }
         })
     }
+}
 
+#[cfg(feature = "payments")]
+impl Client {
     pub async fn commission_info(&self, provider: ProviderId) -> QiwiResult<CommissionInfo> {
         let url = format!("sinap/providers/{}/form", provider);
         Ok(self
             .caller
             .call::<_, CommissionInfoWrapper>(url, Method::GET, &Default::default(), None)
-            .await
-            .context(TransportError)?
+            .await?
             .into_result()?
             .commission)
     }
 
-    pub async fn commission_quote(
+    /// Commission terms of `providers`, for estimating fees offline with
+    /// [`CommissionSnapshot::estimate`].
+    pub async fn snapshot_commissions(
+        &self,
+        providers: &[ProviderId],
+    ) -> QiwiResult<CommissionSnapshot> {
+        let mut snapshot = CommissionSnapshot::default();
+        self.update_commissions(&mut snapshot, providers, chrono::Duration::zero())
+            .await?;
+        Ok(snapshot)
+    }
+
+    /// Fetches the commission terms of `providers` that `snapshot` lacks or has for longer
+    /// than `max_age`.
+    pub async fn update_commissions(
+        &self,
+        snapshot: &mut CommissionSnapshot,
+        providers: &[ProviderId],
+        max_age: chrono::Duration,
+    ) -> QiwiResult<()> {
+        for &provider in providers {
+            if snapshot.get(provider).map_or(false, |entry| {
+                !entry.is_stale_at(max_age, self.clock.utc_now())
+            }) {
+                continue;
+            }
+            let info = self.commission_info(provider).await?;
+            snapshot.insert(provider, info, self.clock.utc_now());
+        }
+        Ok(())
+    }
+
+    /// Commission for paying `amount` in the region's currency from the default balance, see
+    /// [`Client::commission_quote_ex`].
+    pub async fn commission_quote<A: Into<AccountId>>(
         &self,
         provider: ProviderId,
-        account: PhoneNumber,
+        account: A,
         amount: BigDecimal,
-    ) -> QiwiResult<BigDecimal> {
-        let url = format!("sinap/providers/{}/onlineCommission", provider);
-        let account = QiwiUser(account).to_string();
-        Ok(self
-            .caller
-            .call::<_, CommissionQuote>(
-                url,
-                Method::POST,
-                &Default::default(),
-                Some(&json!({
-                    "account": account,
-                    "payment_method": {
-                        "type": "Account",
-                        "accountId": QiwiCurrency(Currency::RUB),
-                    },
-                    "purchaseTotals": {
-                        "total": {
-                            "amount": amount,
-                            "currency": QiwiCurrency(Currency::RUB),
-                        }
-                    }
+    ) -> QiwiResult<CommissionQuote> {
+        self.commission_quote_ex(
+            provider,
+            account.into().format_for(provider),
+            self.region.currency().into(),
+            Money::new(amount, self.region.currency()),
+        )
+        .await
+    }
+
+    /// Commission for paying `total` from the balance in `method_currency`.
+    ///
+    /// `account` is sent verbatim, e.g. a card number or a phone formatted for the provider.
+    pub async fn commission_quote_ex<A: Into<String>>(
+        &self,
+        provider: ProviderId,
+        account: A,
+        method_currency: QiwiCurrency,
+        total: Money,
+    ) -> QiwiResult<CommissionQuote> {
+        self.online_commission_from(
+            provider,
+            account.into(),
+            total,
+            PaymentMethod::account(method_currency),
+        )
+        .await
+    }
+
+    async fn online_commission(
+        &self,
+        provider: ProviderId,
+        account: String,
+        amount: Money,
+    ) -> QiwiResult<CommissionQuote> {
+        self.online_commission_from(
+            provider,
+            account,
+            amount,
+            PaymentMethod::from_account(&self.region.default_account()).unwrap(),
+        )
+        .await
+    }
+
+    async fn online_commission_from(
+        &self,
+        provider: ProviderId,
+        account: String,
+        amount: Money,
+        payment_method: PaymentMethod,
+    ) -> QiwiResult<CommissionQuote> {
+        let url = format!("sinap/providers/{}/onlineCommission", provider);
+        Ok(self
+            .caller
+            .call(
+                url,
+                Method::POST,
+                &Default::default(),
+                Some(&json!(CommissionQuoteRequest {
+                    account,
+                    payment_method,
+                    purchase_totals: PurchaseTotals { total: amount },
                 })),
             )
-            .await
-            .context(TransportError)?
-            .into_result()?
-            .qw_commission
-            .amount)
+            .await?
+            .into_result()?)
+    }
+
+    pub async fn provider_form(&self, provider: ProviderId) -> QiwiResult<ProviderForm> {
+        let url = format!("sinap/api/v2/providers/{}/form", provider);
+        Ok(self
+            .caller
+            .call(url, Method::GET, &Default::default(), None)
+            .await?
+            .into_result()?)
+    }
+
+    /// Sum of successful outgoing P2P transfers in the region's currency since the start of the current month,
+    /// local time of the region.
+    pub async fn p2p_volume_this_month(&self) -> QiwiResult<Money> {
+        let month_start = self.region.month_start(self.clock.utc_now());
+        self.p2p_volume_since(month_start, HISTORY_PAGE_ROWS).await
+    }
+
+    /// Same as [`Client::p2p_volume_this_month`], since `start` and fetching history `rows` entries
+    /// per page, at most 50.
+    ///
+    /// Only outgoing payments from `start` are requested, which QIWI allows for at most
+    /// [`MAX_TOTALS_DAYS`] back.
+    pub async fn p2p_volume_since(&self, start: DateTime<Utc>, rows: u16) -> QiwiResult<Money> {
+        let currency = self.region.currency();
+        let currency_code = QiwiCurrency::from(currency).to_string();
+        let offset = self.region.utc_offset();
+        let end = self.clock.utc_now();
+        ensure!(
+            end >= start,
+            InvalidDateRange {
+                start: Some(start.with_timezone(&offset)),
+                end: Some(end.with_timezone(&offset)),
+            }
+        );
+
+        let mut params = QueryParams::new();
+        params.push(
+            "startDate",
+            format_history_date(&start.with_timezone(&offset)),
+        );
+        params.push("endDate", format_history_date(&end.with_timezone(&offset)));
+        params.push("operation", HistoryOperation::Out.as_str());
+
+        let mut total = BigDecimal::from(0);
+        let mut history = self.history_pages(None, params, rows.clamp(1, HISTORY_PAGE_ROWS));
+        while let Some(entry) = history.next().await.transpose()? {
+            if entry.date < start {
+                break;
+            }
+
+            if matches!(entry.payment_type, PaymentType::Out)
+                && matches!(entry.status, PaymentStatus::Success)
+                && P2P_PROVIDERS.contains(&entry.provider.id)
+                && entry.sum.currency == currency_code
+            {
+                total += entry.sum.amount;
+            }
+        }
+
+        Ok(Money::new(total, currency))
+    }
+
+    /// P2P volume this month and what is left of the [free limit](ClientBuilder::p2p_free_limit).
+    ///
+    /// Fails with [`Error::CurrencyMismatch`] if the free limit is not in the region's currency.
+    pub async fn p2p_free_limit_status(&self) -> QiwiResult<FreeLimitStatus> {
+        let used = self.p2p_volume_this_month().await?;
+        let remaining_estimate = match &self.p2p_free_limit {
+            Some(limit) => {
+                let mut remaining = limit.checked_sub(&used)?;
+                if remaining.amount < BigDecimal::from(0) {
+                    remaining.amount = BigDecimal::from(0);
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+
+        Ok(FreeLimitStatus {
+            used,
+            remaining_estimate,
+        })
+    }
+
+    /// Provider that accepts transfers to the card.
+    pub async fn card_provider(&self, pan: &CardNumber) -> QiwiResult<ProviderId> {
+        let detection: ProviderDetection = self
+            .caller
+            .call(
+                format!(
+                    "sinap/api/refs/{}/containers",
+                    self.mapped_provider(TransferRoute::CardDetection)?
+                ),
+                Method::POST,
+                &Default::default(),
+                Some(&json!({ "cardNumber": pan.as_str() })),
+            )
+            .await?
+            .into_result()?;
+
+        match detection.provider() {
+            Some(provider) => Ok(provider),
+            None => UnknownCardProvider {
+                reason: detection.message,
+            }
+            .fail(),
+        }
+    }
+
+    /// Carrier that accepts payments to the phone account, for [`TransferDirection::Cellular`].
+    pub async fn detect_mobile_provider(&self, phone: &PhoneNumber) -> QiwiResult<ProviderId> {
+        let detection: ProviderDetection = self
+            .caller
+            .call_form(
+                format!("sinap/api/refs/{}/containers", ProviderId::QIWI),
+                Method::POST,
+                &Default::default(),
+                &QueryParams::new().with("phone", QiwiUser::from(phone.clone()).to_string()),
+            )
+            .await?
+            .into_result()?;
+
+        match detection.provider() {
+            Some(provider) => Ok(provider),
+            None => UnknownMobileProvider {
+                reason: detection.message,
+            }
+            .fail(),
+        }
+    }
+
+    fn mapped_provider(&self, route: TransferRoute) -> QiwiResult<ProviderId> {
+        self.providers
+            .get(route)
+            .context(NoProviderMapping { route })
     }
 
-    pub async fn transfer(
+    /// Provider of `direction` from the [provider map](ClientBuilder::provider_overrides),
+    /// detecting it for cards of networks that are not mapped and for phones without a carrier.
+    async fn transfer_provider(&self, direction: &TransferDirection) -> QiwiResult<ProviderId> {
+        match direction {
+            TransferDirection::Qiwi { .. } => self.mapped_provider(TransferRoute::Wallet),
+            TransferDirection::Cellular { carrier, .. } => Ok(ProviderId::from(*carrier)),
+            TransferDirection::CellularAuto { to_phone } => {
+                self.detect_mobile_provider(to_phone).await
+            }
+            TransferDirection::Card {
+                provider: Some(provider),
+                ..
+            }
+            | TransferDirection::BankAccount { provider, .. } => Ok(*provider),
+            TransferDirection::Card {
+                pan,
+                provider: None,
+            } => match pan
+                .network()
+                .and_then(|network| self.providers.get(TransferRoute::Card(network)))
+            {
+                Some(provider) => Ok(provider),
+                None => self.card_provider(pan).await,
+            },
+        }
+    }
+
+    pub async fn quote_transfer(
         &self,
-        id: Option<u64>,
+        direction: &TransferDirection,
         amount: BigDecimal,
-        direction: TransferDirection,
-        comment: String,
+    ) -> QiwiResult<TransferQuote> {
+        direction.validate().context(IncompleteTransfer)?;
+        let provider = self.transfer_provider(direction).await?;
+        let currency = QiwiCurrency::from(direction.currency());
+        let commission = self
+            .commission_quote_ex(
+                provider,
+                direction.account_id().format_for(provider),
+                currency.clone(),
+                Money::new(amount, currency.currency()),
+            )
+            .await?;
+        let free_limit = match direction {
+            TransferDirection::Qiwi { .. } => Some(self.p2p_free_limit_status().await?),
+            _ => None,
+        };
+
+        Ok(TransferQuote {
+            commission,
+            free_limit,
+        })
+    }
+
+    /// Sends the transfer.
+    ///
+    /// The request is taken by reference so that its [`TransferRequest::idempotency_id`] stays available
+    /// if this future fails or is dropped midway, see [`Client::find_transfer_by_client_id`].
+    ///
+    /// Fails with [`Error::PossibleDuplicate`] if [`ClientBuilder::duplicate_guard`] is enabled and a similar
+    /// payment was made recently.
+    ///
+    /// A [source](TransferRequest::source) in another currency is converted by QIWI, it fails with
+    /// [`Error::AccountCurrencyMismatch`] if no cross rate is listed between the two currencies.
+    pub async fn transfer(&self, req: &TransferRequest) -> QiwiResult<TransferData> {
+        req.direction.validate().context(IncompleteTransfer)?;
+        self.check_duplicate(req).await?;
+
+        let direction = &req.direction;
+        let source = req
+            .source
+            .clone()
+            .unwrap_or_else(|| self.region.default_account());
+        let sum = Money::new(req.amount.clone(), direction.currency());
+        if let Some(currency) = source.currency().filter(|c| *c != sum.currency) {
+            let rates = self.cached_cross_rates().await?;
+            ensure!(
+                CrossRate::find(&rates, &currency, &sum.currency).is_some(),
+                AccountCurrencyMismatch {
+                    alias: source,
+                    currency: sum.currency,
+                }
+            );
+        }
+        let provider = self.transfer_provider(direction).await?;
+        let fields = direction.payment_fields(provider);
+
+        self.pay_from(
+            &source,
+            provider,
+            sum,
+            fields,
+            Some(req.comment.clone()),
+            Some(req.idempotency_id()),
+        )
+        .await
+    }
+
+    /// Looks up a recent outgoing payment by the id it was submitted with.
+    ///
+    /// Use this to find out the outcome of a payment whose call failed or was cancelled.
+    pub async fn find_transfer_by_client_id(
+        &self,
+        id: u64,
+    ) -> QiwiResult<Option<PaymentHistoryEntry>> {
+        let horizon = self.clock.utc_now() - chrono::Duration::days(RECONCILIATION_WINDOW_DAYS);
+        let id = id.to_string();
+
+        let mut history = self.payment_history();
+        while let Some(entry) = history.next().await.transpose()? {
+            if entry.date < horizon {
+                break;
+            }
+
+            if matches!(entry.payment_type, PaymentType::Out) && entry.trm_txn_id == id {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Commission for a payment to an arbitrary provider, see [`Client::pay`].
+    pub async fn quote_payment(
+        &self,
+        provider: ProviderId,
+        amount: Money,
+        fields: &BTreeMap<String, String>,
+    ) -> QiwiResult<CommissionQuote> {
+        let account = fields.get("account").cloned().unwrap_or_default();
+        self.online_commission(provider, account, amount).await
+    }
+
+    /// Pays to an arbitrary SINAP provider with the given form fields, sent verbatim, from the
+    /// default balance of the region.
+    ///
+    /// `id` identifies the payment for idempotency and reconciliation, a new one is taken from
+    /// [`Client::next_payment_id`] if not set. Keep it to look the payment up if the call fails.
+    pub async fn pay(
+        &self,
+        provider: ProviderId,
+        amount: Money,
+        fields: BTreeMap<String, String>,
+        comment: Option<String>,
+        id: Option<u64>,
+    ) -> QiwiResult<TransferData> {
+        self.pay_from(
+            &self.region.default_account(),
+            provider,
+            amount,
+            fields,
+            comment,
+            id,
+        )
+        .await
+    }
+
+    /// Same as [`Client::pay`], charging the `source` balance.
+    ///
+    /// The balance may be in another currency than `amount`, which some providers accept and
+    /// convert. The currency of the balance is sent as `paymentMethod.accountId`.
+    pub async fn pay_from(
+        &self,
+        source: &AccountAlias,
+        provider: ProviderId,
+        amount: Money,
+        fields: BTreeMap<String, String>,
+        comment: Option<String>,
+        id: Option<u64>,
     ) -> QiwiResult<TransferData> {
-        let (provider, sum_currency, account) = match direction {
-            TransferDirection::Qiwi {
-                to_phone,
-                to_currency,
-            } => (99, to_currency, to_phone),
-            TransferDirection::Cellular { carrier, to_phone } => {
-                (carrier, penny::Currency::RUB, to_phone)
+        let payment_method = PaymentMethod::from_account(source).context(InvalidAccountAlias {
+            alias: source.clone(),
+        })?;
+
+        self.send_payment(PaymentRequest {
+            provider,
+            id: id.unwrap_or_else(|| self.next_payment_id()).to_string(),
+            sum: amount,
+            payment_method,
+            fields,
+            comment,
+        })
+        .await
+    }
+
+    async fn send_payment(&self, request: PaymentRequest) -> QiwiResult<TransferData> {
+        self.ensure_writable()?;
+
+        if let Some(policy) = &self.payment_policy {
+            policy.check(&request).await.context(PolicyViolation)?;
+        }
+
+        let data = self.send_checked_payment(&request).await;
+
+        if let Some(policy) = &self.payment_policy {
+            let outcome = match &data {
+                Ok(_) => policy.on_success(&request).await,
+                Err(_) => policy.on_failure(&request).await,
+            };
+            if let Err(e) = outcome {
+                log::warn!("Failed to record payment {} in policy: {}", request.id, e);
             }
-        };
+        }
 
-        let url = format!("sinap/api/v2/terms/{}/payments", provider);
+        data
+    }
 
-        Ok(self
+    /// Sends the payment once it has passed the policy.
+    async fn send_checked_payment(&self, request: &PaymentRequest) -> QiwiResult<TransferData> {
+        self.preflight(request).await?;
+        self.approve_payment(request).await?;
+
+        let intent = self.audit_intent(request).await?;
+        let url = format!("sinap/api/v2/terms/{}/payments", request.provider);
+
+        let data = self
             .caller
             .call(
                 url,
                 Method::POST,
                 &Default::default(),
-                Some(&json!({
-                    "id": id.unwrap_or(u64::try_from(Utc::now().timestamp()).unwrap() * 1000).to_string(),
-                    "sum": {
-                        "amount": amount,
-                        "currency": QiwiCurrency(sum_currency),
-                    },
-                    "paymentMethod": {
-                        "type": "Account",
-                        "accountId": QiwiCurrency(Currency::RUB),
-                    },
-                    "fields": {
-                        "account": QiwiUser(account).to_string(),
-                    },
-                    "comment": comment,
-                })),
+                Some(&json!(request)),
             )
             .await
-            .context(TransportError)?
-            .into_result()?)
+            .map_err(Error::from)
+            .and_then(Rsp::into_result);
+        if let Some(intent) = intent {
+            self.audit_outcome(intent, &data).await;
+        }
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                self.note_payment_error(&e);
+                return Err(e);
+            }
+        };
+
+        self.note_preflight_payment(request);
+
+        Ok(data)
+    }
+
+    /// Records the intent to send the payment, returning it for the outcome to be recorded.
+    async fn audit_intent(
+        &self,
+        request: &PaymentRequest,
+    ) -> QiwiResult<Option<audit::AuditEvent>> {
+        let sink = match &self.audit {
+            Some(sink) => sink,
+            None => return Ok(None),
+        };
+
+        let intent = audit::AuditEvent::intent(request, self.clock.utc_now());
+        match sink.record(intent.clone()).await {
+            Ok(()) => {}
+            Err(e) if self.audit_required => return Err(e).context(AuditFailed),
+            Err(e) => log::warn!(
+                "Failed to audit payment {}, sending anyway: {}",
+                request.id,
+                e
+            ),
+        }
+        Ok(Some(intent))
+    }
+
+    async fn audit_outcome(&self, intent: audit::AuditEvent, result: &QiwiResult<TransferData>) {
+        if let Some(sink) = &self.audit {
+            let id = intent.payment_id.clone();
+            let outcome = intent.outcome(result, self.clock.utc_now());
+            if let Err(e) = sink.record(outcome).await {
+                log::error!("Failed to audit the outcome of payment {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Asks the second factor if the payment exceeds the threshold, see [`ClientBuilder::mfa`].
+    async fn approve_payment(&self, request: &PaymentRequest) -> QiwiResult<()> {
+        let (provider, threshold) = match &self.mfa {
+            Some(mfa) => mfa,
+            None => return Ok(()),
+        };
+
+        let amount = if request.sum.currency == threshold.currency {
+            Some(request.sum.amount.clone())
+        } else {
+            match self.cached_cross_rates().await {
+                Ok(rates) => CrossRate::find(&rates, &request.sum.currency, &threshold.currency)
+                    .map(|rate| &request.sum.amount * rate),
+                Err(e) => {
+                    log::warn!("Failed to get cross rates for MFA threshold: {}", e);
+                    None
+                }
+            }
+        };
+        if matches!(amount, Some(amount) if amount <= threshold.amount) {
+            return Ok(());
+        }
+
+        match provider.approve(&mfa::PaymentSummary::from(request)).await {
+            Ok(mfa::Approval::Approved) => Ok(()),
+            Ok(mfa::Approval::Denied { reason }) => MfaDenied { reason }.fail(),
+            Err(mfa::MfaError::Timeout) => MfaTimeout.fail(),
+            Err(source) => Err(Error::MfaError { source }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::str::FromStr};
+
+    fn invalid_token_body() -> String {
+        json!({
+            "serviceName": "api-gateway",
+            "errorCode": "internal.invalid.token",
+            "description": "Invalid token",
+            "userMessage": "Неверный токен",
+        })
+        .to_string()
+    }
+
+    fn client(transport: OfflineTransport) -> Client {
+        Client::with_transport("+79991234567".parse().unwrap(), transport)
+    }
+
+    #[tokio::test]
+    async fn unauthorized_with_qiwi_error() {
+        let transport = OfflineTransport::new();
+        transport.push_error(
+            Method::GET,
+            ApiVersions::default().profile_endpoint(),
+            HttpStatusError::new(401, invalid_token_body()),
+        );
+
+        match client(transport).profile_info().await {
+            Err(Error::Unauthorized {
+                status: 401,
+                body,
+                error: Some(error),
+                correlation_id,
+            }) => {
+                assert_eq!(body, invalid_token_body());
+                assert_eq!(error.error_code, "internal.invalid.token");
+                assert!(correlation_id.is_some());
+                assert_eq!(error.correlation_id, correlation_id);
+            }
+            other => panic!("expected Unauthorized, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn forbidden_without_body() {
+        let transport = OfflineTransport::new();
+        transport.push_error(
+            Method::GET,
+            ApiVersions::default().profile_endpoint(),
+            HttpStatusError::new(403, ""),
+        );
+
+        match client(transport).profile_info().await {
+            Err(Error::Unauthorized {
+                status: 403,
+                error: None,
+                ..
+            }) => {}
+            other => panic!("expected Unauthorized, got {:?}", other),
+        }
+    }
+
+    /// Transport recording the correlation id of every call, served offline.
+    #[derive(Debug)]
+    struct CorrelationRecorder {
+        offline: OfflineTransport,
+        ids: Mutex<Vec<Option<String>>>,
+    }
+
+    impl Transport for CorrelationRecorder {
+        fn call(
+            &self,
+            endpoint: String,
+            method: Method,
+            params: &QueryParams,
+            body: Option<&Value>,
+        ) -> Pin<Box<dyn std::future::Future<Output = Result<String, StdError>> + Send + 'static>>
+        {
+            self.ids.lock().unwrap().push(current_correlation_id());
+            self.offline.call(endpoint, method, params, body)
+        }
+    }
+
+    #[tokio::test]
+    async fn errors_carry_correlation_id_of_their_call() {
+        let endpoint = ApiVersions::default().profile_endpoint();
+        let offline = OfflineTransport::new();
+        offline.push_error(
+            Method::GET,
+            endpoint.clone(),
+            HttpStatusError::new(503, "busy"),
+        );
+        offline.push(
+            Method::GET,
+            endpoint,
+            &json!({ "errorCode": "payment.blocked" }),
+        );
+        let transport = Arc::new(CorrelationRecorder {
+            offline,
+            ids: Mutex::default(),
+        });
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .id_generator(ids::SequentialIdGenerator::new(1))
+            .build();
+
+        let unavailable = client.profile_info().await.unwrap_err();
+        let blocked = client.profile_info().await.unwrap_err();
+        match &blocked {
+            Error::QiwiError { error } => assert_eq!(error.error_code, "payment.blocked"),
+            other => panic!("expected QiwiError, got {:?}", other),
+        }
+
+        // Ids are made of the generator's random bytes, one per call.
+        assert_eq!(
+            *transport.ids.lock().unwrap(),
+            vec![
+                Some("010203040506".to_string()),
+                Some("0708090a0b0c".to_string())
+            ]
+        );
+        assert_eq!(unavailable.correlation_id(), Some("010203040506"));
+        assert_eq!(blocked.correlation_id(), Some("0708090a0b0c"));
+        assert_eq!(current_correlation_id(), None);
+    }
+
+    fn accepted_transfer() -> Value {
+        json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } })
+    }
+
+    /// Client minting ids from 1000, answering payments to `provider` as accepted.
+    fn paying_client(provider: u64) -> (Client, Arc<OfflineTransport>) {
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::POST,
+            format!("sinap/api/v2/terms/{}/payments", provider),
+            &accepted_transfer(),
+        ));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .id_generator(ids::SequentialIdGenerator::new(1_000))
+            .build();
+        (client, transport)
+    }
+
+    fn last_body(transport: &OfflineTransport) -> String {
+        transport.recorded().pop().unwrap().body.unwrap()
+    }
+
+    #[tokio::test]
+    async fn offline_transport_answers_by_canonical_body() {
+        let (client, transport) = paying_client(99);
+        let direction = TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        };
+        let request = PaymentRequest {
+            provider: ProviderId::QIWI,
+            id: "1000".to_string(),
+            sum: rub("10.50"),
+            payment_method: PaymentMethod::account(penny::Currency::RUB.into()),
+            fields: direction.payment_fields(ProviderId::QIWI),
+            comment: Some("Thanks".to_string()),
+        };
+        transport.insert_for_body(
+            Method::POST,
+            "sinap/api/v2/terms/99/payments",
+            &request,
+            &json!({ "transaction": { "id": "20000000042", "state": { "code": "Accepted" } } }),
+        );
+
+        let req = client.transfer_request(
+            BigDecimal::from_str("10.50").unwrap(),
+            direction.clone(),
+            "Thanks",
+        );
+        let data = client.transfer(&req).await.unwrap();
+        assert_eq!(data.transaction.id, "20000000042");
+        assert_eq!(last_body(&transport), json!(request).to_string());
+
+        let req = client.transfer_request(BigDecimal::from_str("10.50").unwrap(), direction, "");
+        let data = client.transfer(&req).await.unwrap();
+        assert_eq!(data.transaction.id, "20000000001");
+    }
+
+    fn usd_rub_client() -> (Client, Arc<OfflineTransport>) {
+        let (client, transport) = paying_client(99);
+        transport.insert(
+            Method::GET,
+            "sinap/crossRates",
+            &json!({ "result": [{ "from": "840", "to": "643", "rate": "73.5" }] }),
+        );
+        (client, transport)
+    }
+
+    #[tokio::test]
+    async fn transfer_from_convertible_balance_is_sent() {
+        let (client, transport) = usd_rub_client();
+        let direction = TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        };
+
+        let req = client
+            .transfer_request(BigDecimal::from(100), direction, "")
+            .source(AccountAlias::QW_WALLET_USD);
+        client.transfer(&req).await.unwrap();
+        let body: Value = serde_json::from_str(&last_body(&transport)).unwrap();
+        assert_eq!(body["paymentMethod"]["accountId"], "840");
+        assert_eq!(body["sum"]["currency"], "643");
+    }
+
+    #[tokio::test]
+    async fn transfer_from_inconvertible_balance_is_refused() {
+        let (client, transport) = usd_rub_client();
+        let direction = TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        };
+
+        let req = client
+            .transfer_request(BigDecimal::from(100), direction, "")
+            .source(AccountAlias::QW_WALLET_EUR);
+        match client.transfer(&req).await {
+            Err(Error::AccountCurrencyMismatch { alias, currency }) => {
+                assert_eq!(alias, AccountAlias::QW_WALLET_EUR);
+                assert_eq!(currency, QiwiCurrency::from(penny::Currency::RUB));
+            }
+            other => panic!("expected AccountCurrencyMismatch, got {:?}", other),
+        }
+        assert!(transport
+            .requests()
+            .iter()
+            .all(|(method, _)| *method == Method::GET));
+    }
+
+    #[tokio::test]
+    async fn numeric_provider_account_is_paid_unchanged() {
+        let provider = ProviderId::from(26_476);
+        let (client, transport) = paying_client(26_476);
+        transport.insert(
+            Method::POST,
+            "sinap/providers/26476/onlineCommission",
+            &json!({
+                "withdrawSum": { "amount": "350", "currency": "643" },
+                "enrollmentSum": { "amount": "350", "currency": "643" },
+                "qwCommission": { "amount": "0", "currency": "643" },
+                "fundingSourceCommission": { "amount": "0", "currency": "643" },
+                "withdrawToEnrollmentRate": "1",
+            }),
+        );
+
+        // Looks like a phone number, but the provider takes contract numbers.
+        let account = AccountId::parse("89161234567", provider, None).unwrap();
+        assert!(matches!(&account, AccountId::Raw(raw) if raw == "89161234567"));
+
+        client
+            .commission_quote(provider, account.clone(), BigDecimal::from(350))
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&last_body(&transport)).unwrap();
+        assert_eq!(body["account"], "89161234567");
+
+        let mut fields = BTreeMap::new();
+        fields.insert("account".to_string(), account.format_for(provider));
+        client
+            .pay(provider, rub("350"), fields, None, None)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(&last_body(&transport)).unwrap();
+        assert_eq!(body["fields"], json!({ "account": "89161234567" }));
+    }
+
+    /// Transport whose payments take an hour to be answered, other requests are served offline.
+    #[derive(Debug)]
+    struct StalledPayments(OfflineTransport);
+
+    impl Transport for StalledPayments {
+        fn call(
+            &self,
+            endpoint: String,
+            method: Method,
+            params: &QueryParams,
+            body: Option<&Value>,
+        ) -> Pin<Box<dyn std::future::Future<Output = Result<String, StdError>> + Send + 'static>>
+        {
+            let stalled = method == Method::POST;
+            let rsp = self.0.call(endpoint, method, params, body);
+            Box::pin(async move {
+                if stalled {
+                    tokio::time::delay_for(std::time::Duration::from_secs(60 * 60)).await;
+                }
+                rsp.await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_transfer_is_found_by_client_id() {
+        let phone: PhoneNumber = "+79991234567".parse().unwrap();
+        let mut sent = history_entry("2020-01-31T14:59:00+03:00", "OUT", "SUCCESS", 99, "10.50");
+        sent["txnId"] = json!(20_000_000_001u64);
+        sent["trmTxnId"] = json!("1000");
+        let offline = OfflineTransport::new()
+            .with(
+                Method::POST,
+                "sinap/api/v2/terms/99/payments",
+                &accepted_transfer(),
+            )
+            .with(
+                Method::GET,
+                ApiVersions::default().history_endpoint(&QiwiUser::from(phone.clone())),
+                &fixtures::history_page(vec![sent]),
+            );
+        let transport = Arc::new(StalledPayments(offline));
+        let client = Client::builder(phone, "")
+            .transport(transport.clone())
+            .clock(clock::ManualClock::new(
+                Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap(),
+            ))
+            .id_generator(ids::SequentialIdGenerator::new(1_000))
+            .build();
+        let direction = TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        };
+
+        let req = client.transfer_request(BigDecimal::from_str("10.50").unwrap(), direction, "");
+        let id = req.idempotency_id();
+        let cancelled =
+            tokio::time::timeout(std::time::Duration::from_millis(50), client.transfer(&req)).await;
+        assert!(cancelled.is_err());
+        let body: Value = serde_json::from_str(&last_body(&transport.0)).unwrap();
+        assert_eq!(body["id"], id.to_string());
+
+        let entry = client
+            .find_transfer_by_client_id(id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.txn_id, 20_000_000_001);
+        assert!(client
+            .find_transfer_by_client_id(id + 1)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn transfer_body_is_reproducible() {
+        let (client, transport) = paying_client(99);
+        let direction = TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        };
+
+        let req = client.transfer_request(
+            BigDecimal::from_str("10.50").unwrap(),
+            direction.clone(),
+            "Thanks",
+        );
+        assert_eq!(req.idempotency_id(), 1_000);
+        client.transfer(&req).await.unwrap();
+        assert_eq!(
+            last_body(&transport),
+            r#"{"comment":"Thanks","fields":{"account":"79035550101"},"id":"1000","paymentMethod":{"accountId":"643","type":"Account"},"sum":{"amount":"10.50","currency":"643"}}"#
+        );
+
+        // Retrying the same request sends the same bytes, a new request gets the next id.
+        client.transfer(&req).await.unwrap();
+        assert_eq!(
+            last_body(&transport),
+            r#"{"comment":"Thanks","fields":{"account":"79035550101"},"id":"1000","paymentMethod":{"accountId":"643","type":"Account"},"sum":{"amount":"10.50","currency":"643"}}"#
+        );
+        let req = client.transfer_request(BigDecimal::from(3), direction, "");
+        client.transfer(&req).await.unwrap();
+        assert_eq!(
+            last_body(&transport),
+            r#"{"comment":"","fields":{"account":"79035550101"},"id":"1001","paymentMethod":{"accountId":"643","type":"Account"},"sum":{"amount":"3","currency":"643"}}"#
+        );
+    }
+
+    /// Client answering payments to the wallet and Visa providers and card detection with 1963.
+    fn mapped_client(overrides: &ProviderMap) -> (Client, Arc<OfflineTransport>) {
+        let mut transport = OfflineTransport::new().with(
+            Method::POST,
+            "sinap/api/refs/1717/containers",
+            &json!({ "code": { "value": "0" }, "message": "1963" }),
+        );
+        for provider in &[99, 100, 1963, 1964] {
+            transport = transport.with(
+                Method::POST,
+                format!("sinap/api/v2/terms/{}/payments", provider),
+                &accepted_transfer(),
+            );
+        }
+        let transport = Arc::new(transport);
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .provider_overrides(overrides)
+            .build();
+        (client, transport)
+    }
+
+    fn wallet_transfer() -> TransferDirection {
+        TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: penny::Currency::RUB,
+        }
+    }
+
+    fn visa_transfer() -> TransferDirection {
+        TransferDirection::Card {
+            pan: "4111 1111 1111 1111".parse().unwrap(),
+            provider: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn transfers_use_provider_map() {
+        for (overrides, direction, endpoints) in vec![
+            (
+                ProviderMap::empty(),
+                wallet_transfer(),
+                vec!["sinap/api/v2/terms/99/payments"],
+            ),
+            (
+                ProviderMap::empty().set(TransferRoute::Wallet, ProviderId::from(100)),
+                wallet_transfer(),
+                vec!["sinap/api/v2/terms/100/payments"],
+            ),
+            (
+                ProviderMap::empty(),
+                visa_transfer(),
+                vec![
+                    "sinap/api/refs/1717/containers",
+                    "sinap/api/v2/terms/1963/payments",
+                ],
+            ),
+            // Mapped networks are not detected.
+            (
+                ProviderMap::empty().set(
+                    TransferRoute::Card(CardNetwork::Visa),
+                    ProviderId::from(1964),
+                ),
+                visa_transfer(),
+                vec!["sinap/api/v2/terms/1964/payments"],
+            ),
+        ] {
+            let (client, transport) = mapped_client(&overrides);
+            let req = client.transfer_request(BigDecimal::from(10), direction, "");
+            client.transfer(&req).await.unwrap();
+            let sent = transport
+                .requests()
+                .into_iter()
+                .map(|(_, endpoint)| endpoint)
+                .collect::<Vec<_>>();
+            assert_eq!(sent, endpoints, "{:?}", overrides);
+        }
+    }
+
+    #[tokio::test]
+    async fn unmapped_route_is_an_error() {
+        let transport = Arc::new(OfflineTransport::new());
+        let mut builder =
+            Client::builder("+79991234567".parse().unwrap(), "").transport(transport.clone());
+        builder.providers = ProviderMap::default().remove(TransferRoute::Wallet);
+        let client = builder.build();
+
+        let req = client.transfer_request(BigDecimal::from(10), wallet_transfer(), "");
+        match client.transfer(&req).await {
+            Err(Error::NoProviderMapping {
+                route: TransferRoute::Wallet,
+            }) => {}
+            other => panic!("expected NoProviderMapping, got {:?}", other),
+        }
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn payment_body_is_reproducible() {
+        let (client, transport) = paying_client(1717);
+        let fields = vec![
+            ("account_type".to_string(), "2".to_string()),
+            ("account".to_string(), "40817810000000000001".to_string()),
+            ("mfo".to_string(), "044525225".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        client
+            .pay(
+                ProviderId::OTHER_BANK,
+                Money::new(
+                    BigDecimal::from_str("1500.00").unwrap(),
+                    penny::Currency::RUB,
+                ),
+                fields,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            last_body(&transport),
+            r#"{"fields":{"account":"40817810000000000001","account_type":"2","mfo":"044525225"},"id":"1000","paymentMethod":{"accountId":"643","type":"Account"},"sum":{"amount":"1500.00","currency":"643"}}"#
+        );
+    }
+
+    #[test]
+    fn unauthorized_survives_portable_round_trip() {
+        let error = Error::unauthorized(401, invalid_token_body(), Some("0a1b2c".into()));
+        let portable = error.to_portable();
+        assert_eq!(portable.http_status, Some(401));
+        assert_eq!(
+            portable.qiwi_code.as_deref(),
+            Some("internal.invalid.token")
+        );
+
+        match Error::from_portable(portable) {
+            Error::Unauthorized {
+                status: 401,
+                error: Some(error),
+                correlation_id,
+                ..
+            } => {
+                assert_eq!(error.error_code, "internal.invalid.token");
+                assert_eq!(correlation_id.as_deref(), Some("0a1b2c"));
+            }
+            other => panic!("expected Unauthorized, got {:?}", other),
+        }
+    }
+
+    fn history_entry(date: &str, kind: &str, status: &str, provider: u64, amount: &str) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["date"] = json!(date);
+        entry["type"] = json!(kind);
+        entry["status"] = json!(status);
+        entry["provider"]["id"] = json!(provider);
+        entry["sum"] = json!({ "amount": amount, "currency": "643" });
+        entry
+    }
+
+    /// Client at 2020-01-31 12:00 UTC with a history page around the start of the month, Moscow time.
+    fn p2p_client(
+        builder: impl FnOnce(ClientBuilder) -> ClientBuilder,
+    ) -> (Client, Arc<OfflineTransport>) {
+        let phone: PhoneNumber = "+79991234567".parse().unwrap();
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::GET,
+            ApiVersions::default().history_endpoint(&QiwiUser::from(phone.clone())),
+            &fixtures::history_page(vec![
+                history_entry("2020-01-20T12:00:00+03:00", "OUT", "SUCCESS", 99, "100.00"),
+                history_entry("2020-01-19T12:00:00+03:00", "IN", "SUCCESS", 99, "50.00"),
+                history_entry("2020-01-18T12:00:00+03:00", "OUT", "ERROR", 99, "30.00"),
+                history_entry("2020-01-17T12:00:00+03:00", "OUT", "SUCCESS", 2, "20.00"),
+                history_entry("2020-01-01T00:00:00+03:00", "OUT", "SUCCESS", 99999, "5.00"),
+                history_entry("2019-12-31T23:59:59+03:00", "OUT", "SUCCESS", 99, "500.00"),
+            ]),
+        ));
+        let clock = clock::ManualClock::new(Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap());
+        let client = builder(
+            Client::builder(phone, "")
+                .transport(transport.clone())
+                .clock(clock),
+        )
+        .build();
+        (client, transport)
+    }
+
+    fn rub(amount: &str) -> Money {
+        Money::new(BigDecimal::from_str(amount).unwrap(), penny::Currency::RUB)
+    }
+
+    fn assert_rub(money: &Money, amount: &str) {
+        assert_eq!(money.currency, QiwiCurrency::from(penny::Currency::RUB));
+        assert_eq!(money.amount, BigDecimal::from_str(amount).unwrap());
+    }
+
+    #[tokio::test]
+    async fn p2p_volume_requests_outgoing_since_month_start() {
+        let (client, transport) = p2p_client(|builder| builder);
+
+        let volume = client.p2p_volume_this_month().await.unwrap();
+        assert_rub(&volume, "105.00");
+
+        let params = transport.recorded().pop().unwrap().params;
+        for (key, value) in &[
+            ("startDate", "2020-01-01T00:00:00+03:00"),
+            ("endDate", "2020-01-31T15:00:00+03:00"),
+            ("operation", "OUT"),
+            ("rows", "50"),
+        ] {
+            assert!(
+                params.contains(&(key.to_string(), value.to_string())),
+                "{} in {:?}",
+                key,
+                params
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn p2p_volume_since_custom_start_and_rows() {
+        let (client, transport) = p2p_client(|builder| builder);
+        let start = Utc.with_ymd_and_hms(2020, 1, 18, 0, 0, 0).unwrap();
+
+        let volume = client.p2p_volume_since(start, 10).await.unwrap();
+        assert_rub(&volume, "100.00");
+
+        let params = transport.recorded().pop().unwrap().params;
+        assert!(params.contains(&("startDate".into(), "2020-01-18T03:00:00+03:00".into())));
+        assert!(params.contains(&("rows".into(), "10".into())));
+
+        let future = Utc.with_ymd_and_hms(2020, 2, 1, 0, 0, 0).unwrap();
+        assert!(matches!(
+            client.p2p_volume_since(future, 10).await,
+            Err(Error::InvalidDateRange { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn free_limit_remaining() {
+        let (client, _) = p2p_client(|builder| builder.p2p_free_limit(rub("150.00")));
+        let status = client.p2p_free_limit_status().await.unwrap();
+        assert_rub(&status.used, "105.00");
+        assert_rub(status.remaining_estimate.as_ref().unwrap(), "45.00");
+
+        let (client, _) = p2p_client(|builder| builder.p2p_free_limit(rub("100.00")));
+        let status = client.p2p_free_limit_status().await.unwrap();
+        assert_rub(status.remaining_estimate.as_ref().unwrap(), "0");
+    }
+
+    #[tokio::test]
+    async fn free_limit_in_other_currency_is_rejected() {
+        let usd = Money::new(BigDecimal::from(1_000), penny::Currency::USD);
+        let (client, _) = p2p_client(|builder| builder.p2p_free_limit(usd));
+        assert!(matches!(
+            client.p2p_free_limit_status().await,
+            Err(Error::CurrencyMismatch { .. })
+        ));
+    }
+
+    const COMMISSION_PROVIDER: ProviderId = ProviderId::VISA_RU;
+
+    /// Client at 2020-01-31 12:00 UTC serving commission terms of 2%, at least 50 RUB, for
+    /// payments of 100 to 15000 RUB.
+    fn commission_client() -> (Client, Arc<OfflineTransport>, Arc<clock::ManualClock>) {
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::GET,
+            format!("sinap/providers/{}/form", COMMISSION_PROVIDER),
+            &json!({
+                "commission": {
+                    "ranges": [
+                        { "bound": "0", "rate": "0.02", "min": "50", "max": "0", "fixed": "0" },
+                    ],
+                    "limits": [{ "currency": 643, "min": "100", "max": "15000" }],
+                },
+            }),
+        ));
+        let clock = Arc::new(clock::ManualClock::new(
+            Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap(),
+        ));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .build();
+        (client, transport, clock)
+    }
+
+    /// `onlineCommission` of converting dollars into 7350 roubles at 73.5 with 2% commission.
+    fn usd_rub_quote() -> Value {
+        json!({
+            "withdrawSum": { "amount": "102.00", "currency": "840" },
+            "enrollmentSum": { "amount": "7350.00", "currency": "643" },
+            "qwCommission": { "amount": "2.00", "currency": "840" },
+            "fundingSourceCommission": { "amount": "0", "currency": "840" },
+            "withdrawToEnrollmentRate": "73.5",
+        })
+    }
+
+    #[tokio::test]
+    async fn conversion_quote_keeps_currencies_apart() {
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::POST,
+            "sinap/providers/1099/onlineCommission",
+            &usd_rub_quote(),
+        ));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .build();
+
+        let quote = client
+            .commission_quote_ex(
+                ProviderId::from(reconcile::CONVERSION_PROVIDER),
+                "79991234567",
+                penny::Currency::USD.into(),
+                rub("7350.00"),
+            )
+            .await
+            .unwrap();
+        let usd = QiwiCurrency::from(penny::Currency::USD);
+        assert_eq!(quote.withdraw_sum.currency, usd);
+        assert_eq!(quote.withdraw_sum.amount, BigDecimal::from(102));
+        assert_rub(&quote.enrollment_sum, "7350");
+        assert_eq!(quote.qw_commission.currency, usd);
+        assert_eq!(
+            quote.withdraw_to_enrollment_rate,
+            BigDecimal::from_str("73.5").unwrap()
+        );
+
+        let body: Value = serde_json::from_str(&last_body(&transport)).unwrap();
+        assert_eq!(body["paymentMethod"]["accountId"], "840");
+        assert_eq!(body["purchaseTotals"]["total"]["currency"], "643");
+
+        // Commissions add up in the currency they are charged in, never across currencies.
+        let commission = quote
+            .qw_commission
+            .checked_add(&quote.funding_source_commission)
+            .unwrap();
+        assert_eq!(commission.amount, BigDecimal::from(2));
+        let mixed: QiwiResult<Money> = quote
+            .withdraw_sum
+            .checked_add(&quote.enrollment_sum)
+            .map_err(Error::from);
+        match mixed {
+            Err(Error::CurrencyMismatch { source }) => {
+                assert_eq!(source.left, usd);
+                assert_eq!(source.right, QiwiCurrency::from(penny::Currency::RUB));
+            }
+            other => panic!("expected CurrencyMismatch, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn commission_snapshot_is_taken_at_client_time() {
+        let (client, _, clock) = commission_client();
+
+        let snapshot = client
+            .snapshot_commissions(&[COMMISSION_PROVIDER])
+            .await
+            .unwrap();
+        let entry = snapshot.get(COMMISSION_PROVIDER).unwrap();
+        assert_eq!(entry.fetched_at, clock.utc_now());
+        assert_rub(
+            &snapshot
+                .estimate(COMMISSION_PROVIDER, &rub("1000"))
+                .unwrap(),
+            "50.00",
+        );
+        assert_rub(
+            &snapshot
+                .estimate(COMMISSION_PROVIDER, &rub("5000"))
+                .unwrap(),
+            "100.00",
+        );
+        assert!(snapshot
+            .estimate(COMMISSION_PROVIDER, &rub("20000"))
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn commissions_are_refetched_when_stale_by_client_time() {
+        let (client, transport, clock) = commission_client();
+        let max_age = chrono::Duration::hours(1);
+        let mut snapshot = client
+            .snapshot_commissions(&[COMMISSION_PROVIDER])
+            .await
+            .unwrap();
+        let taken_at = clock.utc_now();
+
+        clock.advance(std::time::Duration::from_secs(30 * 60));
+        client
+            .update_commissions(&mut snapshot, &[COMMISSION_PROVIDER], max_age)
+            .await
+            .unwrap();
+        assert_eq!(transport.recorded().len(), 1);
+        assert_eq!(
+            snapshot.get(COMMISSION_PROVIDER).unwrap().fetched_at,
+            taken_at
+        );
+        assert!(!snapshot.is_stale_at(max_age, clock.utc_now()));
+
+        clock.advance(std::time::Duration::from_secs(31 * 60));
+        assert!(snapshot.is_stale_at(max_age, clock.utc_now()));
+        client
+            .update_commissions(&mut snapshot, &[COMMISSION_PROVIDER], max_age)
+            .await
+            .unwrap();
+        assert_eq!(transport.recorded().len(), 2);
+        assert_eq!(
+            snapshot.get(COMMISSION_PROVIDER).unwrap().fetched_at,
+            clock.utc_now()
+        );
+    }
+
+    #[tokio::test]
+    async fn history_cursor_resumes_after_restart() {
+        let phone: PhoneNumber = "+79991234567".parse().unwrap();
+        let endpoint = ApiVersions::default().history_endpoint(&QiwiUser::from(phone.clone()));
+        let store = Arc::new(state::MemoryStateStore::default());
+        let client = |transport: Arc<OfflineTransport>| {
+            Client::builder(phone.clone(), "")
+                .transport(transport)
+                .state_store(store.clone())
+                .build()
+        };
+        let mut first_page = fixtures::history_page(fixtures::history_entries(2));
+        first_page["nextTxnId"] = json!(7);
+        first_page["nextTxnDate"] = json!("2020-01-01T00:00:00+03:00");
+        let last_page = fixtures::history_page(fixtures::history_entries(1));
+
+        let transport = Arc::new(OfflineTransport::new());
+        transport.push(Method::GET, endpoint.as_str(), &first_page);
+        transport.push(Method::GET, endpoint.as_str(), &last_page);
+        {
+            // Stopped while reading the last page.
+            let mut history = client(transport).payment_history_from("export");
+            for _ in 0..3 {
+                history.next().await.unwrap().unwrap();
+            }
+        }
+
+        let transport =
+            Arc::new(OfflineTransport::new().with(Method::GET, endpoint.as_str(), &last_page));
+        let mut history = client(transport.clone()).payment_history_from("export");
+        let mut entries = 0;
+        while let Some(entry) = history.next().await {
+            entry.unwrap();
+            entries += 1;
+        }
+        assert_eq!(entries, 1);
+        let params = &transport.recorded()[0].params;
+        assert!(params.contains(&("nextTxnId".to_string(), "7".to_string())));
+
+        // Once exhausted, the cursor yields nothing and sends no requests.
+        let transport = Arc::new(OfflineTransport::new());
+        let mut history = client(transport.clone()).payment_history_from("export");
+        assert!(history.next().await.is_none());
+        assert!(transport.requests().is_empty());
     }
 }
@@ -0,0 +1,201 @@
+//! Second factor approval of large payments, see [`ClientBuilder::mfa`](crate::ClientBuilder::mfa).
+
+use {
+    crate::{Money, PaymentRequest, ProviderId, StdError},
+    async_trait::async_trait,
+    serde::{Deserialize, Serialize},
+    snafu::*,
+    std::{fmt::Debug, time::Duration},
+    tokio::io::{AsyncBufReadExt, BufReader},
+};
+
+const TERMINAL_TIMEOUT: Duration = Duration::from_secs(120);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(600);
+const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Payment awaiting approval.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentSummary {
+    pub payment_id: String,
+    pub provider: ProviderId,
+    pub amount: Money,
+    /// Recipient, e.g. phone number or card.
+    pub account: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl From<&PaymentRequest> for PaymentSummary {
+    fn from(req: &PaymentRequest) -> Self {
+        Self {
+            payment_id: req.id.clone(),
+            provider: req.provider,
+            amount: req.sum.clone(),
+            account: req.fields.get("account").cloned(),
+            comment: req.comment.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Approval {
+    Approved,
+    Denied { reason: Option<String> },
+}
+
+#[derive(Debug, Snafu)]
+pub enum MfaError {
+    #[snafu(display("no answer from the second factor"))]
+    Timeout,
+    #[snafu(display("second factor failed: {}", source))]
+    ProviderError { source: StdError },
+}
+
+/// Approves payments above the threshold before they are sent.
+#[async_trait]
+pub trait MfaProvider: Debug + Send + Sync + 'static {
+    async fn approve(&self, summary: &PaymentSummary) -> Result<Approval, MfaError>;
+}
+
+/// Asks on the terminal, anything but `y` or `yes` denies the payment.
+#[derive(Clone, Debug)]
+pub struct TerminalMfa {
+    timeout: Duration,
+}
+
+impl Default for TerminalMfa {
+    fn default() -> Self {
+        Self {
+            timeout: TERMINAL_TIMEOUT,
+        }
+    }
+}
+
+impl TerminalMfa {
+    /// How long to wait for an answer, two minutes by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl MfaProvider for TerminalMfa {
+    async fn approve(&self, summary: &PaymentSummary) -> Result<Approval, MfaError> {
+        eprintln!(
+            "Payment {} of {} to provider {}{} requires approval.",
+            summary.payment_id,
+            summary.amount,
+            summary.provider,
+            summary
+                .account
+                .as_ref()
+                .map(|account| format!(", account {}", account))
+                .unwrap_or_default()
+        );
+        eprint!("Approve? [y/N] ");
+
+        let mut answer = String::new();
+        let read = BufReader::new(tokio::io::stdin()).read_line(&mut answer);
+        match tokio::time::timeout(self.timeout, read).await {
+            Err(_) => Err(MfaError::Timeout),
+            Ok(Err(e)) => Err(MfaError::ProviderError { source: e.into() }),
+            Ok(Ok(_)) => Ok(match answer.trim().to_ascii_lowercase().as_str() {
+                "y" | "yes" => Approval::Approved,
+                _ => Approval::Denied { reason: None },
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum WebhookStatus {
+    Pending,
+    Approved,
+    Denied { reason: Option<String> },
+}
+
+/// Asks an external service over HTTP.
+///
+/// The [`PaymentSummary`] is POSTed as JSON to the URL. The service answers, and later answers
+/// GET requests to `<url>/<payment id>`, with `{"status": "pending"}`, `{"status": "approved"}`
+/// or `{"status": "denied", "reason": "..."}`. Pending payments are polled until the timeout.
+#[derive(Clone, Debug)]
+pub struct WebhookMfa {
+    http: reqwest::Client,
+    url: reqwest::Url,
+    poll_interval: Duration,
+    timeout: Duration,
+}
+
+impl WebhookMfa {
+    pub fn new(url: reqwest::Url) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url,
+            poll_interval: WEBHOOK_POLL_INTERVAL,
+            timeout: WEBHOOK_TIMEOUT,
+        }
+    }
+
+    /// Five seconds by default.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// How long to wait for a decision, ten minutes by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn post(&self, summary: &PaymentSummary) -> Result<WebhookStatus, StdError> {
+        Ok(self
+            .http
+            .post(self.url.clone())
+            .json(summary)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn poll(&self, payment_id: &str) -> Result<WebhookStatus, StdError> {
+        let url = format!("{}/{}", self.url.as_str().trim_end_matches('/'), payment_id);
+        Ok(self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn decide(&self, summary: &PaymentSummary) -> Result<Approval, StdError> {
+        let mut status = self.post(summary).await?;
+        loop {
+            match status {
+                WebhookStatus::Approved => return Ok(Approval::Approved),
+                WebhookStatus::Denied { reason } => return Ok(Approval::Denied { reason }),
+                WebhookStatus::Pending => {
+                    tokio::time::delay_for(self.poll_interval).await;
+                    status = self.poll(&summary.payment_id).await?;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MfaProvider for WebhookMfa {
+    async fn approve(&self, summary: &PaymentSummary) -> Result<Approval, MfaError> {
+        match tokio::time::timeout(self.timeout, self.decide(summary)).await {
+            Err(_) => Err(MfaError::Timeout),
+            Ok(rsp) => rsp.context(ProviderError),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use {
+    crate::*,
+    serde::Serialize,
+    std::{
+        collections::{HashMap, VecDeque},
+        future::Future,
+    },
+};
+
+/// Request received by [`OfflineTransport`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OfflineRequest {
+    pub method: Method,
+    pub endpoint: String,
+    /// Query parameters in the order they were added.
+    pub params: Vec<(String, String)>,
+    /// JSON body, or the form as a JSON object of strings, as it would be sent.
+    pub body: Option<String>,
+}
+
+/// Transport serving canned responses, never touching the network.
+///
+/// Endpoints without a response fail with [`Error::Offline`]. All requests are recorded,
+/// see [`OfflineTransport::requests`] and [`OfflineTransport::recorded`].
+#[derive(Debug, Default)]
+pub struct OfflineTransport {
+    fixtures: Mutex<HashMap<(Method, String), String>>,
+    /// Served to requests with the canonical body, see [`OfflineTransport::insert_for_body`].
+    by_body: Mutex<HashMap<(Method, String, String), String>>,
+    /// Served once each before the fixture, see [`OfflineTransport::push`].
+    scripted: Mutex<HashMap<(Method, String), VecDeque<Result<String, StdError>>>>,
+    requests: Mutex<Vec<OfflineRequest>>,
+}
+
+impl OfflineTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport preloaded with [`fixtures::typical_wallet`] for the given phone.
+    pub fn typical_wallet(phone: &PhoneNumber) -> Self {
+        let transport = Self::new();
+        for (method, endpoint, body) in fixtures::typical_wallet(phone) {
+            transport.insert(method, endpoint, &body);
+        }
+        transport
+    }
+
+    /// Serves `body` in response to requests to `endpoint`, replacing the previous response.
+    pub fn insert<E: Into<String>>(&self, method: Method, endpoint: E, body: &Value) {
+        self.fixtures
+            .lock()
+            .unwrap()
+            .insert((method, endpoint.into()), body.to_string());
+    }
+
+    pub fn with<E: Into<String>>(self, method: Method, endpoint: E, body: &Value) -> Self {
+        self.insert(method, endpoint, body);
+        self
+    }
+
+    /// Serves `response` to requests to `endpoint` whose body is `request`, ahead of the
+    /// other responses.
+    ///
+    /// Bodies are compared in canonical form, with object keys sorted as they are sent, so
+    /// `request` may be e.g. a [`PaymentRequest`] built in any order.
+    pub fn insert_for_body<E, B>(&self, method: Method, endpoint: E, request: &B, response: &Value)
+    where
+        E: Into<String>,
+        B: Serialize,
+    {
+        let request = serde_json::to_value(request).expect("request body is always serializable");
+        self.by_body.lock().unwrap().insert(
+            (method, endpoint.into(), request.to_string()),
+            response.to_string(),
+        );
+    }
+
+    /// Serves `body` once, after the responses pushed before and ahead of the one set with [`OfflineTransport::insert`].
+    pub fn push<E: Into<String>>(&self, method: Method, endpoint: E, body: &Value) {
+        self.push_response(method, endpoint.into(), Ok(body.to_string()));
+    }
+
+    /// Fails one request with `error`, e.g. [`HttpStatusError`], in the order of [`OfflineTransport::push`].
+    pub fn push_error<E, R>(&self, method: Method, endpoint: E, error: R)
+    where
+        E: Into<String>,
+        R: std::error::Error + Send + Sync + 'static,
+    {
+        self.push_response(method, endpoint.into(), Err(Box::new(error)));
+    }
+
+    fn push_response(&self, method: Method, endpoint: String, rsp: Result<String, StdError>) {
+        self.scripted
+            .lock()
+            .unwrap()
+            .entry((method, endpoint))
+            .or_default()
+            .push_back(rsp);
+    }
+
+    /// Methods and endpoints of the requests made so far, in order.
+    pub fn requests(&self) -> Vec<(Method, String)> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| (request.method.clone(), request.endpoint.clone()))
+            .collect()
+    }
+
+    /// Requests made so far with their parameters and bodies, in order.
+    pub fn recorded(&self) -> Vec<OfflineRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Transport for OfflineTransport {
+    fn call(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        body: Option<&Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        self.requests.lock().unwrap().push(OfflineRequest {
+            method: method.clone(),
+            endpoint: endpoint.clone(),
+            params: params
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            body: body.map(Value::to_string),
+        });
+
+        let by_body = body.and_then(|body| {
+            self.by_body
+                .lock()
+                .unwrap()
+                .get(&(method.clone(), endpoint.clone(), body.to_string()))
+                .cloned()
+        });
+        let key = (method, endpoint);
+        let scripted = by_body.map(Ok).or_else(|| {
+            self.scripted
+                .lock()
+                .unwrap()
+                .get_mut(&key)
+                .and_then(VecDeque::pop_front)
+        });
+        let rsp = scripted.unwrap_or_else(|| {
+            self.fixtures
+                .lock()
+                .unwrap()
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| {
+                    Box::new(NoFixture {
+                        endpoint: key.1.clone(),
+                    }) as StdError
+                })
+        });
+
+        Box::pin(async move { rsp })
+    }
+}
@@ -0,0 +1,171 @@
+//! Journal of multi-step operations, so that an interrupted one can be inspected and continued.
+
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+};
+
+/// Kind of the operations of [`Client::issue_virtual_card`].
+pub const CARD_ORDER: &str = "card-order";
+
+const INDEX_KEY: &str = "operations.pending";
+
+fn operation_key(id: &str) -> String {
+    format!("operation.{}", id)
+}
+
+fn to_value<T: Serialize>(value: &T) -> QiwiResult<Value> {
+    serde_json::to_value(value)
+        .map_err(|e| Box::new(e) as StdError)
+        .context(StateStoreError)
+}
+
+/// One completed step of an [`Operation`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationStep {
+    pub name: String,
+    /// Id the step's request was sent with, to look it up on QIWI side.
+    pub request_id: String,
+    pub response: Value,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Operation {
+    pub id: String,
+    /// What the operation does, e.g. [`CARD_ORDER`].
+    pub kind: String,
+    /// Parameters the operation was started with, to continue it with the same ones.
+    #[serde(default)]
+    pub input: Value,
+    pub started_at: DateTime<Utc>,
+    pub steps: Vec<OperationStep>,
+    /// Id of the request being sent for the next step, see [`OperationLog::prepare_step`].
+    #[serde(default)]
+    pub pending_request_id: Option<String>,
+    pub completed: bool,
+}
+
+impl Operation {
+    pub fn step(&self, name: &str) -> Option<&OperationStep> {
+        self.steps.iter().find(|step| step.name == name)
+    }
+
+    pub fn last_step(&self) -> Option<&OperationStep> {
+        self.steps.last()
+    }
+}
+
+/// Operations kept in the client's [state store](ClientBuilder::state_store), see [`Client::operation_log`].
+pub struct OperationLog<'a> {
+    client: &'a Client,
+}
+
+impl<'a> OperationLog<'a> {
+    fn store(&self) -> &dyn state::StateStore {
+        &*self.client.local_state
+    }
+
+    async fn pending_ids(&self) -> QiwiResult<Vec<String>> {
+        Ok(state::load(self.store(), INDEX_KEY)
+            .await
+            .context(StateStoreError)?
+            .unwrap_or_default())
+    }
+
+    async fn save(&self, op: &Operation) -> QiwiResult<()> {
+        state::save(self.store(), &operation_key(&op.id), op)
+            .await
+            .context(StateStoreError)
+    }
+
+    /// Updates the list of incomplete operations. Updates are serialized within the client.
+    async fn update_index(&self, id: &str, pending: bool) -> QiwiResult<()> {
+        let _guard = self.client.operations_lock.lock().await;
+        let mut ids = self.pending_ids().await?;
+        ids.retain(|pending_id| pending_id != id);
+        if pending {
+            ids.push(id.to_string());
+        }
+        state::save(self.store(), INDEX_KEY, &ids)
+            .await
+            .context(StateStoreError)
+    }
+
+    pub async fn start<T: Serialize>(&self, kind: &str, input: &T) -> QiwiResult<Operation> {
+        let op = Operation {
+            id: self.client.ids.next_token(),
+            kind: kind.to_string(),
+            input: to_value(input)?,
+            started_at: self.client.clock.utc_now(),
+            steps: vec![],
+            pending_request_id: None,
+            completed: false,
+        };
+        self.save(&op).await?;
+        self.update_index(&op.id, true).await?;
+        Ok(op)
+    }
+
+    pub async fn get(&self, id: &str) -> QiwiResult<Option<Operation>> {
+        state::load(self.store(), &operation_key(id))
+            .await
+            .context(StateStoreError)
+    }
+
+    /// Saves the id the next step's request is about to be sent with, so that after an
+    /// interruption it can be looked up on QIWI side or sent again with the same id.
+    pub async fn prepare_step(&self, op: &mut Operation, request_id: &str) -> QiwiResult<()> {
+        op.pending_request_id = Some(request_id.to_string());
+        self.save(op).await
+    }
+
+    /// Appends a step, saving it before returning.
+    pub async fn record_step<T: Serialize>(
+        &self,
+        op: &mut Operation,
+        name: &str,
+        request_id: &str,
+        response: &T,
+    ) -> QiwiResult<()> {
+        op.steps.push(OperationStep {
+            name: name.to_string(),
+            request_id: request_id.to_string(),
+            response: to_value(response)?,
+            at: self.client.clock.utc_now(),
+        });
+        op.pending_request_id = None;
+        self.save(op).await
+    }
+
+    pub async fn complete(&self, op: &mut Operation) -> QiwiResult<()> {
+        op.completed = true;
+        self.save(op).await?;
+        self.update_index(&op.id, false).await
+    }
+
+    /// Operations started but not completed, oldest first.
+    pub async fn pending(&self) -> QiwiResult<Vec<Operation>> {
+        let mut ops = Vec::new();
+        for id in self.pending_ids().await? {
+            if let Some(op) = self.get(&id).await? {
+                ops.push(op);
+            }
+        }
+        Ok(ops)
+    }
+}
+
+impl Client {
+    /// Journal of multi-step operations. Kept in memory only without a state store.
+    pub fn operation_log(&self) -> OperationLog<'_> {
+        OperationLog { client: self }
+    }
+
+    /// Operations that were started but not completed, e.g. because the process stopped midway.
+    pub async fn pending_operations(&self) -> QiwiResult<Vec<Operation>> {
+        self.operation_log().pending().await
+    }
+}
@@ -0,0 +1,412 @@
+//! Guardrails for outgoing payments.
+
+use {
+    crate::{
+        clock::{Clock, SystemClock},
+        Money, MoneyBag, PaymentRequest, QiwiCurrency, StdError,
+    },
+    async_trait::async_trait,
+    chrono::prelude::*,
+    snafu::*,
+    std::{
+        collections::HashSet,
+        fmt::Debug,
+        sync::{Arc, Mutex},
+    },
+};
+
+#[derive(Debug, Snafu)]
+pub enum PolicyViolation {
+    #[snafu(display("payment of {} exceeds per-transfer limit of {}", amount, limit))]
+    TransferLimitExceeded { amount: Money, limit: Money },
+    #[snafu(display(
+        "payment of {} on top of {} spent today exceeds daily limit of {}",
+        amount,
+        spent,
+        limit
+    ))]
+    DailyLimitExceeded {
+        amount: Money,
+        spent: Money,
+        limit: Money,
+    },
+    #[snafu(display("limit is set in {}, but payment is in {}", limit_currency, currency))]
+    UnsupportedCurrency {
+        currency: QiwiCurrency,
+        limit_currency: QiwiCurrency,
+    },
+    #[snafu(display("destination {} is not allowed", account))]
+    DestinationNotAllowed { account: String },
+    #[snafu(display("failed to access spending store: {}", source))]
+    StoreError { source: StdError },
+}
+
+/// Checks every payment before it is sent.
+#[async_trait]
+pub trait PaymentPolicy: Debug + Send + Sync + 'static {
+    async fn check(&self, req: &PaymentRequest) -> Result<(), PolicyViolation>;
+
+    /// Called after the payment has been accepted by QIWI.
+    ///
+    /// Errors returned here are only logged, since the payment has already been made.
+    async fn on_success(&self, _req: &PaymentRequest) -> Result<(), PolicyViolation> {
+        Ok(())
+    }
+
+    /// Called when a payment that has passed [`check`](Self::check) was not made, e.g. because it was rejected by QIWI.
+    async fn on_failure(&self, _req: &PaymentRequest) -> Result<(), PolicyViolation> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpendingRecord {
+    pub time: DateTime<Utc>,
+    pub amount: Money,
+}
+
+/// Persistence of past spending for rolling limits.
+#[async_trait]
+pub trait SpendingStore: Debug + Send + Sync + 'static {
+    /// Records made after `since`.
+    async fn load(&self, since: DateTime<Utc>) -> Result<Vec<SpendingRecord>, StdError>;
+    async fn append(&self, record: SpendingRecord) -> Result<(), StdError>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemorySpendingStore {
+    records: Mutex<Vec<SpendingRecord>>,
+}
+
+#[async_trait]
+impl SpendingStore for MemorySpendingStore {
+    async fn load(&self, since: DateTime<Utc>) -> Result<Vec<SpendingRecord>, StdError> {
+        Ok(self
+            .records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.time > since)
+            .cloned()
+            .collect())
+    }
+
+    async fn append(&self, record: SpendingRecord) -> Result<(), StdError> {
+        let mut records = self.records.lock().unwrap();
+        let horizon = record.time - chrono::Duration::days(1);
+        records.retain(|record| record.time > horizon);
+        records.push(record);
+        Ok(())
+    }
+}
+
+/// Policy with per-transfer and rolling 24 hour limits and destination lists.
+///
+/// Destinations are matched against the `account` field of the payment.
+///
+/// A payment passing the daily limit reserves its amount until it succeeds or fails, so
+/// that concurrent payments can not overrun the limit together.
+#[derive(Debug)]
+pub struct SimplePolicy {
+    transfer_limit: Option<Money>,
+    daily_limit: Option<Money>,
+    allowed: Option<HashSet<String>>,
+    denied: HashSet<String>,
+    store: Box<dyn SpendingStore>,
+    clock: Arc<dyn Clock>,
+    /// Payments in flight by id, locked for the whole check.
+    ///
+    /// A reservation left behind by a failed [`SpendingStore::append`] still lapses with the window.
+    reserved: tokio::sync::Mutex<Vec<(String, SpendingRecord)>>,
+}
+
+impl Default for SimplePolicy {
+    fn default() -> Self {
+        Self {
+            transfer_limit: None,
+            daily_limit: None,
+            allowed: None,
+            denied: HashSet::new(),
+            store: Box::new(MemorySpendingStore::default()),
+            clock: Arc::new(SystemClock),
+            reserved: Default::default(),
+        }
+    }
+}
+
+impl SimplePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn transfer_limit(mut self, limit: Money) -> Self {
+        self.transfer_limit = Some(limit);
+        self
+    }
+
+    pub fn daily_limit(mut self, limit: Money) -> Self {
+        self.daily_limit = Some(limit);
+        self
+    }
+
+    /// Once any destination is allowed, all others are denied.
+    pub fn allow<T: Into<String>>(mut self, account: T) -> Self {
+        self.allowed
+            .get_or_insert_with(HashSet::new)
+            .insert(account.into());
+        self
+    }
+
+    pub fn deny<T: Into<String>>(mut self, account: T) -> Self {
+        self.denied.insert(account.into());
+        self
+    }
+
+    pub fn store<S: SpendingStore>(mut self, store: S) -> Self {
+        self.store = Box::new(store);
+        self
+    }
+
+    /// Tells the time of the rolling window, e.g. [`ManualClock`](crate::clock::ManualClock) in tests.
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Spent within the last 24 hours, including payments in flight.
+    async fn spent_today(
+        &self,
+        currency: &QiwiCurrency,
+        reserved: &[(String, SpendingRecord)],
+        since: DateTime<Utc>,
+    ) -> Result<Money, PolicyViolation> {
+        let records = self.store.load(since).await.context(StoreError)?;
+
+        Ok(records
+            .iter()
+            .chain(reserved.iter().map(|(_, record)| record))
+            .filter(|record| record.time > since)
+            .map(|record| record.amount.clone())
+            .collect::<MoneyBag>()
+            .get(currency))
+    }
+
+    fn release(reserved: &mut Vec<(String, SpendingRecord)>, req: &PaymentRequest) {
+        reserved.retain(|(id, _)| id != &req.id);
+    }
+}
+
+fn ensure_currency(amount: &Money, limit: &Money) -> Result<(), PolicyViolation> {
+    ensure!(
+        amount.currency == limit.currency,
+        UnsupportedCurrency {
+            currency: amount.currency.clone(),
+            limit_currency: limit.currency.clone(),
+        }
+    );
+
+    Ok(())
+}
+
+#[async_trait]
+impl PaymentPolicy for SimplePolicy {
+    async fn check(&self, req: &PaymentRequest) -> Result<(), PolicyViolation> {
+        let account = req.fields.get("account").cloned().unwrap_or_default();
+        ensure!(
+            !self.denied.contains(&account)
+                && self
+                    .allowed
+                    .as_ref()
+                    .map(|allowed| allowed.contains(&account))
+                    .unwrap_or(true),
+            DestinationNotAllowed { account }
+        );
+
+        if let Some(limit) = &self.transfer_limit {
+            ensure_currency(&req.sum, limit)?;
+            ensure!(
+                req.sum.amount <= limit.amount,
+                TransferLimitExceeded {
+                    amount: req.sum.clone(),
+                    limit: limit.clone(),
+                }
+            );
+        }
+
+        if let Some(limit) = &self.daily_limit {
+            ensure_currency(&req.sum, limit)?;
+            let mut reserved = self.reserved.lock().await;
+            // A payment checked again, e.g. when retried, is not counted twice.
+            Self::release(&mut reserved, req);
+            let now = self.clock.utc_now();
+            let spent = self
+                .spent_today(&limit.currency, &reserved, now - chrono::Duration::days(1))
+                .await?;
+            ensure!(
+                &spent.amount + &req.sum.amount <= limit.amount,
+                DailyLimitExceeded {
+                    amount: req.sum.clone(),
+                    spent,
+                    limit: limit.clone(),
+                }
+            );
+            reserved.push((
+                req.id.clone(),
+                SpendingRecord {
+                    time: now,
+                    amount: req.sum.clone(),
+                },
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn on_success(&self, req: &PaymentRequest) -> Result<(), PolicyViolation> {
+        if self.daily_limit.is_some() {
+            // The record is appended before the reservation is released, so that the amount is never missing from a check.
+            let mut reserved = self.reserved.lock().await;
+            self.store
+                .append(SpendingRecord {
+                    time: self.clock.utc_now(),
+                    amount: req.sum.clone(),
+                })
+                .await
+                .context(StoreError)?;
+            Self::release(&mut reserved, req);
+        }
+
+        Ok(())
+    }
+
+    async fn on_failure(&self, req: &PaymentRequest) -> Result<(), PolicyViolation> {
+        Self::release(&mut *self.reserved.lock().await, req);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{clock::ManualClock, PaymentMethod, ProviderId},
+        penny::Currency,
+        std::time::Duration,
+    };
+
+    fn rub(amount: u32) -> Money {
+        Money::new(amount.into(), Currency::RUB)
+    }
+
+    fn payment(id: &str, amount: u32) -> PaymentRequest {
+        PaymentRequest {
+            provider: ProviderId::QIWI,
+            id: id.to_string(),
+            sum: rub(amount),
+            payment_method: PaymentMethod::account(Currency::RUB.into()),
+            fields: std::iter::once(("account".to_string(), "+79035550101".to_string())).collect(),
+            comment: None,
+        }
+    }
+
+    fn policy() -> (SimplePolicy, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(
+            Utc.with_ymd_and_hms(2020, 1, 15, 12, 0, 0).unwrap(),
+        ));
+        let policy = SimplePolicy::new()
+            .daily_limit(rub(100))
+            .clock(clock.clone());
+        (policy, clock)
+    }
+
+    async fn pay(policy: &SimplePolicy, req: &PaymentRequest) {
+        policy.check(req).await.unwrap();
+        policy.on_success(req).await.unwrap();
+    }
+
+    fn is_daily_limit_exceeded(result: Result<(), PolicyViolation>) -> bool {
+        matches!(result, Err(PolicyViolation::DailyLimitExceeded { .. }))
+    }
+
+    #[tokio::test]
+    async fn rolling_window() {
+        let (policy, clock) = policy();
+
+        pay(&policy, &payment("1", 60)).await;
+        clock.advance(Duration::from_secs(12 * 3600));
+        pay(&policy, &payment("2", 40)).await;
+        assert!(is_daily_limit_exceeded(
+            policy.check(&payment("3", 1)).await
+        ));
+
+        // The first payment leaves the window, the second one still counts.
+        clock.advance(Duration::from_secs(12 * 3600 + 1));
+        policy.check(&payment("3", 60)).await.unwrap();
+        policy.on_failure(&payment("3", 60)).await.unwrap();
+        assert!(is_daily_limit_exceeded(
+            policy.check(&payment("4", 61)).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn checks_reserve_amount() {
+        let (policy, _) = policy();
+
+        policy.check(&payment("1", 60)).await.unwrap();
+        // The first payment is still in flight.
+        assert!(is_daily_limit_exceeded(
+            policy.check(&payment("2", 50)).await
+        ));
+        policy.check(&payment("2", 40)).await.unwrap();
+        assert!(is_daily_limit_exceeded(
+            policy.check(&payment("3", 1)).await
+        ));
+
+        // Recording a success keeps the amount counted.
+        policy.on_success(&payment("1", 60)).await.unwrap();
+        assert!(is_daily_limit_exceeded(
+            policy.check(&payment("3", 1)).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn failure_releases_reservation() {
+        let (policy, _) = policy();
+
+        policy.check(&payment("1", 60)).await.unwrap();
+        policy.on_failure(&payment("1", 60)).await.unwrap();
+        pay(&policy, &payment("2", 100)).await;
+        assert!(is_daily_limit_exceeded(
+            policy.check(&payment("3", 1)).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn repeated_check_counts_once() {
+        let (policy, _) = policy();
+
+        policy.check(&payment("1", 60)).await.unwrap();
+        policy.check(&payment("1", 60)).await.unwrap();
+        policy.check(&payment("2", 40)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_checks() {
+        let (policy, _) = policy();
+        let policy = Arc::new(policy);
+
+        let checks = (0..10)
+            .map(|i| {
+                let policy = policy.clone();
+                tokio::spawn(async move { policy.check(&payment(&i.to_string(), 30)).await })
+            })
+            .collect::<Vec<_>>();
+        let mut passed = 0;
+        for check in checks {
+            if check.await.unwrap().is_ok() {
+                passed += 1;
+            }
+        }
+        assert_eq!(passed, 3);
+    }
+}
@@ -0,0 +1,405 @@
+use {
+    crate::{clock::Clock, ids::IdGenerator},
+    std::{
+        cmp::min,
+        collections::{BTreeSet, HashMap},
+        fmt,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// Gap kept between any two polls of a client by default.
+pub(crate) const DEFAULT_POLL_MIN_GAP: Duration = Duration::from_secs(1);
+
+/// Share of the interval polls are randomly delayed by.
+const JITTER_SHARE: f64 = 0.1;
+const MAX_JITTER: Duration = Duration::from_secs(5);
+
+/// Fractional part of the golden ratio, spreads start offsets of consecutive registrations evenly.
+const STAGGER_STEP: f64 = 0.618_034;
+
+#[derive(Debug)]
+struct Poller {
+    interval: Duration,
+    /// Time the last slot was planned for, before it was moved to keep the gap.
+    last_planned: Option<Instant>,
+    /// Offset of the first slot.
+    stagger: Duration,
+    registrations: usize,
+}
+
+#[derive(Debug, Default)]
+struct Schedule {
+    pollers: HashMap<String, Poller>,
+    /// Slots reserved by all pollers.
+    reserved: BTreeSet<Instant>,
+    registered_total: u64,
+}
+
+/// Spaces out polls of the client's watchers, see [`Client::poll_coordinator`](crate::Client::poll_coordinator).
+///
+/// Each kind of poll recurs with its interval, first polls of different kinds are staggered across
+/// their intervals and every poll is delayed by a small random jitter. Any two polls are at least
+/// the minimum gap apart. Slots are reserved when requested, so a poller that takes long to ask for
+/// its next slot gets the next free one and does not push back the others.
+pub struct PollCoordinator {
+    min_gap: Duration,
+    ids: Arc<dyn IdGenerator>,
+    clock: Arc<dyn Clock>,
+    schedule: Mutex<Schedule>,
+}
+
+impl fmt::Debug for PollCoordinator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PollCoordinator")
+            .field("min_gap", &self.min_gap)
+            .field("schedule", &self.schedule)
+            .finish()
+    }
+}
+
+impl PollCoordinator {
+    pub fn new(min_gap: Duration, ids: Arc<dyn IdGenerator>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            min_gap,
+            ids,
+            clock,
+            schedule: Default::default(),
+        }
+    }
+
+    pub fn min_gap(&self) -> Duration {
+        self.min_gap
+    }
+
+    /// Registers a kind of poll, unregistered when the returned handle is dropped.
+    ///
+    /// Handles registered under the same kind share its schedule.
+    pub fn register(self: &Arc<Self>, kind: &str, interval: Duration) -> PollRegistration {
+        let mut schedule = self.schedule.lock().unwrap();
+        schedule.registered_total += 1;
+        let n = schedule.registered_total;
+        let poller = schedule
+            .pollers
+            .entry(kind.to_string())
+            .or_insert_with(|| Poller {
+                interval,
+                last_planned: None,
+                stagger: interval.mul_f64((n as f64 * STAGGER_STEP).fract()),
+                registrations: 0,
+            });
+        poller.interval = interval;
+        poller.registrations += 1;
+
+        PollRegistration {
+            coordinator: self.clone(),
+            kind: kind.to_string(),
+        }
+    }
+
+    fn unregister(&self, kind: &str) {
+        let mut schedule = self.schedule.lock().unwrap();
+        if let Some(poller) = schedule.pollers.get_mut(kind) {
+            poller.registrations -= 1;
+            if poller.registrations == 0 {
+                schedule.pollers.remove(kind);
+            }
+        }
+    }
+
+    fn set_interval(&self, kind: &str, interval: Duration) {
+        if let Some(poller) = self.schedule.lock().unwrap().pollers.get_mut(kind) {
+            poller.interval = interval;
+        }
+    }
+
+    fn jitter(&self, interval: Duration) -> Duration {
+        let mut buf = [0; 2];
+        self.ids.fill_random(&mut buf);
+        let share = f64::from(u16::from_le_bytes(buf)) / f64::from(u16::MAX);
+        interval
+            .mul_f64(JITTER_SHARE)
+            .min(MAX_JITTER)
+            .mul_f64(share)
+    }
+
+    /// Reserves the next slot of `kind` as of `now`. Unregistered kinds get the earliest free slot.
+    fn reserve(&self, kind: &str, now: Instant) -> Instant {
+        let mut schedule = self.schedule.lock().unwrap();
+        let gap = self.min_gap;
+
+        // Slots in the past no longer constrain anything.
+        let expired = now.checked_sub(gap).unwrap_or(now);
+        schedule.reserved = schedule.reserved.split_off(&expired);
+
+        let planned = match schedule.pollers.get(kind) {
+            Some(poller) => {
+                // A poller late for its slot continues from now rather than catching up.
+                let base = match poller.last_planned {
+                    Some(last) => (last + poller.interval).max(now),
+                    None => now + poller.stagger,
+                };
+                Some(base + self.jitter(poller.interval))
+            }
+            None => None,
+        };
+
+        let mut slot = planned.unwrap_or(now).max(now);
+        let lower = slot.checked_sub(gap).unwrap_or(slot);
+        for &reserved in schedule.reserved.range(lower..) {
+            if reserved >= slot + gap {
+                break;
+            }
+            slot = reserved + gap;
+        }
+
+        schedule.reserved.insert(slot);
+        // The planned time is kept, so that moving a slot to keep the gap does not shift the later ones.
+        if let (Some(poller), Some(planned)) = (schedule.pollers.get_mut(kind), planned) {
+            poller.last_planned = Some(planned);
+        }
+        slot
+    }
+
+    /// Waits for the next slot of `kind`.
+    pub async fn next_slot(&self, kind: &str) {
+        let slot = self.reserve(kind, self.clock.now());
+        self.clock.sleep_until(slot).await;
+    }
+}
+
+/// Registration of a poll kind with [`PollCoordinator`], removed on drop.
+#[derive(Debug)]
+pub struct PollRegistration {
+    coordinator: Arc<PollCoordinator>,
+    kind: String,
+}
+
+impl PollRegistration {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// Changes the interval starting with the next slot, e.g. to back off after failures.
+    pub fn set_interval(&self, interval: Duration) {
+        self.coordinator.set_interval(&self.kind, interval)
+    }
+
+    pub async fn next_slot(&self) {
+        self.coordinator.next_slot(&self.kind).await
+    }
+}
+
+impl Drop for PollRegistration {
+    fn drop(&mut self) {
+        self.coordinator.unregister(&self.kind)
+    }
+}
+
+/// Polling settings of watchers, e.g. [`Client::watch_payments`](crate::Client::watch_payments).
+#[derive(Clone, Debug)]
+pub struct WatchOptions {
+    /// Delay between polls while the API is healthy.
+    pub interval: Duration,
+    /// Upper bound for the delay after consecutive failures.
+    pub max_interval: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_interval: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Health of a polling watcher, reported alongside the items it yields.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatcherStatus {
+    /// Last polls have failed, the watcher is backing off.
+    Degraded {
+        consecutive_failures: u32,
+        next_attempt_in: Duration,
+    },
+}
+
+/// Exponential backoff shared by polling watchers.
+///
+/// Every failure doubles the delay up to `max`, a success resets it to `base`.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    current: Duration,
+    consecutive_failures: u32,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            current: base,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Delay before the next attempt.
+    pub fn delay(&self) -> Duration {
+        self.current
+    }
+
+    pub fn on_success(&mut self) -> Duration {
+        self.consecutive_failures = 0;
+        self.current = self.base;
+        self.current
+    }
+
+    pub fn on_failure(&mut self) -> Duration {
+        self.consecutive_failures += 1;
+        self.current = min(self.current * 2, self.max);
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{clock::ManualClock, ids::SequentialIdGenerator},
+        chrono::Utc,
+    };
+
+    const GAP: Duration = Duration::from_secs(1);
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    fn coordinator() -> (Arc<PollCoordinator>, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let coordinator = Arc::new(PollCoordinator::new(
+            GAP,
+            Arc::new(SequentialIdGenerator::new(0)),
+            clock.clone(),
+        ));
+        (coordinator, clock)
+    }
+
+    #[test]
+    fn slots_keep_min_gap() {
+        let (coordinator, clock) = coordinator();
+        let _a = coordinator.register("a", INTERVAL);
+        let _b = coordinator.register("b", INTERVAL);
+
+        let now = clock.now();
+        let mut slots = vec![
+            coordinator.reserve("a", now),
+            coordinator.reserve("b", now),
+            coordinator.reserve("unregistered", now),
+            coordinator.reserve("unregistered", now),
+        ];
+        slots.sort();
+        assert!(slots.windows(2).all(|pair| pair[1] - pair[0] >= GAP));
+    }
+
+    #[test]
+    fn first_slots_are_staggered() {
+        let (coordinator, clock) = coordinator();
+        let _a = coordinator.register("a", INTERVAL);
+        let _b = coordinator.register("b", INTERVAL);
+
+        let now = clock.now();
+        let a = coordinator.reserve("a", now) - now;
+        let b = coordinator.reserve("b", now) - now;
+        // Golden ratio steps of the interval, 6.2 and 2.4 seconds, plus jitter.
+        assert!(a > Duration::from_secs(6) && a < Duration::from_secs(8));
+        assert!(b > Duration::from_secs(2) && b < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn recurs_with_interval() {
+        let (coordinator, clock) = coordinator();
+        let _a = coordinator.register("a", INTERVAL);
+
+        let first = coordinator.reserve("a", clock.now());
+        let second = coordinator.reserve("a", first);
+        let jitter = INTERVAL.mul_f64(JITTER_SHARE);
+        assert!(second - first >= INTERVAL - jitter);
+        assert!(second - first <= INTERVAL + jitter);
+    }
+
+    #[test]
+    fn late_poller_continues_from_now() {
+        let (coordinator, clock) = coordinator();
+        let _a = coordinator.register("a", INTERVAL);
+
+        let first = coordinator.reserve("a", clock.now());
+        let late = first + INTERVAL * 3 + Duration::from_secs(5);
+        let next = coordinator.reserve("a", late);
+        assert!(next >= late);
+        assert!(next - late <= INTERVAL.mul_f64(JITTER_SHARE));
+    }
+
+    #[test]
+    fn set_interval_applies_to_next_slot() {
+        let (coordinator, clock) = coordinator();
+        let a = coordinator.register("a", INTERVAL);
+
+        let first = coordinator.reserve("a", clock.now());
+        a.set_interval(INTERVAL * 6);
+        let second = coordinator.reserve("a", first);
+        assert!(second - first >= INTERVAL * 6);
+    }
+
+    #[test]
+    fn drop_unregisters() {
+        let (coordinator, _) = coordinator();
+        let a = coordinator.register("a", INTERVAL);
+        let a2 = coordinator.register("a", INTERVAL);
+        drop(a);
+        assert!(coordinator
+            .schedule
+            .lock()
+            .unwrap()
+            .pollers
+            .contains_key("a"));
+        drop(a2);
+        assert!(coordinator.schedule.lock().unwrap().pollers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn next_slot_waits_on_clock() {
+        let (coordinator, clock) = coordinator();
+        let a = coordinator.register("a", INTERVAL);
+
+        let start = clock.now();
+        a.next_slot().await;
+        let first = clock.now();
+        a.next_slot().await;
+        assert!(first > start);
+        assert!(clock.now() - first >= INTERVAL - INTERVAL.mul_f64(JITTER_SHARE));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(INTERVAL, INTERVAL * 5);
+        assert_eq!(backoff.delay(), INTERVAL);
+
+        let delays = (0..4).map(|_| backoff.on_failure()).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            vec![INTERVAL * 2, INTERVAL * 4, INTERVAL * 5, INTERVAL * 5]
+        );
+        assert_eq!(backoff.consecutive_failures(), 4);
+        assert_eq!(backoff.delay(), INTERVAL * 5);
+
+        assert_eq!(backoff.on_success(), INTERVAL);
+        assert_eq!(backoff.consecutive_failures(), 0);
+        assert_eq!(backoff.on_failure(), INTERVAL * 2);
+        assert_eq!(backoff.consecutive_failures(), 1);
+    }
+}
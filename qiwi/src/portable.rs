@@ -0,0 +1,826 @@
+//! Serializable form of errors for passing them between processes, see [`Error::to_portable`].
+
+use {
+    crate::*,
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+};
+
+/// Source error carried by its message, e.g. of a failed state store in another process.
+#[derive(Clone, Debug)]
+pub struct RemoteSource(pub String);
+
+impl Display for RemoteSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RemoteSource {}
+
+/// [`Error`] as plain data, e.g. for sending it to another process as JSON.
+///
+/// Backtraces are dropped and source errors other than [`Error`] itself are kept as their
+/// messages, everything else survives [`Error::from_portable`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PortableError {
+    /// Variant of [`Error`], followed by the variant of the source for transport, policy and
+    /// second factor errors, e.g. `QiwiError` or `TransportError.NetworkError`.
+    pub kind: String,
+    /// [`Error`] as displayed.
+    pub message: String,
+    /// QIWI error code of [`Error::QiwiError`].
+    #[serde(default)]
+    pub qiwi_code: Option<String>,
+    #[serde(default)]
+    pub http_status: Option<u16>,
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+    /// See [`Error::is_retryable`].
+    #[serde(default)]
+    pub retryable: bool,
+    /// Other fields of the variant by their camel case names.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<String, Value>,
+    /// Source of errors wrapping another [`Error`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<Box<PortableError>>,
+}
+
+impl Display for PortableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PortableError {}
+
+impl PortableError {
+    pub(crate) fn detail<V: Serialize>(&mut self, name: &str, value: V) {
+        self.details.insert(
+            name.to_string(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+    }
+
+    pub(crate) fn field<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        serde_json::from_value(self.details.get(name)?.clone()).ok()
+    }
+
+    pub(crate) fn source_field(&self, name: &str) -> Option<StdError> {
+        self.field::<String>(name)
+            .map(|message| Box::new(RemoteSource(message)) as StdError)
+    }
+
+    fn rebuild_cause(&self) -> Option<Box<Error>> {
+        Some(Box::new(Error::from_portable(
+            (**self.cause.as_ref()?).clone(),
+        )))
+    }
+}
+
+fn policy_violation_kind(
+    violation: &policy::PolicyViolation,
+    p: &mut PortableError,
+) -> &'static str {
+    use policy::PolicyViolation::*;
+
+    match violation {
+        TransferLimitExceeded { amount, limit } => {
+            p.detail("amount", amount);
+            p.detail("limit", limit);
+            "TransferLimitExceeded"
+        }
+        DailyLimitExceeded {
+            amount,
+            spent,
+            limit,
+        } => {
+            p.detail("amount", amount);
+            p.detail("spent", spent);
+            p.detail("limit", limit);
+            "DailyLimitExceeded"
+        }
+        UnsupportedCurrency {
+            currency,
+            limit_currency,
+        } => {
+            p.detail("currency", currency);
+            p.detail("limitCurrency", limit_currency);
+            "UnsupportedCurrency"
+        }
+        DestinationNotAllowed { account } => {
+            p.detail("account", account);
+            "DestinationNotAllowed"
+        }
+        StoreError { source } => {
+            p.detail("source", source.to_string());
+            "StoreError"
+        }
+    }
+}
+
+fn policy_violation(kind: &str, p: &PortableError) -> Option<policy::PolicyViolation> {
+    use policy::PolicyViolation::*;
+
+    Some(match kind {
+        "TransferLimitExceeded" => TransferLimitExceeded {
+            amount: p.field("amount")?,
+            limit: p.field("limit")?,
+        },
+        "DailyLimitExceeded" => DailyLimitExceeded {
+            amount: p.field("amount")?,
+            spent: p.field("spent")?,
+            limit: p.field("limit")?,
+        },
+        "UnsupportedCurrency" => UnsupportedCurrency {
+            currency: p.field("currency")?,
+            limit_currency: p.field("limitCurrency")?,
+        },
+        "DestinationNotAllowed" => DestinationNotAllowed {
+            account: p.field("account")?,
+        },
+        "StoreError" => StoreError {
+            source: p.source_field("source")?,
+        },
+        _ => return None,
+    })
+}
+
+impl Error {
+    /// Plain data form of the error, see [`PortableError`].
+    pub fn to_portable(&self) -> PortableError {
+        let mut p = PortableError {
+            kind: String::new(),
+            message: self.to_string(),
+            qiwi_code: None,
+            http_status: None,
+            endpoint: None,
+            correlation_id: self.correlation_id().map(str::to_string),
+            retryable: self.is_retryable(),
+            details: BTreeMap::new(),
+            cause: None,
+        };
+        let mut sub_kind = None;
+        let kind = match self {
+            Self::TransportError { source } => {
+                source.write_portable(&mut p);
+                sub_kind = Some(source.portable_kind());
+                "TransportError"
+            }
+            Self::QiwiError { error } => {
+                p.qiwi_code = Some(error.error_code.clone());
+                p.detail("error", error);
+                "QiwiError"
+            }
+            Self::Unauthorized {
+                status,
+                body,
+                error,
+                ..
+            } => {
+                p.http_status = Some(*status);
+                p.qiwi_code = error.as_ref().map(|error| error.error_code.clone());
+                p.detail("body", body);
+                "Unauthorized"
+            }
+            Self::AuthorizationCallbackError { source, .. } => {
+                p.detail("source", source.to_string());
+                "AuthorizationCallbackError"
+            }
+            Self::InvalidAccountAlias { alias } => {
+                p.detail("alias", alias);
+                "InvalidAccountAlias"
+            }
+            Self::ReadOnlyMode { until, reason } => {
+                p.detail("until", until);
+                p.detail("reason", reason);
+                "ReadOnlyMode"
+            }
+            Self::AuditFailed { source } => {
+                p.detail("source", source.to_string());
+                "AuditFailed"
+            }
+            Self::PolicyViolation { source } => {
+                sub_kind = Some(policy_violation_kind(source, &mut p));
+                "PolicyViolation"
+            }
+            Self::MfaDenied { reason } => {
+                p.detail("reason", reason);
+                "MfaDenied"
+            }
+            Self::MfaTimeout => "MfaTimeout",
+            Self::MfaError { source } => {
+                sub_kind = Some(match source {
+                    mfa::MfaError::Timeout => "Timeout",
+                    mfa::MfaError::ProviderError { source } => {
+                        p.detail("source", source.to_string());
+                        "ProviderError"
+                    }
+                });
+                "MfaError"
+            }
+            Self::CurrencyMismatch { source } => {
+                p.detail("left", &source.left);
+                p.detail("right", &source.right);
+                "CurrencyMismatch"
+            }
+            Self::InsufficientFunds {
+                available,
+                required,
+            } => {
+                p.detail("available", available);
+                p.detail("required", required);
+                "InsufficientFunds"
+            }
+            Self::LimitExceeded { remaining } => {
+                p.detail("remaining", remaining);
+                "LimitExceeded"
+            }
+            Self::AccountCurrencyMismatch { alias, currency } => {
+                p.detail("alias", alias);
+                p.detail("currency", currency);
+                "AccountCurrencyMismatch"
+            }
+            Self::WebhookLost { old_url, source } => {
+                p.detail("oldUrl", old_url);
+                p.cause = Some(Box::new(source.to_portable()));
+                "WebhookLost"
+            }
+            Self::InvalidWebhookKey { source } => {
+                p.detail("source", source.to_string());
+                "InvalidWebhookKey"
+            }
+            Self::Offline { endpoint } => {
+                p.endpoint = Some(endpoint.clone());
+                "Offline"
+            }
+            Self::ContractViolation {
+                endpoint, details, ..
+            } => {
+                p.endpoint = Some(endpoint.clone());
+                p.detail("details", details);
+                "ContractViolation"
+            }
+            Self::UnexpectedApiResponse {
+                api,
+                version,
+                source,
+            } => {
+                p.detail("api", api);
+                p.detail("version", version);
+                source.write_portable(&mut p);
+                sub_kind = Some(source.portable_kind());
+                "UnexpectedApiResponse"
+            }
+            Self::PossibleDuplicate { existing_txn_id } => {
+                p.detail("existingTxnId", existing_txn_id);
+                "PossibleDuplicate"
+            }
+            Self::NoCrossRate { from, to } => {
+                p.detail("from", from);
+                p.detail("to", to);
+                "NoCrossRate"
+            }
+            Self::RateMoved { planned, current } => {
+                p.detail("planned", planned);
+                p.detail("current", current);
+                "RateMoved"
+            }
+            Self::InvalidConfirmation => "InvalidConfirmation",
+            Self::ConfirmationExpired { expired_at } => {
+                p.detail("expiredAt", expired_at);
+                "ConfirmationExpired"
+            }
+            Self::StateStoreError { source } => {
+                p.detail("source", source.to_string());
+                "StateStoreError"
+            }
+            Self::UnknownOperation { id } => {
+                p.detail("id", id);
+                "UnknownOperation"
+            }
+            Self::InvalidStatementPeriod { from, till } => {
+                p.detail("from", from);
+                p.detail("till", till);
+                "InvalidStatementPeriod"
+            }
+            Self::InvalidDateRange { start, end } => {
+                p.detail("start", start);
+                p.detail("end", end);
+                "InvalidDateRange"
+            }
+            Self::DateRangeTooWide {
+                start,
+                end,
+                max_days,
+            } => {
+                p.detail("start", start);
+                p.detail("end", end);
+                p.detail("maxDays", max_days);
+                "DateRangeTooWide"
+            }
+            Self::ReceiptDownloadFailed { txn_id, source } => {
+                p.detail("txnId", txn_id);
+                p.cause = Some(Box::new(source.to_portable()));
+                "ReceiptDownloadFailed"
+            }
+            Self::IncompleteTransfer { source } => {
+                p.detail("missing", &source.0);
+                "IncompleteTransfer"
+            }
+            Self::NoProviderMapping { route } => {
+                p.detail("route", route);
+                "NoProviderMapping"
+            }
+            Self::UnknownMobileProvider { reason } => {
+                p.detail("reason", reason);
+                "UnknownMobileProvider"
+            }
+            Self::UnknownCardProvider { reason } => {
+                p.detail("reason", reason);
+                "UnknownCardProvider"
+            }
+            Self::TransactionNotFound { txn_id } => {
+                p.detail("txnId", txn_id);
+                "TransactionNotFound"
+            }
+            Self::StatementNotReady => "StatementNotReady",
+            Self::DocumentNotReady { txn_id } => {
+                p.detail("txnId", txn_id);
+                "DocumentNotReady"
+            }
+            Self::NoBankDocument { txn_id } => {
+                p.detail("txnId", txn_id);
+                "NoBankDocument"
+            }
+            Self::Remote { error } => return error.clone(),
+        };
+        p.kind = match sub_kind {
+            Some(sub_kind) => format!("{}.{}", kind, sub_kind),
+            None => kind.to_string(),
+        };
+        p
+    }
+
+    /// Error described by `portable`, or [`Error::Remote`] if the kind is unknown to this
+    /// version of the crate or its details do not fit.
+    pub fn from_portable(portable: PortableError) -> Self {
+        Self::rebuild(&portable).unwrap_or(Self::Remote { error: portable })
+    }
+
+    fn rebuild(p: &PortableError) -> Option<Self> {
+        let (kind, sub_kind) = match p.kind.find('.') {
+            Some(i) => (&p.kind[..i], Some(&p.kind[i + 1..])),
+            None => (p.kind.as_str(), None),
+        };
+        let correlation_id = p.correlation_id.clone();
+        Some(match (kind, sub_kind) {
+            ("TransportError", Some(sub_kind)) => Self::TransportError {
+                source: transport::Error::from_portable(sub_kind, p)?,
+            },
+            ("QiwiError", None) => {
+                let error = match p.field::<QiwiApiError>("error") {
+                    Some(error) => error,
+                    None => QiwiApiError::new(p.qiwi_code.clone()?),
+                };
+                Self::QiwiError {
+                    error: QiwiApiError {
+                        correlation_id,
+                        ..error
+                    },
+                }
+            }
+            ("Unauthorized", None) => {
+                Self::unauthorized(p.http_status?, p.field("body")?, correlation_id)
+            }
+            ("AuthorizationCallbackError", None) => {
+                AuthorizationCallbackError { correlation_id }.into_error(p.source_field("source")?)
+            }
+            ("InvalidAccountAlias", None) => Self::InvalidAccountAlias {
+                alias: p.field("alias")?,
+            },
+            ("ReadOnlyMode", None) => Self::ReadOnlyMode {
+                until: p.field("until")?,
+                reason: p.field("reason")?,
+            },
+            ("AuditFailed", None) => Self::AuditFailed {
+                source: p.source_field("source")?,
+            },
+            ("PolicyViolation", Some(sub_kind)) => Self::PolicyViolation {
+                source: policy_violation(sub_kind, p)?,
+            },
+            ("MfaDenied", None) => Self::MfaDenied {
+                reason: p.field("reason")?,
+            },
+            ("MfaTimeout", None) => Self::MfaTimeout,
+            ("MfaError", Some("Timeout")) => Self::MfaError {
+                source: mfa::MfaError::Timeout,
+            },
+            ("MfaError", Some("ProviderError")) => Self::MfaError {
+                source: mfa::MfaError::ProviderError {
+                    source: p.source_field("source")?,
+                },
+            },
+            ("CurrencyMismatch", None) => Self::CurrencyMismatch {
+                source: MismatchedCurrencies {
+                    left: p.field("left")?,
+                    right: p.field("right")?,
+                },
+            },
+            ("InsufficientFunds", None) => Self::InsufficientFunds {
+                available: p.field("available")?,
+                required: p.field("required")?,
+            },
+            ("LimitExceeded", None) => Self::LimitExceeded {
+                remaining: p.field("remaining")?,
+            },
+            ("AccountCurrencyMismatch", None) => Self::AccountCurrencyMismatch {
+                alias: p.field("alias")?,
+                currency: p.field("currency")?,
+            },
+            ("WebhookLost", None) => Self::WebhookLost {
+                old_url: p.field("oldUrl")?,
+                source: p.rebuild_cause()?,
+            },
+            ("InvalidWebhookKey", None) => Self::InvalidWebhookKey {
+                source: p.source_field("source")?,
+            },
+            ("Offline", None) => Self::Offline {
+                endpoint: p.endpoint.clone()?,
+            },
+            ("ContractViolation", None) => Self::ContractViolation {
+                endpoint: p.endpoint.clone()?,
+                details: p.field("details")?,
+                correlation_id,
+            },
+            ("UnexpectedApiResponse", Some(sub_kind)) => Self::UnexpectedApiResponse {
+                // Only versioned APIs are reported, see `versions::versioned_error`.
+                api: match p.field::<String>("api")?.as_str() {
+                    "person-profile" => "person-profile",
+                    "payment-history" => "payment-history",
+                    _ => return None,
+                },
+                version: p.field("version")?,
+                source: transport::Error::from_portable(sub_kind, p)?,
+            },
+            ("PossibleDuplicate", None) => Self::PossibleDuplicate {
+                existing_txn_id: p.field("existingTxnId")?,
+            },
+            ("NoCrossRate", None) => Self::NoCrossRate {
+                from: p.field("from")?,
+                to: p.field("to")?,
+            },
+            ("RateMoved", None) => Self::RateMoved {
+                planned: p.field("planned")?,
+                current: p.field("current")?,
+            },
+            ("InvalidConfirmation", None) => Self::InvalidConfirmation,
+            ("ConfirmationExpired", None) => Self::ConfirmationExpired {
+                expired_at: p.field("expiredAt")?,
+            },
+            ("StateStoreError", None) => Self::StateStoreError {
+                source: p.source_field("source")?,
+            },
+            ("UnknownOperation", None) => Self::UnknownOperation { id: p.field("id")? },
+            ("InvalidStatementPeriod", None) => Self::InvalidStatementPeriod {
+                from: p.field("from")?,
+                till: p.field("till")?,
+            },
+            ("InvalidDateRange", None) => Self::InvalidDateRange {
+                start: p.field("start")?,
+                end: p.field("end")?,
+            },
+            ("DateRangeTooWide", None) => Self::DateRangeTooWide {
+                start: p.field("start")?,
+                end: p.field("end")?,
+                max_days: p.field("maxDays")?,
+            },
+            ("ReceiptDownloadFailed", None) => Self::ReceiptDownloadFailed {
+                txn_id: p.field("txnId")?,
+                source: p.rebuild_cause()?,
+            },
+            ("IncompleteTransfer", None) => Self::IncompleteTransfer {
+                source: MissingBankFields(
+                    p.field::<Vec<String>>("missing")?
+                        .iter()
+                        .map(|name| {
+                            BankTransferFields::MANDATORY_FIELDS
+                                .iter()
+                                .find(|known| **known == name.as_str())
+                                .copied()
+                        })
+                        .collect::<Option<_>>()?,
+                ),
+            },
+            ("NoProviderMapping", None) => Self::NoProviderMapping {
+                route: p.field("route")?,
+            },
+            ("UnknownMobileProvider", None) => Self::UnknownMobileProvider {
+                reason: p.field("reason")?,
+            },
+            ("UnknownCardProvider", None) => Self::UnknownCardProvider {
+                reason: p.field("reason")?,
+            },
+            ("TransactionNotFound", None) => Self::TransactionNotFound {
+                txn_id: p.field("txnId")?,
+            },
+            ("StatementNotReady", None) => Self::StatementNotReady,
+            ("DocumentNotReady", None) => Self::DocumentNotReady {
+                txn_id: p.field("txnId")?,
+            },
+            ("NoBankDocument", None) => Self::NoBankDocument {
+                txn_id: p.field("txnId")?,
+            },
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, penny::Currency, std::str::FromStr};
+
+    fn rub(amount: u32) -> Money {
+        Money::new(amount.into(), Currency::RUB)
+    }
+
+    fn remote(message: &str) -> StdError {
+        Box::new(RemoteSource(message.to_string()))
+    }
+
+    fn date(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, day, 12, 0, 0).unwrap()
+    }
+
+    /// One error of every variant, with sources of every kind for nested ones.
+    fn errors() -> Vec<Error> {
+        use policy::PolicyViolation;
+
+        let msk = FixedOffset::east_opt(3 * 3600).unwrap();
+        let qiwi_error = QiwiApiError {
+            description: Some("Payment blocked".into()),
+            ..QiwiApiError::new("payment.blocked")
+        };
+        vec![
+            Error::TransportError {
+                source: transport::Error::from_network_error(HttpStatusError::new(503, "busy")),
+            },
+            Error::TransportError {
+                source: transport::Error::from_network_error(RemoteSource("reset".into())),
+            },
+            Error::TransportError {
+                source: transport::Error::from_parse_error(RemoteSource("eof".into())),
+            },
+            Error::TransportError {
+                source: transport::Error::Offline {
+                    endpoint: "sinap/crossRates".into(),
+                },
+            },
+            Error::TransportError {
+                source: transport::Error::ContractViolation {
+                    endpoint: "sinap/crossRates".into(),
+                    details: "missing rate".into(),
+                    correlation_id: Some("0a1b2c".into()),
+                },
+            },
+            Error::QiwiError { error: qiwi_error },
+            Error::unauthorized(
+                401,
+                json!({ "errorCode": "internal.invalid.token" }).to_string(),
+                Some("0a1b2c".into()),
+            ),
+            Error::unauthorized(403, "Forbidden".into(), None),
+            AuthorizationCallbackError {
+                correlation_id: Some("0a1b2c".to_string()),
+            }
+            .into_error(remote("token expired")),
+            Error::InvalidAccountAlias {
+                alias: AccountAlias::QW_WALLET_USD,
+            },
+            Error::ReadOnlyMode {
+                until: date(31),
+                reason: "audit".into(),
+            },
+            Error::AuditFailed {
+                source: remote("disk full"),
+            },
+            Error::PolicyViolation {
+                source: PolicyViolation::TransferLimitExceeded {
+                    amount: rub(200),
+                    limit: rub(100),
+                },
+            },
+            Error::PolicyViolation {
+                source: PolicyViolation::DailyLimitExceeded {
+                    amount: rub(50),
+                    spent: rub(80),
+                    limit: rub(100),
+                },
+            },
+            Error::PolicyViolation {
+                source: PolicyViolation::UnsupportedCurrency {
+                    currency: Currency::USD.into(),
+                    limit_currency: Currency::RUB.into(),
+                },
+            },
+            Error::PolicyViolation {
+                source: PolicyViolation::DestinationNotAllowed {
+                    account: "+79035550101".into(),
+                },
+            },
+            Error::PolicyViolation {
+                source: PolicyViolation::StoreError {
+                    source: remote("locked"),
+                },
+            },
+            Error::MfaDenied {
+                reason: Some("unknown device".into()),
+            },
+            Error::MfaDenied { reason: None },
+            Error::MfaTimeout,
+            Error::MfaError {
+                source: mfa::MfaError::Timeout,
+            },
+            Error::MfaError {
+                source: mfa::MfaError::ProviderError {
+                    source: remote("push failed"),
+                },
+            },
+            Error::CurrencyMismatch {
+                source: MismatchedCurrencies {
+                    left: Currency::RUB.into(),
+                    right: Currency::EUR.into(),
+                },
+            },
+            Error::InsufficientFunds {
+                available: rub(10),
+                required: rub(100),
+            },
+            Error::LimitExceeded { remaining: rub(10) },
+            Error::AccountCurrencyMismatch {
+                alias: AccountAlias::QW_WALLET_EUR,
+                currency: Currency::KZT.into(),
+            },
+            Error::WebhookLost {
+                old_url: "https://example.com/hook".into(),
+                source: Box::new(Error::Offline {
+                    endpoint: "payment-notifier/v1/hooks".into(),
+                }),
+            },
+            Error::InvalidWebhookKey {
+                source: remote("bad base64"),
+            },
+            Error::Offline {
+                endpoint: "sinap/crossRates".into(),
+            },
+            Error::ContractViolation {
+                endpoint: "sinap/crossRates".into(),
+                details: "missing rate".into(),
+                correlation_id: Some("0a1b2c".into()),
+            },
+            Error::UnexpectedApiResponse {
+                api: "payment-history",
+                version: "v2".into(),
+                source: transport::Error::from_parse_error(RemoteSource("eof".into())),
+            },
+            Error::PossibleDuplicate {
+                existing_txn_id: 11_181_101_215,
+            },
+            Error::NoCrossRate {
+                from: Currency::USD.into(),
+                to: Currency::KZT.into(),
+            },
+            Error::RateMoved {
+                planned: BigDecimal::from_str("72.15").unwrap(),
+                current: BigDecimal::from_str("73.40").unwrap(),
+            },
+            Error::InvalidConfirmation,
+            Error::ConfirmationExpired {
+                expired_at: date(1),
+            },
+            Error::StateStoreError {
+                source: remote("permission denied"),
+            },
+            Error::UnknownOperation { id: "op-1".into() },
+            Error::InvalidStatementPeriod {
+                from: NaiveDate::from_ymd_opt(2020, 1, 31).unwrap(),
+                till: NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+            },
+            Error::InvalidDateRange {
+                start: Some(msk.with_ymd_and_hms(2020, 1, 31, 0, 0, 0).unwrap()),
+                end: None,
+            },
+            Error::DateRangeTooWide {
+                start: date(1),
+                end: date(31),
+                max_days: 7,
+            },
+            Error::ReceiptDownloadFailed {
+                txn_id: 11_181_101_215,
+                source: Box::new(Error::DocumentNotReady {
+                    txn_id: 11_181_101_215,
+                }),
+            },
+            Error::IncompleteTransfer {
+                source: MissingBankFields(vec!["mfo", "lname"]),
+            },
+            Error::NoProviderMapping {
+                route: TransferRoute::Card(CardNetwork::Mir),
+            },
+            Error::UnknownMobileProvider {
+                reason: "no operator".into(),
+            },
+            Error::UnknownCardProvider {
+                reason: "unknown BIN".into(),
+            },
+            Error::TransactionNotFound {
+                txn_id: 11_181_101_215,
+            },
+            Error::StatementNotReady,
+            Error::DocumentNotReady {
+                txn_id: 11_181_101_215,
+            },
+            Error::NoBankDocument {
+                txn_id: 11_181_101_215,
+            },
+        ]
+    }
+
+    #[test]
+    fn every_variant_survives_round_trip() {
+        for error in errors() {
+            let portable = error.to_portable();
+            let json = serde_json::to_string(&portable).unwrap();
+            let received = serde_json::from_str::<PortableError>(&json).unwrap();
+            assert_eq!(received, portable, "{}", json);
+
+            let rebuilt = Error::from_portable(received);
+            if let Error::Remote { .. } = rebuilt {
+                panic!("{} was not rebuilt from {}", portable.kind, json);
+            }
+            assert_eq!(rebuilt.to_portable(), portable, "{}", json);
+            assert_eq!(rebuilt.to_string(), error.to_string());
+            assert_eq!(rebuilt.is_retryable(), error.is_retryable());
+            assert_eq!(rebuilt.correlation_id(), error.correlation_id());
+        }
+    }
+
+    #[test]
+    fn kinds_name_variants_and_sources() {
+        let kinds = errors()
+            .iter()
+            .map(|error| error.to_portable().kind)
+            .collect::<Vec<_>>();
+        for kind in &[
+            "TransportError.NetworkError",
+            "TransportError.ParseError",
+            "PolicyViolation.StoreError",
+            "MfaError.ProviderError",
+            "UnexpectedApiResponse.ParseError",
+            "AccountCurrencyMismatch",
+            "NoProviderMapping",
+        ] {
+            assert!(
+                kinds.iter().any(|k| k == kind),
+                "no {} in {:?}",
+                kind,
+                kinds
+            );
+        }
+
+        let portable = Error::AccountCurrencyMismatch {
+            alias: AccountAlias::QW_WALLET_EUR,
+            currency: Currency::KZT.into(),
+        }
+        .to_portable();
+        assert_eq!(
+            portable.details,
+            vec![
+                ("alias".to_string(), json!("qw_wallet_eur")),
+                ("currency".to_string(), json!("398")),
+            ]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>()
+        );
+    }
+
+    #[test]
+    fn unknown_kinds_stay_remote() {
+        let mut portable = Error::MfaTimeout.to_portable();
+        portable.kind = "SomethingNew".into();
+        let error = Error::from_portable(portable.clone());
+        assert!(matches!(error, Error::Remote { .. }));
+        assert_eq!(error.to_portable(), portable);
+
+        // Known kinds missing their details cannot be rebuilt either.
+        let mut portable = Error::NoBankDocument { txn_id: 1 }.to_portable();
+        portable.details.clear();
+        assert!(matches!(
+            Error::from_portable(portable),
+            Error::Remote { .. }
+        ));
+    }
+}
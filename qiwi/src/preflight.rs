@@ -0,0 +1,306 @@
+use {
+    crate::*,
+    std::time::{Duration, Instant},
+};
+
+/// How long fetched balances and limits are trusted.
+const PREFLIGHT_CACHE_TTL: Duration = Duration::from_secs(5);
+
+const P2P_LIMIT_TYPE: &str = "PAYMENTS_P2P";
+
+#[derive(Clone, Debug)]
+struct Snapshot {
+    fetched_at: Instant,
+    balances: Vec<(AccountAlias, Money)>,
+    p2p_rest: Vec<Money>,
+}
+
+impl Snapshot {
+    fn balance(&self, alias: &AccountAlias) -> Option<&Money> {
+        self.balances
+            .iter()
+            .find(|(a, _)| a == alias)
+            .map(|(_, balance)| balance)
+    }
+
+    fn p2p_rest(&self, currency: &QiwiCurrency) -> Option<&Money> {
+        self.p2p_rest.iter().find(|rest| rest.currency == *currency)
+    }
+
+    fn balance_mut(&mut self, alias: &AccountAlias) -> Option<&mut Money> {
+        self.balances
+            .iter_mut()
+            .find(|(a, _)| a == alias)
+            .map(|(_, balance)| balance)
+    }
+
+    fn p2p_rest_mut(&mut self, currency: &QiwiCurrency) -> Option<&mut Money> {
+        self.p2p_rest
+            .iter_mut()
+            .find(|rest| rest.currency == *currency)
+    }
+}
+
+/// Balances and limits shared by consecutive payments.
+#[derive(Debug, Default)]
+pub(crate) struct PreflightCache {
+    snapshot: Mutex<Option<Snapshot>>,
+}
+
+fn is_p2p(provider: ProviderId) -> bool {
+    P2P_PROVIDERS
+        .iter()
+        .any(|&id| ProviderId::from(id) == provider)
+}
+
+/// Balance debited by the payment, unless the debited amount is not known in advance as for conversions.
+fn source_alias(request: &PaymentRequest) -> Option<AccountAlias> {
+    if request.payment_method.account_id != request.sum.currency {
+        return None;
+    }
+
+    AccountAlias::from_currency(request.payment_method.account_id.currency())
+}
+
+impl Client {
+    async fn fetch_preflight_snapshot(&self) -> QiwiResult<Snapshot> {
+        let accounts = self.caller.call::<_, AccountBalancesWrapper>(
+            format!("funding-sources/v2/persons/{}/accounts", self.user),
+            Method::GET,
+            &Default::default(),
+            None,
+        );
+        let limits = self.caller.call::<_, LimitRestsWrapper>(
+            format!("qw-limits/v1/persons/{}/actual-limits", self.user),
+            Method::GET,
+            &QueryParams::new().with("types[0]", P2P_LIMIT_TYPE),
+            None,
+        );
+        let (accounts, limits) = tokio::join!(accounts, limits);
+
+        let balances = accounts?
+            .into_result()?
+            .accounts
+            .into_iter()
+            .filter_map(|account| Some((account.alias, account.balance?)))
+            .collect();
+        let p2p_rest = limits?
+            .into_result()?
+            .limits
+            .into_iter()
+            .flat_map(|(_, limits)| limits)
+            .filter(|limit| limit.limit_type == P2P_LIMIT_TYPE)
+            .map(|limit| Money {
+                amount: limit.rest,
+                currency: limit.currency,
+            })
+            .collect();
+
+        Ok(Snapshot {
+            fetched_at: self.clock.now(),
+            balances,
+            p2p_rest,
+        })
+    }
+
+    /// Fails if the payment is bound to be rejected for lack of funds or limit.
+    ///
+    /// Only the payment sum is checked, commission is not known at this point.
+    pub(crate) async fn preflight(&self, request: &PaymentRequest) -> QiwiResult<()> {
+        let cache = match &self.preflight {
+            Some(cache) => cache,
+            None => return Ok(()),
+        };
+
+        let now = self.clock.now();
+        let cached = cache.snapshot.lock().unwrap().clone().filter(|snapshot| {
+            now.saturating_duration_since(snapshot.fetched_at) < PREFLIGHT_CACHE_TTL
+        });
+        let snapshot = match cached {
+            Some(snapshot) => snapshot,
+            None => {
+                let snapshot = self.fetch_preflight_snapshot().await?;
+                *cache.snapshot.lock().unwrap() = Some(snapshot.clone());
+                snapshot
+            }
+        };
+
+        if let Some(available) = source_alias(request).and_then(|alias| snapshot.balance(&alias)) {
+            ensure!(
+                available.amount >= request.sum.amount,
+                InsufficientFunds {
+                    available: available.clone(),
+                    required: request.sum.clone(),
+                }
+            );
+        }
+
+        if is_p2p(request.provider) {
+            if let Some(remaining) = snapshot.p2p_rest(&request.sum.currency) {
+                ensure!(
+                    remaining.amount >= request.sum.amount,
+                    LimitExceeded {
+                        remaining: remaining.clone(),
+                    }
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Accounts for a sent payment in cached balances and limits.
+    pub(crate) fn note_preflight_payment(&self, request: &PaymentRequest) {
+        let cache = match &self.preflight {
+            Some(cache) => cache,
+            None => return,
+        };
+
+        let mut snapshot = cache.snapshot.lock().unwrap();
+        let snapshot = match snapshot.as_mut() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+
+        if let Some(balance) = source_alias(request).and_then(|alias| snapshot.balance_mut(&alias))
+        {
+            balance.amount -= &request.sum.amount;
+        }
+        if is_p2p(request.provider) {
+            if let Some(rest) = snapshot.p2p_rest_mut(&request.sum.currency) {
+                rest.amount -= &request.sum.amount;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, serde_json::json};
+
+    fn phone() -> PhoneNumber {
+        "+79991234567".parse().unwrap()
+    }
+
+    fn accounts_endpoint() -> String {
+        format!(
+            "funding-sources/v2/persons/{}/accounts",
+            QiwiUser::from(phone())
+        )
+    }
+
+    fn limits_endpoint() -> String {
+        format!(
+            "qw-limits/v1/persons/{}/actual-limits",
+            QiwiUser::from(phone())
+        )
+    }
+
+    fn payments_endpoint() -> String {
+        format!("sinap/api/v2/terms/{}/payments", ProviderId::QIWI)
+    }
+
+    fn client(p2p_rest: &str) -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let transport = Arc::new(
+            OfflineTransport::new()
+                .with(Method::GET, accounts_endpoint(), &fixtures::accounts())
+                .with(
+                    Method::GET,
+                    limits_endpoint(),
+                    &json!({
+                        "limits": {
+                            "RU": [{ "type": P2P_LIMIT_TYPE, "currency": "643", "rest": p2p_rest }],
+                        },
+                    }),
+                )
+                .with(
+                    Method::POST,
+                    payments_endpoint(),
+                    &json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } }),
+                ),
+        );
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let client = Client::builder(phone(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .preflight_checks(true)
+            .build();
+        (client, transport, clock)
+    }
+
+    fn transfer(client: &Client, amount: u32) -> TransferRequest {
+        client.transfer_request(
+            BigDecimal::from(amount),
+            TransferDirection::Qiwi {
+                to_phone: "+79035550101".parse().unwrap(),
+                to_currency: penny::Currency::RUB,
+            },
+            "",
+        )
+    }
+
+    fn count(transport: &OfflineTransport, method: Method, endpoint: &str) -> usize {
+        transport
+            .requests()
+            .into_iter()
+            .filter(|request| request.0 == method && request.1 == endpoint)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn batch_fetches_balances_and_limits_once() {
+        let (client, transport, _) = client("10000");
+        for _ in 0..50 {
+            client.transfer(&transfer(&client, 10)).await.unwrap();
+        }
+
+        assert_eq!(count(&transport, Method::POST, &payments_endpoint()), 50);
+        assert_eq!(count(&transport, Method::GET, &accounts_endpoint()), 1);
+        assert_eq!(count(&transport, Method::GET, &limits_endpoint()), 1);
+        assert_eq!(transport.requests().len(), 52);
+    }
+
+    #[tokio::test]
+    async fn snapshot_expires() {
+        let (client, transport, clock) = client("10000");
+        client.transfer(&transfer(&client, 10)).await.unwrap();
+        clock.advance(PREFLIGHT_CACHE_TTL);
+        client.transfer(&transfer(&client, 10)).await.unwrap();
+
+        assert_eq!(count(&transport, Method::GET, &accounts_endpoint()), 2);
+        assert_eq!(count(&transport, Method::GET, &limits_endpoint()), 2);
+    }
+
+    #[tokio::test]
+    async fn sent_payments_reduce_cached_limit() {
+        let (client, transport, _) = client("300");
+        for _ in 0..30 {
+            client.transfer(&transfer(&client, 10)).await.unwrap();
+        }
+
+        match client.transfer(&transfer(&client, 10)).await {
+            Err(Error::LimitExceeded { remaining }) => {
+                assert_eq!(remaining.amount, BigDecimal::from(0))
+            }
+            other => panic!("expected LimitExceeded, got {:?}", other),
+        }
+        assert_eq!(count(&transport, Method::POST, &payments_endpoint()), 30);
+        assert_eq!(count(&transport, Method::GET, &limits_endpoint()), 1);
+    }
+
+    #[tokio::test]
+    async fn insufficient_funds_fail_locally() {
+        let (client, transport, _) = client("100000");
+        match client.transfer(&transfer(&client, 20_000)).await {
+            Err(Error::InsufficientFunds {
+                available,
+                required,
+            }) => {
+                assert_eq!(available.amount, "12345.67".parse::<BigDecimal>().unwrap());
+                assert_eq!(required.amount, BigDecimal::from(20_000));
+            }
+            other => panic!("expected InsufficientFunds, got {:?}", other),
+        }
+        assert_eq!(count(&transport, Method::POST, &payments_endpoint()), 0);
+    }
+}
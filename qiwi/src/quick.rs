@@ -0,0 +1,178 @@
+//! One-shot calls for small scripts.
+//!
+//! Every function builds a client with [`Client::builder`] and [`QUICK_TIMEOUT`], makes the
+//! call and drops the client. Build a [`Client`] to make several calls or to configure it.
+//!
+//! ```no_run
+//! # async fn run() -> qiwi::QiwiResult<()> {
+//! let phone = "+79991234567".parse().unwrap();
+//! for (account, balance) in qiwi::quick::balance(phone, "token").await? {
+//!     println!("{}: {}", account, balance);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use {crate::*, std::time::Duration};
+
+/// Timeout of every request made by the functions of this module.
+pub const QUICK_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn client<T: Display>(phone: PhoneNumber, token: T) -> Client {
+    Client::builder(phone, token)
+        .request_timeout(QUICK_TIMEOUT)
+        .build()
+}
+
+/// Balances by account alias, e.g. `qw_wallet_rub`. Accounts without a balance are skipped.
+pub async fn balance<T: Display>(
+    phone: PhoneNumber,
+    token: T,
+) -> QiwiResult<Vec<(String, BigDecimal)>> {
+    balance_of(&client(phone, token)).await
+}
+
+async fn balance_of(client: &Client) -> QiwiResult<Vec<(String, BigDecimal)>> {
+    Ok(client
+        .accounts()
+        .await?
+        .into_iter()
+        .filter_map(|account| Some((account.alias.to_string(), account.balance?.amount)))
+        .collect())
+}
+
+/// Transfers `amount_rub` to the QIWI wallet at `to` and returns the transaction id.
+#[cfg(feature = "payments")]
+pub async fn send<T: Display>(
+    phone: PhoneNumber,
+    token: T,
+    to: PhoneNumber,
+    amount_rub: BigDecimal,
+) -> QiwiResult<String> {
+    send_from(&client(phone, token), to, amount_rub).await
+}
+
+#[cfg(feature = "payments")]
+async fn send_from(client: &Client, to: PhoneNumber, amount_rub: BigDecimal) -> QiwiResult<String> {
+    let req = client.transfer_request(
+        amount_rub,
+        TransferDirection::Qiwi {
+            to_phone: to,
+            to_currency: penny::Currency::RUB,
+        },
+        "",
+    );
+    Ok(client.transfer(&req).await?.transaction.id)
+}
+
+/// Up to `n` latest payments, newest first.
+#[cfg(feature = "history")]
+pub async fn last_payments<T: Display>(
+    phone: PhoneNumber,
+    token: T,
+    n: usize,
+) -> QiwiResult<Vec<PaymentHistoryEntry>> {
+    last_payments_of(&client(phone, token), n).await
+}
+
+#[cfg(feature = "history")]
+async fn last_payments_of(client: &Client, n: usize) -> QiwiResult<Vec<PaymentHistoryEntry>> {
+    client
+        .payment_history()
+        .take(n)
+        .collect::<QiwiResult<Vec<_>>>()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        qiwi_mock_server::{Config, MockServer},
+        std::str::FromStr,
+    };
+
+    const PHONE: &str = "+79991234567";
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    fn server() -> MockServer {
+        MockServer::start(
+            "127.0.0.1:0".parse().unwrap(),
+            Config::new(PHONE.parse().unwrap(), TOKEN),
+        )
+        .unwrap()
+    }
+
+    fn client(server: &MockServer) -> Client {
+        Client::builder(PHONE.parse().unwrap(), TOKEN)
+            .base_url(server.url())
+            .request_timeout(QUICK_TIMEOUT)
+            .build()
+    }
+
+    fn amount(s: &str) -> BigDecimal {
+        BigDecimal::from_str(s).unwrap()
+    }
+
+    #[tokio::test]
+    async fn balance_of_every_account() {
+        let server = server();
+
+        assert_eq!(
+            balance_of(&client(&server)).await.unwrap(),
+            vec![
+                ("qw_wallet_rub".to_string(), amount("12345.67")),
+                ("qw_wallet_usd".to_string(), amount("150.00")),
+                ("qw_wallet_eur".to_string(), amount("20.50")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "payments")]
+    #[tokio::test]
+    async fn send_and_find_in_history() {
+        let server = server();
+        let client = client(&server);
+
+        let txn_id = send_from(&client, "+79035550101".parse().unwrap(), amount("100"))
+            .await
+            .unwrap();
+        assert_eq!(txn_id, "20000000001");
+        let payments = server.report()["payments"].as_array().cloned().unwrap();
+        assert_eq!(payments.len(), 1);
+        assert_eq!(
+            amount(payments[0]["sum"]["amount"].as_str().unwrap()),
+            amount("100")
+        );
+        assert_eq!(payments[0]["sum"]["currency"], json!("643"));
+        assert!(payments[0]["fields"]["account"]
+            .as_str()
+            .unwrap()
+            .ends_with("9035550101"));
+
+        let last = last_payments_of(&client, 1).await.unwrap();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].txn_id.to_string(), txn_id);
+        assert_eq!(last[0].sum.amount, amount("100"));
+    }
+
+    #[cfg(feature = "history")]
+    #[tokio::test]
+    async fn last_payments_across_pages() {
+        let server = server();
+        let expected = fixtures::history_entries(fixtures::TYPICAL_HISTORY_LEN)
+            .iter()
+            .take(60)
+            .map(|entry| entry["txnId"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+
+        let last = last_payments_of(&client(&server), 60).await.unwrap();
+        assert_eq!(
+            last.iter().map(|entry| entry.txn_id).collect::<Vec<_>>(),
+            expected
+        );
+        // Fewer payments than asked for are all returned.
+        let all = last_payments_of(&client(&server), 1000).await.unwrap();
+        assert_eq!(all.len(), fixtures::TYPICAL_HISTORY_LEN);
+    }
+}
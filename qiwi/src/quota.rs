@@ -0,0 +1,378 @@
+use {
+    crate::clock::Clock,
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        fmt,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Group of endpoints sharing a quota on QIWI side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum EndpointCategory {
+    /// `person-profile`
+    Profile,
+    /// `payment-history`
+    History,
+    /// `sinap`: providers, commissions and payments.
+    Payments,
+    /// `funding-sources`
+    Funding,
+    /// `qw-limits`
+    Limits,
+    /// `payment-notifier`
+    Webhooks,
+    Other,
+}
+
+impl EndpointCategory {
+    const ALL: [Self; 7] = [
+        Self::Profile,
+        Self::History,
+        Self::Payments,
+        Self::Funding,
+        Self::Limits,
+        Self::Webhooks,
+        Self::Other,
+    ];
+
+    pub fn of(endpoint: &str) -> Self {
+        match endpoint.split('/').next().unwrap_or_default() {
+            "person-profile" => Self::Profile,
+            "payment-history" => Self::History,
+            "sinap" => Self::Payments,
+            "funding-sources" => Self::Funding,
+            "qw-limits" => Self::Limits,
+            "payment-notifier" => Self::Webhooks,
+            _ => Self::Other,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Number of requests sent in the rolling windows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct WindowUsage {
+    pub last_minute: u64,
+    pub last_hour: u64,
+}
+
+impl std::ops::AddAssign for WindowUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.last_minute += other.last_minute;
+        self.last_hour += other.last_hour;
+    }
+}
+
+/// Requests sent by a client, see [`Client::quota_usage`](crate::Client::quota_usage).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct QuotaUsage {
+    pub total: WindowUsage,
+    /// Categories without requests are omitted.
+    pub by_category: BTreeMap<EndpointCategory, WindowUsage>,
+    /// Requests that failed without a response from QIWI or with an HTTP error.
+    pub errors: WindowUsage,
+}
+
+/// Ring of coarse time buckets, each packed into one atomic as `bucket number << 24 | count`.
+///
+/// Counts from a bucket are dropped once the ring wraps around to it.
+struct Window {
+    buckets: Vec<AtomicU64>,
+    width: u64,
+}
+
+const COUNT_BITS: u32 = 24;
+const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+
+impl Window {
+    fn new(len: usize, width: u64) -> Self {
+        Self {
+            buckets: (0..len).map(|_| AtomicU64::new(0)).collect(),
+            width,
+        }
+    }
+
+    fn record(&self, now: u64) {
+        let n = now / self.width;
+        let slot = &self.buckets[(n % self.buckets.len() as u64) as usize];
+        let mut current = slot.load(Ordering::Relaxed);
+        loop {
+            let new = if current >> COUNT_BITS == n {
+                (current + 1).min(n << COUNT_BITS | COUNT_MASK)
+            } else {
+                n << COUNT_BITS | 1
+            };
+            match slot.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn sum(&self, now: u64) -> u64 {
+        let n = now / self.width;
+        let oldest = (n + 1).saturating_sub(self.buckets.len() as u64);
+        self.buckets
+            .iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .filter(|v| (oldest..=n).contains(&(v >> COUNT_BITS)))
+            .map(|v| v & COUNT_MASK)
+            .sum()
+    }
+}
+
+/// Six 10 second buckets.
+const MINUTE_BUCKETS: (usize, u64) = (6, 10);
+/// Sixty 1 minute buckets.
+const HOUR_BUCKETS: (usize, u64) = (60, 60);
+
+/// Share of the soft quota after which requests start being delayed.
+const SOFT_QUOTA_START: f64 = 0.5;
+
+struct CategoryCounters {
+    minute: Window,
+    hour: Window,
+}
+
+impl CategoryCounters {
+    fn new() -> Self {
+        Self {
+            minute: Window::new(MINUTE_BUCKETS.0, MINUTE_BUCKETS.1),
+            hour: Window::new(HOUR_BUCKETS.0, HOUR_BUCKETS.1),
+        }
+    }
+
+    fn record(&self, now: u64) {
+        self.minute.record(now);
+        self.hour.record(now);
+    }
+
+    fn usage(&self, now: u64) -> WindowUsage {
+        WindowUsage {
+            last_minute: self.minute.sum(now),
+            last_hour: self.hour.sum(now),
+        }
+    }
+}
+
+/// Counts requests per [`EndpointCategory`] in rolling minute and hour windows.
+pub(crate) struct QuotaTracker {
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    categories: Vec<CategoryCounters>,
+    errors: CategoryCounters,
+    soft_per_minute: Option<u32>,
+}
+
+impl fmt::Debug for QuotaTracker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuotaTracker")
+            .field("usage", &self.usage())
+            .field("soft_per_minute", &self.soft_per_minute)
+            .finish()
+    }
+}
+
+impl QuotaTracker {
+    pub fn new(soft_per_minute: Option<u32>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            started: clock.now(),
+            clock,
+            categories: EndpointCategory::ALL
+                .iter()
+                .map(|_| CategoryCounters::new())
+                .collect(),
+            errors: CategoryCounters::new(),
+            soft_per_minute,
+        }
+    }
+
+    pub fn soft_limit(&self) -> Option<u32> {
+        self.soft_per_minute
+    }
+
+    /// Seconds since the tracker was created.
+    fn now(&self) -> u64 {
+        self.clock
+            .now()
+            .saturating_duration_since(self.started)
+            .as_secs()
+    }
+
+    pub fn record(&self, category: EndpointCategory) {
+        self.record_at(category, self.now())
+    }
+
+    fn record_at(&self, category: EndpointCategory, now: u64) {
+        self.categories[category.index()].record(now);
+    }
+
+    /// Marks one of the recorded requests as failed.
+    pub fn record_error(&self) {
+        self.errors.record(self.now());
+    }
+
+    pub fn usage(&self) -> QuotaUsage {
+        self.usage_at(self.now())
+    }
+
+    fn usage_at(&self, now: u64) -> QuotaUsage {
+        let mut usage = QuotaUsage {
+            errors: self.errors.usage(now),
+            ..Default::default()
+        };
+        for (&category, counters) in EndpointCategory::ALL.iter().zip(&self.categories) {
+            let window = counters.usage(now);
+            if window.last_hour > 0 || window.last_minute > 0 {
+                usage.total += window;
+                usage.by_category.insert(category, window);
+            }
+        }
+        usage
+    }
+
+    /// How long to wait before sending the next request to stay under the soft quota.
+    pub fn delay(&self) -> Option<Duration> {
+        self.delay_at(self.now())
+    }
+
+    fn delay_at(&self, now: u64) -> Option<Duration> {
+        let limit = self.soft_per_minute?;
+        let used = self.usage_at(now).total.last_minute;
+        soft_quota_delay(used, limit)
+    }
+
+    /// Waits for `delay` returned by [`QuotaTracker::delay`].
+    pub async fn sleep(&self, delay: Duration) {
+        self.clock.sleep_until(self.clock.now() + delay).await
+    }
+}
+
+/// No delay under half of the quota, then growing quadratically so that at the quota requests
+/// are spaced by `1 minute / limit`, the rate the quota allows.
+fn soft_quota_delay(used: u64, limit: u32) -> Option<Duration> {
+    if limit == 0 {
+        return Some(Duration::from_secs(MINUTE_BUCKETS.1));
+    }
+    let share = used as f64 / f64::from(limit);
+    if share < SOFT_QUOTA_START {
+        return None;
+    }
+    let pressure = (share - SOFT_QUOTA_START) / (1.0 - SOFT_QUOTA_START);
+    let interval = 60.0 / f64::from(limit);
+    Some(Duration::from_secs_f64(interval * pressure * pressure))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, chrono::Utc};
+
+    #[test]
+    fn window_drops_expired_buckets() {
+        let window = Window::new(MINUTE_BUCKETS.0, MINUTE_BUCKETS.1);
+        window.record(0);
+        window.record(9);
+        window.record(10);
+        window.record(59);
+        assert_eq!(window.sum(59), 4);
+        // The first bucket leaves the window, the rest stay until their own turn.
+        assert_eq!(window.sum(60), 2);
+        assert_eq!(window.sum(69), 2);
+        assert_eq!(window.sum(70), 1);
+        assert_eq!(window.sum(120), 0);
+    }
+
+    #[test]
+    fn window_reuses_wrapped_bucket() {
+        let window = Window::new(MINUTE_BUCKETS.0, MINUTE_BUCKETS.1);
+        window.record(0);
+        window.record(5);
+        // Same slot of the ring, one minute later.
+        window.record(61);
+        assert_eq!(window.sum(61), 1);
+    }
+
+    #[test]
+    fn tracker_rolls_over_with_clock() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let tracker = QuotaTracker::new(None, clock.clone());
+        for _ in 0..3 {
+            tracker.record(EndpointCategory::History);
+        }
+        tracker.record(EndpointCategory::Profile);
+        tracker.record_error();
+
+        let usage = tracker.usage();
+        assert_eq!(
+            usage.total,
+            WindowUsage {
+                last_minute: 4,
+                last_hour: 4
+            }
+        );
+        assert_eq!(usage.by_category[&EndpointCategory::History].last_minute, 3);
+        assert_eq!(usage.errors.last_minute, 1);
+
+        clock.advance(Duration::from_secs(61));
+        let usage = tracker.usage();
+        assert_eq!(
+            usage.total,
+            WindowUsage {
+                last_minute: 0,
+                last_hour: 4
+            }
+        );
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(tracker.usage(), QuotaUsage::default());
+    }
+
+    #[test]
+    fn soft_quota_delay_curve() {
+        let millis = |used| {
+            soft_quota_delay(used, 100).map(|delay| (delay.as_secs_f64() * 1000.0).round() as u64)
+        };
+
+        assert_eq!(millis(0), None);
+        assert_eq!(millis(49), None);
+        assert_eq!(millis(50), Some(0));
+        // Quarter of the 600 ms interval halfway between the start and the quota.
+        assert_eq!(millis(75), Some(150));
+        assert_eq!(millis(100), Some(600));
+
+        let delays = (50..=100)
+            .map(|used| soft_quota_delay(used, 100).unwrap())
+            .collect::<Vec<_>>();
+        assert!(delays.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert_eq!(soft_quota_delay(0, 0), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn tracker_delay_follows_usage() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let tracker = QuotaTracker::new(Some(10), clock.clone());
+        for _ in 0..4 {
+            tracker.record(EndpointCategory::Payments);
+        }
+        assert_eq!(tracker.delay(), None);
+        for _ in 0..6 {
+            tracker.record(EndpointCategory::Payments);
+        }
+        assert_eq!(tracker.delay(), Some(Duration::from_secs(6)));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(tracker.delay(), None);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::*;
+
+#[cfg(feature = "payments")]
+use {log::*, std::time::Duration};
+
+/// Error codes QIWI responds with when the wallet may not make payments.
+const RESTRICTION_CODES: &[&str] = &[
+    "payment.blocked",
+    "wallet.blocked",
+    "person.blocked",
+    "outgoing.payments.restricted",
+];
+
+#[cfg(feature = "payments")]
+pub(crate) const DEFAULT_READ_ONLY_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[cfg(feature = "payments")]
+#[derive(Clone, Debug)]
+pub(crate) struct ReadOnlyState {
+    until: DateTime<Utc>,
+    reason: String,
+}
+
+impl Error {
+    /// Whether the error means the wallet is restricted from making payments.
+    pub fn is_wallet_restriction(&self) -> bool {
+        match self {
+            Self::QiwiError { error } => RESTRICTION_CODES.contains(&error.error_code.as_str()),
+            _ => false,
+        }
+    }
+}
+
+impl Client {
+    /// Whether money-moving calls are currently refused locally, see [`ClientBuilder::auto_readonly_on_restriction`].
+    #[cfg(feature = "payments")]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only_state().is_some()
+    }
+
+    /// Never without the `payments` feature, as there are no payments to refuse.
+    #[cfg(not(feature = "payments"))]
+    pub fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "payments")]
+impl Client {
+    fn read_only_state(&self) -> Option<ReadOnlyState> {
+        let mut state = self.read_only.lock().unwrap();
+        if state
+            .as_ref()
+            .map(|state| state.until <= self.clock.utc_now())
+            .unwrap_or(false)
+        {
+            *state = None;
+        }
+
+        state.clone()
+    }
+
+    pub(crate) fn ensure_writable(&self) -> QiwiResult<()> {
+        if let Some(state) = self.read_only_state() {
+            return ReadOnlyMode {
+                until: state.until,
+                reason: state.reason,
+            }
+            .fail();
+        }
+
+        Ok(())
+    }
+
+    /// Switches the client to read-only mode if the payment failed because of wallet restrictions.
+    pub(crate) fn note_payment_error(&self, error: &Error) {
+        if !self.auto_readonly || !error.is_wallet_restriction() {
+            return;
+        }
+
+        let until = self.clock.utc_now()
+            + chrono::Duration::from_std(self.read_only_ttl)
+                .unwrap_or_else(|_| chrono::Duration::hours(1));
+        warn!(
+            "Wallet is restricted, refusing payments until {}: {}",
+            until, error
+        );
+        *self.read_only.lock().unwrap() = Some(ReadOnlyState {
+            until,
+            reason: error.to_string(),
+        });
+    }
+
+    /// Leaves read-only mode early once the wallet is seen without restrictions.
+    pub(crate) fn note_restrictions(&self, restrictions: &[Restriction]) {
+        if !restrictions.is_empty() {
+            return;
+        }
+
+        let mut state = self.read_only.lock().unwrap();
+        if state.take().is_some() {
+            info!("Wallet is no longer restricted, allowing payments");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "payments"))]
+mod tests {
+    use {
+        super::*, crate::clock::ManualClock, serde_json::json, std::str::FromStr,
+        std::time::Duration,
+    };
+
+    const PROVIDER: ProviderId = ProviderId::MTS;
+    const TTL: Duration = Duration::from_secs(10 * 60);
+
+    fn payments_endpoint() -> String {
+        format!("sinap/api/v2/terms/{}/payments", PROVIDER)
+    }
+
+    fn restrictions_endpoint() -> String {
+        format!(
+            "person-profile/v1/persons/{}/status/restrictions",
+            QiwiUser::from("+79991234567".parse::<PhoneNumber>().unwrap())
+        )
+    }
+
+    fn blocked() -> Value {
+        json!({
+            "serviceName": "payment-sinap",
+            "errorCode": "payment.blocked",
+            "userMessage": "Платежи запрещены",
+        })
+    }
+
+    fn accepted() -> Value {
+        json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } })
+    }
+
+    fn client(auto_readonly: bool) -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let transport =
+            Arc::new(OfflineTransport::new().with(Method::POST, payments_endpoint(), &accepted()));
+        let clock = Arc::new(ManualClock::new(
+            Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap(),
+        ));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .auto_readonly_on_restriction(auto_readonly)
+            .read_only_ttl(TTL)
+            .build();
+        (client, transport, clock)
+    }
+
+    async fn pay(client: &Client) -> QiwiResult<TransferData> {
+        client
+            .pay(
+                PROVIDER,
+                Money::new(BigDecimal::from_str("100").unwrap(), penny::Currency::RUB),
+                vec![("account".to_string(), "12345".to_string())]
+                    .into_iter()
+                    .collect(),
+                None,
+                None,
+            )
+            .await
+    }
+
+    /// Client which has been refused a payment with `payment.blocked`.
+    async fn restricted_client() -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let (client, transport, clock) = client(true);
+        transport.push(Method::POST, payments_endpoint(), &blocked());
+        match pay(&client).await {
+            Err(error @ Error::QiwiError { .. }) => assert!(error.is_wallet_restriction()),
+            other => panic!("expected QiwiError, got {:?}", other),
+        }
+        (client, transport, clock)
+    }
+
+    #[tokio::test]
+    async fn restriction_error_switches_to_read_only() {
+        let (client, transport, clock) = restricted_client().await;
+        assert!(client.is_read_only());
+
+        match pay(&client).await {
+            Err(Error::ReadOnlyMode { until, reason }) => {
+                assert_eq!(until, clock.utc_now() + chrono::Duration::minutes(10));
+                assert!(reason.contains("payment.blocked"), "{}", reason);
+            }
+            other => panic!("expected ReadOnlyMode, got {:?}", other),
+        }
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_only_refuses_concurrent_payments_without_requests() {
+        let (client, transport, _) = restricted_client().await;
+
+        let (first, second, third) = tokio::join!(pay(&client), pay(&client), pay(&client));
+        for result in vec![first, second, third] {
+            assert!(matches!(result, Err(Error::ReadOnlyMode { .. })));
+        }
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_endpoints_work_while_read_only() {
+        let (client, transport, _) = restricted_client().await;
+        transport.insert(
+            Method::GET,
+            restrictions_endpoint(),
+            &json!([{
+                "restrictionCode": "OUTGOING_PAYMENTS",
+                "restrictionDescription": "Исходящие платежи запрещены",
+            }]),
+        );
+
+        assert_eq!(client.restrictions().await.unwrap().len(), 1);
+        assert!(client.is_read_only());
+    }
+
+    #[tokio::test]
+    async fn read_only_expires_after_ttl() {
+        let (client, _, clock) = restricted_client().await;
+
+        clock.advance(TTL - Duration::from_secs(1));
+        assert!(client.is_read_only());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!client.is_read_only());
+        pay(&client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn no_restrictions_leave_read_only_early() {
+        let (client, transport, _) = restricted_client().await;
+        transport.insert(Method::GET, restrictions_endpoint(), &json!([]));
+
+        assert!(client.restrictions().await.unwrap().is_empty());
+        assert!(!client.is_read_only());
+        pay(&client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn restrictions_are_ignored_unless_enabled() {
+        let (client, transport, _) = client(false);
+        transport.push(Method::POST, payments_endpoint(), &blocked());
+
+        assert!(matches!(pay(&client).await, Err(Error::QiwiError { .. })));
+        assert!(!client.is_read_only());
+        pay(&client).await.unwrap();
+    }
+}
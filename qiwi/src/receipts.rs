@@ -0,0 +1,73 @@
+//! Bulk download of receipts.
+
+use {
+    crate::{statement::fetch_cheque, *},
+    std::sync::atomic::{AtomicBool, Ordering},
+    tokio::sync::{mpsc, Semaphore},
+};
+
+impl Client {
+    /// PDF receipts of payments matching `filter`, at most `concurrency` downloads at a time.
+    ///
+    /// Payments without a receipt ready are skipped. Receipts come in the order downloads complete,
+    /// each with its payment to tell them apart. A failed download yields
+    /// [`Error::ReceiptDownloadFailed`] and the rest continue, while a history error ends the stream.
+    /// Downloads count towards the [soft quota](ClientBuilder::soft_quota) like any other call.
+    /// Dropping the stream stops reading history once a running download finishes.
+    pub fn prefetch_receipts(
+        &self,
+        filter: PaymentHistoryFilter,
+        concurrency: usize,
+    ) -> QiwiResult<Pin<Box<dyn Stream<Item = QiwiResult<(PaymentHistoryEntry, Vec<u8>)>> + Send>>>
+    {
+        let mut history = self.payment_history_filtered(filter)?;
+        let concurrency = concurrency.max(1);
+        let caller = self.caller.clone();
+        let (tx, rx) = mpsc::channel(concurrency);
+        let closed = Arc::new(AtomicBool::new(false));
+
+        tokio::spawn(async move {
+            let slots = Arc::new(Semaphore::new(concurrency));
+            while !closed.load(Ordering::Relaxed) {
+                let entry = match history.next().await {
+                    Some(entry) => entry,
+                    None => return,
+                };
+                let entry = match entry {
+                    Ok(entry) if entry.cheque_ready => entry,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        let _ = tx.clone().send(Err(e)).await;
+                        return;
+                    }
+                };
+
+                // Released by the download once it is done.
+                slots.acquire().await.forget();
+                let txn_type = match entry.payment_type {
+                    PaymentType::In => TransactionType::In,
+                    PaymentType::Out | PaymentType::QiwiCard => TransactionType::Out,
+                };
+                let download = fetch_cheque(&caller, entry.txn_id, txn_type, ChequeFormat::Pdf);
+                let slots = slots.clone();
+                let closed = closed.clone();
+                let mut tx = tx.clone();
+                tokio::spawn(async move {
+                    let txn_id = entry.txn_id;
+                    let item = download.await.map(|data| (entry, data)).map_err(|source| {
+                        Error::ReceiptDownloadFailed {
+                            txn_id,
+                            source: Box::new(source),
+                        }
+                    });
+                    if tx.send(item).await.is_err() {
+                        closed.store(true, Ordering::Relaxed);
+                    }
+                    slots.add_permits(1);
+                });
+            }
+        });
+
+        Ok(Box::pin(rx))
+    }
+}
@@ -0,0 +1,227 @@
+//! Matching of history entries that belong to the same money movement.
+
+use crate::*;
+
+/// Provider of conversions between own balances.
+pub const CONVERSION_PROVIDER: u64 = 1099;
+
+#[derive(Clone, Debug)]
+pub struct LinkOptions {
+    /// Largest time difference between the two entries of one conversion.
+    pub tolerance: chrono::Duration,
+    /// Largest difference between the converted outgoing amount and the incoming one.
+    pub amount_tolerance: BigDecimal,
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: chrono::Duration::minutes(2),
+            amount_tolerance: BigDecimal::new(1.into(), 2),
+        }
+    }
+}
+
+/// Both sides of a conversion between own balances.
+#[derive(Clone, Debug)]
+pub struct LinkedPair {
+    pub outgoing: PaymentHistoryEntry,
+    pub incoming: PaymentHistoryEntry,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LinkResult {
+    pub pairs: Vec<LinkedPair>,
+    /// Entries that are not part of any pair, in their original order.
+    pub unmatched: Vec<PaymentHistoryEntry>,
+}
+
+fn is_conversion(entry: &PaymentHistoryEntry) -> bool {
+    entry.provider.id == CONVERSION_PROVIDER && matches!(entry.status, PaymentStatus::Success)
+}
+
+fn amounts_match(
+    outgoing: &PaymentHistoryEntry,
+    incoming: &PaymentHistoryEntry,
+    tolerance: &BigDecimal,
+) -> bool {
+    let close = |a: BigDecimal, b: &BigDecimal| (a - b).abs() <= *tolerance;
+
+    // The rate may be reported on either side, depending on conversion direction.
+    close(
+        &outgoing.sum.amount * &outgoing.currency_rate,
+        &incoming.sum.amount,
+    ) || close(
+        &incoming.sum.amount * &incoming.currency_rate,
+        &outgoing.sum.amount,
+    )
+}
+
+/// [`link_internal_transfers_with`] with default options.
+pub fn link_internal_transfers<I>(entries: I) -> LinkResult
+where
+    I: IntoIterator<Item = PaymentHistoryEntry>,
+{
+    link_internal_transfers_with(entries, &LinkOptions::default())
+}
+
+/// Pairs up outgoing and incoming entries of conversions between own balances.
+///
+/// Each outgoing entry, oldest first, is linked to the unmatched incoming entry in another currency
+/// with a matching amount that is closest in time, ties broken by the lower transaction id.
+/// This way indistinguishable conversions made within the same minute are paired in order
+/// and every entry is used at most once.
+pub fn link_internal_transfers_with<I>(entries: I, options: &LinkOptions) -> LinkResult
+where
+    I: IntoIterator<Item = PaymentHistoryEntry>,
+{
+    let entries = entries.into_iter().collect::<Vec<_>>();
+
+    let mut outgoing = Vec::new();
+    let mut incoming = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if is_conversion(entry) {
+            match entry.payment_type {
+                PaymentType::Out => outgoing.push(i),
+                PaymentType::In => incoming.push(i),
+                PaymentType::QiwiCard => {}
+            }
+        }
+    }
+    outgoing.sort_by_key(|&i| (entries[i].date, entries[i].txn_id));
+
+    let mut taken = vec![false; entries.len()];
+    let mut links = Vec::new();
+    for out_idx in outgoing {
+        let out = &entries[out_idx];
+        let candidate = incoming
+            .iter()
+            .copied()
+            .filter(|&in_idx| !taken[in_idx])
+            .filter(|&in_idx| {
+                let inc = &entries[in_idx];
+                inc.sum.currency != out.sum.currency
+                    && (inc.date - out.date).num_milliseconds().abs()
+                        <= options.tolerance.num_milliseconds()
+                    && amounts_match(out, inc, &options.amount_tolerance)
+            })
+            .min_by_key(|&in_idx| {
+                let inc = &entries[in_idx];
+                ((inc.date - out.date).num_milliseconds().abs(), inc.txn_id)
+            });
+
+        if let Some(in_idx) = candidate {
+            taken[in_idx] = true;
+            links.push((out_idx, in_idx));
+        }
+    }
+
+    let mut slots = entries.into_iter().map(Some).collect::<Vec<_>>();
+    let pairs = links
+        .into_iter()
+        .map(|(out_idx, in_idx)| LinkedPair {
+            outgoing: slots[out_idx].take().unwrap(),
+            incoming: slots[in_idx].take().unwrap(),
+        })
+        .collect();
+
+    LinkResult {
+        pairs,
+        unmatched: slots.into_iter().flatten().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    fn entry(
+        txn_id: u64,
+        time: &str,
+        kind: &str,
+        amount: &str,
+        currency: &str,
+        rate: &str,
+    ) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["txnId"] = json!(txn_id);
+        entry["date"] = json!(format!("2020-01-31T{}+03:00", time));
+        entry["type"] = json!(kind);
+        entry["status"] = json!("SUCCESS");
+        entry["provider"]["id"] = json!(CONVERSION_PROVIDER);
+        entry["sum"] = json!({ "amount": amount, "currency": currency });
+        entry["currencyRate"] = json!(rate);
+        entry
+    }
+
+    fn link(entries: Vec<Value>) -> LinkResult {
+        link_internal_transfers(
+            entries
+                .into_iter()
+                .map(|entry| serde_json::from_value::<PaymentHistoryEntry>(entry).unwrap()),
+        )
+    }
+
+    fn pair_ids(result: &LinkResult) -> Vec<(u64, u64)> {
+        result
+            .pairs
+            .iter()
+            .map(|pair| (pair.outgoing.txn_id, pair.incoming.txn_id))
+            .collect()
+    }
+
+    fn unmatched_ids(result: &LinkResult) -> Vec<u64> {
+        result.unmatched.iter().map(|entry| entry.txn_id).collect()
+    }
+
+    #[test]
+    fn conversion_sides_are_paired_by_converted_amount() {
+        let mut payment = entry(3, "12:00:10", "OUT", "100.00", "840", "1");
+        payment["provider"]["id"] = json!(99);
+        let result = link(vec![
+            // Newest first, as in history.
+            entry(2, "12:00:05", "IN", "100.00", "840", "73.5"),
+            payment,
+            entry(1, "12:00:00", "OUT", "7350.00", "643", "1"),
+            entry(4, "11:00:05", "IN", "7349.99", "643", "1"),
+            entry(5, "11:00:00", "OUT", "100.00", "840", "73.5"),
+        ]);
+
+        assert_eq!(pair_ids(&result), vec![(5, 4), (1, 2)]);
+        assert_eq!(unmatched_ids(&result), vec![3]);
+    }
+
+    #[test]
+    fn unpaired_conversion_entries_are_left_in_order() {
+        let mut failed = entry(6, "12:00:05", "IN", "100.00", "840", "73.5");
+        failed["status"] = json!("ERROR");
+        let result = link(vec![
+            // Beyond the tolerance of two minutes.
+            entry(7, "12:02:01", "IN", "100.00", "840", "73.5"),
+            failed,
+            // Same currency as the outgoing side.
+            entry(5, "12:00:05", "IN", "7350.00", "643", "1"),
+            // Off by more than a kopeck.
+            entry(4, "12:00:05", "IN", "99.98", "840", "73.5"),
+            entry(1, "12:00:00", "OUT", "7350.00", "643", "1"),
+        ]);
+
+        assert!(result.pairs.is_empty());
+        assert_eq!(unmatched_ids(&result), vec![7, 6, 5, 4, 1]);
+    }
+
+    #[test]
+    fn identical_conversions_are_paired_in_order() {
+        let result = link(vec![
+            entry(4, "12:00:40", "OUT", "7350.00", "643", "1"),
+            entry(3, "12:00:30", "IN", "100.00", "840", "73.5"),
+            entry(1, "12:00:20", "OUT", "7350.00", "643", "1"),
+            entry(2, "12:00:10", "IN", "100.00", "840", "73.5"),
+        ]);
+
+        // Both incoming entries are 10 seconds from the first conversion, it takes the one
+        // with the lower id and leaves the other to the next one.
+        assert_eq!(pair_ids(&result), vec![(1, 2), (4, 3)]);
+        assert!(result.unmatched.is_empty());
+    }
+}
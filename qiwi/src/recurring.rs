@@ -0,0 +1,230 @@
+use crate::*;
+
+/// Outcome of [`Client::pay_once_per_period`].
+#[derive(Clone, Debug)]
+pub enum RecurringOutcome {
+    /// The payment for this period has been made before.
+    AlreadyPaid(PaymentHistoryEntry),
+    Paid(TransferData),
+}
+
+/// Marker put into the comment of recurring payments, e.g. `[qiwi:internet:2020-01]`.
+pub fn recurring_marker(tag: &str, bucket: &str) -> String {
+    format!("[qiwi:{}:{}]", tag, bucket)
+}
+
+impl Client {
+    /// Pays unless a payment tagged with `tag` has already been made within the current period.
+    ///
+    /// Payments are recognized by a marker in the comment, see [`recurring_marker`]. Payments in progress
+    /// count as made, so running this again while the previous payment is processed does not pay twice.
+    pub async fn pay_once_per_period<A: Into<AccountId>>(
+        &self,
+        provider: ProviderId,
+        account: A,
+        amount: BigDecimal,
+        period: Period,
+        tag: &str,
+    ) -> QiwiResult<RecurringOutcome> {
+        let now = self.clock.utc_now();
+        let start = period.start(now, self.region);
+        let marker = recurring_marker(tag, &period.bucket(now, self.region));
+
+        let mut history = self.payment_history();
+        while let Some(entry) = history.next().await.transpose()? {
+            if entry.date < start {
+                break;
+            }
+
+            if matches!(entry.payment_type, PaymentType::Out)
+                && !matches!(entry.status, PaymentStatus::Error)
+                && entry.comment.contains(&marker)
+            {
+                return Ok(RecurringOutcome::AlreadyPaid(entry));
+            }
+        }
+
+        let mut fields = BTreeMap::new();
+        fields.insert("account".to_string(), account.into().format_for(provider));
+
+        Ok(RecurringOutcome::Paid(
+            self.pay(
+                provider,
+                Money::new(amount, self.region.currency()),
+                fields,
+                Some(marker),
+                None,
+            )
+            .await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, serde_json::json};
+
+    const INTERNET: u64 = 26_476;
+    const TAG: &str = "internet";
+
+    fn entry(date: &str, status: &str, comment: &str) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["date"] = json!(date);
+        entry["type"] = json!("OUT");
+        entry["status"] = json!(status);
+        entry["provider"]["id"] = json!(INTERNET);
+        entry["comment"] = json!(comment);
+        entry
+    }
+
+    fn client(
+        phone: &str,
+        now: DateTime<Utc>,
+        history: Vec<Value>,
+    ) -> (Client, Arc<OfflineTransport>) {
+        let phone: PhoneNumber = phone.parse().unwrap();
+        let transport = Arc::new(
+            OfflineTransport::new()
+                .with(
+                    Method::GET,
+                    ApiVersions::default().history_endpoint(&QiwiUser::from(phone.clone())),
+                    &fixtures::history_page(history),
+                )
+                .with(
+                    Method::POST,
+                    format!("sinap/api/v2/terms/{}/payments", INTERNET),
+                    &json!({ "transaction": { "id": "20000000001", "state": { "code": "Accepted" } } }),
+                ),
+        );
+        let client = Client::builder(phone, "")
+            .transport(transport.clone())
+            .clock(ManualClock::new(now))
+            .build();
+        (client, transport)
+    }
+
+    async fn pay(client: &Client) -> RecurringOutcome {
+        client
+            .pay_once_per_period(
+                ProviderId::from(INTERNET),
+                AccountId::Raw("1234567".into()),
+                BigDecimal::from(500),
+                Period::Month,
+                TAG,
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Comment of the payment sent, if any.
+    fn sent_comment(transport: &OfflineTransport) -> Option<String> {
+        let request = transport
+            .recorded()
+            .into_iter()
+            .find(|request| request.method == Method::POST)?;
+        let body: Value = serde_json::from_str(&request.body.unwrap()).unwrap();
+        Some(body["comment"].as_str().unwrap().to_string())
+    }
+
+    /// 2020-02-01 12:00, Moscow time.
+    fn february() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 2, 1, 9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn marker() {
+        assert_eq!(recurring_marker(TAG, "2020-01"), "[qiwi:internet:2020-01]");
+    }
+
+    #[tokio::test]
+    async fn paid_last_month_pays_again() {
+        let (client, transport) = client(
+            "+79991234567",
+            february(),
+            vec![entry(
+                "2020-01-31T23:59:00+03:00",
+                "SUCCESS",
+                "[qiwi:internet:2020-01]",
+            )],
+        );
+
+        assert!(matches!(pay(&client).await, RecurringOutcome::Paid(_)));
+        assert_eq!(
+            sent_comment(&transport).as_deref(),
+            Some("[qiwi:internet:2020-02]")
+        );
+    }
+
+    #[tokio::test]
+    async fn paid_this_month_is_not_paid_again() {
+        for status in &["SUCCESS", "WAITING"] {
+            let (client, transport) = client(
+                "+79991234567",
+                february(),
+                vec![entry(
+                    "2020-02-01T00:01:00+03:00",
+                    status,
+                    "Internet [qiwi:internet:2020-02]",
+                )],
+            );
+
+            match pay(&client).await {
+                RecurringOutcome::AlreadyPaid(entry) => {
+                    assert_eq!(entry.comment, "Internet [qiwi:internet:2020-02]")
+                }
+                other => panic!("expected AlreadyPaid, got {:?}", other),
+            }
+            assert_eq!(sent_comment(&transport), None, "{}", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_or_other_payments_this_month_do_not_count() {
+        let (client, transport) = client(
+            "+79991234567",
+            february(),
+            vec![
+                entry(
+                    "2020-02-01T11:00:00+03:00",
+                    "ERROR",
+                    "[qiwi:internet:2020-02]",
+                ),
+                entry(
+                    "2020-02-01T10:00:00+03:00",
+                    "SUCCESS",
+                    "[qiwi:phone:2020-02]",
+                ),
+            ],
+        );
+
+        assert!(matches!(pay(&client).await, RecurringOutcome::Paid(_)));
+        assert!(sent_comment(&transport).is_some());
+    }
+
+    #[tokio::test]
+    async fn month_boundary_is_in_local_time() {
+        // Still January in Moscow, already February in Almaty.
+        let now = Utc.with_ymd_and_hms(2020, 1, 31, 19, 30, 0).unwrap();
+        let history = || {
+            vec![entry(
+                "2020-01-31T10:00:00+00:00",
+                "SUCCESS",
+                "[qiwi:internet:2020-01]",
+            )]
+        };
+
+        let (client, transport) = client("+79991234567", now, history());
+        assert!(matches!(
+            pay(&client).await,
+            RecurringOutcome::AlreadyPaid(_)
+        ));
+        assert_eq!(sent_comment(&transport), None);
+
+        let (client, transport) = client("+77011234567", now, history());
+        assert!(matches!(pay(&client).await, RecurringOutcome::Paid(_)));
+        assert_eq!(
+            sent_comment(&transport).as_deref(),
+            Some("[qiwi:internet:2020-02]")
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use {crate::*, serde::Serialize, std::collections::HashMap};
+
+/// Who the money was sent to or received from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub enum Counterparty {
+    /// Wallet-to-wallet transfer, keyed by the other wallet number.
+    Account(String),
+    /// Payment to or from a service provider.
+    Provider(u64),
+}
+
+impl Counterparty {
+    pub fn of(entry: &PaymentHistoryEntry) -> Self {
+        if !P2P_PROVIDERS.contains(&entry.provider.id) || entry.account.is_empty() {
+            return Self::Provider(entry.provider.id);
+        }
+
+        Self::Account(
+            QiwiUser::parse(&entry.account)
+                .map(|user| user.to_string())
+                .unwrap_or_else(|| entry.account.clone()),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CounterpartyTotals {
+    pub counterparty: Counterparty,
+    pub incoming: MoneyBag,
+    pub outgoing: MoneyBag,
+}
+
+impl CounterpartyTotals {
+    /// Sum of incoming and outgoing amounts in all currencies, used for ordering.
+    pub fn turnover(&self) -> BigDecimal {
+        self.incoming
+            .iter()
+            .chain(self.outgoing.iter())
+            .fold(BigDecimal::from(0), |acc, v| acc + v.amount.abs())
+    }
+}
+
+/// Totals of successful payments per counterparty, largest turnover first.
+///
+/// This is what [`Client::counterparty_report`] computes, usable on exported history as well.
+pub fn counterparty_totals<'a, I>(entries: I) -> Vec<CounterpartyTotals>
+where
+    I: IntoIterator<Item = &'a PaymentHistoryEntry>,
+{
+    let mut totals = HashMap::<Counterparty, CounterpartyTotals>::new();
+    for entry in entries {
+        if !matches!(entry.status, PaymentStatus::Success) {
+            continue;
+        }
+
+        let counterparty = Counterparty::of(entry);
+        let item = totals
+            .entry(counterparty.clone())
+            .or_insert_with(|| CounterpartyTotals {
+                counterparty,
+                incoming: Default::default(),
+                outgoing: Default::default(),
+            });
+        let bucket = match entry.payment_type {
+            PaymentType::In => &mut item.incoming,
+            PaymentType::Out | PaymentType::QiwiCard => &mut item.outgoing,
+        };
+        bucket.add(Money {
+            amount: entry.sum.amount.clone(),
+            currency: match entry.sum.currency.parse() {
+                Ok(currency) => currency,
+                Err(_) => {
+                    log::warn!(
+                        "Skipping payment {} in unknown currency {}",
+                        entry.txn_id,
+                        entry.sum.currency
+                    );
+                    continue;
+                }
+            },
+        });
+    }
+
+    let mut totals = totals
+        .into_iter()
+        .map(|(_, v)| (v.turnover(), v))
+        .collect::<Vec<_>>();
+    totals.sort_by(|(a_turnover, a), (b_turnover, b)| {
+        b_turnover
+            .cmp(a_turnover)
+            .then_with(|| a.counterparty.cmp(&b.counterparty))
+    });
+
+    totals.into_iter().map(|(_, v)| v).collect()
+}
+
+/// Result of [`Client::counterparty_report`].
+#[derive(Clone, Debug, Serialize)]
+pub struct CounterpartyReport {
+    /// Sources the payments were limited to, empty if the report covers all of them.
+    pub sources: Vec<HistorySource>,
+    /// Largest turnover first.
+    pub totals: Vec<CounterpartyTotals>,
+}
+
+impl CounterpartyReport {
+    /// Report on entries fetched or exported before, counting those from or to `sources` only.
+    ///
+    /// Sources are told from the entries, see [`HistorySource::of`], where
+    /// [`Client::counterparty_report`] leaves filtering to QIWI.
+    pub fn from_entries<'a, I>(entries: I, sources: &[HistorySource]) -> Self
+    where
+        I: IntoIterator<Item = &'a PaymentHistoryEntry>,
+    {
+        let filter = sources
+            .iter()
+            .fold(PaymentHistoryFilter::new(), |filter, source| {
+                filter.source(*source)
+            });
+        Self {
+            totals: counterparty_totals(
+                entries
+                    .into_iter()
+                    .filter(|entry| filter.matches_sources(entry)),
+            ),
+            sources: filter.sources,
+        }
+    }
+}
+
+impl Client {
+    /// Incoming and outgoing totals per counterparty for payments made within `[start, end)`.
+    ///
+    /// With `sources`, only payments from or to them are counted, e.g. [`HistorySource::QwRub`]
+    /// to leave out card payments. QIWI filters them, use [`CounterpartyReport::from_entries`]
+    /// for history fetched before.
+    pub async fn counterparty_report(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        sources: &[HistorySource],
+    ) -> QiwiResult<CounterpartyReport> {
+        let filter = sources.iter().fold(
+            PaymentHistoryFilter::new()
+                .start_date(start.into())
+                .end_date(end.into()),
+            |filter, source| filter.source(*source),
+        );
+        let mut entries = Vec::new();
+        let mut history = self.payment_history_filtered(filter.clone())?;
+        while let Some(entry) = history.next().await.transpose()? {
+            if entry.date < start {
+                break;
+            }
+
+            if entry.date < end {
+                entries.push(entry);
+            }
+        }
+
+        Ok(CounterpartyReport {
+            sources: filter.sources,
+            totals: counterparty_totals(&entries),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    const CARD_PROVIDER: u64 = 1963;
+
+    fn user() -> QiwiUser {
+        QiwiUser::from("+79991234567".parse::<PhoneNumber>().unwrap())
+    }
+
+    fn fixture_entries() -> Vec<Value> {
+        fixtures::history_entries(fixtures::TYPICAL_HISTORY_LEN)
+    }
+
+    /// Entries QIWI returns for `sources`, told by the provider rather than the account.
+    fn served(sources: &[HistorySource]) -> Vec<Value> {
+        fixture_entries()
+            .into_iter()
+            .filter(|entry| {
+                let source = if entry["provider"]["id"] == json!(CARD_PROVIDER) {
+                    HistorySource::Card
+                } else {
+                    HistorySource::QwRub
+                };
+                sources.contains(&source)
+            })
+            .collect()
+    }
+
+    fn client(transport: Arc<OfflineTransport>) -> Client {
+        Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport)
+            .build()
+    }
+
+    fn params(transport: &OfflineTransport) -> Vec<(String, String)> {
+        transport.recorded().pop().unwrap().params
+    }
+
+    fn source_params(sources: &[&str]) -> Vec<(String, String)> {
+        sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| (format!("sources[{}]", i), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn sources_of_entries() {
+        let entries = fixture_entries()
+            .into_iter()
+            .map(|entry| serde_json::from_value::<PaymentHistoryEntry>(entry).unwrap())
+            .collect::<Vec<_>>();
+        for entry in &entries {
+            let expected = if entry.provider.id == CARD_PROVIDER {
+                HistorySource::Card
+            } else {
+                HistorySource::QwRub
+            };
+            assert_eq!(HistorySource::of(entry), Some(expected), "{:?}", entry);
+        }
+
+        let mut dollars = entries[0].clone();
+        dollars.account = "+79991234567".to_string();
+        dollars.sum.currency = "840".to_string();
+        assert_eq!(HistorySource::of(&dollars), Some(HistorySource::QwUsd));
+        dollars.sum.currency = "398".to_string();
+        assert_eq!(HistorySource::of(&dollars), None);
+        assert!(PaymentHistoryFilter::new().matches_sources(&dollars));
+        assert!(!PaymentHistoryFilter::new()
+            .source(HistorySource::QwRub)
+            .matches_sources(&dollars));
+    }
+
+    #[tokio::test]
+    async fn server_and_client_side_filtering_agree() {
+        let start = Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2100, 1, 1, 0, 0, 0).unwrap();
+        let entries = fixture_entries()
+            .into_iter()
+            .map(|entry| serde_json::from_value::<PaymentHistoryEntry>(entry).unwrap())
+            .collect::<Vec<_>>();
+
+        for (sources, names) in vec![
+            (vec![HistorySource::Card], vec!["CARD"]),
+            (vec![HistorySource::QwRub], vec!["QW_RUB"]),
+            (
+                vec![HistorySource::QwRub, HistorySource::Card],
+                vec!["QW_RUB", "CARD"],
+            ),
+        ] {
+            let transport = Arc::new(OfflineTransport::new().with(
+                Method::GET,
+                ApiVersions::default().history_endpoint(&user()),
+                &fixtures::history_page(served(&sources)),
+            ));
+            let server_side = client(transport.clone())
+                .counterparty_report(start, end, &sources)
+                .await
+                .unwrap();
+            assert!(source_params(&names)
+                .iter()
+                .all(|param| params(&transport).contains(param)));
+
+            let client_side = CounterpartyReport::from_entries(&entries, &sources);
+            assert!(!client_side.totals.is_empty());
+            assert_eq!(server_side.sources, sources);
+            assert_eq!(client_side.sources, sources);
+            assert_eq!(
+                serde_json::to_value(&server_side).unwrap(),
+                serde_json::to_value(&client_side).unwrap(),
+                "{:?}",
+                sources
+            );
+        }
+    }
+
+    #[test]
+    fn no_sources_count_everything() {
+        let entries = fixture_entries()
+            .into_iter()
+            .map(|entry| serde_json::from_value::<PaymentHistoryEntry>(entry).unwrap())
+            .collect::<Vec<_>>();
+        let report = CounterpartyReport::from_entries(&entries, &[]);
+        assert!(report.sources.is_empty());
+        assert_eq!(
+            serde_json::to_value(&report.totals).unwrap(),
+            serde_json::to_value(&counterparty_totals(&entries)).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn totals_pass_sources_through() {
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::GET,
+            ApiVersions::default().totals_endpoint(&user()),
+            &json!({ "incomingTotal": [], "outgoingTotal": [] }),
+        ));
+        let end = Utc.with_ymd_and_hms(2020, 1, 31, 0, 0, 0).unwrap();
+        let filter = PaymentHistoryFilter::new()
+            .source(HistorySource::QwRub)
+            .source(HistorySource::Card);
+
+        let totals = client(transport.clone())
+            .payment_totals(end - chrono::Duration::days(30), end, &filter)
+            .await
+            .unwrap();
+        assert_eq!(totals.sources, filter.sources);
+        let params = params(&transport);
+        assert_eq!(
+            params[params.len() - 2..].to_vec(),
+            source_params(&["QW_RUB", "CARD"])
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use {
+    crate::{
+        clock::{Clock, SystemClock},
+        *,
+    },
+    std::{future::Future, sync::atomic::AtomicU64, sync::atomic::Ordering},
+};
+
+/// Synthetic transaction ids start here, far above real ones.
+const SYNTHETIC_TXN_BASE: u64 = 9_000_000_000_000_000;
+
+/// Set in `extras` of synthetic history entries.
+pub const SANDBOX_EXTRA: &str = "sandbox";
+
+#[derive(Clone, Debug)]
+struct SyntheticTxn {
+    txn_id: u64,
+    client_id: String,
+    provider: u64,
+    account: String,
+    sum: Value,
+    comment: String,
+    date: DateTime<Utc>,
+    polls: u32,
+}
+
+impl SyntheticTxn {
+    /// `Accepted` payments are reported as waiting on the first status poll and succeed afterwards.
+    fn status(&self) -> &'static str {
+        if self.polls >= 2 {
+            "SUCCESS"
+        } else {
+            "WAITING"
+        }
+    }
+
+    fn history_entry(&self, person_id: &str) -> Value {
+        json!({
+            "txnId": self.txn_id,
+            "personId": person_id.parse::<u64>().unwrap_or_default(),
+            "date": self.date.to_rfc3339(),
+            "errorCode": 0,
+            "error": "",
+            "type": "OUT",
+            "status": self.status(),
+            "statusText": self.status(),
+            "trmTxnId": self.client_id,
+            "account": self.account,
+            "sum": self.sum,
+            "commission": { "amount": "0", "currency": self.sum["currency"] },
+            "total": self.sum,
+            "provider": {
+                "id": self.provider,
+                "shortName": "",
+                "longName": "",
+                "logoUrl": "",
+                "description": "",
+                "keys": "",
+                "siteUrl": "",
+            },
+            "comment": self.comment,
+            "currencyRate": "1",
+            "extras": { SANDBOX_EXTRA: true },
+            "chequeReady": false,
+            "bankDocumentAvailable": false,
+            "bankDocumentReady": false,
+            "repeatPaymentEnabled": false,
+            "favoritePaymentEnabled": false,
+            "regularPaymentEnabled": false,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct SandboxState {
+    next_txn: AtomicU64,
+    txns: Mutex<Vec<SyntheticTxn>>,
+}
+
+/// Transport passing reads through to `inner` while simulating payments.
+///
+/// Payments are accepted without reaching QIWI and show up in the first page of payment history,
+/// tagged with [`SANDBOX_EXTRA`]. Their status moves from `WAITING` to `SUCCESS` as they are polled
+/// with `payment-history/v2/transactions/{id}`.
+#[derive(Debug)]
+pub struct SandboxTransport {
+    inner: Arc<dyn Transport>,
+    clock: Arc<dyn Clock>,
+    state: Arc<SandboxState>,
+}
+
+impl SandboxTransport {
+    pub fn new(inner: Arc<dyn Transport>) -> Self {
+        Self::with_clock(inner, Arc::new(SystemClock))
+    }
+
+    /// Same as [`SandboxTransport::new`], dating payments by `clock`.
+    pub fn with_clock(inner: Arc<dyn Transport>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner,
+            clock,
+            state: Default::default(),
+        }
+    }
+
+    fn accept_payment(&self, endpoint: &str, body: Option<&Value>) -> Result<String, StdError> {
+        let provider = endpoint
+            .trim_start_matches("sinap/api/v2/terms/")
+            .trim_end_matches("/payments")
+            .parse::<u64>()?;
+        let body = body.ok_or("payment request without body")?;
+        let txn = SyntheticTxn {
+            txn_id: SYNTHETIC_TXN_BASE + self.state.next_txn.fetch_add(1, Ordering::SeqCst),
+            client_id: body["id"].as_str().unwrap_or_default().to_string(),
+            provider,
+            account: body["fields"]["account"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            sum: body["sum"].clone(),
+            comment: body["comment"].as_str().unwrap_or_default().to_string(),
+            date: self.clock.utc_now(),
+            polls: 0,
+        };
+
+        let rsp = json!({
+            "id": txn.client_id,
+            "transaction": {
+                "id": txn.txn_id.to_string(),
+                "state": { "code": "Accepted" },
+            },
+        });
+        self.state.txns.lock().unwrap().push(txn);
+
+        Ok(rsp.to_string())
+    }
+
+    /// Status of a synthetic transaction, if `txn_id` refers to one.
+    fn poll_transaction(&self, txn_id: &str) -> Option<String> {
+        let txn_id = txn_id.parse::<u64>().ok()?;
+        let mut txns = self.state.txns.lock().unwrap();
+        let txn = txns.iter_mut().find(|txn| txn.txn_id == txn_id)?;
+        txn.polls += 1;
+
+        Some(txn.history_entry("").to_string())
+    }
+}
+
+impl Transport for SandboxTransport {
+    fn call(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        body: Option<&Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        if method == Method::POST
+            && endpoint.starts_with("sinap/api/v2/terms/")
+            && endpoint.ends_with("/payments")
+        {
+            let rsp = self.accept_payment(&endpoint, body);
+            return Box::pin(async move { rsp });
+        }
+
+        if method == Method::GET {
+            if let Some(txn_id) = endpoint.strip_prefix("payment-history/v2/transactions/") {
+                if let Some(rsp) = self.poll_transaction(txn_id) {
+                    return Box::pin(async move { Ok(rsp) });
+                }
+            }
+        }
+
+        let first_history_page = method == Method::GET
+            && endpoint.starts_with("payment-history/")
+            && endpoint.ends_with("/payments")
+            && !params.iter().any(|(key, _)| key == "nextTxnId");
+        let person_id = endpoint
+            .split('/')
+            .skip_while(|part| *part != "persons")
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        let state = self.state.clone();
+        let rsp = self.inner.call(endpoint, method, params, body);
+        Box::pin(async move {
+            let rsp = rsp.await?;
+            if !first_history_page {
+                return Ok(rsp);
+            }
+
+            let mut page = serde_json::from_str::<Value>(&rsp)?;
+            if let Some(data) = page.get_mut("data").and_then(Value::as_array_mut) {
+                let txns = state.txns.lock().unwrap();
+                let synthetic = txns.iter().rev().map(|txn| txn.history_entry(&person_id));
+                *data = synthetic.chain(data.drain(..)).collect();
+            }
+
+            Ok(page.to_string())
+        })
+    }
+
+    fn call_form(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        form: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        self.inner.call_form(endpoint, method, params, form)
+    }
+
+    fn call_bytes(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, StdError>> + Send + 'static>> {
+        self.inner.call_bytes(endpoint, method, params)
+    }
+}
+
+#[cfg(all(test, feature = "payments"))]
+mod tests {
+    use {super::*, crate::clock::ManualClock, tokio::stream::StreamExt};
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 2, 1, 12, 0, 0).unwrap()
+    }
+
+    /// Sandbox client at [`now`] over a typical wallet.
+    fn client() -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let phone: PhoneNumber = "+79991234567".parse().unwrap();
+        let transport = Arc::new(OfflineTransport::typical_wallet(&phone));
+        let clock = Arc::new(ManualClock::new(now()));
+        let client = Client::builder(phone, "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .sandbox(true)
+            .build();
+        (client, transport, clock)
+    }
+
+    fn transfer() -> TransferRequest {
+        TransferRequest::with_id(
+            1000,
+            BigDecimal::from(100),
+            TransferDirection::Qiwi {
+                to_phone: "+79035550101".parse().unwrap(),
+                to_currency: penny::Currency::RUB,
+            },
+            "Lunch",
+        )
+    }
+
+    #[tokio::test]
+    async fn payments_are_accepted_without_reaching_qiwi() {
+        let (client, transport, _) = client();
+
+        let first = client.transfer(&transfer()).await.unwrap();
+        let second = client.transfer(&transfer()).await.unwrap();
+        assert_eq!(first.transaction.state.code, "Accepted");
+        assert_eq!(
+            (
+                first.transaction.id.as_str(),
+                second.transaction.id.as_str()
+            ),
+            ("9000000000000000", "9000000000000001")
+        );
+        assert!(transport
+            .requests()
+            .iter()
+            .all(|(method, _)| *method != Method::POST));
+
+        // Reads still go to the inner transport.
+        client.profile_info().await.unwrap();
+        assert_eq!(
+            transport.requests().pop().unwrap().1,
+            "person-profile/v1/profile/current"
+        );
+    }
+
+    #[tokio::test]
+    async fn accepted_payments_succeed_on_later_polls() {
+        let (client, transport, _) = client();
+        let txn_id = client
+            .transfer(&transfer())
+            .await
+            .unwrap()
+            .transaction
+            .id
+            .parse()
+            .unwrap();
+
+        let mut statuses = Vec::new();
+        for _ in 0..3 {
+            statuses.push(
+                client
+                    .transaction_info(txn_id, TransactionType::Out)
+                    .await
+                    .unwrap()
+                    .status,
+            );
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                PaymentStatus::Waiting,
+                PaymentStatus::Success,
+                PaymentStatus::Success
+            ]
+        );
+        assert!(transport.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn payments_are_woven_into_history() {
+        let (client, _, clock) = client();
+        client.transfer(&transfer()).await.unwrap();
+        clock.advance(std::time::Duration::from_secs(60));
+        client.transfer(&transfer()).await.unwrap();
+
+        let mut history = client.payment_history();
+        let mut entries = Vec::new();
+        while let Some(entry) = history.next().await {
+            entries.push(entry.unwrap());
+        }
+        assert_eq!(entries.len(), fixtures::TYPICAL_HISTORY_LEN + 2);
+
+        let synthetic = entries
+            .iter()
+            .take_while(|entry| entry.extras.contains_key(SANDBOX_EXTRA))
+            .map(|entry| (entry.txn_id, entry.date, entry.trm_txn_id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            synthetic,
+            vec![
+                (
+                    SYNTHETIC_TXN_BASE + 1,
+                    now() + chrono::Duration::minutes(1),
+                    "1000"
+                ),
+                (SYNTHETIC_TXN_BASE, now(), "1000"),
+            ]
+        );
+        assert!(entries[2..]
+            .iter()
+            .all(|entry| !entry.extras.contains_key(SANDBOX_EXTRA)));
+    }
+}
@@ -0,0 +1,294 @@
+//! Persistence of state that should survive restarts, e.g. of [`Client::watch_payments`](crate::Client::watch_payments).
+
+use {
+    crate::{
+        codec::{self, Codec, JsonCodec},
+        StdError,
+    },
+    async_trait::async_trait,
+    serde::{de::DeserializeOwned, Serialize},
+    std::{
+        collections::HashMap,
+        fmt::Debug,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Key-value storage for client state, see [`ClientBuilder::state_store`](crate::ClientBuilder::state_store).
+#[async_trait]
+pub trait StateStore: Debug + Send + Sync + 'static {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StdError>;
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), StdError>;
+    /// Removes the value and returns it. Of concurrent calls for the same key, only one gets the value.
+    async fn take(&self, key: &str) -> Result<Option<Vec<u8>>, StdError>;
+
+    /// Format of values written to the store. Values are read in any format.
+    fn codec(&self) -> &dyn Codec {
+        &JsonCodec
+    }
+}
+
+#[async_trait]
+impl<S: StateStore + ?Sized> StateStore for Arc<S> {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StdError> {
+        (**self).get(key).await
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), StdError> {
+        (**self).put(key, value).await
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<Vec<u8>>, StdError> {
+        (**self).take(key).await
+    }
+
+    fn codec(&self) -> &dyn Codec {
+        (**self).codec()
+    }
+}
+
+/// State kept for the lifetime of the process only.
+#[derive(Debug, Default)]
+pub struct MemoryStateStore {
+    values: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+#[async_trait]
+impl StateStore for MemoryStateStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StdError> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), StdError> {
+        self.values.lock().unwrap().insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn take(&self, key: &str) -> Result<Option<Vec<u8>>, StdError> {
+        Ok(self.values.lock().unwrap().remove(key))
+    }
+}
+
+/// State kept in a directory, one `<key>.json` file per key, or other extension with another codec.
+///
+/// Characters other than ASCII letters, digits, `-`, `_` and `.` are replaced with `_` in file names.
+/// Files are replaced atomically, so a crash leaves either the old or the new value.
+/// After switching codecs, values are still read from files written with the old one.
+#[derive(Clone, Debug)]
+pub struct FileStateStore {
+    dir: PathBuf,
+    codec: Arc<dyn Codec>,
+}
+
+impl FileStateStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+            codec: Arc::new(JsonCodec),
+        }
+    }
+
+    /// Writes values with `codec` instead of JSON.
+    pub fn with_codec<C: Codec>(mut self, codec: C) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.path_with(key, self.codec.extension())
+    }
+
+    /// File written before switching from JSON to another codec.
+    fn legacy_path(&self, key: &str) -> Option<PathBuf> {
+        let extension = JsonCodec.extension();
+        if self.codec.extension() == extension {
+            None
+        } else {
+            Some(self.path_with(key, extension))
+        }
+    }
+
+    fn path_with(&self, key: &str, extension: &str) -> PathBuf {
+        let name = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        self.dir.join(format!("{}.{}", name, extension))
+    }
+
+    async fn read(path: &Path) -> Result<Option<Vec<u8>>, StdError> {
+        match tokio::fs::read(path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn take_file(path: &Path) -> Result<Option<Vec<u8>>, StdError> {
+        let taken = path.with_extension(format!("taken.{}", uuid::Uuid::new_v4()));
+        match tokio::fs::rename(path, &taken).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let data = tokio::fs::read(&taken).await?;
+        tokio::fs::remove_file(&taken).await?;
+        Ok(Some(data))
+    }
+}
+
+#[async_trait]
+impl StateStore for FileStateStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StdError> {
+        if let Some(data) = Self::read(&self.path(key)).await? {
+            return Ok(Some(data));
+        }
+        match self.legacy_path(key) {
+            Some(path) => Self::read(&path).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<(), StdError> {
+        let path = self.path(key);
+        let tmp = path.with_extension(format!("{}.tmp", self.codec.extension()));
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&tmp, value).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        // The new value takes precedence, the legacy file would only come back after a take.
+        if let Some(legacy) = self.legacy_path(key) {
+            match tokio::fs::remove_file(&legacy).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// The file is first renamed to a unique name, so that only one caller can read it.
+    async fn take(&self, key: &str) -> Result<Option<Vec<u8>>, StdError> {
+        if let Some(data) = Self::take_file(&self.path(key)).await? {
+            return Ok(Some(data));
+        }
+        match self.legacy_path(key) {
+            Some(path) => Self::take_file(&path).await,
+            None => Ok(None),
+        }
+    }
+
+    fn codec(&self) -> &dyn Codec {
+        &*self.codec
+    }
+}
+
+pub(crate) async fn load<T: DeserializeOwned>(
+    store: &dyn StateStore,
+    key: &str,
+) -> Result<Option<T>, StdError> {
+    match store.get(key).await? {
+        Some(data) => Ok(Some(codec::decode(&data)?)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) async fn save<T: Serialize + Sync>(
+    store: &dyn StateStore,
+    key: &str,
+    value: &T,
+) -> Result<(), StdError> {
+    store.put(key, codec::encode(store.codec(), value)?).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "watch-payments.79991234567";
+
+    #[tokio::test]
+    async fn memory_store_round_trip() {
+        let store = MemoryStateStore::default();
+        assert_eq!(store.get(KEY).await.unwrap(), None);
+
+        store.put(KEY, b"1".to_vec()).await.unwrap();
+        store.put(KEY, b"2".to_vec()).await.unwrap();
+        assert_eq!(store.get(KEY).await.unwrap(), Some(b"2".to_vec()));
+
+        assert_eq!(store.take(KEY).await.unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.take(KEY).await.unwrap(), None);
+        assert_eq!(store.get(KEY).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn file_store_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStateStore::new(dir.path().join("state"));
+        store.put(KEY, b"1".to_vec()).await.unwrap();
+        store.put(KEY, b"2".to_vec()).await.unwrap();
+        drop(store);
+
+        let reopened = FileStateStore::new(dir.path().join("state"));
+        assert_eq!(reopened.get(KEY).await.unwrap(), Some(b"2".to_vec()));
+        // No temporary files are left behind.
+        let files = std::fs::read_dir(reopened.dir())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec![format!("{}.json", KEY)]);
+
+        assert_eq!(reopened.take(KEY).await.unwrap(), Some(b"2".to_vec()));
+        assert_eq!(reopened.get(KEY).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn file_names_are_sanitized() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStateStore::new(dir.path());
+        store
+            .put("export/../all rows", b"1".to_vec())
+            .await
+            .unwrap();
+
+        assert!(dir.path().join("export_.._all_rows.json").is_file());
+        assert_eq!(
+            store.get("export/../all rows").await.unwrap(),
+            Some(b"1".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_takes_get_the_value_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStateStore::new(dir.path());
+        store.put(KEY, b"1".to_vec()).await.unwrap();
+
+        let (first, second) = tokio::join!(store.take(KEY), store.take(KEY));
+        let mut taken = vec![first.unwrap(), second.unwrap()];
+        taken.sort();
+        assert_eq!(taken, vec![None, Some(b"1".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn typed_values_round_trip() {
+        let store = MemoryStateStore::default();
+        assert_eq!(load::<Vec<u64>>(&store, KEY).await.unwrap(), None);
+
+        save(&store, KEY, &vec![1u64, 2]).await.unwrap();
+        assert_eq!(
+            load::<Vec<u64>>(&store, KEY).await.unwrap(),
+            Some(vec![1, 2])
+        );
+    }
+}
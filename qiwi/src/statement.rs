@@ -0,0 +1,264 @@
+use {crate::*, std::future::Future};
+
+/// Longest period a statement can cover.
+pub const MAX_STATEMENT_DAYS: i64 = 90;
+
+/// Returned by QIWI while the statement is being generated.
+const STATEMENT_NOT_READY: &str = "statement.not.ready";
+
+/// Returned by QIWI until the bank issues the payment order.
+#[cfg(feature = "history")]
+const DOCUMENT_NOT_READY: &str = "document.not.ready";
+
+/// Downloads a receipt, see [`Client::cheque`].
+pub(crate) fn fetch_cheque(
+    caller: &CallerWrapper,
+    txn_id: u64,
+    txn_type: TransactionType,
+    format: ChequeFormat,
+) -> impl Future<Output = QiwiResult<Vec<u8>>> + Send + 'static {
+    let params = QueryParams::new()
+        .with("type", txn_type.as_str())
+        .with("format", format.as_str());
+    let data = caller.call_bytes(
+        format!("payment-history/v1/transactions/{}/cheque/file", txn_id),
+        Method::GET,
+        &params,
+    );
+
+    async move {
+        let data = data.await?;
+        if let Ok(Rsp::Error(error)) = serde_json::from_slice::<Rsp<Value>>(&data) {
+            return Err(Error::QiwiError { error });
+        }
+
+        Ok(data)
+    }
+}
+
+impl Client {
+    /// Official statement of the wallet for `from..=till`, days in the local time of the region.
+    ///
+    /// Generating a statement takes a while. Until it is ready, [`Error::StatementNotReady`] is
+    /// returned and the call should be repeated a few minutes later.
+    pub async fn account_statement(
+        &self,
+        from: NaiveDate,
+        till: NaiveDate,
+        format: StatementFormat,
+    ) -> QiwiResult<Vec<u8>> {
+        ensure!(
+            from <= till && (till - from).num_days() < MAX_STATEMENT_DAYS,
+            InvalidStatementPeriod { from, till }
+        );
+
+        let offset = self.region.utc_offset();
+        let start = offset
+            .from_local_datetime(&from.and_hms_opt(0, 0, 0).unwrap())
+            .unwrap();
+        let end = offset
+            .from_local_datetime(
+                &till
+                    .succ_opt()
+                    .unwrap_or(till)
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap(),
+            )
+            .unwrap();
+        let params = QueryParams::new()
+            .with("startDate", start.to_rfc3339())
+            .with("endDate", end.to_rfc3339())
+            .with("format", format.as_str());
+
+        let data = self
+            .caller
+            .call_bytes(
+                format!("payment-history/v1/persons/{}/statement/file", self.user),
+                Method::GET,
+                &params,
+            )
+            .await?;
+
+        // Errors come as JSON instead of the document.
+        if let Ok(Rsp::Error(error)) = serde_json::from_slice::<Rsp<Value>>(&data) {
+            ensure!(error.error_code != STATEMENT_NOT_READY, StatementNotReady);
+            return Err(Error::QiwiError { error });
+        }
+
+        Ok(data)
+    }
+
+    /// Receipt of a successful transaction.
+    pub async fn cheque(
+        &self,
+        txn_id: u64,
+        txn_type: TransactionType,
+        format: ChequeFormat,
+    ) -> QiwiResult<Vec<u8>> {
+        fetch_cheque(&self.caller, txn_id, txn_type, format).await
+    }
+
+    /// Asks QIWI to email the receipt of a successful transaction to `email`.
+    pub async fn send_cheque_to_email(
+        &self,
+        txn_id: u64,
+        txn_type: TransactionType,
+        email: &str,
+    ) -> QiwiResult<()> {
+        self.caller
+            .call::<_, ()>(
+                format!("payment-history/v1/transactions/{}/cheque/send", txn_id),
+                Method::POST,
+                &Default::default(),
+                Some(&json!({
+                    "type": txn_type.as_str(),
+                    "email": email,
+                })),
+            )
+            .await?
+            .into_result()
+    }
+
+    /// Payment order issued by the bank for an outgoing transfer to a bank account, as PDF.
+    ///
+    /// The document appears once the transfer settles, see `bank_document_ready` of
+    /// [`PaymentHistoryEntry`]. Until then [`Error::DocumentNotReady`] is returned. Other payments,
+    /// e.g. wallet to wallet, never have one and fail with [`Error::NoBankDocument`].
+    #[cfg(feature = "history")]
+    pub async fn bank_document(&self, txn_id: u64) -> QiwiResult<Vec<u8>> {
+        let entry = self.transaction_info(txn_id, TransactionType::Out).await?;
+        ensure!(entry.bank_document_available, NoBankDocument { txn_id });
+        ensure!(entry.bank_document_ready, DocumentNotReady { txn_id });
+
+        let data = self
+            .caller
+            .call_bytes(
+                format!(
+                    "{}/bank-document/file",
+                    self.api_versions.transaction_endpoint(txn_id)
+                ),
+                Method::GET,
+                &QueryParams::new().with("format", "PDF"),
+            )
+            .await?;
+
+        if let Ok(Rsp::Error(error)) = serde_json::from_slice::<Rsp<Value>>(&data) {
+            ensure!(
+                error.error_code != DOCUMENT_NOT_READY,
+                DocumentNotReady { txn_id }
+            );
+            return Err(Error::QiwiError { error });
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(all(test, feature = "history"))]
+mod tests {
+    use super::*;
+
+    const TXN_ID: u64 = 20_000_000_001;
+
+    fn transaction() -> String {
+        ApiVersions::default().transaction_endpoint(TXN_ID)
+    }
+
+    fn document() -> String {
+        format!("{}/bank-document/file", transaction())
+    }
+
+    /// Client answering the transaction with the given document flags.
+    fn client(available: bool, ready: bool) -> (Client, Arc<OfflineTransport>) {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["txnId"] = json!(TXN_ID);
+        entry["bankDocumentAvailable"] = json!(available);
+        entry["bankDocumentReady"] = json!(ready);
+        let transport = Arc::new(OfflineTransport::new().with(Method::GET, transaction(), &entry));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .build();
+        (client, transport)
+    }
+
+    fn endpoints(transport: &OfflineTransport) -> Vec<String> {
+        transport
+            .requests()
+            .into_iter()
+            .map(|(_, endpoint)| endpoint)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ready_document_is_downloaded() {
+        let (client, transport) = client(true, true);
+        transport.insert(Method::GET, document(), &json!("%PDF-1.4"));
+
+        let data = client.bank_document(TXN_ID).await.unwrap();
+        assert_eq!(data, json!("%PDF-1.4").to_string().into_bytes());
+        let recorded = transport.recorded();
+        assert_eq!(
+            recorded[0].params,
+            vec![("type".to_string(), "OUT".to_string())]
+        );
+        assert_eq!(recorded[1].endpoint, document());
+        assert_eq!(
+            recorded[1].params,
+            vec![("format".to_string(), "PDF".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsettled_transfer_is_not_ready() {
+        let (client, transport) = client(true, false);
+
+        match client.bank_document(TXN_ID).await {
+            Err(e @ Error::DocumentNotReady { txn_id: TXN_ID }) => {
+                assert_eq!(e.retry_later(), Some(DOCUMENT_RETRY_DELAY));
+                assert!(e.is_retryable());
+            }
+            other => panic!("expected DocumentNotReady, got {:?}", other),
+        }
+        // The document is not requested before the flag is set.
+        assert_eq!(endpoints(&transport), vec![transaction()]);
+    }
+
+    #[tokio::test]
+    async fn document_not_issued_yet_is_not_ready() {
+        let (client, transport) = client(true, true);
+        transport.push(
+            Method::GET,
+            document(),
+            &json!({ "errorCode": DOCUMENT_NOT_READY }),
+        );
+        transport.push(
+            Method::GET,
+            document(),
+            &json!({ "errorCode": "payment.blocked" }),
+        );
+
+        match client.bank_document(TXN_ID).await {
+            Err(Error::DocumentNotReady { txn_id: TXN_ID }) => {}
+            other => panic!("expected DocumentNotReady, got {:?}", other),
+        }
+        // Other errors are passed as they are.
+        match client.bank_document(TXN_ID).await {
+            Err(Error::QiwiError { error }) => assert_eq!(error.error_code, "payment.blocked"),
+            other => panic!("expected QiwiError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn wallet_transfer_has_no_document() {
+        let (client, transport) = client(false, false);
+
+        match client.bank_document(TXN_ID).await {
+            Err(e @ Error::NoBankDocument { txn_id: TXN_ID }) => {
+                assert_eq!(e.retry_later(), None);
+                assert!(!e.is_retryable());
+            }
+            other => panic!("expected NoBankDocument, got {:?}", other),
+        }
+        assert_eq!(endpoints(&transport), vec![transaction()]);
+    }
+}
@@ -0,0 +1,153 @@
+//! Adapters for the client's streams.
+
+use std::{
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::stream::Stream;
+
+/// Stream of items of a fallible stream collected into chunks. See [`TryStreamExt::try_chunks`].
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct TryChunks<S, T, E> {
+    stream: S,
+    cap: usize,
+    buf: Vec<T>,
+    pending_error: Option<E>,
+    done: bool,
+}
+
+// Fields are never pinned.
+impl<S: Unpin, T, E> Unpin for TryChunks<S, T, E> {}
+
+impl<S, T, E> TryChunks<S, T, E> {
+    fn take_chunk(&mut self) -> Vec<T> {
+        mem::replace(&mut self.buf, Vec::with_capacity(self.cap))
+    }
+}
+
+impl<S, T, E> Stream for TryChunks<S, T, E>
+where
+    S: Stream<Item = Result<T, E>> + Unpin,
+{
+    type Item = Result<Vec<T>, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(e) = this.pending_error.take() {
+            return Poll::Ready(Some(Err(e)));
+        }
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        loop {
+            match Pin::new(&mut this.stream).poll_next(cx) {
+                Poll::Ready(Some(Ok(item))) => {
+                    this.buf.push(item);
+                    if this.buf.len() >= this.cap {
+                        return Poll::Ready(Some(Ok(this.take_chunk())));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    if this.buf.is_empty() {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+
+                    // Buffered items go out first, the error follows on the next poll.
+                    this.pending_error = Some(e);
+                    return Poll::Ready(Some(Ok(this.take_chunk())));
+                }
+                Poll::Ready(None) => {
+                    this.done = true;
+                    if this.buf.is_empty() {
+                        return Poll::Ready(None);
+                    }
+
+                    return Poll::Ready(Some(Ok(this.take_chunk())));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+pub trait TryStreamExt: Stream {
+    /// Collects items into chunks of `cap`, e.g. for bulk inserts.
+    ///
+    /// The last chunk may be shorter. If the stream fails, items buffered so far are yielded
+    /// as a chunk before the error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cap` is zero.
+    fn try_chunks<T, E>(self, cap: usize) -> TryChunks<Self, T, E>
+    where
+        Self: Stream<Item = Result<T, E>> + Sized,
+    {
+        assert!(cap > 0, "chunk size must be positive");
+        TryChunks {
+            stream: self,
+            cap,
+            buf: Vec::with_capacity(cap),
+            pending_error: None,
+            done: false,
+        }
+    }
+}
+
+impl<S: Stream> TryStreamExt for S {}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, tokio::stream::StreamExt};
+
+    async fn chunks(
+        items: Vec<Result<u32, &'static str>>,
+        cap: usize,
+    ) -> Vec<Result<Vec<u32>, &'static str>> {
+        tokio::stream::iter(items).try_chunks(cap).collect().await
+    }
+
+    #[tokio::test]
+    async fn exact_multiple_has_no_partial_chunk() {
+        assert_eq!(
+            chunks((1..=6).map(Ok).collect(), 3).await,
+            vec![Ok(vec![1, 2, 3]), Ok(vec![4, 5, 6])]
+        );
+    }
+
+    #[tokio::test]
+    async fn short_last_chunk_is_flushed_at_end() {
+        assert_eq!(
+            chunks((1..=7).map(Ok).collect(), 3).await,
+            vec![Ok(vec![1, 2, 3]), Ok(vec![4, 5, 6]), Ok(vec![7])]
+        );
+        assert!(chunks(vec![], 3).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn buffered_items_are_yielded_before_error() {
+        assert_eq!(
+            chunks(vec![Ok(1), Ok(2), Ok(3), Ok(4), Err("failed"), Ok(5)], 3).await,
+            vec![Ok(vec![1, 2, 3]), Ok(vec![4]), Err("failed"), Ok(vec![5])]
+        );
+    }
+
+    #[tokio::test]
+    async fn error_after_full_chunk_is_yielded_alone() {
+        assert_eq!(
+            chunks(vec![Ok(1), Ok(2), Err("failed"), Err("again")], 2).await,
+            vec![Ok(vec![1, 2]), Err("failed"), Err("again")]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk size must be positive")]
+    fn zero_cap_panics() {
+        let _ = tokio::stream::iter(Vec::<Result<u32, ()>>::new()).try_chunks(0);
+    }
+}
@@ -0,0 +1,360 @@
+//! Mirroring payment history into another storage, e.g. a database.
+
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+    std::collections::HashSet,
+};
+
+/// Position of a [`HistorySyncer`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncCursor {
+    /// Entries older than this are no longer tracked.
+    pub window_start: DateTime<Utc>,
+    /// Highest acknowledged transaction id.
+    pub high_water_txn_id: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+pub enum SyncEvent {
+    /// Entry not seen before.
+    New(PaymentHistoryEntry),
+    /// Entry whose status changed since it was last seen.
+    Updated(PaymentHistoryEntry),
+    /// Entry that was seen within the window before but is no longer in history,
+    /// e.g. because QIWI reversed it.
+    Removed { txn_id: u64 },
+    /// Entries older than the cursor's window start are no longer tracked. They are still in history,
+    /// and are not reported as removed.
+    WindowAdvanced(SyncCursor),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tracked {
+    status: PaymentStatus,
+    date: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SyncState {
+    window_start: Option<DateTime<Utc>>,
+    high_water_txn_id: Option<u64>,
+    tracked: BTreeMap<u64, Tracked>,
+}
+
+/// Compares payment history with what was seen before and reports the differences.
+///
+/// Progress is saved only by [`HistorySyncer::ack`], so events of a poll that were not acknowledged,
+/// e.g. because the process crashed while handling them, are emitted again by the next poll.
+pub struct HistorySyncer<'a> {
+    client: &'a Client,
+    key: String,
+    window: chrono::Duration,
+    acked: Option<SyncState>,
+    pending: Option<SyncState>,
+}
+
+impl<'a> HistorySyncer<'a> {
+    async fn acked_state(&mut self) -> QiwiResult<SyncState> {
+        if let Some(state) = &self.acked {
+            return Ok(state.clone());
+        }
+
+        let state = state::load(&*self.client.local_state, &self.key)
+            .await
+            .context(StateStoreError)?
+            .unwrap_or_default();
+        self.acked = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Scans history within the window and returns the changes since the last acknowledged poll, oldest first.
+    pub async fn poll(&mut self) -> QiwiResult<Vec<SyncEvent>> {
+        let mut state = self.acked_state().await?;
+        let window_start = self.client.clock.utc_now() - self.window;
+
+        let mut changes = Vec::new();
+        let mut seen = HashSet::new();
+        let mut history = self.client.payment_history();
+        while let Some(entry) = history.next().await.transpose()? {
+            if entry.date < window_start {
+                break;
+            }
+
+            seen.insert(entry.txn_id);
+            let event = match state.tracked.get(&entry.txn_id) {
+                None => SyncEvent::New,
+                Some(tracked) if tracked.status != entry.status => SyncEvent::Updated,
+                Some(_) => continue,
+            };
+            state.tracked.insert(
+                entry.txn_id,
+                Tracked {
+                    status: entry.status.clone(),
+                    date: entry.date,
+                },
+            );
+            state.high_water_txn_id = state.high_water_txn_id.max(Some(entry.txn_id));
+            changes.push(event(entry));
+        }
+        let mut events = changes.into_iter().rev().collect::<Vec<_>>();
+
+        let removed = state
+            .tracked
+            .iter()
+            .filter(|(txn_id, tracked)| tracked.date >= window_start && !seen.contains(*txn_id))
+            .map(|(&txn_id, _)| txn_id)
+            .collect::<Vec<_>>();
+        for txn_id in removed {
+            state.tracked.remove(&txn_id);
+            events.push(SyncEvent::Removed { txn_id });
+        }
+
+        let tracked = state.tracked.len();
+        state
+            .tracked
+            .retain(|_, tracked| tracked.date >= window_start);
+        state.window_start = Some(window_start);
+        if state.tracked.len() < tracked {
+            events.push(SyncEvent::WindowAdvanced(SyncCursor {
+                window_start,
+                high_water_txn_id: state.high_water_txn_id,
+            }));
+        }
+
+        self.pending = Some(state);
+        Ok(events)
+    }
+
+    /// Saves the progress of the last poll, once its events have been handled.
+    pub async fn ack(&mut self) -> QiwiResult<()> {
+        if let Some(state) = self.pending.take() {
+            state::save(&*self.client.local_state, &self.key, &state)
+                .await
+                .context(StateStoreError)?;
+            self.acked = Some(state);
+        }
+
+        Ok(())
+    }
+
+    /// Position as of the last acknowledged poll.
+    pub fn cursor(&self) -> Option<SyncCursor> {
+        let state = self.acked.as_ref()?;
+        Some(SyncCursor {
+            window_start: state.window_start?,
+            high_water_txn_id: state.high_water_txn_id,
+        })
+    }
+}
+
+impl Client {
+    /// Syncer tracking entries of the last `window` of history, with progress saved under `key`.
+    ///
+    /// Progress survives restarts only with a [state store](ClientBuilder::state_store).
+    pub fn history_syncer(&self, key: &str, window: std::time::Duration) -> HistorySyncer<'_> {
+        HistorySyncer {
+            client: self,
+            key: format!("history-sync.{}", key),
+            window: chrono::Duration::from_std(window)
+                .unwrap_or_else(|_| chrono::Duration::days(RECONCILIATION_WINDOW_DAYS)),
+            acked: None,
+            pending: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::clock::ManualClock, serde_json::json, std::time::Duration};
+
+    const WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn phone() -> PhoneNumber {
+        "+79991234567".parse().unwrap()
+    }
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 31, 12, 0, 0).unwrap()
+    }
+
+    /// Entry made `hours_ago` before [`start`].
+    fn entry(txn_id: u64, hours_ago: i64, status: &str) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["txnId"] = json!(txn_id);
+        entry["date"] = json!((start() - chrono::Duration::hours(hours_ago)).to_rfc3339());
+        entry["status"] = json!(status);
+        entry
+    }
+
+    /// Process with its own client, sharing the transport, the clock and the state store, so
+    /// that dropping it without an ack simulates a crash.
+    struct World {
+        transport: Arc<OfflineTransport>,
+        clock: Arc<ManualClock>,
+        store: Arc<state::MemoryStateStore>,
+    }
+
+    impl World {
+        fn new(entries: Vec<Value>) -> Self {
+            let world = Self {
+                transport: Arc::new(OfflineTransport::new()),
+                clock: Arc::new(ManualClock::new(start())),
+                store: Arc::new(state::MemoryStateStore::default()),
+            };
+            world.set_history(entries);
+            world
+        }
+
+        fn set_history(&self, entries: Vec<Value>) {
+            self.transport.insert(
+                Method::GET,
+                ApiVersions::default().history_endpoint(&QiwiUser::from(phone())),
+                &fixtures::history_page(entries),
+            );
+        }
+
+        fn client(&self) -> Client {
+            Client::builder(phone(), "")
+                .transport(self.transport.clone())
+                .clock(self.clock.clone())
+                .state_store(self.store.clone())
+                .build()
+        }
+    }
+
+    /// Events as comparable strings, e.g. `new 3`.
+    fn describe(events: &[SyncEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                SyncEvent::New(entry) => format!("new {}", entry.txn_id),
+                SyncEvent::Updated(entry) => format!("updated {} {:?}", entry.txn_id, entry.status),
+                SyncEvent::Removed { txn_id } => format!("removed {}", txn_id),
+                SyncEvent::WindowAdvanced(cursor) => {
+                    format!("window {}", cursor.window_start.to_rfc3339())
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn crash_before_ack_redelivers() {
+        let world = World::new(vec![
+            entry(3, 1, "SUCCESS"),
+            entry(2, 2, "WAITING"),
+            entry(1, 3, "SUCCESS"),
+        ]);
+
+        {
+            let client = world.client();
+            let mut syncer = client.history_syncer("db", WINDOW);
+            let events = syncer.poll().await.unwrap();
+            assert_eq!(describe(&events), vec!["new 1", "new 2", "new 3"]);
+            // Crash while handling the events, nothing acknowledged.
+            assert!(syncer.cursor().is_none());
+        }
+
+        let client = world.client();
+        let mut syncer = client.history_syncer("db", WINDOW);
+        let events = syncer.poll().await.unwrap();
+        assert_eq!(describe(&events), vec!["new 1", "new 2", "new 3"]);
+        syncer.ack().await.unwrap();
+        assert_eq!(syncer.cursor().unwrap().high_water_txn_id, Some(3));
+
+        assert!(syncer.poll().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ack_survives_restart() {
+        let world = World::new(vec![entry(2, 2, "WAITING"), entry(1, 3, "SUCCESS")]);
+        {
+            let client = world.client();
+            let mut syncer = client.history_syncer("db", WINDOW);
+            syncer.poll().await.unwrap();
+            syncer.ack().await.unwrap();
+        }
+
+        world.set_history(vec![
+            entry(3, 1, "SUCCESS"),
+            entry(2, 2, "SUCCESS"),
+            entry(1, 3, "SUCCESS"),
+        ]);
+        {
+            let client = world.client();
+            let mut syncer = client.history_syncer("db", WINDOW);
+            let events = syncer.poll().await.unwrap();
+            assert_eq!(describe(&events), vec!["updated 2 Success", "new 3"]);
+            // The cursor is that of the acknowledged poll until this one is acknowledged.
+            assert_eq!(syncer.cursor().unwrap().high_water_txn_id, Some(2));
+        }
+
+        // Crashed again, the same changes come back.
+        let client = world.client();
+        let mut syncer = client.history_syncer("db", WINDOW);
+        let events = syncer.poll().await.unwrap();
+        assert_eq!(describe(&events), vec!["updated 2 Success", "new 3"]);
+        syncer.ack().await.unwrap();
+        assert_eq!(syncer.cursor().unwrap().high_water_txn_id, Some(3));
+        assert!(syncer.poll().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unacked_poll_is_replaced_by_the_next() {
+        let world = World::new(vec![entry(1, 3, "SUCCESS")]);
+        let client = world.client();
+        let mut syncer = client.history_syncer("db", WINDOW);
+        assert_eq!(describe(&syncer.poll().await.unwrap()), vec!["new 1"]);
+
+        // Polling again without an ack reports the changes since the last ack, not since the last poll.
+        world.set_history(vec![entry(2, 1, "SUCCESS"), entry(1, 3, "SUCCESS")]);
+        assert_eq!(
+            describe(&syncer.poll().await.unwrap()),
+            vec!["new 1", "new 2"]
+        );
+        syncer.ack().await.unwrap();
+        assert!(syncer.poll().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn window_advances_on_the_clock() {
+        let world = World::new(vec![entry(2, 1, "SUCCESS"), entry(1, 20, "SUCCESS")]);
+        let client = world.client();
+        let mut syncer = client.history_syncer("db", WINDOW);
+        syncer.poll().await.unwrap();
+        syncer.ack().await.unwrap();
+        assert_eq!(
+            syncer.cursor().unwrap().window_start,
+            start() - chrono::Duration::days(1)
+        );
+
+        // Entry 1 drops out of the window, it is not reported as removed.
+        world.clock.advance(Duration::from_secs(5 * 60 * 60));
+        let events = syncer.poll().await.unwrap();
+        let window_start = start() + chrono::Duration::hours(5) - chrono::Duration::days(1);
+        assert_eq!(
+            describe(&events),
+            vec![format!("window {}", window_start.to_rfc3339())]
+        );
+        syncer.ack().await.unwrap();
+        assert_eq!(syncer.cursor().unwrap().window_start, window_start);
+        assert!(syncer.poll().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn entry_missing_within_window_is_removed() {
+        let world = World::new(vec![entry(2, 1, "SUCCESS"), entry(1, 3, "SUCCESS")]);
+        let client = world.client();
+        let mut syncer = client.history_syncer("db", WINDOW);
+        syncer.poll().await.unwrap();
+        syncer.ack().await.unwrap();
+
+        world.set_history(vec![entry(2, 1, "SUCCESS")]);
+        assert_eq!(describe(&syncer.poll().await.unwrap()), vec!["removed 1"]);
+        syncer.ack().await.unwrap();
+        assert!(syncer.poll().await.unwrap().is_empty());
+    }
+}
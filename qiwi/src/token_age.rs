@@ -0,0 +1,214 @@
+use {
+    crate::*,
+    serde::{Deserialize, Serialize},
+};
+
+/// QIWI does not report token expiry, personal tokens are usually valid for about six months.
+pub const DEFAULT_TOKEN_LIFETIME_DAYS: i64 = 180;
+
+/// How long before the estimated expiry warnings start by default.
+pub const DEFAULT_TOKEN_WARNING_DAYS: i64 = 14;
+
+const TOKEN_ISSUED_KEY: &str = "token-issued";
+
+/// Issue time of the current token as kept in the state store.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenRecord {
+    issued_at: DateTime<Utc>,
+}
+
+impl Client {
+    /// Remembers that the current token was issued at `at`, in the state store if there is one.
+    ///
+    /// Call this after issuing a new token, e.g. together with [`Client::set_token`].
+    pub async fn record_token_issued(&self, at: DateTime<Utc>) -> QiwiResult<()> {
+        *self.token_issued_at.lock().unwrap() = Some(at);
+        state::save(
+            &*self.local_state,
+            TOKEN_ISSUED_KEY,
+            &TokenRecord { issued_at: at },
+        )
+        .await
+        .context(StateStoreError)
+    }
+
+    async fn token_issued_at(&self) -> QiwiResult<Option<DateTime<Utc>>> {
+        if let Some(at) = *self.token_issued_at.lock().unwrap() {
+            return Ok(Some(at));
+        }
+
+        let record = state::load::<TokenRecord>(&*self.local_state, TOKEN_ISSUED_KEY)
+            .await
+            .context(StateStoreError)?;
+        Ok(record.map(|record| {
+            *self.token_issued_at.lock().unwrap() = Some(record.issued_at);
+            record.issued_at
+        }))
+    }
+
+    /// Time since the token was issued, if known, see [`ClientBuilder::token_issued_at`].
+    pub async fn token_age(&self) -> QiwiResult<Option<chrono::Duration>> {
+        Ok(self
+            .token_issued_at()
+            .await?
+            .map(|at| self.clock.utc_now() - at))
+    }
+
+    /// When the token is likely to expire, see [`ClientBuilder::token_lifetime`].
+    ///
+    /// Logs a warning once the estimate is closer than [`ClientBuilder::token_expiry_warning`].
+    pub async fn token_expiry_estimate(&self) -> QiwiResult<Option<DateTime<Utc>>> {
+        let issued_at = match self.token_issued_at().await? {
+            Some(at) => at,
+            None => return Ok(None),
+        };
+
+        let expiry = issued_at + self.token_lifetime;
+        if expiry - self.clock.utc_now() < self.token_expiry_warning {
+            log::warn!(
+                "API token issued at {} is likely to expire around {}, issue a new one at qiwi.com/api",
+                issued_at,
+                expiry
+            );
+        }
+        Ok(Some(expiry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            clock::ManualClock,
+            state::{FileStateStore, MemoryStateStore, StateStore},
+        },
+    };
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 2, 1, 12, 0, 0).unwrap()
+    }
+
+    fn builder() -> ClientBuilder {
+        Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(OfflineTransport::new())
+            .clock(ManualClock::new(now()))
+    }
+
+    #[tokio::test]
+    async fn unknown_without_issue_time() {
+        let client = builder().build();
+        assert_eq!(client.token_age().await.unwrap(), None);
+        assert_eq!(client.token_expiry_estimate().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn estimate_from_issue_time() {
+        let issued = now() - chrono::Duration::days(30);
+        let client = builder().token_issued_at(issued).build();
+        assert_eq!(
+            client.token_age().await.unwrap(),
+            Some(chrono::Duration::days(30))
+        );
+        assert_eq!(
+            client.token_expiry_estimate().await.unwrap(),
+            Some(issued + chrono::Duration::days(DEFAULT_TOKEN_LIFETIME_DAYS))
+        );
+
+        let client = builder()
+            .token_issued_at(issued)
+            .token_lifetime(chrono::Duration::days(90))
+            .build();
+        assert_eq!(
+            client.token_expiry_estimate().await.unwrap(),
+            Some(issued + chrono::Duration::days(90))
+        );
+    }
+
+    #[tokio::test]
+    async fn estimate_is_past_for_old_tokens() {
+        let issued = now() - chrono::Duration::days(DEFAULT_TOKEN_LIFETIME_DAYS + 1);
+        let client = builder().token_issued_at(issued).build();
+        assert!(client.token_expiry_estimate().await.unwrap().unwrap() < now());
+    }
+
+    #[tokio::test]
+    async fn recorded_issue_time_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let issued = now() - chrono::Duration::days(7);
+        builder()
+            .state_store(FileStateStore::new(dir.path()))
+            .build()
+            .record_token_issued(issued)
+            .await
+            .unwrap();
+
+        let restarted = builder()
+            .state_store(FileStateStore::new(dir.path()))
+            .build();
+        assert_eq!(
+            restarted.token_age().await.unwrap(),
+            Some(chrono::Duration::days(7))
+        );
+    }
+
+    #[tokio::test]
+    async fn builder_takes_precedence_over_store() {
+        let store = Arc::new(MemoryStateStore::default());
+        builder()
+            .state_store(store.clone())
+            .build()
+            .record_token_issued(now() - chrono::Duration::days(100))
+            .await
+            .unwrap();
+
+        let client = builder()
+            .state_store(store)
+            .token_issued_at(now() - chrono::Duration::days(1))
+            .build();
+        assert_eq!(
+            client.token_age().await.unwrap(),
+            Some(chrono::Duration::days(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_stored_record_format() {
+        // Records written by earlier versions of the client must keep loading.
+        let store = Arc::new(MemoryStateStore::default());
+        store
+            .put(
+                TOKEN_ISSUED_KEY,
+                br#"{"issuedAt":"2020-01-01T12:00:00Z"}"#.to_vec(),
+            )
+            .await
+            .unwrap();
+
+        let client = builder().state_store(store.clone()).build();
+        assert_eq!(
+            client.token_age().await.unwrap(),
+            Some(chrono::Duration::days(31))
+        );
+
+        client.record_token_issued(now()).await.unwrap();
+        let saved: serde_json::Value =
+            serde_json::from_slice(&store.get(TOKEN_ISSUED_KEY).await.unwrap().unwrap()).unwrap();
+        assert_eq!(
+            saved,
+            serde_json::json!({ "issuedAt": "2020-02-01T12:00:00Z" })
+        );
+    }
+
+    #[tokio::test]
+    async fn broken_record_is_an_error() {
+        let store = Arc::new(MemoryStateStore::default());
+        store
+            .put(TOKEN_ISSUED_KEY, b"not json".to_vec())
+            .await
+            .unwrap();
+
+        let client = builder().state_store(store).build();
+        assert!(client.token_age().await.is_err());
+    }
+}
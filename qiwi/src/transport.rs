@@ -1,17 +1,33 @@
 use {
+    crate::{
+        compat,
+        contract::ResponseValidator,
+        http_cache::{CachedResponse, HttpCache},
+        ids::IdGenerator,
+        portable::PortableError,
+        quota::*,
+    },
+    async_trait::async_trait,
     headers::*,
     http::Method,
     log::*,
     reqwest_ext::*,
-    serde::{Deserialize, Serialize},
+    serde::{ser::SerializeSeq, Deserialize, Serialize, Serializer},
     serde_json::Value,
+    smallvec::SmallVec,
     snafu::*,
     std::{
-        collections::HashMap,
-        fmt::{Debug, Display},
+        borrow::Cow,
+        cell::RefCell,
+        fmt::{self, Debug, Display},
         future::Future,
+        ops::Range,
         pin::Pin,
-        sync::Arc,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex, RwLock,
+        },
+        time::{Duration, Instant},
     },
 };
 
@@ -22,10 +38,25 @@ pub enum Error {
     NetworkError {
         source: StdError,
         backtrace: Backtrace,
+        correlation_id: Option<String>,
     },
     ParseError {
         source: StdError,
         backtrace: Backtrace,
+        correlation_id: Option<String>,
+    },
+    TokenProviderError {
+        source: StdError,
+        backtrace: Backtrace,
+        correlation_id: Option<String>,
+    },
+    #[snafu(display("no offline response for {}", endpoint))]
+    Offline { endpoint: String },
+    #[snafu(display("response of {} violates the contract: {}", endpoint, details))]
+    ContractViolation {
+        endpoint: String,
+        details: String,
+        correlation_id: Option<String>,
     },
 }
 
@@ -34,82 +65,744 @@ impl Error {
     where
         E: std::error::Error + Send + Sync + 'static,
     {
-        NetworkError.into_error(Box::new(error))
+        NetworkError {
+            correlation_id: None::<String>,
+        }
+        .into_error(Box::new(error))
     }
 
     pub fn from_parse_error<E>(error: E) -> Self
     where
         E: std::error::Error + Send + Sync + 'static,
     {
-        ParseError.into_error(Box::new(error))
+        ParseError {
+            correlation_id: None::<String>,
+        }
+        .into_error(Box::new(error))
+    }
+}
+
+/// QIWI error body, e.g.
+///
+/// ```json
+/// {
+///     "serviceName": "payment-history",
+///     "errorCode": "internal.invalid.token",
+///     "description": "Invalid token",
+///     "userMessage": "Неверный токен",
+///     "dateTime": "2020-03-12T09:44:50.726+03:00",
+///     "traceId": "fc4fd1c60d5d1c5e"
+/// }
+/// ```
+///
+/// Only `errorCode` is always present. Displayed as the error code.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QiwiApiError {
+    /// Machine-readable code, e.g. `internal.invalid.token` or `payment.blocked`.
+    #[serde(alias = "error_code")]
+    pub error_code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Message meant for the wallet owner, usually in Russian.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_time: Option<String>,
+    /// Id to give QIWI support when asking about the failure.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    /// Set by [`CallerWrapper::call`].
+    #[serde(skip)]
+    pub correlation_id: Option<String>,
+}
+
+impl QiwiApiError {
+    /// Error with only the code known.
+    pub fn new<S: Into<String>>(error_code: S) -> Self {
+        Self {
+            error_code: error_code.into(),
+            service_name: None,
+            description: None,
+            user_message: None,
+            date_time: None,
+            trace_id: None,
+            correlation_id: None,
+        }
+    }
+}
+
+impl Display for QiwiApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.error_code)
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase", untagged)]
+#[serde(untagged)]
 pub enum Rsp<T> {
-    Error {
-        #[serde(rename = "errorCode")]
-        error: String,
-    },
+    Error(QiwiApiError),
     OK(T),
 }
 
+/// Value that can be written into query string.
+pub trait ParamValue {
+    fn write_to(self, buf: &mut String);
+}
+
+macro_rules! impl_integer_param {
+    ($($t:ty),*) => {
+        $(
+            impl ParamValue for $t {
+                fn write_to(self, buf: &mut String) {
+                    buf.push_str(itoa::Buffer::new().format(self));
+                }
+            }
+        )*
+    };
+}
+
+impl_integer_param!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl ParamValue for bool {
+    fn write_to(self, buf: &mut String) {
+        buf.push_str(if self { "true" } else { "false" });
+    }
+}
+
+impl ParamValue for &str {
+    fn write_to(self, buf: &mut String) {
+        buf.push_str(self);
+    }
+}
+
+impl ParamValue for String {
+    fn write_to(self, buf: &mut String) {
+        buf.push_str(&self);
+    }
+}
+
+impl ParamValue for &String {
+    fn write_to(self, buf: &mut String) {
+        buf.push_str(self);
+    }
+}
+
+/// Key of a query parameter, kept as given or written into the shared buffer.
+#[derive(Clone, Debug)]
+enum ParamKey {
+    Given(Cow<'static, str>),
+    Buffered(Range<usize>),
+}
+
+/// Query string parameters.
+///
+/// All values and indexed keys share one buffer, so building parameters does not allocate
+/// per value. Keys may repeat.
+#[derive(Clone, Default)]
+pub struct QueryParams {
+    buf: String,
+    pairs: SmallVec<[(ParamKey, Range<usize>); 8]>,
+}
+
+impl QueryParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: ParamValue,
+    {
+        let start = self.buf.len();
+        value.write_to(&mut self.buf);
+        self.pairs
+            .push((ParamKey::Given(key.into()), start..self.buf.len()));
+        self
+    }
+
+    /// Pushes `values` under `key[0]`, `key[1]` and so on.
+    pub fn push_indexed<V, I>(&mut self, key: &str, values: I) -> &mut Self
+    where
+        V: ParamValue,
+        I: IntoIterator<Item = V>,
+    {
+        for (i, value) in values.into_iter().enumerate() {
+            let key_start = self.buf.len();
+            self.buf.push_str(key);
+            self.buf.push('[');
+            i.write_to(&mut self.buf);
+            self.buf.push(']');
+            let value_start = self.buf.len();
+            value.write_to(&mut self.buf);
+            self.pairs.push((
+                ParamKey::Buffered(key_start..value_start),
+                value_start..self.buf.len(),
+            ));
+        }
+        self
+    }
+
+    pub fn with<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<Cow<'static, str>>,
+        V: ParamValue,
+    {
+        self.push(key, value);
+        self
+    }
+
+    /// Removes all parameters, keeping allocated memory for reuse.
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.pairs.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(move |(key, value)| {
+            let key = match key {
+                ParamKey::Given(key) => key.as_ref(),
+                ParamKey::Buffered(range) => &self.buf[range.clone()],
+            };
+            (key, &self.buf[value.clone()])
+        })
+    }
+}
+
+impl Debug for QueryParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for QueryParams {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.pairs.len()))?;
+        for pair in self.iter() {
+            seq.serialize_element(&pair)?;
+        }
+        seq.end()
+    }
+}
+
+/// String that is not shown in debug output.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new<T: Into<String>>(secret: T) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SecretString(<redacted>)")
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+/// Source of the API token, queried before requests.
+#[async_trait]
+pub trait TokenProvider: Send + Sync + 'static {
+    async fn token(&self) -> Result<SecretString, StdError>;
+}
+
+#[async_trait]
+impl TokenProvider for String {
+    async fn token(&self) -> Result<SecretString, StdError> {
+        Ok(self.as_str().into())
+    }
+}
+
+#[async_trait]
+impl TokenProvider for &'static str {
+    async fn token(&self) -> Result<SecretString, StdError> {
+        Ok((*self).into())
+    }
+}
+
+/// Token provider failure, passed through [`Transport`] as is.
+#[derive(Debug)]
+pub struct TokenUnavailable(pub StdError);
+
+impl Display for TokenUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to get API token: {}", self.0)
+    }
+}
+
+impl std::error::Error for TokenUnavailable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// Returned by [`RemoteCaller`] for HTTP errors without a QIWI error payload.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    message: String,
+    pub body: String,
+}
+
+impl Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Received error {} with data: {}",
+            self.message, self.body
+        )
+    }
+}
+
+impl HttpStatusError {
+    /// Error with `status` and response `body`, e.g. to be returned by a custom transport.
+    pub fn new<B: Into<String>>(status: u16, body: B) -> Self {
+        Self {
+            status,
+            message: format!("HTTP status {}", status),
+            body: body.into(),
+        }
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Returned by offline transports for endpoints they have no response for.
+#[derive(Debug)]
+pub struct NoFixture {
+    pub endpoint: String,
+}
+
+impl Display for NoFixture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no offline response for {}", self.endpoint)
+    }
+}
+
+impl std::error::Error for NoFixture {}
+
+/// Keeps the token from [`TokenProvider`] for a while, so that it is not queried on every request.
+pub struct TokenCache {
+    provider: Arc<dyn TokenProvider>,
+    ttl: Duration,
+    cached: Mutex<Option<(SecretString, Instant)>>,
+}
+
+impl TokenCache {
+    pub fn new(provider: Arc<dyn TokenProvider>, ttl: Duration) -> Self {
+        Self {
+            provider,
+            ttl,
+            cached: Default::default(),
+        }
+    }
+
+    pub async fn get(&self) -> Result<SecretString, StdError> {
+        let cached = self.cached.lock().unwrap().clone();
+        if let Some((token, fetched_at)) = cached {
+            if fetched_at.elapsed() < self.ttl {
+                return Ok(token);
+            }
+        }
+
+        let token = self.provider.token().await?;
+        *self.cached.lock().unwrap() = Some((token.clone(), Instant::now()));
+
+        Ok(token)
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Drops the cached token, so that it is fetched anew on next request.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap() = None;
+    }
+}
+
+impl Debug for TokenCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TokenCache")
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
 pub trait Transport: Debug + Send + Sync + 'static {
     fn call(
         &self,
         endpoint: String,
         method: Method,
-        params: &HashMap<&str, String>,
+        params: &QueryParams,
         body: Option<&Value>,
     ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>>;
+
+    /// Same as [`Transport::call`] for endpoints taking `application/x-www-form-urlencoded` body.
+    ///
+    /// By default the form is passed to [`Transport::call`] as a JSON object of strings.
+    fn call_form(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        form: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        let body = Value::Object(
+            form.iter()
+                .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+                .collect(),
+        );
+        self.call(endpoint, method, params, Some(&body))
+    }
+
+    /// Same as [`Transport::call`] for endpoints returning documents rather than JSON.
+    ///
+    /// By default the response of [`Transport::call`] is returned as is.
+    fn call_bytes(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, StdError>> + Send + 'static>> {
+        let rsp = self.call(endpoint, method, params, None);
+        Box::pin(async move { Ok(rsp.await?.into_bytes()) })
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn call(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        body: Option<&Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        (**self).call(endpoint, method, params, body)
+    }
+
+    fn call_form(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        form: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        (**self).call_form(endpoint, method, params, form)
+    }
+
+    fn call_bytes(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, StdError>> + Send + 'static>> {
+        (**self).call_bytes(endpoint, method, params)
+    }
+}
+
+enum RequestBody {
+    Empty,
+    Json(Value),
+    Form(QueryParams),
 }
 
+/// Transport over HTTPS.
+///
+/// Base URL and token may be replaced at any time. Requests already started keep the old ones.
 #[derive(Debug)]
 pub struct RemoteCaller {
     pub http_client: reqwest::Client,
-    pub addr: String,
-    pub bearer: Option<String>,
+    addr: RwLock<Arc<str>>,
+    token: RwLock<Option<Arc<TokenCache>>>,
+    hosts: Arc<HostRotation>,
+    cache: RwLock<Option<Arc<HttpCache>>>,
 }
 
-impl Transport for RemoteCaller {
-    fn call(
+/// How often the primary host is probed while a fallback one is used.
+const PRIMARY_PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Fallback hosts and the one currently used, index 0 being the primary.
+#[derive(Debug, Default)]
+struct HostRotation {
+    fallback: RwLock<Vec<Arc<str>>>,
+    active: AtomicUsize,
+    probing: AtomicBool,
+    last_probe: Mutex<Option<Instant>>,
+    /// [`PRIMARY_PROBE_INTERVAL`] if not set.
+    probe_interval: RwLock<Option<Duration>>,
+}
+
+impl HostRotation {
+    fn switch_to(&self, index: usize, host: &str) {
+        if self.active.swap(index, Ordering::Relaxed) != index {
+            info!("Switched to API host {}", host);
+        }
+    }
+
+    /// Checks in the background whether the primary host is reachable again.
+    fn maybe_probe_primary(self: &Arc<Self>, client: &reqwest::Client, primary: Arc<str>) {
+        {
+            let interval = self
+                .probe_interval
+                .read()
+                .unwrap()
+                .unwrap_or(PRIMARY_PROBE_INTERVAL);
+            let mut last_probe = self.last_probe.lock().unwrap();
+            if matches!(*last_probe, Some(t) if t.elapsed() < interval)
+                || self.probing.swap(true, Ordering::Relaxed)
+            {
+                return;
+            }
+            *last_probe = Some(Instant::now());
+        }
+
+        let hosts = self.clone();
+        let probe = client.get(&*primary).send();
+        tokio::spawn(async move {
+            // Any HTTP response means the host is reachable.
+            if probe.await.is_ok() {
+                hosts.switch_to(0, &primary);
+            }
+            hosts.probing.store(false, Ordering::Relaxed);
+        });
+    }
+}
+
+impl RemoteCaller {
+    pub fn new<A: Into<String>>(
+        http_client: reqwest::Client,
+        addr: A,
+        token: Option<Arc<TokenCache>>,
+    ) -> Self {
+        Self {
+            http_client,
+            addr: RwLock::new(addr.into().into()),
+            token: RwLock::new(token),
+            hosts: Default::default(),
+            cache: Default::default(),
+        }
+    }
+
+    /// Hosts tried in turn when the current one cannot be connected to.
+    ///
+    /// The host that worked is used for subsequent requests, while the primary one is probed
+    /// every minute to switch back to it, see [`RemoteCaller::set_primary_probe_interval`].
+    pub fn set_fallback_hosts<I, A>(&self, hosts: I)
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        *self.hosts.fallback.write().unwrap() =
+            hosts.into_iter().map(|host| host.into().into()).collect();
+        self.hosts.active.store(0, Ordering::Relaxed);
+    }
+
+    /// How often the primary host is probed while a fallback one is used, a minute by default.
+    pub fn set_primary_probe_interval(&self, interval: Duration) {
+        *self.hosts.probe_interval.write().unwrap() = Some(interval);
+    }
+
+    /// Host requests are currently sent to.
+    pub fn active_addr(&self) -> Arc<str> {
+        let index = self.hosts.active.load(Ordering::Relaxed);
+        match index.checked_sub(1) {
+            Some(i) => self.hosts.fallback.read().unwrap().get(i).cloned(),
+            None => None,
+        }
+        .unwrap_or_else(|| self.addr())
+    }
+
+    pub fn addr(&self) -> Arc<str> {
+        self.addr.read().unwrap().clone()
+    }
+
+    pub fn set_addr<A: Into<String>>(&self, addr: A) {
+        *self.addr.write().unwrap() = addr.into().into();
+    }
+
+    /// Cache lifetime of the current token, if any.
+    pub fn token_ttl(&self) -> Option<Duration> {
+        self.token.read().unwrap().as_ref().map(|token| token.ttl())
+    }
+
+    pub fn set_token(&self, token: Option<Arc<TokenCache>>) {
+        *self.token.write().unwrap() = token;
+    }
+
+    /// Make `GET` requests conditional on the responses in `cache`, see [`HttpCache`].
+    pub fn set_http_cache(&self, cache: Option<Arc<HttpCache>>) {
+        *self.cache.write().unwrap() = cache;
+    }
+}
+
+impl RemoteCaller {
+    /// `Accept` is always sent, `Content-Type` only with a body as some endpoints reject it otherwise.
+    fn request(
         &self,
         endpoint: String,
         method: Method,
-        params: &HashMap<&str, String>,
-        body: Option<&Value>,
-    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        params: &QueryParams,
+        body: RequestBody,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, StdError>> + Send + 'static>> {
         let client = self.http_client.clone();
-        let uri = format!("{}/{}", self.addr, endpoint);
+        let mut bases = vec![self.addr()];
+        bases.extend(self.hosts.fallback.read().unwrap().iter().cloned());
+        let hosts = self.hosts.clone();
         trace!(
             "Sending request to endpoint {} with params: {:?}",
             endpoint,
             params
         );
 
-        let mut req = client
-            .request(method, &uri)
-            .query(params)
-            .typed_header(ContentType::json());
-        if let Some(bearer) = self.bearer.as_ref() {
-            req = req.bearer_auth(&bearer);
-        }
-
-        if let Some(body) = body {
-            req = req.json(body);
-        }
+        let cache = match (&method, &body) {
+            (&Method::GET, RequestBody::Empty) => self.cache.read().unwrap().clone(),
+            _ => None,
+        };
+        let cache_url = HttpCache::url(&endpoint, params);
+        let params = params.clone();
+        let correlation_id = current_correlation_id();
+        let token = self.token.read().unwrap().clone();
 
         Box::pin(async move {
-            let rsp = req.send().await?;
+            let cached: Option<CachedResponse> = match &cache {
+                Some(cache) => cache.get(&cache_url).await,
+                None => None,
+            };
+            let start = hosts.active.load(Ordering::Relaxed) % bases.len();
+            let mut attempt = 0;
+            let mut token_refreshed = false;
+            let (index, rsp) = loop {
+                let index = (start + attempt) % bases.len();
+                let uri = format!("{}/{}", bases[index], endpoint);
+                let mut req = client
+                    .request(method.clone(), &uri)
+                    .query(&params)
+                    .header(http::header::ACCEPT, "application/json");
+                if let Some(correlation_id) = &correlation_id {
+                    req = req.header("X-Correlation-Id", correlation_id.as_str());
+                }
+                if let Some(cached) = &cached {
+                    if let Some(etag) = &cached.etag {
+                        req = req.header(http::header::IF_NONE_MATCH, etag.as_str());
+                    }
+                    if let Some(last_modified) = &cached.last_modified {
+                        req = req.header(http::header::IF_MODIFIED_SINCE, last_modified.as_str());
+                    }
+                }
+                if let Some(token) = &token {
+                    let token = token
+                        .get()
+                        .await
+                        .map_err(|e| Box::new(TokenUnavailable(e)) as StdError)?;
+                    req = req.bearer_auth(token.expose_secret());
+                }
+
+                req = match &body {
+                    RequestBody::Empty => req,
+                    RequestBody::Json(body) => req.typed_header(ContentType::json()).json(body),
+                    RequestBody::Form(form) => {
+                        req.typed_header(ContentType::form_url_encoded()).form(form)
+                    }
+                };
+
+                let rsp = match req.send().await {
+                    Ok(rsp) => rsp,
+                    Err(e) if e.is_connect() && attempt + 1 < bases.len() => {
+                        warn!(
+                            "Failed to connect to {}, trying the next host: {}",
+                            bases[index], e
+                        );
+                        attempt += 1;
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                hosts.switch_to(index, &bases[index]);
+
+                // The token may have been rotated since it was cached, fetch it again once.
+                if rsp.status() == reqwest::StatusCode::UNAUTHORIZED && !token_refreshed {
+                    if let Some(token) = &token {
+                        token.invalidate();
+                        token_refreshed = true;
+                        continue;
+                    }
+                }
+
+                break (index, rsp);
+            };
+            if index != 0 {
+                hosts.maybe_probe_primary(&client, bases[0].clone());
+            }
+            if let (Some(cache), Some(cached)) = (&cache, cached) {
+                if rsp.status() == reqwest::StatusCode::NOT_MODIFIED {
+                    trace!("Serving cached response of {}", cache_url);
+                    cache.touch(&cache_url).await;
+                    return Ok(cached.into_body());
+                }
+            }
             let err = rsp.error_for_status_ref().err();
+            let validators = (
+                header_value(rsp.headers(), reqwest::header::ETAG),
+                header_value(rsp.headers(), reqwest::header::LAST_MODIFIED),
+            );
 
-            let data = rsp.text().await?;
+            let data = rsp.bytes().await?.to_vec();
 
-            trace!("Received HTTP response: {}", data);
+            trace!("Received HTTP response: {}", String::from_utf8_lossy(&data));
 
             if let Some(err) = err {
-                return Err(format!("Received error {} with data: {}", err, data).into());
+                let status = err.status().map_or(0, |status| status.as_u16());
+                // QIWI error payloads are passed on to be reported as API errors, except for
+                // authorization failures, which are reported as such whatever the body.
+                if status != 401
+                    && status != 403
+                    && serde_json::from_slice::<Value>(&data)
+                        .map(|v| v.get("errorCode").is_some())
+                        .unwrap_or(false)
+                {
+                    return Ok(data);
+                }
+
+                return Err(Box::new(HttpStatusError {
+                    status,
+                    message: err.to_string(),
+                    body: String::from_utf8_lossy(&data).into_owned(),
+                }));
+            }
+
+            match (&cache, validators) {
+                (None, _) | (_, (None, None)) => {}
+                (Some(cache), (etag, last_modified)) => {
+                    cache.insert(&cache_url, etag, last_modified, &data).await
+                }
             }
 
             Ok(data)
@@ -117,29 +810,574 @@ impl Transport for RemoteCaller {
     }
 }
 
+impl Transport for RemoteCaller {
+    fn call(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        body: Option<&Value>,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        let body = match body {
+            Some(body) => RequestBody::Json(body.clone()),
+            None => RequestBody::Empty,
+        };
+        text(self.request(endpoint, method, params, body))
+    }
+
+    fn call_form(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+        form: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+        text(self.request(endpoint, method, params, RequestBody::Form(form.clone())))
+    }
+
+    fn call_bytes(
+        &self,
+        endpoint: String,
+        method: Method,
+        params: &QueryParams,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<u8>, StdError>> + Send + 'static>> {
+        self.request(endpoint, method, params, RequestBody::Empty)
+    }
+}
+
+fn header_value(
+    headers: &reqwest::header::HeaderMap,
+    name: reqwest::header::HeaderName,
+) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn text(
+    rsp: Pin<Box<dyn Future<Output = Result<Vec<u8>, StdError>> + Send + 'static>>,
+) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+    Box::pin(async move { Ok(String::from_utf8(rsp.await?)?) })
+}
+
+thread_local! {
+    static CORRELATION_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Correlation id of the call being started, see [`CallerWrapper::call`].
+///
+/// Set only while [`Transport`] methods are called to create the request future, so transports
+/// that forward it have to read it there rather than in the future.
+pub fn current_correlation_id() -> Option<String> {
+    CORRELATION_ID.with(|id| id.borrow().clone())
+}
+
+fn with_correlation_id<T>(correlation_id: &str, f: impl FnOnce() -> T) -> T {
+    let previous = CORRELATION_ID.with(|id| id.replace(Some(correlation_id.to_string())));
+    let v = f();
+    CORRELATION_ID.with(|id| *id.borrow_mut() = previous);
+    v
+}
+
+type TransportFuture<T> = Pin<Box<dyn Future<Output = Result<T, StdError>> + Send + 'static>>;
+
+/// Every call gets a correlation id from the client's [`IdGenerator`], which is logged, sent to
+/// QIWI as `X-Correlation-Id` by [`RemoteCaller`] and attached to errors of the call.
 #[derive(Clone, Debug)]
 pub struct CallerWrapper {
     pub transport: Arc<dyn Transport>,
+    /// Shared by all clones.
+    pub(crate) quota: Arc<QuotaTracker>,
+    pub(crate) ids: Arc<dyn IdGenerator>,
+    /// Set in lenient mode, see [`ClientBuilder::lenient_parsing`](crate::ClientBuilder::lenient_parsing).
+    pub(crate) compat: Option<Arc<compat::CompatLog>>,
+    /// See [`ClientBuilder::response_validator`](crate::ClientBuilder::response_validator).
+    pub(crate) validator: Option<Arc<dyn ResponseValidator>>,
+}
+
+impl Error {
+    /// Classifies a failure reported by [`Transport`].
+    fn from_transport(e: StdError) -> Self {
+        let e = match e.downcast::<TokenUnavailable>() {
+            Ok(e) => {
+                return TokenProviderError {
+                    correlation_id: None::<String>,
+                }
+                .into_error(e.0)
+            }
+            Err(e) => e,
+        };
+        match e.downcast::<NoFixture>() {
+            Ok(e) => Error::Offline {
+                endpoint: e.endpoint,
+            },
+            Err(e) => NetworkError {
+                correlation_id: None::<String>,
+            }
+            .into_error(e),
+        }
+    }
+
+    /// HTTP status of the response, if the call failed with one, see [`HttpStatusError`].
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            Self::NetworkError { source, .. } => source
+                .downcast_ref::<HttpStatusError>()
+                .map(|error| error.status),
+            _ => None,
+        }
+    }
+
+    /// Id of the call that failed, see [`CallerWrapper`].
+    pub fn correlation_id(&self) -> Option<&str> {
+        match self {
+            Self::NetworkError { correlation_id, .. }
+            | Self::ParseError { correlation_id, .. }
+            | Self::TokenProviderError { correlation_id, .. }
+            | Self::ContractViolation { correlation_id, .. } => correlation_id.as_deref(),
+            Self::Offline { .. } => None,
+        }
+    }
+
+    fn with_correlation_id(mut self, id: &str) -> Self {
+        match &mut self {
+            Self::NetworkError { correlation_id, .. }
+            | Self::ParseError { correlation_id, .. }
+            | Self::TokenProviderError { correlation_id, .. }
+            | Self::ContractViolation { correlation_id, .. } => {
+                *correlation_id = Some(id.to_string())
+            }
+            Self::Offline { .. } => {}
+        }
+        self
+    }
+
+    /// Whether repeating the call may succeed: network failures and HTTP errors other than
+    /// client ones, except for rate limiting.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::NetworkError { .. } => match self.http_status() {
+                Some(status) => status >= 500 || status == 429,
+                None => true,
+            },
+            _ => false,
+        }
+    }
+
+    pub(crate) fn portable_kind(&self) -> &'static str {
+        match self {
+            Self::NetworkError { .. } => "NetworkError",
+            Self::ParseError { .. } => "ParseError",
+            Self::TokenProviderError { .. } => "TokenProviderError",
+            Self::Offline { .. } => "Offline",
+            Self::ContractViolation { .. } => "ContractViolation",
+        }
+    }
+
+    /// Writes the fields other than the correlation id, see [`crate::Error::to_portable`].
+    pub(crate) fn write_portable(&self, p: &mut PortableError) {
+        match self {
+            Self::NetworkError { source, .. } => match source.downcast_ref::<HttpStatusError>() {
+                Some(error) => {
+                    p.http_status = Some(error.status);
+                    p.detail("httpMessage", &error.message);
+                    p.detail("body", &error.body);
+                }
+                None => p.detail("source", source.to_string()),
+            },
+            Self::ParseError { source, .. } | Self::TokenProviderError { source, .. } => {
+                p.detail("source", source.to_string())
+            }
+            Self::Offline { endpoint } => p.endpoint = Some(endpoint.clone()),
+            Self::ContractViolation {
+                endpoint, details, ..
+            } => {
+                p.endpoint = Some(endpoint.clone());
+                p.detail("details", details);
+            }
+        }
+    }
+
+    pub(crate) fn from_portable(kind: &str, p: &PortableError) -> Option<Self> {
+        let correlation_id = p.correlation_id.clone();
+        Some(match kind {
+            "NetworkError" => {
+                let source: StdError = match p.http_status {
+                    Some(status) => Box::new(HttpStatusError {
+                        status,
+                        message: p.field("httpMessage")?,
+                        body: p.field("body")?,
+                    }),
+                    None => p.source_field("source")?,
+                };
+                NetworkError { correlation_id }.into_error(source)
+            }
+            "ParseError" => ParseError { correlation_id }.into_error(p.source_field("source")?),
+            "TokenProviderError" => {
+                TokenProviderError { correlation_id }.into_error(p.source_field("source")?)
+            }
+            "Offline" => Self::Offline {
+                endpoint: p.endpoint.clone()?,
+            },
+            "ContractViolation" => Self::ContractViolation {
+                endpoint: p.endpoint.clone()?,
+                details: p.field("details")?,
+                correlation_id,
+            },
+            _ => return None,
+        })
+    }
 }
 
 impl CallerWrapper {
+    /// Same caller without the response validator, for calls whose responses are known to
+    /// deviate from the contract.
+    pub fn unvalidated(&self) -> Self {
+        Self {
+            validator: None,
+            ..self.clone()
+        }
+    }
+
+    /// Performs the call and returns the response body as is. The body is never validated.
+    pub fn call_raw<E>(
+        &self,
+        endpoint: E,
+        method: Method,
+        params: &QueryParams,
+        body: Option<&Value>,
+    ) -> impl Future<Output = Result<String, Error>> + Send + 'static
+    where
+        E: Display,
+    {
+        let endpoint = endpoint.to_string();
+        let (category, correlation_id) = self.begin(&endpoint);
+        let c = with_correlation_id(&correlation_id, || {
+            self.transport.call(endpoint, method, params, body)
+        });
+        self.metered(category, correlation_id, c)
+    }
+
+    /// Same as [`CallerWrapper::call_raw`], sending the body as a form.
+    pub fn call_form_raw<E>(
+        &self,
+        endpoint: E,
+        method: Method,
+        params: &QueryParams,
+        form: &QueryParams,
+    ) -> impl Future<Output = Result<String, Error>> + Send + 'static
+    where
+        E: Display,
+    {
+        let endpoint = endpoint.to_string();
+        let (category, correlation_id) = self.begin(&endpoint);
+        let c = with_correlation_id(&correlation_id, || {
+            self.transport.call_form(endpoint, method, params, form)
+        });
+        self.metered(category, correlation_id, c)
+    }
+
+    /// Same as [`CallerWrapper::call_raw`] for endpoints returning documents.
+    pub fn call_bytes<E>(
+        &self,
+        endpoint: E,
+        method: Method,
+        params: &QueryParams,
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + Send + 'static
+    where
+        E: Display,
+    {
+        let endpoint = endpoint.to_string();
+        let (category, correlation_id) = self.begin(&endpoint);
+        let c = with_correlation_id(&correlation_id, || {
+            self.transport.call_bytes(endpoint, method, params)
+        });
+        self.metered(category, correlation_id, c)
+    }
+
+    fn begin(&self, endpoint: &str) -> (EndpointCategory, String) {
+        let correlation_id = self.ids.next_correlation_id();
+        debug!("[{}] Calling {}", correlation_id, endpoint);
+        (EndpointCategory::of(endpoint), correlation_id)
+    }
+
+    fn metered<T>(
+        &self,
+        category: EndpointCategory,
+        correlation_id: String,
+        c: TransportFuture<T>,
+    ) -> impl Future<Output = Result<T, Error>> + Send + 'static
+    where
+        T: Send + 'static,
+    {
+        let quota = self.quota.clone();
+        async move {
+            if let Some(delay) = quota.delay() {
+                debug!(
+                    "[{}] Soft quota reached, delaying request by {:?}",
+                    correlation_id, delay
+                );
+                quota.sleep(delay).await;
+            }
+            quota.record(category);
+            let rsp = c.await;
+            if let Err(e) = &rsp {
+                debug!("[{}] Call failed: {}", correlation_id, e);
+                quota.record_error();
+            }
+            rsp.map_err(|e| Error::from_transport(e).with_correlation_id(&correlation_id))
+        }
+    }
+
+    /// Performs the call and parses the response. An empty response body, e.g. of `201 Created`, is parsed as `null`.
+    ///
+    /// In lenient mode former field names are renamed before parsing and reported to the compat log.
+    /// With a response validator the body is parsed once into a [`Value`], which is validated
+    /// and then turned into `T`.
     pub fn call<E, T>(
         &self,
         endpoint: E,
         method: Method,
-        params: &HashMap<&str, String>,
+        params: &QueryParams,
         body: Option<&Value>,
     ) -> impl Future<Output = Result<Rsp<T>, Error>> + Send + 'static
     where
         E: Display,
         T: for<'de> Deserialize<'de> + Send + 'static,
     {
-        let c = self
-            .transport
-            .call(endpoint.to_string(), method, params, body);
+        let endpoint = endpoint.to_string();
+        let (category, correlation_id) = self.begin(&endpoint);
+        let c = with_correlation_id(&correlation_id, || {
+            self.transport.call(endpoint.clone(), method, params, body)
+        });
+        let c = self.metered(category, correlation_id.clone(), c);
+        self.parsed(endpoint, correlation_id, c)
+    }
+
+    /// Same as [`CallerWrapper::call`], sending the body as a form.
+    pub fn call_form<E, T>(
+        &self,
+        endpoint: E,
+        method: Method,
+        params: &QueryParams,
+        form: &QueryParams,
+    ) -> impl Future<Output = Result<Rsp<T>, Error>> + Send + 'static
+    where
+        E: Display,
+        T: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let endpoint = endpoint.to_string();
+        let (category, correlation_id) = self.begin(&endpoint);
+        let c = with_correlation_id(&correlation_id, || {
+            self.transport
+                .call_form(endpoint.clone(), method, params, form)
+        });
+        let c = self.metered(category, correlation_id.clone(), c);
+        self.parsed(endpoint, correlation_id, c)
+    }
+
+    fn parsed<T>(
+        &self,
+        call_endpoint: String,
+        correlation_id: String,
+        c: impl Future<Output = Result<String, Error>> + Send + 'static,
+    ) -> impl Future<Output = Result<Rsp<T>, Error>> + Send + 'static
+    where
+        T: for<'de> Deserialize<'de> + Send + 'static,
+    {
+        let compat = self.compat.clone();
+        let validator = self.validator.clone();
         async move {
-            Ok(serde_json::from_str(&c.await.context(NetworkError)?)
-                .map_err(Error::from_parse_error)?)
+            let data = c.await?;
+            let data = if data.trim().is_empty() {
+                "null"
+            } else {
+                data.as_str()
+            };
+            let parse_error = |e: serde_json::Error| {
+                Error::from_parse_error(e).with_correlation_id(&correlation_id)
+            };
+            let mut rsp = if compat.is_none() && validator.is_none() {
+                serde_json::from_str::<Rsp<T>>(data).map_err(parse_error)?
+            } else {
+                let mut value = serde_json::from_str::<Value>(data).map_err(parse_error)?;
+                if let Some(log) = compat {
+                    let notes = compat::normalize(&mut value);
+                    if !notes.is_empty() {
+                        log.record(compat::CompatReport {
+                            endpoint: call_endpoint.clone(),
+                            correlation_id: correlation_id.clone(),
+                            notes,
+                        });
+                    }
+                }
+                if let Some(validator) = validator {
+                    if !is_error_body(&value) {
+                        validate(&*validator, &call_endpoint, &correlation_id, &value)?;
+                    }
+                }
+                serde_json::from_value::<Rsp<T>>(value).map_err(parse_error)?
+            };
+            if let Rsp::Error(error) = &mut rsp {
+                error.correlation_id = Some(correlation_id);
+            }
+            Ok(rsp)
+        }
+    }
+}
+
+/// QIWI error body, see [`Rsp::Error`].
+fn is_error_body(value: &Value) -> bool {
+    value
+        .get("errorCode")
+        .or_else(|| value.get("error_code"))
+        .map_or(false, Value::is_string)
+}
+
+fn validate(
+    validator: &dyn ResponseValidator,
+    endpoint: &str,
+    correlation_id: &str,
+    value: &Value,
+) -> Result<(), Error> {
+    match validator.validate(endpoint, value) {
+        Ok(warnings) => {
+            for warning in warnings {
+                warn!("[{}] {}: {}", correlation_id, endpoint, warning);
+            }
+            Ok(())
+        }
+        Err(details) => Err(Error::ContractViolation {
+            endpoint: endpoint.to_string(),
+            details,
+            correlation_id: Some(correlation_id.to_string()),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, serde_json::json};
+
+    const TOKEN_INVALID: &str = r#"{
+        "serviceName": "payment-history",
+        "errorCode": "internal.invalid.token",
+        "description": "Invalid token",
+        "userMessage": "Неверный токен",
+        "dateTime": "2020-03-12T09:44:50.726+03:00",
+        "traceId": "fc4fd1c60d5d1c5e"
+    }"#;
+
+    const PAYMENT_BLOCKED: &str = r#"{
+        "serviceName": "payment-processing",
+        "errorCode": "payment.blocked",
+        "description": "Payment is blocked",
+        "userMessage": "Платеж заблокирован",
+        "dateTime": "2020-03-12T10:01:12.342+03:00",
+        "traceId": "3e0b5f5a2a2e4c1b"
+    }"#;
+
+    fn error(body: &str) -> QiwiApiError {
+        match serde_json::from_str::<Rsp<Value>>(body).unwrap() {
+            Rsp::Error(error) => error,
+            Rsp::OK(value) => panic!("parsed as success: {}", value),
+        }
+    }
+
+    #[test]
+    fn token_invalid() {
+        assert_eq!(
+            error(TOKEN_INVALID),
+            QiwiApiError {
+                service_name: Some("payment-history".into()),
+                description: Some("Invalid token".into()),
+                user_message: Some("Неверный токен".into()),
+                date_time: Some("2020-03-12T09:44:50.726+03:00".into()),
+                trace_id: Some("fc4fd1c60d5d1c5e".into()),
+                ..QiwiApiError::new("internal.invalid.token")
+            }
+        );
+        assert_eq!(error(TOKEN_INVALID).to_string(), "internal.invalid.token");
+    }
+
+    #[test]
+    fn payment_blocked() {
+        let rsp = serde_json::from_str::<Rsp<Value>>(PAYMENT_BLOCKED).unwrap();
+        match rsp.into_result() {
+            Err(crate::Error::QiwiError { error }) => {
+                assert_eq!(error.error_code, "payment.blocked");
+                assert_eq!(error.service_name.as_deref(), Some("payment-processing"));
+                assert_eq!(error.user_message.as_deref(), Some("Платеж заблокирован"));
+                assert_eq!(error.trace_id.as_deref(), Some("3e0b5f5a2a2e4c1b"));
+                assert_eq!(error.correlation_id, None);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_code_is_required() {
+        assert_eq!(
+            error(r#"{ "errorCode": "payment.blocked" }"#),
+            QiwiApiError::new("payment.blocked")
+        );
+        assert_eq!(
+            error(r#"{ "error_code": "internal.invalid.token" }"#),
+            QiwiApiError::new("internal.invalid.token")
+        );
+    }
+
+    #[test]
+    fn serialized_without_absent_fields() {
+        let error = QiwiApiError {
+            trace_id: Some("fc4fd1c60d5d1c5e".into()),
+            correlation_id: Some("1".into()),
+            ..QiwiApiError::new("internal.invalid.token")
+        };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({ "errorCode": "internal.invalid.token", "traceId": "fc4fd1c60d5d1c5e" })
+        );
+        assert_eq!(
+            serde_json::from_str::<QiwiApiError>(TOKEN_INVALID).unwrap(),
+            serde_json::from_value::<QiwiApiError>(
+                serde_json::to_value(error(TOKEN_INVALID)).unwrap()
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn success_is_not_an_error() {
+        let body = json!({ "accounts": [], "errorCode": 0 });
+        assert!(!is_error_body(&body));
+        match serde_json::from_value::<Rsp<Value>>(json!({ "accounts": [] })).unwrap() {
+            Rsp::OK(value) => assert_eq!(value, json!({ "accounts": [] })),
+            Rsp::Error(error) => panic!("parsed as error: {}", error),
         }
     }
+
+    #[test]
+    fn indexed_keys_share_the_buffer() {
+        let mut params = QueryParams::new();
+        params
+            .push("rows", 50)
+            .push_indexed("sources", vec!["QW_RUB", "CARD"])
+            .push("operation", "OUT")
+            .push_indexed("types", Vec::<&str>::new());
+        let pairs = vec![
+            ("rows", "50"),
+            ("sources[0]", "QW_RUB"),
+            ("sources[1]", "CARD"),
+            ("operation", "OUT"),
+        ];
+        assert_eq!(params.iter().collect::<Vec<_>>(), pairs);
+        assert_eq!(params.clone().iter().collect::<Vec<_>>(), pairs);
+
+        params.clear();
+        assert!(params.is_empty());
+        params.push_indexed("sources", (0..12).map(|i| i * 10));
+        assert_eq!(params.iter().nth(11), Some(("sources[11]", "110")));
+    }
 }
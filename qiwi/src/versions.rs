@@ -0,0 +1,207 @@
+use crate::*;
+
+/// Versions of versioned QIWI API endpoints used by the client.
+///
+/// Override them with [`ClientBuilder::api_versions`] to try out a new endpoint version before
+/// the crate is updated for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiVersions {
+    /// `person-profile` API, `v1` by default.
+    pub person_profile: String,
+    /// `payment-history` API, `v2` by default.
+    pub payment_history: String,
+}
+
+impl Default for ApiVersions {
+    fn default() -> Self {
+        Self {
+            person_profile: "v1".into(),
+            payment_history: "v2".into(),
+        }
+    }
+}
+
+impl ApiVersions {
+    pub(crate) fn profile_endpoint(&self) -> String {
+        format!("person-profile/{}/profile/current", self.person_profile)
+    }
+
+    #[cfg(feature = "history")]
+    pub(crate) fn transaction_endpoint(&self, txn_id: u64) -> String {
+        format!(
+            "payment-history/{}/transactions/{}",
+            self.payment_history, txn_id
+        )
+    }
+
+    #[cfg(feature = "history")]
+    pub(crate) fn totals_endpoint(&self, user: &QiwiUser) -> String {
+        format!(
+            "payment-history/{}/persons/{}/payments/total",
+            self.payment_history, user
+        )
+    }
+
+    #[cfg(any(feature = "history", feature = "test-util"))]
+    pub(crate) fn history_endpoint(&self, user: &QiwiUser) -> String {
+        format!(
+            "payment-history/{}/persons/{}/payments",
+            self.payment_history, user
+        )
+    }
+}
+
+/// Converts transport error, naming the API version if the response could not be parsed.
+pub(crate) fn versioned_error(
+    api: &'static str,
+    version: &str,
+) -> impl FnOnce(transport::Error) -> Error {
+    let version = version.to_string();
+    move |source| match source {
+        source @ transport::Error::ParseError { .. } => Error::UnexpectedApiResponse {
+            api,
+            version,
+            source,
+        },
+        source => Error::from(source),
+    }
+}
+
+#[cfg(all(test, feature = "history"))]
+mod tests {
+    use {super::*, serde_json::json, tokio::stream::StreamExt};
+
+    const TXN_ID: u64 = 20_000_000_001;
+
+    fn versions() -> ApiVersions {
+        ApiVersions {
+            person_profile: "v2".into(),
+            payment_history: "v3".into(),
+        }
+    }
+
+    fn client(transport: OfflineTransport) -> (Client, Arc<OfflineTransport>) {
+        let transport = Arc::new(transport);
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .api_versions(versions())
+            .build();
+        (client, transport)
+    }
+
+    #[tokio::test]
+    async fn overridden_versions_change_request_paths() {
+        let user = QiwiUser::from("+79991234567".parse::<PhoneNumber>().unwrap());
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["bankDocumentAvailable"] = json!(true);
+        entry["bankDocumentReady"] = json!(true);
+        let transaction = format!("payment-history/v3/transactions/{}", TXN_ID);
+        let (client, transport) = client(
+            OfflineTransport::new()
+                .with(
+                    Method::GET,
+                    "person-profile/v2/profile/current",
+                    &fixtures::profile(),
+                )
+                .with(
+                    Method::GET,
+                    format!("payment-history/v3/persons/{}/payments", user),
+                    &fixtures::history_page(fixtures::history_entries(2)),
+                )
+                .with(Method::GET, &transaction, &entry)
+                .with(
+                    Method::GET,
+                    format!("{}/bank-document/file", transaction),
+                    &json!("%PDF"),
+                )
+                .with(Method::POST, format!("{}/cancel", transaction), &json!({})),
+        );
+
+        client.profile_info().await.unwrap();
+        client.payment_history().next().await.unwrap().unwrap();
+        client
+            .payment_history_filtered(Default::default())
+            .unwrap()
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        let now = Utc::now();
+        let _ = client
+            .payment_totals(now - chrono::Duration::days(1), now, &Default::default())
+            .await;
+        client
+            .transaction_info(TXN_ID, TransactionType::Out)
+            .await
+            .unwrap();
+        client.bank_document(TXN_ID).await.unwrap();
+        client.cancel_payment(TXN_ID).await.unwrap();
+
+        let history = format!("payment-history/v3/persons/{}/payments", user);
+        assert_eq!(
+            transport
+                .requests()
+                .into_iter()
+                .map(|(_, endpoint)| endpoint)
+                .collect::<Vec<_>>(),
+            vec![
+                "person-profile/v2/profile/current".to_string(),
+                history.clone(),
+                history.clone(),
+                format!("{}/total", history),
+                transaction.clone(),
+                transaction.clone(),
+                format!("{}/bank-document/file", transaction),
+                format!("{}/cancel", transaction),
+                transaction.clone(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unparsable_response_names_configured_version() {
+        let (client, _) = client(OfflineTransport::new().with(
+            Method::GET,
+            "person-profile/v2/profile/current",
+            &json!([]),
+        ));
+
+        let error = client.profile_info().await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unexpected response from person-profile API v2"),
+            "{}",
+            error
+        );
+        match error {
+            Error::UnexpectedApiResponse { api, version, .. } => {
+                assert_eq!(api, "person-profile");
+                assert_eq!(version, "v2");
+            }
+            other => panic!("expected UnexpectedApiResponse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn defaults_match_current_endpoints() {
+        let user = QiwiUser::from("+79991234567".parse::<PhoneNumber>().unwrap());
+        let versions = ApiVersions::default();
+        assert_eq!(
+            versions.profile_endpoint(),
+            "person-profile/v1/profile/current"
+        );
+        assert_eq!(
+            versions.history_endpoint(&user),
+            "payment-history/v2/persons/79991234567/payments"
+        );
+        assert_eq!(
+            versions.totals_endpoint(&user),
+            "payment-history/v2/persons/79991234567/payments/total"
+        );
+        assert_eq!(
+            versions.transaction_endpoint(1),
+            "payment-history/v2/transactions/1"
+        );
+    }
+}
@@ -0,0 +1,750 @@
+use {
+    crate::*,
+    async_stream::stream,
+    log::*,
+    serde::{Deserialize, Serialize},
+    std::time::Duration,
+};
+
+/// Polling settings for [`Client::wait_for_transfer`].
+#[derive(Clone, Debug)]
+pub struct WaitOptions {
+    /// How long to wait for the transaction to leave `WAITING`.
+    pub timeout: Duration,
+    /// Delay between polls while the API is healthy.
+    pub interval: Duration,
+    /// Upper bound for the delay after consecutive failures.
+    pub max_interval: Duration,
+    /// Cancel the transaction if it is still `WAITING` at the timeout, see [`Client::cancel_payment`].
+    pub cancel_on_timeout: bool,
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(300),
+            interval: Duration::from_secs(5),
+            max_interval: Duration::from_secs(60),
+            cancel_on_timeout: false,
+        }
+    }
+}
+
+impl WaitOptions {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn cancel_on_timeout(mut self, enabled: bool) -> Self {
+        self.cancel_on_timeout = enabled;
+        self
+    }
+}
+
+/// Result of [`Client::cancel_payment`], as found by checking the transaction after the request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// The payment will not be made.
+    Cancelled,
+    /// QIWI refused to cancel the payment, which has not succeeded either.
+    NotCancellable { reason: String },
+    /// The payment succeeded before it could be cancelled, whatever QIWI answered to the request.
+    AlreadyCompleted,
+}
+
+#[derive(Clone, Debug)]
+pub enum WatchEvent {
+    Payment(PaymentHistoryEntry),
+    Status(WatcherStatus),
+}
+
+/// History entries requested per page by [`Client::watch_payments`].
+const WATCH_ROWS: u16 = 50;
+
+/// Saved by [`Client::watch_payments`].
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WatcherState {
+    last_seen_txn_id: u64,
+}
+
+async fn save_last_seen(store: &Option<Arc<dyn state::StateStore>>, key: &str, txn_id: u64) {
+    if let Some(store) = store {
+        let state = WatcherState {
+            last_seen_txn_id: txn_id,
+        };
+        if let Err(e) = state::save(&**store, key, &state).await {
+            warn!("Failed to save watcher state: {}", e);
+        }
+    }
+}
+
+/// Payments newer than `seen`, oldest first, fetching pages back to it. Only the latest page
+/// without `seen`.
+async fn new_payments(
+    caller: &CallerWrapper,
+    endpoint: &str,
+    version: &str,
+    seen: Option<u64>,
+) -> QiwiResult<Vec<PaymentHistoryEntry>> {
+    let mut entries = Vec::new();
+    let mut next_txn = None;
+    loop {
+        let mut args = QueryParams::new().with("rows", WATCH_ROWS);
+        if let Some((date, id)) = next_txn.take() {
+            args.push("nextTxnDate", date);
+            args.push("nextTxnId", id);
+        }
+        let history = caller
+            .call::<_, PaymentHistoryData>(endpoint, Method::GET, &args, None)
+            .await
+            .map_err(versions::versioned_error("payment-history", version))?
+            .into_result()?;
+
+        let seen = match seen {
+            Some(seen) => seen,
+            None => {
+                entries.extend(history.data);
+                break;
+            }
+        };
+        let reached = history.data.iter().any(|entry| entry.txn_id <= seen);
+        entries.extend(history.data.into_iter().filter(|entry| entry.txn_id > seen));
+        match (history.next_txn_date, history.next_txn_id) {
+            (Some(date), Some(id)) if !reached => next_txn = Some((date, id)),
+            _ => break,
+        }
+    }
+    entries.sort_by_key(|entry| entry.txn_id);
+    Ok(entries)
+}
+
+impl Client {
+    /// Polls payment history and yields payments not seen before, oldest first.
+    ///
+    /// Payments which happened before the first successful poll are not emitted. If more than a
+    /// page of payments arrives between two polls, earlier pages are fetched back to the last
+    /// payment seen. Failed polls are reported as [`WatcherStatus::Degraded`] and retried with
+    /// backoff.
+    ///
+    /// With a [state store](ClientBuilder::state_store) the last seen payment is saved after each one is consumed,
+    /// and a restarted watcher continues after it instead of starting afresh.
+    pub fn watch_payments(
+        &self,
+        options: WatchOptions,
+    ) -> Pin<Box<dyn Stream<Item = WatchEvent> + Send>> {
+        let caller = self.caller.clone();
+        let endpoint = self.api_versions.history_endpoint(&self.user);
+        let version = self.api_versions.payment_history.clone();
+        let store = self.state_store.clone();
+        let key = format!("watch-payments.{}", self.user);
+        let polls = self.polls.clone();
+        Box::pin(stream! {
+            let slots = polls.register("watch-payments", options.interval);
+            let mut backoff = Backoff::new(options.interval, options.max_interval);
+            let mut last_seen: Option<u64> = match &store {
+                Some(store) => match state::load::<WatcherState>(&**store, &key).await {
+                    Ok(state) => state.map(|state| state.last_seen_txn_id),
+                    Err(e) => {
+                        warn!("Failed to load watcher state: {}", e);
+                        None
+                    }
+                },
+                None => None,
+            };
+            loop {
+                let delay = match new_payments(&caller, &endpoint, &version, last_seen).await {
+                    Ok(entries) => {
+                        if last_seen.is_some() {
+                            for entry in entries {
+                                let txn_id = entry.txn_id;
+                                yield WatchEvent::Payment(entry);
+                                last_seen = Some(txn_id);
+                                save_last_seen(&store, &key, txn_id).await;
+                            }
+                        } else {
+                            // Even an empty history is a baseline, its first payment is new.
+                            let newest = entries.last().map_or(0, |entry| entry.txn_id);
+                            last_seen = Some(newest);
+                            save_last_seen(&store, &key, newest).await;
+                        }
+
+                        backoff.on_success()
+                    }
+                    Err(e) => {
+                        let next_attempt_in = backoff.on_failure();
+                        warn!("Failed to poll payment history: {}", e);
+                        yield WatchEvent::Status(WatcherStatus::Degraded {
+                            consecutive_failures: backoff.consecutive_failures(),
+                            next_attempt_in,
+                        });
+
+                        next_attempt_in
+                    }
+                };
+
+                slots.set_interval(delay);
+                slots.next_slot().await;
+            }
+        })
+    }
+
+    /// Polls an outgoing transaction until it is no longer `WAITING` or `options.timeout` passes.
+    ///
+    /// Returns the last state seen, which is still [`PaymentStatus::Waiting`] on timeout. Failed
+    /// polls are retried, backing off only after transport errors. A transaction not yet visible
+    /// in the history is polled again at the current interval. The error is returned if the last
+    /// poll before the timeout has failed.
+    ///
+    /// With [`WaitOptions::cancel_on_timeout`] a transaction still `WAITING` at the timeout is
+    /// cancelled, and its state after the cancellation is returned: [`PaymentStatus::Success`]
+    /// if it completed meanwhile, still `WAITING` if QIWI refused to cancel it.
+    pub async fn wait_for_transfer(
+        &self,
+        txn_id: u64,
+        options: &WaitOptions,
+    ) -> QiwiResult<PaymentHistoryEntry> {
+        let deadline = self.clock.now() + options.timeout;
+        let mut backoff = Backoff::new(options.interval, options.max_interval);
+        let slots = self
+            .polls
+            .register(&format!("wait-for-transfer.{}", txn_id), options.interval);
+        loop {
+            let delay = match self.transaction_info(txn_id, TransactionType::Out).await {
+                Ok(entry) => {
+                    if entry.status != PaymentStatus::Waiting {
+                        return Ok(entry);
+                    }
+                    if self.clock.now() >= deadline {
+                        if options.cancel_on_timeout {
+                            warn!("Transaction {} is still waiting, cancelling it", txn_id);
+                            return Ok(self.cancel_and_check(txn_id).await?.1);
+                        }
+                        return Ok(entry);
+                    }
+                    backoff.on_success()
+                }
+                Err(e) => {
+                    if self.clock.now() >= deadline {
+                        return Err(e);
+                    }
+                    warn!("Failed to poll transaction {}: {}", txn_id, e);
+                    match e {
+                        Error::TransportError { .. } => backoff.on_failure(),
+                        _ => backoff.delay(),
+                    }
+                }
+            };
+
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            slots.set_interval(delay.min(remaining));
+            slots.next_slot().await;
+        }
+    }
+
+    /// Asks QIWI to cancel a `WAITING` outgoing payment, which some providers allow.
+    ///
+    /// The payment may complete while the request is in flight, so the outcome is decided by the
+    /// state of the transaction after the request rather than by the response.
+    pub async fn cancel_payment(&self, txn_id: u64) -> QiwiResult<CancelOutcome> {
+        Ok(self.cancel_and_check(txn_id).await?.0)
+    }
+
+    async fn cancel_and_check(
+        &self,
+        txn_id: u64,
+    ) -> QiwiResult<(CancelOutcome, PaymentHistoryEntry)> {
+        let rsp = self
+            .caller
+            .call::<_, Value>(
+                format!("{}/cancel", self.api_versions.transaction_endpoint(txn_id)),
+                Method::POST,
+                &Default::default(),
+                None,
+            )
+            .await?
+            .into_result();
+        let refusal = match rsp {
+            Ok(_) => None,
+            Err(Error::QiwiError { error }) => Some(error.description.unwrap_or(error.error_code)),
+            Err(e) => return Err(e),
+        };
+
+        let entry = self.transaction_info(txn_id, TransactionType::Out).await?;
+        let outcome = match (&entry.status, refusal) {
+            (PaymentStatus::Success, _) => CancelOutcome::AlreadyCompleted,
+            (_, None) => CancelOutcome::Cancelled,
+            (_, Some(reason)) => CancelOutcome::NotCancellable { reason },
+        };
+        Ok((outcome, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*, crate::clock::ManualClock, serde_json::json, std::future::Future,
+        tokio::stream::StreamExt,
+    };
+
+    const TXN_ID: u64 = 10_000_000_001;
+
+    fn endpoint() -> String {
+        format!("payment-history/v2/transactions/{}", TXN_ID)
+    }
+
+    fn entry(status: &str) -> Value {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["type"] = json!("OUT");
+        entry["status"] = json!(status);
+        entry
+    }
+
+    fn client() -> (Client, Arc<OfflineTransport>, Arc<ManualClock>) {
+        let transport = Arc::new(OfflineTransport::new());
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .clock(clock.clone())
+            .build();
+        (client, transport, clock)
+    }
+
+    fn options() -> WaitOptions {
+        WaitOptions {
+            timeout: Duration::from_secs(300),
+            interval: Duration::from_secs(5),
+            max_interval: Duration::from_secs(60),
+            cancel_on_timeout: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn waits_until_not_waiting() {
+        let (client, transport, clock) = client();
+        transport.push(Method::GET, endpoint(), &entry("WAITING"));
+        transport.push(Method::GET, endpoint(), &entry("WAITING"));
+        transport.insert(Method::GET, endpoint(), &entry("SUCCESS"));
+
+        let start = clock.now();
+        let entry = client.wait_for_transfer(TXN_ID, &options()).await.unwrap();
+        assert_eq!(entry.status, PaymentStatus::Success);
+        assert_eq!(transport.requests().len(), 3);
+        assert!(clock.now() - start >= Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn times_out_while_waiting() {
+        let (client, transport, clock) = client();
+        transport.insert(Method::GET, endpoint(), &entry("WAITING"));
+
+        let start = clock.now();
+        let options = options().timeout(Duration::from_secs(60));
+        let entry = client.wait_for_transfer(TXN_ID, &options).await.unwrap();
+        assert_eq!(entry.status, PaymentStatus::Waiting);
+
+        // The last poll is at the deadline, plus at most the jitter of the slot.
+        let elapsed = clock.now() - start;
+        assert!(elapsed >= Duration::from_secs(60));
+        assert!(elapsed < Duration::from_secs(60) + options.interval);
+    }
+
+    #[tokio::test]
+    async fn transaction_not_found_does_not_back_off() {
+        let (client, transport, clock) = client();
+        for _ in 0..3 {
+            transport.push_error(Method::GET, endpoint(), HttpStatusError::new(404, ""));
+        }
+        transport.insert(Method::GET, endpoint(), &entry("SUCCESS"));
+
+        let start = clock.now();
+        let entry = client.wait_for_transfer(TXN_ID, &options()).await.unwrap();
+        assert_eq!(entry.status, PaymentStatus::Success);
+        assert_eq!(transport.requests().len(), 4);
+        // Three intervals, the first one staggered by up to another interval, and jitter.
+        assert!(clock.now() - start < Duration::from_secs(25));
+    }
+
+    #[tokio::test]
+    async fn transport_errors_back_off() {
+        let (client, transport, clock) = client();
+        for _ in 0..3 {
+            transport.push_error(Method::GET, endpoint(), HttpStatusError::new(502, ""));
+        }
+        transport.insert(Method::GET, endpoint(), &entry("SUCCESS"));
+
+        let start = clock.now();
+        let entry = client.wait_for_transfer(TXN_ID, &options()).await.unwrap();
+        assert_eq!(entry.status, PaymentStatus::Success);
+        // 10, 20 and 40 seconds.
+        assert!(clock.now() - start >= Duration::from_secs(70));
+    }
+
+    #[tokio::test]
+    async fn returns_error_of_last_poll() {
+        let (client, transport, clock) = client();
+        for _ in 0..10 {
+            transport.push_error(Method::GET, endpoint(), HttpStatusError::new(502, ""));
+        }
+
+        let start = clock.now();
+        let options = options().timeout(Duration::from_secs(30));
+        let e = client
+            .wait_for_transfer(TXN_ID, &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e, Error::TransportError { ref source } if source.http_status() == Some(502))
+        );
+        assert!(clock.now() - start >= Duration::from_secs(30));
+    }
+
+    fn cancel_endpoint() -> String {
+        format!("{}/cancel", endpoint())
+    }
+
+    fn refusal() -> Value {
+        json!({
+            "serviceName": "payment-processing",
+            "errorCode": "payment.cancel.forbidden",
+            "description": "Payment cannot be cancelled",
+        })
+    }
+
+    #[tokio::test]
+    async fn cancel_accepted() {
+        let (client, transport, _) = client();
+        transport.insert(Method::POST, cancel_endpoint(), &json!({}));
+        transport.insert(Method::GET, endpoint(), &entry("ERROR"));
+
+        assert_eq!(
+            client.cancel_payment(TXN_ID).await.unwrap(),
+            CancelOutcome::Cancelled
+        );
+        assert_eq!(
+            transport.requests(),
+            vec![(Method::POST, cancel_endpoint()), (Method::GET, endpoint())]
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_refused() {
+        let (client, transport, _) = client();
+        transport.insert(Method::POST, cancel_endpoint(), &refusal());
+        transport.insert(Method::GET, endpoint(), &entry("WAITING"));
+
+        assert_eq!(
+            client.cancel_payment(TXN_ID).await.unwrap(),
+            CancelOutcome::NotCancellable {
+                reason: "Payment cannot be cancelled".into()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_without_cancel_leaves_payment_waiting() {
+        let (client, transport, _) = client();
+        transport.insert(Method::GET, endpoint(), &entry("WAITING"));
+
+        let options = options().timeout(Duration::from_secs(30));
+        let entry = client.wait_for_transfer(TXN_ID, &options).await.unwrap();
+        assert_eq!(entry.status, PaymentStatus::Waiting);
+        assert!(transport
+            .requests()
+            .iter()
+            .all(|(method, _)| *method == Method::GET));
+    }
+
+    /// Which of the cancellation and the completion of a waiting payment reaches QIWI first.
+    #[derive(Debug)]
+    enum Race {
+        CancelFirst,
+        CompleteFirst { cancel_accepted: bool },
+    }
+
+    /// Payment that stays `WAITING` until the cancellation request arrives.
+    #[derive(Debug)]
+    struct RacingPayment {
+        race: Race,
+        status: Mutex<&'static str>,
+    }
+
+    impl Transport for RacingPayment {
+        fn call(
+            &self,
+            path: String,
+            method: Method,
+            _: &QueryParams,
+            _: Option<&Value>,
+        ) -> Pin<Box<dyn Future<Output = Result<String, StdError>> + Send + 'static>> {
+            let rsp = if method == Method::POST && path == cancel_endpoint() {
+                let mut status = self.status.lock().unwrap();
+                match self.race {
+                    Race::CancelFirst => {
+                        *status = "ERROR";
+                        json!({})
+                    }
+                    Race::CompleteFirst { cancel_accepted } => {
+                        *status = "SUCCESS";
+                        if cancel_accepted {
+                            json!({})
+                        } else {
+                            refusal()
+                        }
+                    }
+                }
+            } else {
+                assert_eq!((method, path), (Method::GET, endpoint()));
+                entry(*self.status.lock().unwrap())
+            };
+            Box::pin(async move { Ok(rsp.to_string()) })
+        }
+    }
+
+    /// Waits for the racing payment with cancellation at the timeout, returning the entry and
+    /// the outcome of cancelling the payment once more.
+    async fn wait_racing(race: Race) -> (PaymentHistoryEntry, CancelOutcome) {
+        let transport = Arc::new(RacingPayment {
+            race,
+            status: Mutex::new("WAITING"),
+        });
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport)
+            .clock(ManualClock::new(Utc::now()))
+            .build();
+
+        let options = options()
+            .timeout(Duration::from_secs(30))
+            .cancel_on_timeout(true);
+        let entry = client.wait_for_transfer(TXN_ID, &options).await.unwrap();
+        (entry, client.cancel_payment(TXN_ID).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn timeout_cancels_payment() {
+        let (entry, outcome) = wait_racing(Race::CancelFirst).await;
+        assert_eq!(entry.status, PaymentStatus::Error);
+        assert_eq!(outcome, CancelOutcome::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn payment_completed_before_cancel_is_reported() {
+        let (entry, outcome) = wait_racing(Race::CompleteFirst {
+            cancel_accepted: false,
+        })
+        .await;
+        assert_eq!(entry.status, PaymentStatus::Success);
+        assert_eq!(outcome, CancelOutcome::AlreadyCompleted);
+    }
+
+    #[tokio::test]
+    async fn accepted_cancel_of_completed_payment_is_not_trusted() {
+        let (entry, outcome) = wait_racing(Race::CompleteFirst {
+            cancel_accepted: true,
+        })
+        .await;
+        assert_eq!(entry.status, PaymentStatus::Success);
+        assert_eq!(outcome, CancelOutcome::AlreadyCompleted);
+    }
+
+    fn history_endpoint() -> String {
+        ApiVersions::default().history_endpoint(&QiwiUser::from(
+            "+79991234567".parse::<PhoneNumber>().unwrap(),
+        ))
+    }
+
+    fn payment(txn_id: u64) -> Value {
+        let mut entry = entry("SUCCESS");
+        entry["txnId"] = json!(txn_id);
+        entry
+    }
+
+    /// Page of payments with the ids from `newest` down to `oldest`, linking to the next one.
+    fn page(newest: u64, oldest: u64, next: bool) -> Value {
+        let mut page = fixtures::history_page((oldest..=newest).rev().map(payment).collect());
+        if next {
+            page["nextTxnId"] = json!(oldest - 1);
+            page["nextTxnDate"] = json!("2020-01-01T00:00:00+03:00");
+        }
+        page
+    }
+
+    fn watch_options() -> WatchOptions {
+        WatchOptions {
+            interval: Duration::from_secs(30),
+            max_interval: Duration::from_secs(100),
+        }
+    }
+
+    async fn next_payment(events: &mut Pin<Box<dyn Stream<Item = WatchEvent> + Send>>) -> u64 {
+        match events.next().await {
+            Some(WatchEvent::Payment(entry)) => entry.txn_id,
+            other => panic!("expected payment, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn first_payment_after_empty_history_is_emitted() {
+        let (client, transport, _) = client();
+        transport.push(
+            Method::GET,
+            history_endpoint(),
+            &fixtures::history_page(vec![]),
+        );
+        transport.insert(Method::GET, history_endpoint(), &page(1, 1, false));
+
+        let mut events = client.watch_payments(watch_options());
+        assert_eq!(next_payment(&mut events).await, 1);
+    }
+
+    #[tokio::test]
+    async fn payments_before_first_poll_are_not_emitted() {
+        let (client, transport, _) = client();
+        transport.push(Method::GET, history_endpoint(), &page(10, 1, false));
+        transport.insert(Method::GET, history_endpoint(), &page(12, 1, false));
+
+        let mut events = client.watch_payments(watch_options());
+        assert_eq!(next_payment(&mut events).await, 11);
+        assert_eq!(next_payment(&mut events).await, 12);
+    }
+
+    #[tokio::test]
+    async fn burst_beyond_a_page_is_fetched_back_to_last_seen() {
+        let (client, transport, _) = client();
+        transport.push(Method::GET, history_endpoint(), &page(100, 51, true));
+        transport.push(Method::GET, history_endpoint(), &page(250, 201, true));
+        transport.push(Method::GET, history_endpoint(), &page(200, 151, true));
+        transport.push(Method::GET, history_endpoint(), &page(150, 101, true));
+        transport.insert(Method::GET, history_endpoint(), &page(100, 51, true));
+
+        let mut events = client.watch_payments(watch_options());
+        for txn_id in 101..=250 {
+            assert_eq!(next_payment(&mut events).await, txn_id);
+        }
+
+        // The baseline is a single page, the burst is paged back until a seen payment shows up.
+        let requests = transport.recorded();
+        let next_ids = requests
+            .iter()
+            .map(|request| {
+                request
+                    .params
+                    .iter()
+                    .find(|(key, _)| key == "nextTxnId")
+                    .map(|(_, id)| id.clone())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            next_ids[..5],
+            [
+                None,
+                None,
+                Some("200".to_string()),
+                Some("150".to_string()),
+                Some("100".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_back_off_and_success_resets() {
+        let (client, transport, clock) = client();
+        for _ in 0..3 {
+            transport.push_error(
+                Method::GET,
+                history_endpoint(),
+                HttpStatusError::new(502, ""),
+            );
+        }
+        transport.push(
+            Method::GET,
+            history_endpoint(),
+            &fixtures::history_page(vec![]),
+        );
+        transport.push_error(
+            Method::GET,
+            history_endpoint(),
+            HttpStatusError::new(502, ""),
+        );
+        transport.insert(Method::GET, history_endpoint(), &page(1, 1, false));
+
+        let mut events = client.watch_payments(watch_options());
+        let mut degraded = Vec::new();
+        for _ in 0..4 {
+            match events.next().await {
+                Some(WatchEvent::Status(status)) => degraded.push((clock.now(), status)),
+                other => panic!("expected status, got {:?}", other),
+            }
+        }
+        let statuses = degraded
+            .iter()
+            .map(|(_, status)| status.clone())
+            .collect::<Vec<_>>();
+        let status = |consecutive_failures, secs| WatcherStatus::Degraded {
+            consecutive_failures,
+            next_attempt_in: Duration::from_secs(secs),
+        };
+        // Doubling up to the cap, then the base interval again after a success.
+        assert_eq!(
+            statuses,
+            vec![status(1, 60), status(2, 100), status(3, 100), status(1, 60)]
+        );
+
+        let gaps = degraded
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .collect::<Vec<_>>();
+        // The first retry is staggered instead, like the first poll of any watcher.
+        let jitter = Duration::from_secs(5);
+        assert!(gaps[0] <= Duration::from_secs(30) + jitter, "{:?}", gaps);
+        for (gap, expected) in gaps[1..].iter().zip(&[100, 100 + 30]) {
+            let expected = Duration::from_secs(*expected);
+            assert!(
+                *gap >= expected && *gap <= expected + jitter * 2,
+                "{:?}",
+                gaps
+            );
+        }
+
+        assert_eq!(next_payment(&mut events).await, 1);
+    }
+
+    fn persistent_client(transport: Arc<OfflineTransport>, dir: &std::path::Path) -> Client {
+        Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport)
+            .clock(ManualClock::new(Utc::now()))
+            .state_store(state::FileStateStore::new(dir))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn restarted_watcher_resumes_after_last_seen() {
+        let dir = tempfile::tempdir().unwrap();
+        let transport = Arc::new(OfflineTransport::new());
+        transport.push(Method::GET, history_endpoint(), &page(10, 1, false));
+        transport.push(Method::GET, history_endpoint(), &page(12, 1, false));
+        {
+            let client = persistent_client(transport, dir.path());
+            let mut events = client.watch_payments(watch_options());
+            assert_eq!(next_payment(&mut events).await, 11);
+            assert_eq!(next_payment(&mut events).await, 12);
+            // Payment 12 is saved as consumed once the next event is asked for.
+            assert!(matches!(
+                events.next().await,
+                Some(WatchEvent::Status(WatcherStatus::Degraded { .. }))
+            ));
+        }
+
+        // Payments 13 and 14 arrive while the process is down.
+        let transport = Arc::new(OfflineTransport::new().with(
+            Method::GET,
+            history_endpoint(),
+            &page(14, 1, false),
+        ));
+        let client = persistent_client(transport, dir.path());
+        let mut events = client.watch_payments(watch_options());
+        assert_eq!(next_payment(&mut events).await, 13);
+        assert_eq!(next_payment(&mut events).await, 14);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::*;
+
+/// Reported when there is no active webhook.
+const HOOK_NOT_FOUND: &str = "hook.not.found";
+
+impl Client {
+    /// Currently active webhook, if any.
+    pub async fn active_webhook(&self) -> QiwiResult<Option<WebhookInfo>> {
+        let rsp = self
+            .caller
+            .call(
+                "payment-notifier/v1/hooks/active",
+                Method::GET,
+                &Default::default(),
+                None,
+            )
+            .await?
+            .into_result();
+
+        match rsp {
+            Ok(info) => Ok(Some(info)),
+            Err(Error::QiwiError { error }) if error.error_code == HOOK_NOT_FOUND => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Registers a webhook. Fails if another one is already active.
+    ///
+    /// With `send_test`, QIWI sends a test notification to the new webhook right away.
+    pub async fn register_webhook(
+        &self,
+        url: &str,
+        txn_type: WebhookTxnType,
+        send_test: bool,
+    ) -> QiwiResult<WebhookInfo> {
+        let hook = self
+            .caller
+            .call(
+                "payment-notifier/v1/hooks",
+                Method::PUT,
+                &QueryParams::new()
+                    .with("hookType", 1)
+                    .with("param", url)
+                    .with("txnType", txn_type.code()),
+                None,
+            )
+            .await?
+            .into_result()?;
+
+        if send_test {
+            self.test_hook().await?;
+        }
+
+        Ok(hook)
+    }
+
+    /// Asks QIWI to send a test notification to the active webhook, returning its confirmation message.
+    ///
+    /// Fails with [`Error::QiwiError`] if there is no active webhook.
+    pub async fn test_hook(&self) -> QiwiResult<String> {
+        Ok(self
+            .caller
+            .call::<_, WebhookResponse>(
+                "payment-notifier/v1/hooks/test",
+                Method::GET,
+                &Default::default(),
+                None,
+            )
+            .await?
+            .into_result()?
+            .response)
+    }
+
+    /// Deletes the webhook, returning the confirmation message of QIWI.
+    pub async fn delete_webhook(&self, hook_id: &str) -> QiwiResult<String> {
+        let url = format!("payment-notifier/v1/hooks/{}", hook_id);
+        Ok(self
+            .caller
+            .call::<_, WebhookResponse>(url, Method::DELETE, &Default::default(), None)
+            .await?
+            .into_result()?
+            .response)
+    }
+
+    /// Secret key for verifying notification signatures.
+    pub async fn webhook_key(&self, hook_id: &str) -> QiwiResult<String> {
+        let url = format!("payment-notifier/v1/hooks/{}/key", hook_id);
+        Ok(self
+            .caller
+            .call::<_, WebhookKey>(url, Method::GET, &Default::default(), None)
+            .await?
+            .into_result()?
+            .key)
+    }
+
+    /// Replaces the secret key of the webhook, the old one stops working.
+    pub async fn new_webhook_key(&self, hook_id: &str) -> QiwiResult<String> {
+        let url = format!("payment-notifier/v1/hooks/{}/newkey", hook_id);
+        Ok(self
+            .caller
+            .call::<_, WebhookKey>(url, Method::POST, &Default::default(), None)
+            .await?
+            .into_result()?
+            .key)
+    }
+
+    /// Makes sure the active webhook points at `url`, replacing the current one if needed.
+    ///
+    /// QIWI allows only one webhook, so an existing one has to be deleted before registering
+    /// the new one. If registration fails after that, [`Error::WebhookLost`] is returned:
+    /// the wallet is left without any webhook.
+    pub async fn ensure_webhook(
+        &self,
+        url: &str,
+        txn_type: WebhookTxnType,
+    ) -> QiwiResult<EnsureOutcome> {
+        let old_url = match self.active_webhook().await? {
+            Some(active) if active.url() == url && active.txn_type == txn_type => {
+                return Ok(EnsureOutcome::AlreadyCorrect)
+            }
+            Some(active) => {
+                self.delete_webhook(&active.hook_id).await?;
+                Some(active.hook_parameters.url)
+            }
+            None => None,
+        };
+
+        let hook = match self.register_webhook(url, txn_type, false).await {
+            Ok(hook) => hook,
+            Err(e) => {
+                return Err(match old_url {
+                    Some(old_url) => Error::WebhookLost {
+                        old_url,
+                        source: Box::new(e),
+                    },
+                    None => e,
+                })
+            }
+        };
+        let key = self.webhook_key(&hook.hook_id).await?;
+
+        Ok(match old_url {
+            Some(old_url) => EnsureOutcome::Replaced { old_url, key },
+            None => EnsureOutcome::Created { key },
+        })
+    }
+}
+
+#[cfg(feature = "history")]
+impl Client {
+    /// Compares the notification with the history entry of its transaction, see [`WebhookPayment::matches_entry`].
+    ///
+    /// Useful before acting on a notification, e.g. crediting an order.
+    pub async fn verify_webhook_against_history(
+        &self,
+        payload: &WebhookPayment,
+    ) -> QiwiResult<MatchReport> {
+        let payment = match &payload.payment {
+            Some(payment) => payment,
+            None => return Ok(MatchReport::default()),
+        };
+        let txn_id = match payment.txn_id.parse() {
+            Ok(txn_id) => txn_id,
+            Err(_) => {
+                return Ok(MatchReport {
+                    mismatches: vec![FieldMismatch {
+                        field: "txnId",
+                        webhook: payment.txn_id.clone(),
+                        history: "none".to_string(),
+                    }],
+                })
+            }
+        };
+        let txn_type = match payment.payment_type {
+            PaymentType::In => TransactionType::In,
+            PaymentType::Out | PaymentType::QiwiCard => TransactionType::Out,
+        };
+
+        let entry = self.transaction_info(txn_id, txn_type).await?;
+        Ok(payload.matches_entry(&entry))
+    }
+}
+
+/// Checks that `payload` was signed with the webhook key, see [`Client::webhook_key`] and
+/// [`signature::verify_webhook_signature`].
+pub fn verify_webhook_signature(payload: &WebhookPayment, base64_key: &str) -> QiwiResult<bool> {
+    signature::verify_webhook_signature(payload, base64_key)
+        .map_err(|e| Box::new(e.0) as StdError)
+        .context(InvalidWebhookKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// History entry and the notification of its payment, with float rounded amounts.
+    fn notified(entry: Value) -> (PaymentHistoryEntry, WebhookPayment) {
+        let payload = serde_json::from_value(fixtures::webhook_payment(&entry)).unwrap();
+        (serde_json::from_value(entry).unwrap(), payload)
+    }
+
+    fn fields(report: &MatchReport) -> Vec<&'static str> {
+        report
+            .mismatches
+            .iter()
+            .map(|mismatch| mismatch.field)
+            .collect()
+    }
+
+    #[test]
+    fn rounded_amounts_match_within_a_kopeck() {
+        for entry in fixtures::history_entries(fixtures::TYPICAL_HISTORY_LEN) {
+            let (entry, payload) = notified(entry);
+            let report = payload.matches_entry(&entry);
+            assert!(report.is_match(), "{:?}", report);
+
+            // Notifications are off by a fraction of a kopeck, commissions are zero.
+            let exact = payload.matches_entry_within(&entry, &BigDecimal::from(0));
+            assert_eq!(fields(&exact), vec!["sum.amount", "total.amount"]);
+        }
+    }
+
+    #[test]
+    fn mismatches_are_reported_by_field() {
+        let mut entry = fixtures::history_entries(1).remove(0);
+        entry["status"] = json!("SUCCESS");
+        entry["sum"] = json!({ "amount": "100.00", "currency": "643" });
+        entry["total"] = entry["sum"].clone();
+        let mut payload = fixtures::webhook_payment(&entry);
+        payload["payment"]["txnId"] = json!("1");
+        payload["payment"]["status"] = json!("ERROR");
+        payload["payment"]["sum"]["amount"] = json!(100.02);
+        payload["payment"]["total"]["currency"] = json!("840");
+        let payload = serde_json::from_value::<WebhookPayment>(payload).unwrap();
+        let entry = serde_json::from_value(entry).unwrap();
+
+        let report = payload.matches_entry(&entry);
+        assert_eq!(
+            fields(&report),
+            vec!["txnId", "status", "sum.amount", "total.currency"]
+        );
+        assert_eq!(report.mismatches[0].webhook, "1");
+        assert_eq!(report.mismatches[2].history, "100.00");
+        assert_eq!(report.mismatches[3].webhook, "840");
+        assert_eq!(report.mismatches[3].history, "643");
+
+        // A wider tolerance still catches everything else.
+        let report = payload.matches_entry_within(&entry, &BigDecimal::from(1));
+        assert_eq!(fields(&report), vec!["txnId", "status", "total.currency"]);
+    }
+
+    #[test]
+    fn test_notification_has_no_payment() {
+        let (entry, mut payload) = notified(fixtures::history_entries(1).remove(0));
+        payload.payment = None;
+        assert_eq!(fields(&payload.matches_entry(&entry)), vec!["payment"]);
+    }
+
+    #[cfg(feature = "history")]
+    #[tokio::test]
+    async fn notification_is_verified_against_its_transaction() {
+        let entries = fixtures::history_entries(2);
+        let transport = Arc::new(OfflineTransport::new());
+        for entry in &entries {
+            transport.insert(
+                Method::GET,
+                ApiVersions::default().transaction_endpoint(entry["txnId"].as_u64().unwrap()),
+                entry,
+            );
+        }
+        let client = Client::builder("+79991234567".parse().unwrap(), "")
+            .transport(transport.clone())
+            .build();
+
+        for entry in &entries {
+            let (_, payload) = notified(entry.clone());
+            let report = client
+                .verify_webhook_against_history(&payload)
+                .await
+                .unwrap();
+            assert!(report.is_match(), "{:?}", report);
+        }
+        let types = transport
+            .recorded()
+            .into_iter()
+            .map(|request| request.params)
+            .collect::<Vec<_>>();
+        let expected = entries
+            .iter()
+            .map(|entry| {
+                vec![(
+                    "type".to_string(),
+                    entry["type"].as_str().unwrap().to_string(),
+                )]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(types, expected);
+
+        // Another transaction's entry does not match.
+        let (_, mut payload) = notified(entries[0].clone());
+        payload.payment.as_mut().unwrap().txn_id = entries[1]["txnId"].to_string();
+        let report = client
+            .verify_webhook_against_history(&payload)
+            .await
+            .unwrap();
+        assert!(fields(&report).contains(&"sum.amount"), "{:?}", report);
+
+        // Notifications without a payment are not looked up.
+        payload.payment = None;
+        let requests = transport.requests().len();
+        assert!(client
+            .verify_webhook_against_history(&payload)
+            .await
+            .unwrap()
+            .is_match());
+        assert_eq!(transport.requests().len(), requests);
+    }
+}
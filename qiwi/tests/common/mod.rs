@@ -0,0 +1,51 @@
+//! Client talking to a mock server over real HTTP.
+
+#![allow(dead_code)]
+
+use {
+    qiwi::{Client, ClientBuilder},
+    qiwi_mock_server::{Config, MockServer},
+    serde_json::Value,
+};
+
+pub const PHONE: &str = "+79991234567";
+pub const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+/// Mock server on the current runtime, expecting [`TOKEN`].
+pub fn start() -> MockServer {
+    start_with(Config::new(PHONE.parse().unwrap(), TOKEN))
+}
+
+pub fn start_with(config: Config) -> MockServer {
+    MockServer::start("127.0.0.1:0".parse().unwrap(), config).unwrap()
+}
+
+/// Builder of a client for `server`, authorized with `token`.
+pub fn builder<T: std::fmt::Display>(server: &MockServer, token: T) -> ClientBuilder {
+    Client::builder(PHONE.parse().unwrap(), token).base_url(server.url())
+}
+
+pub fn client(server: &MockServer) -> Client {
+    builder(server, TOKEN).build()
+}
+
+/// Requests received by the server so far.
+pub fn requests(server: &MockServer) -> Vec<Value> {
+    server.report()["requests"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Requests received by the server so far whose path ends with `suffix`.
+pub fn requests_to(server: &MockServer, suffix: &str) -> usize {
+    requests(server)
+        .iter()
+        .filter(|request| {
+            request["path"]
+                .as_str()
+                .unwrap_or_default()
+                .ends_with(suffix)
+        })
+        .count()
+}
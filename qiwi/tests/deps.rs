@@ -0,0 +1,72 @@
+//! Both ways of naming the types of the public API: through conversions from primitive types
+//! only, and with the third-party crates re-exported by [`qiwi::deps`].
+
+use std::convert::TryFrom;
+
+mod primitives {
+    use {super::*, qiwi::*};
+
+    #[test]
+    fn money_from_strings_and_codes() {
+        let rub = QiwiCurrency::try_from(643u16).unwrap();
+        assert_eq!(rub, QiwiCurrency::try_from("RUB").unwrap());
+        assert_eq!(rub, "643".parse().unwrap());
+        assert!(QiwiCurrency::try_from(999u16).is_err());
+
+        let money = Money::try_from("100.50 RUB").unwrap();
+        assert_eq!(money.currency, rub);
+        assert_eq!(money.to_string(), "100.50 643");
+        assert!(Money::try_from("100.50").is_err());
+
+        let float = Money::try_from((0.1, rub.clone())).unwrap();
+        assert_eq!(float.to_string(), "0.1 643");
+        assert!(Money::try_from((f64::NAN, rub)).is_err());
+    }
+
+    #[test]
+    fn wallet_from_string() {
+        let user = QiwiUser::try_from("+79991234567").unwrap();
+        assert_eq!(user.to_string(), "79991234567");
+        assert_eq!(
+            QiwiUser::try_from("79991234567").unwrap().to_string(),
+            "79991234567"
+        );
+        assert!(QiwiUser::try_from("not a phone").is_err());
+    }
+}
+
+mod facade {
+    use {
+        super::*,
+        qiwi::{
+            deps::{bigdecimal::BigDecimal, chrono::prelude::*, penny, phonenumber::PhoneNumber},
+            *,
+        },
+    };
+
+    #[test]
+    fn money_from_dependency_types() {
+        let money = Money::new(BigDecimal::from(100), penny::Currency::RUB);
+        assert_eq!(money.currency, QiwiCurrency::from(penny::Currency::RUB));
+        assert_eq!(
+            penny::Currency::from(money.currency.clone()),
+            penny::Currency::RUB
+        );
+        assert_eq!(money.amount, Money::try_from("100 RUB").unwrap().amount);
+    }
+
+    #[test]
+    fn client_from_dependency_types() {
+        let phone: PhoneNumber = "+79991234567".parse().unwrap();
+        assert_eq!(
+            QiwiUser::from(phone.clone()).to_string(),
+            QiwiUser::try_from("+79991234567").unwrap().to_string()
+        );
+
+        let client = Client::builder(phone, "token").build();
+        assert_eq!(client.region(), Region::Russia);
+
+        let created: DateTime<Utc> = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(created.year(), 2020);
+    }
+}
@@ -0,0 +1,255 @@
+#![cfg(feature = "doh")]
+
+mod common;
+
+use {
+    common::*,
+    hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    },
+    qiwi::{clock::ManualClock, deps::chrono::Utc, doh::DohResolver},
+    serde_json::{json, Value},
+    std::{
+        collections::HashMap,
+        convert::Infallible,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+};
+
+/// DoH server answering queries from a table of names, recording them.
+#[derive(Clone, Default)]
+struct StubDoh {
+    /// HTTP status and body by queried name, 404 for other names.
+    answers: Arc<Mutex<HashMap<String, (u16, Value)>>>,
+    queries: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl StubDoh {
+    fn answer(&self, name: &str, status: u16, body: Value) {
+        self.answers
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (status, body));
+    }
+
+    /// Answers `name` with `A` records of the addresses and TTLs.
+    fn resolve(&self, name: &str, records: &[(&str, u64)]) {
+        let answers = records
+            .iter()
+            .map(|(addr, ttl)| json!({ "name": name, "type": 1, "TTL": ttl, "data": addr }))
+            .collect::<Vec<_>>();
+        self.answer(name, 200, json!({ "Status": 0, "Answer": answers }));
+    }
+
+    fn queries(&self, name: &str) -> usize {
+        self.queries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(queried, _)| queried == name)
+            .count()
+    }
+
+    fn handle(&self, req: Request<Body>) -> Response<Body> {
+        // Names and types of the tests need no percent-decoding.
+        let query = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                Some((pair.next()?.to_string(), pair.next()?.to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+        let name = query.get("name").cloned().unwrap_or_default();
+        let record_type = query.get("type").cloned().unwrap_or_default();
+        self.queries
+            .lock()
+            .unwrap()
+            .push((name.clone(), record_type));
+
+        let (status, body) = self
+            .answers
+            .lock()
+            .unwrap()
+            .get(&name)
+            .cloned()
+            .unwrap_or((404, Value::Null));
+        let mut rsp = Response::new(Body::from(body.to_string()));
+        *rsp.status_mut() = StatusCode::from_u16(status).unwrap();
+        rsp
+    }
+
+    /// Serves on a free port of the current runtime, returning the query URL.
+    fn start(&self) -> String {
+        let stub = self.clone();
+        let make_service = make_service_fn(move |_| {
+            let stub = stub.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let rsp = stub.handle(req);
+                    async move { Ok::<_, Infallible>(rsp) }
+                }))
+            }
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        format!("http://{}/dns-query", addr)
+    }
+}
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+fn stub_resolver(stub: &StubDoh) -> (DohResolver, Arc<ManualClock>) {
+    let clock = Arc::new(ManualClock::new(Utc::now()));
+    (DohResolver::with_clock(stub.start(), clock.clone()), clock)
+}
+
+#[tokio::test]
+async fn lookup() {
+    let stub = StubDoh::default();
+    stub.answer(
+        "edge.qiwi.com",
+        200,
+        json!({
+            "Status": 0,
+            "Answer": [
+                { "name": "edge.qiwi.com", "type": 5, "TTL": 300, "data": "edge.qiwi.net." },
+                { "name": "edge.qiwi.net", "type": 1, "TTL": 300, "data": "91.232.230.222" },
+                { "name": "edge.qiwi.net", "type": 1, "TTL": 300, "data": "91.213.51.222" },
+            ],
+        }),
+    );
+    let (resolver, _) = stub_resolver(&stub);
+
+    assert_eq!(
+        resolver.lookup("edge.qiwi.com").await.unwrap(),
+        vec![ip("91.232.230.222"), ip("91.213.51.222")]
+    );
+    assert_eq!(
+        *stub.queries.lock().unwrap(),
+        vec![("edge.qiwi.com".to_string(), "A".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn resolutions_are_cached_for_their_ttl() {
+    let stub = StubDoh::default();
+    stub.resolve(
+        "edge.qiwi.com",
+        &[("91.232.230.222", 300), ("91.213.51.222", 120)],
+    );
+    let (resolver, clock) = stub_resolver(&stub);
+
+    resolver.lookup("edge.qiwi.com").await.unwrap();
+    clock.advance(Duration::from_secs(119));
+    resolver.lookup("edge.qiwi.com").await.unwrap();
+    assert_eq!(stub.queries("edge.qiwi.com"), 1);
+
+    // The shortest TTL of the answer applies.
+    clock.advance(Duration::from_secs(1));
+    stub.resolve("edge.qiwi.com", &[("91.213.51.222", 300)]);
+    assert_eq!(
+        resolver.lookup("edge.qiwi.com").await.unwrap(),
+        vec![ip("91.213.51.222")]
+    );
+    assert_eq!(stub.queries("edge.qiwi.com"), 2);
+}
+
+#[tokio::test]
+async fn ttl_is_clamped() {
+    let stub = StubDoh::default();
+    stub.resolve("short.example", &[("192.0.2.1", 1)]);
+    stub.resolve("long.example", &[("192.0.2.2", 86_400)]);
+    let (resolver, clock) = stub_resolver(&stub);
+
+    resolver.lookup("short.example").await.unwrap();
+    resolver.lookup("long.example").await.unwrap();
+    clock.advance(Duration::from_secs(29));
+    resolver.lookup("short.example").await.unwrap();
+    assert_eq!(stub.queries("short.example"), 1);
+    clock.advance(Duration::from_secs(1));
+    resolver.lookup("short.example").await.unwrap();
+    assert_eq!(stub.queries("short.example"), 2);
+
+    clock.advance(Duration::from_secs(60 * 60 - 30 - 1));
+    resolver.lookup("long.example").await.unwrap();
+    assert_eq!(stub.queries("long.example"), 1);
+    clock.advance(Duration::from_secs(1));
+    resolver.lookup("long.example").await.unwrap();
+    assert_eq!(stub.queries("long.example"), 2);
+}
+
+#[tokio::test]
+async fn failed_lookups_are_not_cached() {
+    let stub = StubDoh::default();
+    stub.answer("nxdomain.example", 200, json!({ "Status": 3 }));
+    stub.answer(
+        "ipv6.example",
+        200,
+        json!({
+            "Status": 0,
+            "Answer": [{ "name": "ipv6.example", "type": 28, "TTL": 300, "data": "2001:db8::1" }],
+        }),
+    );
+    stub.answer("broken.example", 500, Value::Null);
+    let (resolver, _) = stub_resolver(&stub);
+
+    for name in &["nxdomain.example", "ipv6.example", "broken.example"] {
+        assert!(resolver.lookup(name).await.is_err(), "{}", name);
+        assert!(resolver.lookup(name).await.is_err(), "{}", name);
+        assert_eq!(stub.queries(name), 2, "{}", name);
+    }
+}
+
+/// Mock server reached as `localhost`, so that its host goes through the resolver.
+fn localhost_url(server: &qiwi_mock_server::MockServer) -> String {
+    format!("http://localhost:{}", server.addr().port())
+}
+
+#[tokio::test]
+async fn client_resolves_api_host_over_doh() {
+    let server = start();
+    let stub = StubDoh::default();
+    stub.resolve("localhost", &[("127.0.0.1", 300)]);
+    let client = qiwi::Client::builder(PHONE.parse().unwrap(), TOKEN)
+        .base_url(localhost_url(&server))
+        .doh_resolver(stub.start())
+        .build();
+
+    client.accounts().await.unwrap();
+    client.profile_info().await.unwrap();
+    assert_eq!(stub.queries("localhost"), 1);
+    assert_eq!(requests(&server).len(), 2);
+}
+
+#[tokio::test]
+async fn client_falls_back_to_system_dns() {
+    let server = start();
+    let stub = StubDoh::default();
+    stub.answer("localhost", 200, json!({ "Status": 2 }));
+    let client = qiwi::Client::builder(PHONE.parse().unwrap(), TOKEN)
+        .base_url(localhost_url(&server))
+        .doh_resolver(stub.start())
+        .build();
+
+    client.accounts().await.unwrap();
+    assert_eq!(stub.queries("localhost"), 1);
+    assert_eq!(requests(&server).len(), 1);
+
+    // An unreachable DoH server falls back the same way.
+    let unreachable = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9);
+    let client = qiwi::Client::builder(PHONE.parse().unwrap(), TOKEN)
+        .base_url(localhost_url(&server))
+        .doh_resolver(format!("http://{}/dns-query", unreachable))
+        .build();
+    client.accounts().await.unwrap();
+    assert_eq!(requests(&server).len(), 2);
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use {
+    common::*,
+    qiwi::*,
+    qiwi_mock_server::{Config, Control, MockServer},
+    std::{net::SocketAddr, sync::Arc, time::Duration},
+};
+
+const PROFILE: &str = "person-profile/v1/profile/current";
+
+/// Address nothing listens on, so that connecting to it fails.
+fn unreachable_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+fn url(addr: SocketAddr) -> String {
+    format!("http://{}", addr)
+}
+
+fn remote(primary: String, fallback: Vec<String>) -> RemoteCaller {
+    let token = TokenCache::new(Arc::new(TOKEN), Duration::from_secs(60));
+    let remote = RemoteCaller::new(reqwest::Client::new(), primary, Some(Arc::new(token)));
+    remote.set_fallback_hosts(fallback);
+    remote
+}
+
+async fn get(remote: &RemoteCaller) -> Result<String, StdError> {
+    remote
+        .call(PROFILE.to_string(), Method::GET, &QueryParams::new(), None)
+        .await
+}
+
+#[tokio::test]
+async fn unreachable_hosts_fail_over_to_the_next_one() {
+    let server = start();
+    let remote = remote(
+        url(unreachable_addr()),
+        vec![url(unreachable_addr()), server.url()],
+    );
+
+    get(&remote).await.unwrap();
+    assert_eq!(&*remote.active_addr(), server.url().as_str());
+    assert_eq!(requests_to(&server, PROFILE), 1);
+
+    // The working host is used right away for subsequent calls.
+    get(&remote).await.unwrap();
+    assert_eq!(requests_to(&server, PROFILE), 2);
+}
+
+#[tokio::test]
+async fn http_errors_do_not_fail_over() {
+    let primary = start();
+    primary
+        .control(Control {
+            maintenance: Some(true),
+            ..Default::default()
+        })
+        .await;
+    let fallback = start();
+    let remote = remote(primary.url(), vec![fallback.url()]);
+
+    assert!(get(&remote).await.is_err());
+    assert_eq!(&*remote.active_addr(), primary.url().as_str());
+    assert!(requests(&fallback).is_empty());
+}
+
+#[tokio::test]
+async fn primary_is_switched_back_to_once_reachable() {
+    let addr = unreachable_addr();
+    let fallback = start();
+    let remote = remote(url(addr), vec![fallback.url()]);
+    remote.set_primary_probe_interval(Duration::from_millis(0));
+
+    get(&remote).await.unwrap();
+    assert_eq!(&*remote.active_addr(), fallback.url().as_str());
+
+    let primary = MockServer::start(addr, Config::new(PHONE.parse().unwrap(), TOKEN)).unwrap();
+    // Calls keep going to the fallback host until a probe finds the primary one reachable.
+    for _ in 0..100 {
+        if &*remote.active_addr() == primary.url().as_str() {
+            break;
+        }
+        get(&remote).await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+    }
+    assert_eq!(&*remote.active_addr(), primary.url().as_str());
+
+    let served_by_fallback = requests_to(&fallback, PROFILE);
+    get(&remote).await.unwrap();
+    assert_eq!(requests_to(&primary, PROFILE), 1);
+    assert_eq!(requests_to(&fallback, PROFILE), served_by_fallback);
+}
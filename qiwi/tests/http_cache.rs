@@ -0,0 +1,148 @@
+mod common;
+
+use {
+    common::*,
+    qiwi::{http_cache::HttpCacheLimits, *},
+    qiwi_mock_server::{Control, MockServer, Validators},
+};
+
+const HISTORY: &str = "payment-history/v2/persons/{wallet}/payments";
+const ACCOUNTS: &str = "funding-sources/v2/persons/{wallet}/accounts";
+
+async fn fetch(client: &Client, endpoint: &str, rows: u16) -> String {
+    let params = if endpoint == HISTORY {
+        QueryParams::new().with("rows", rows)
+    } else {
+        QueryParams::new()
+    };
+    client
+        .call_raw(endpoint, Method::GET, params, None::<()>)
+        .await
+        .unwrap()
+}
+
+/// Statuses the server answered requests to paths ending with `suffix` with, in order.
+fn statuses(server: &MockServer, suffix: &str) -> Vec<u64> {
+    requests(server)
+        .iter()
+        .filter(|request| {
+            request["path"]
+                .as_str()
+                .unwrap_or_default()
+                .ends_with(suffix)
+        })
+        .map(|request| request["status"].as_u64().unwrap())
+        .collect()
+}
+
+async fn serve_with(validators: Validators) -> MockServer {
+    let server = start();
+    server
+        .control(Control {
+            validators: Some(validators),
+            ..Default::default()
+        })
+        .await;
+    server
+}
+
+/// Responses served from the cache on 304 are the ones a client without the cache gets with 200.
+async fn not_modified_is_served_from_cache(validators: Validators) {
+    let server = serve_with(validators).await;
+    let cached = builder(&server, TOKEN).http_cache(true).build();
+    let plain = client(&server);
+
+    let first = fetch(&cached, HISTORY, 5).await;
+    let revalidated = fetch(&cached, HISTORY, 5).await;
+    let fresh = fetch(&plain, HISTORY, 5).await;
+    assert_eq!(revalidated.as_bytes(), first.as_bytes());
+    assert_eq!(revalidated.as_bytes(), fresh.as_bytes());
+    assert_eq!(statuses(&server, "/payments"), vec![200, 304, 200]);
+
+    // A changed history is fetched anew, then revalidated again.
+    server
+        .control(Control {
+            history_len: Some(30),
+            ..Default::default()
+        })
+        .await;
+    let changed = fetch(&cached, HISTORY, 5).await;
+    let revalidated = fetch(&cached, HISTORY, 5).await;
+    let fresh = fetch(&plain, HISTORY, 5).await;
+    assert_ne!(changed, first);
+    assert_eq!(changed.as_bytes(), fresh.as_bytes());
+    assert_eq!(revalidated.as_bytes(), fresh.as_bytes());
+    assert_eq!(
+        statuses(&server, "/payments"),
+        vec![200, 304, 200, 200, 304, 200]
+    );
+}
+
+#[tokio::test]
+async fn etag() {
+    not_modified_is_served_from_cache(Validators::Etag).await;
+}
+
+#[tokio::test]
+async fn last_modified() {
+    not_modified_is_served_from_cache(Validators::LastModified).await;
+}
+
+#[tokio::test]
+async fn responses_without_validators_are_not_cached() {
+    let server = serve_with(Validators::None).await;
+    let cached = builder(&server, TOKEN).http_cache(true).build();
+
+    fetch(&cached, HISTORY, 5).await;
+    fetch(&cached, HISTORY, 5).await;
+    assert_eq!(statuses(&server, "/payments"), vec![200, 200]);
+}
+
+#[tokio::test]
+async fn least_recently_used_is_evicted() {
+    let server = serve_with(Validators::Etag).await;
+    let cached = builder(&server, TOKEN)
+        .http_cache_limits(HttpCacheLimits {
+            max_entries: 2,
+            ..Default::default()
+        })
+        .build();
+
+    fetch(&cached, HISTORY, 5).await;
+    fetch(&cached, HISTORY, 10).await;
+    // Revalidating the first page makes the second one the least recently used.
+    fetch(&cached, HISTORY, 5).await;
+    fetch(&cached, ACCOUNTS, 0).await;
+    assert_eq!(statuses(&server, "/payments"), vec![200, 200, 304]);
+
+    let first = fetch(&cached, HISTORY, 5).await;
+    let evicted = fetch(&cached, HISTORY, 10).await;
+    assert_eq!(
+        statuses(&server, "/payments"),
+        vec![200, 200, 304, 304, 200]
+    );
+    assert_eq!(first, fetch(&client(&server), HISTORY, 5).await);
+    assert_eq!(evicted, fetch(&client(&server), HISTORY, 10).await);
+}
+
+#[tokio::test]
+async fn oversized_responses_are_not_cached() {
+    let server = serve_with(Validators::LastModified).await;
+    let small = fetch(&client(&server), HISTORY, 1).await;
+    let cached = builder(&server, TOKEN)
+        .http_cache_limits(HttpCacheLimits {
+            max_entry_size: small.len(),
+            ..Default::default()
+        })
+        .build();
+
+    // The first 200 is the uncached request measuring the page.
+    fetch(&cached, HISTORY, 1).await;
+    fetch(&cached, HISTORY, 1).await;
+    fetch(&cached, HISTORY, 2).await;
+    fetch(&cached, HISTORY, 2).await;
+    assert_eq!(
+        statuses(&server, "/payments"),
+        vec![200, 200, 304, 200, 200]
+    );
+}
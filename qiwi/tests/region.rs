@@ -0,0 +1,98 @@
+mod common;
+
+use {
+    common::*,
+    qiwi::{
+        clock::ManualClock,
+        deps::{bigdecimal::BigDecimal, chrono::prelude::*, penny},
+        *,
+    },
+    qiwi_mock_server::{Config, MockServer},
+    serde_json::json,
+    std::sync::Arc,
+    tokio::stream::StreamExt,
+};
+
+const KZ_PHONE: &str = "+77011234567";
+const KZ_RECIPIENT: &str = "+77017654321";
+
+fn kz_server() -> MockServer {
+    start_with(Config::new(KZ_PHONE.parse().unwrap(), TOKEN))
+}
+
+fn kz_builder(server: &MockServer) -> ClientBuilder {
+    Client::builder(KZ_PHONE.parse().unwrap(), TOKEN).base_url(server.url())
+}
+
+#[tokio::test]
+async fn kz_wallet_transfers_in_tenge() {
+    let server = kz_server();
+    let client = kz_builder(&server).build();
+    assert_eq!(client.region(), Region::Kazakhstan);
+
+    let quote = client
+        .commission_quote(
+            ProviderId::QIWI,
+            AccountId::parse_in("87017654321", ProviderId::QIWI, None, client.region()).unwrap(),
+            BigDecimal::from(500),
+        )
+        .await
+        .unwrap();
+    assert_eq!(quote.withdraw_sum.currency, penny::Currency::KZT.into());
+
+    let transfer = TransferRequest::with_id(
+        1000,
+        BigDecimal::from(500),
+        TransferDirection::Qiwi {
+            to_phone: KZ_RECIPIENT.parse().unwrap(),
+            to_currency: penny::Currency::KZT,
+        },
+        "rent",
+    );
+    client.transfer(&transfer).await.unwrap();
+
+    let payments = server.report()["payments"].as_array().cloned().unwrap();
+    assert_eq!(payments.len(), 1);
+    assert_eq!(payments[0]["sum"]["currency"], json!("398"));
+    assert_eq!(payments[0]["paymentMethod"]["accountId"], json!("398"));
+    assert!(payments[0]["fields"]["account"]
+        .as_str()
+        .unwrap()
+        .ends_with("7017654321"));
+
+    // The transfer is on top of the history, in tenge.
+    let entry = client.payment_history().next().await.unwrap().unwrap();
+    assert_eq!(entry.trm_txn_id, "1000");
+    assert_eq!(entry.sum.currency, "398");
+}
+
+#[tokio::test]
+async fn kz_history_boundaries_are_in_local_time() {
+    let server = kz_server();
+    // Already March in Almaty, still February in Moscow.
+    let now = Utc.with_ymd_and_hms(2020, 2, 29, 20, 0, 0).unwrap();
+    let client = kz_builder(&server)
+        .clock(Arc::new(ManualClock::new(now)))
+        .build();
+
+    let volume = client.p2p_volume_this_month().await.unwrap();
+    assert_eq!(volume.currency, penny::Currency::KZT.into());
+
+    let history = requests(&server)
+        .into_iter()
+        .find(|request| {
+            request["path"]
+                .as_str()
+                .unwrap_or_default()
+                .ends_with("/payments")
+        })
+        .unwrap();
+    assert_eq!(
+        history["query"]["startDate"],
+        json!("2020-03-01T00:00:00+05:00")
+    );
+    assert_eq!(
+        history["query"]["endDate"],
+        json!("2020-03-01T01:00:00+05:00")
+    );
+}
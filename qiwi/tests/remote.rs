@@ -0,0 +1,402 @@
+mod common;
+
+use {
+    async_trait::async_trait,
+    common::*,
+    qiwi::*,
+    qiwi_mock_server::{Control, Fixture},
+    serde::Deserialize,
+    serde_json::{json, Value},
+    std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+    },
+    tokio::stream::StreamExt,
+};
+
+#[tokio::test]
+async fn invalid_token_is_unauthorized() {
+    let server = start();
+    let client = builder(&server, "ffffffffffffffffffffffffffffffff").build();
+
+    match client.profile_info().await {
+        Err(Error::Unauthorized {
+            status: 401,
+            error: Some(error),
+            ..
+        }) => assert_eq!(error.error_code, "internal.invalid.token"),
+        other => panic!("expected Unauthorized, got {:?}", other),
+    }
+}
+
+/// Token that can be rotated while the client is running, counting how often it is fetched.
+#[derive(Clone, Default)]
+struct RotatingToken(Arc<(Mutex<String>, AtomicUsize)>);
+
+impl RotatingToken {
+    fn new(token: &str) -> Self {
+        let provider = Self::default();
+        provider.rotate(token);
+        provider
+    }
+
+    fn rotate(&self, token: &str) {
+        *(self.0).0.lock().unwrap() = token.to_string();
+    }
+
+    fn fetches(&self) -> usize {
+        (self.0).1.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl TokenProvider for RotatingToken {
+    async fn token(&self) -> Result<SecretString, StdError> {
+        (self.0).1.fetch_add(1, Ordering::SeqCst);
+        Ok((self.0).0.lock().unwrap().as_str().into())
+    }
+}
+
+const ROTATED_TOKEN: &str = "fedcba9876543210fedcba9876543210";
+
+#[tokio::test]
+async fn token_rotation_mid_history() {
+    let server = start();
+    let provider = RotatingToken::new(TOKEN);
+    let client = builder(&server, "")
+        .token_provider(provider.clone())
+        .build();
+
+    let mut history = client.payment_history();
+    let mut entries = Vec::new();
+    for _ in 0..50 {
+        entries.push(history.next().await.unwrap().unwrap());
+    }
+
+    // Rotated while the first page is being read, the cached token is rejected on the next one.
+    server
+        .control(Control {
+            token: Some(ROTATED_TOKEN.into()),
+            ..Default::default()
+        })
+        .await;
+    provider.rotate(ROTATED_TOKEN);
+
+    while let Some(entry) = history.next().await {
+        entries.push(entry.unwrap());
+    }
+    assert_eq!(entries.len(), fixtures::TYPICAL_HISTORY_LEN);
+    let mut txn_ids = entries.iter().map(|entry| entry.txn_id).collect::<Vec<_>>();
+    txn_ids.sort_unstable();
+    txn_ids.dedup();
+    assert_eq!(txn_ids.len(), entries.len());
+
+    let pages = requests(&server)
+        .into_iter()
+        .filter(|request| {
+            request["path"]
+                .as_str()
+                .unwrap_or_default()
+                .ends_with("/payments")
+        })
+        .map(|request| request["authorized"].as_bool().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(pages, vec![true, false, true, true]);
+    assert_eq!(provider.fetches(), 2);
+}
+
+#[tokio::test]
+async fn rejected_token_is_fetched_again_once() {
+    let server = start();
+    let provider = RotatingToken::new("ffffffffffffffffffffffffffffffff");
+    let client = builder(&server, "")
+        .token_provider(provider.clone())
+        .build();
+
+    match client.profile_info().await {
+        Err(Error::Unauthorized { status: 401, .. }) => {}
+        other => panic!("expected Unauthorized, got {:?}", other),
+    }
+    assert_eq!(requests(&server).len(), 2);
+    assert_eq!(provider.fetches(), 2);
+
+    // Once the provider has the right token, the next call recovers after a single rejection.
+    provider.rotate(TOKEN);
+    client.profile_info().await.unwrap();
+    assert_eq!(requests(&server).len(), 4);
+    assert_eq!(provider.fetches(), 3);
+}
+
+/// Calls running concurrently with the swaps of [`swap_during_calls`].
+const CONCURRENT_CALLS: usize = 200;
+
+/// Runs [`CONCURRENT_CALLS`] profile requests while another thread keeps calling `swap` with
+/// the number of the swap, checking that each of them succeeds. Returns the number of swaps.
+async fn swap_during_calls<F>(client: &Arc<Client>, swap: F) -> usize
+where
+    F: Fn(&Client, usize) + Send + 'static,
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    let swaps = Arc::new(AtomicUsize::new(0));
+    let swapper = thread::spawn({
+        let client = client.clone();
+        let stop = stop.clone();
+        let swaps = swaps.clone();
+        move || {
+            while !stop.load(Ordering::SeqCst) {
+                swap(&client, swaps.fetch_add(1, Ordering::SeqCst));
+            }
+        }
+    });
+    while swaps.load(Ordering::SeqCst) == 0 {
+        thread::yield_now();
+    }
+
+    let calls = (0..CONCURRENT_CALLS)
+        .map(|_| {
+            let client = client.clone();
+            tokio::spawn(async move { client.profile_info().await })
+        })
+        .collect::<Vec<_>>();
+    for call in calls {
+        call.await.unwrap().unwrap();
+    }
+
+    stop.store(true, Ordering::SeqCst);
+    swapper.join().unwrap();
+    swaps.load(Ordering::SeqCst)
+}
+
+#[tokio::test]
+async fn token_swaps_during_concurrent_calls() {
+    let server = start();
+    let client = Arc::new(client(&server));
+
+    let swaps = swap_during_calls(&client, |client, swap| {
+        if swap % 2 == 0 {
+            client.set_token(TOKEN);
+        } else {
+            client.set_token(RotatingToken::new(TOKEN));
+        }
+    })
+    .await;
+    assert!(swaps > 0);
+    let requests = requests(&server);
+    assert_eq!(requests.len(), CONCURRENT_CALLS);
+    assert!(requests
+        .iter()
+        .all(|request| request["authorized"].as_bool().unwrap()));
+
+    // The last swap is used by the calls that follow it.
+    client.set_token("ffffffffffffffffffffffffffffffff");
+    match client.profile_info().await {
+        Err(Error::Unauthorized { status: 401, .. }) => {}
+        other => panic!("expected Unauthorized, got {:?}", other),
+    }
+    client.set_token(TOKEN);
+    client.profile_info().await.unwrap();
+}
+
+#[tokio::test]
+async fn base_url_swaps_during_concurrent_calls() {
+    let primary = start();
+    let alternate = start();
+    let client = Arc::new(client(&primary));
+
+    let urls = [primary.url(), alternate.url()];
+    let swaps = swap_during_calls(&client, move |client, swap| {
+        client.set_base_url(urls[swap % 2].as_str());
+    })
+    .await;
+    assert!(swaps > 0);
+    // Every call reached exactly one of the hosts.
+    assert_eq!(
+        requests(&primary).len() + requests(&alternate).len(),
+        CONCURRENT_CALLS
+    );
+
+    client.set_base_url(alternate.url());
+    let received = (requests(&primary).len(), requests(&alternate).len());
+    client.profile_info().await.unwrap();
+    assert_eq!(
+        (requests(&primary).len(), requests(&alternate).len()),
+        (received.0, received.1 + 1)
+    );
+}
+
+/// Endpoint the client has no method for.
+const LOYALTY: &str = "loyalty/v1/persons/{wallet}/points";
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct LoyaltyPoints {
+    points: u32,
+    level: String,
+}
+
+#[tokio::test]
+async fn unsupported_endpoint_is_called_directly() {
+    let server = start();
+    let body = json!({ "points": 120, "level": "silver" });
+    server
+        .control(Control {
+            fixture: Some(Fixture {
+                method: "GET".into(),
+                path: "loyalty/v1/persons/79991234567/points".into(),
+                body: body.clone(),
+            }),
+            ..Default::default()
+        })
+        .await;
+    let client = client(&server);
+
+    let points: LoyaltyPoints = client
+        .call(
+            LOYALTY,
+            Method::GET,
+            QueryParams::new().with("year", 2020),
+            None::<()>,
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        points,
+        LoyaltyPoints {
+            points: 120,
+            level: "silver".into(),
+        }
+    );
+    let request = requests(&server).pop().unwrap();
+    assert_eq!(
+        request["path"],
+        json!("loyalty/v1/persons/79991234567/points")
+    );
+    assert_eq!(request["query"]["year"], json!("2020"));
+    assert_eq!(request["authorized"], json!(true));
+
+    let raw = client
+        .call_raw(LOYALTY, Method::GET, QueryParams::new(), None::<()>)
+        .await
+        .unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&raw).unwrap(), body);
+
+    // Errors are mapped as for the dedicated methods.
+    let unauthorized = builder(&server, "ffffffffffffffffffffffffffffffff").build();
+    match unauthorized
+        .call::<LoyaltyPoints, ()>(LOYALTY, Method::GET, QueryParams::new(), None)
+        .await
+    {
+        Err(Error::Unauthorized { status: 401, .. }) => {}
+        other => panic!("expected Unauthorized, got {:?}", other),
+    }
+}
+
+/// Headers of `request` other than those set by the HTTP stack itself and the correlation id,
+/// which differs per call, by name.
+fn api_headers(request: &Value) -> Vec<(String, Vec<String>)> {
+    request["headers"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(name, _)| {
+            !matches!(
+                name.as_str(),
+                "host" | "content-length" | "accept-encoding" | "user-agent" | "x-correlation-id"
+            )
+        })
+        .map(|(name, values)| {
+            let values = values
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|value| value.as_str().unwrap().to_string())
+                .collect();
+            (name.clone(), values)
+        })
+        .collect()
+}
+
+fn last_request_to(server: &qiwi_mock_server::MockServer, suffix: &str) -> Value {
+    requests(server)
+        .into_iter()
+        .filter(|request| request["path"].as_str().unwrap().ends_with(suffix))
+        .last()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn content_type_is_sent_only_with_a_body() {
+    let server = start();
+    let client = client(&server);
+    let header = |name: &str, value: &str| (name.to_string(), vec![value.to_string()]);
+    let authorization = header("authorization", &format!("Bearer {}", TOKEN));
+
+    client.profile_info().await.unwrap();
+    assert_eq!(
+        api_headers(&last_request_to(&server, "/profile/current")),
+        vec![header("accept", "application/json"), authorization.clone()]
+    );
+
+    let transfer = TransferRequest::new(
+        deps::bigdecimal::BigDecimal::from(100),
+        TransferDirection::Qiwi {
+            to_phone: "+79035550101".parse().unwrap(),
+            to_currency: deps::penny::Currency::RUB,
+        },
+        "Lunch",
+    );
+    client.transfer(&transfer).await.unwrap();
+    assert_eq!(
+        api_headers(&last_request_to(&server, "terms/99/payments")),
+        vec![
+            header("accept", "application/json"),
+            authorization.clone(),
+            header("content-type", "application/json"),
+        ]
+    );
+
+    // Not answered by the mock server, only the request matters.
+    let _ = client
+        .detect_mobile_provider(&"+79161234567".parse().unwrap())
+        .await;
+    assert_eq!(
+        api_headers(&last_request_to(&server, "/containers")),
+        vec![
+            header("accept", "application/json"),
+            authorization,
+            header("content-type", "application/x-www-form-urlencoded"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn correlation_id_is_sent_and_attached_to_errors() {
+    let server = start();
+    let client = builder(&server, TOKEN)
+        .id_generator(ids::SequentialIdGenerator::new(1))
+        .build();
+
+    client.profile_info().await.unwrap();
+    server
+        .control(Control {
+            maintenance: Some(true),
+            ..Default::default()
+        })
+        .await;
+    let error = client.profile_info().await.unwrap_err();
+    assert_eq!(error.correlation_id(), Some("0708090a0b0c"));
+
+    let sent = requests(&server)
+        .iter()
+        .filter(|request| {
+            request["path"]
+                .as_str()
+                .unwrap_or_default()
+                .ends_with("/profile/current")
+        })
+        .map(|request| request["headers"]["x-correlation-id"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(sent, vec![json!(["010203040506"]), json!(["0708090a0b0c"])]);
+}
@@ -0,0 +1,40 @@
+//! Signature corpus of `qiwi-types`, verified through the re-exports of this crate.
+#![cfg(feature = "webhooks")]
+
+use {
+    qiwi::{verify_webhook_signature, Error, WebhookPayment},
+    serde_json::Value,
+};
+
+const CORPUS: &str = include_str!("../../qiwi-types/tests/data/webhook-signatures.json");
+
+#[test]
+fn corpus() {
+    let cases = serde_json::from_str::<Vec<Value>>(CORPUS).unwrap();
+    assert!(!cases.is_empty());
+
+    for case in cases {
+        let name = case["name"].as_str().unwrap();
+        let payload = serde_json::from_value::<WebhookPayment>(case["payload"].clone())
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+        let outcome = match verify_webhook_signature(&payload, case["key"].as_str().unwrap()) {
+            Ok(true) => "valid",
+            Ok(false) => "invalid",
+            Err(Error::InvalidWebhookKey { .. }) => "invalid-key",
+            Err(e) => panic!("{}: {}", name, e),
+        };
+        assert_eq!(outcome, case["expected"], "{}", name);
+
+        // The payload survives a round trip through the models unchanged.
+        let reparsed =
+            serde_json::from_value::<WebhookPayment>(serde_json::to_value(&payload).unwrap())
+                .unwrap();
+        assert_eq!(
+            verify_webhook_signature(&reparsed, case["key"].as_str().unwrap()).ok(),
+            verify_webhook_signature(&payload, case["key"].as_str().unwrap()).ok(),
+            "{}",
+            name
+        );
+    }
+}
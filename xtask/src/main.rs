@@ -0,0 +1,80 @@
+//! Development tasks, run with `cargo xtask <task>`.
+
+use std::{
+    env,
+    process::{exit, Command},
+};
+
+/// Endpoint groups of the `qiwi` crate, see its `Cargo.toml`.
+const FEATURES: &[&str] = &[
+    "cards",
+    "bills",
+    "history",
+    "identification",
+    "payments",
+    "webhooks",
+];
+
+/// Optional extras that are not endpoint groups, checked on top of `full`.
+const EXTRAS: &[&str] = &["cbor", "doh"];
+
+/// No groups, each group alone, each group but one, everything, and everything with the extras.
+fn feature_matrix() -> Vec<Vec<&'static str>> {
+    let mut matrix = vec![vec![]];
+    matrix.extend(FEATURES.iter().map(|&feature| vec![feature]));
+    matrix.extend(FEATURES.iter().map(|&excluded| {
+        FEATURES
+            .iter()
+            .copied()
+            .filter(|&feature| feature != excluded)
+            .collect()
+    }));
+    matrix.push(vec!["full"]);
+    matrix.push(
+        std::iter::once("full")
+            .chain(EXTRAS.iter().copied())
+            .collect(),
+    );
+    matrix
+}
+
+/// Checks that the `qiwi` crate compiles without warnings with every feature set of the matrix.
+fn check_features() -> bool {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let mut failed = Vec::new();
+    for features in feature_matrix() {
+        let features = features.join(",");
+        eprintln!("checking qiwi with features [{}]", features);
+        let status = Command::new(&cargo)
+            .args(&["check", "--package", "qiwi", "--all-targets"])
+            .args(&["--no-default-features", "--features", &features])
+            .env("RUSTFLAGS", "-D warnings")
+            .status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(_) => failed.push(features),
+            Err(e) => {
+                eprintln!("failed to run {}: {}", cargo, e);
+                return false;
+            }
+        }
+    }
+
+    for features in &failed {
+        eprintln!("qiwi does not compile with features [{}]", features);
+    }
+    failed.is_empty()
+}
+
+fn main() {
+    let ok = match env::args().nth(1).as_deref() {
+        Some("check-features") => check_features(),
+        _ => {
+            eprintln!("usage: cargo xtask check-features");
+            false
+        }
+    };
+    if !ok {
+        exit(1);
+    }
+}